@@ -0,0 +1,11 @@
+#![no_main]
+use dcd::jdwp::Packet;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = data;
+    // Reads packets back to back until the input runs out or is rejected.
+    while let Ok(packet) = Packet::read_max(&mut reader, 1 << 16) {
+        assert_eq!(Packet::read(&mut &packet.to_bytes()[..]).unwrap(), packet);
+    }
+});
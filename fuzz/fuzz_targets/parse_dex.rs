@@ -0,0 +1,8 @@
+#![no_main]
+use dcd::redefine;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = redefine::parse_dex(data);
+    let _ = redefine::parse_class(data);
+});
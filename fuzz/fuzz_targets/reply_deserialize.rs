@@ -0,0 +1,24 @@
+#![no_main]
+use dcd::jdwp::{Command,IDSizes,Reply};
+use libfuzzer_sys::fuzz_target;
+
+// Set, command and ID sizes come from the first three bytes; the sizes
+// byte holds one bit per ID kind, 4 or 8 bytes.
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 3 {
+        return;
+    }
+    let size = |bit: u8| if data[2] & (1 << bit) != 0 { 8 } else { 4 };
+    let sizes = IDSizes { field: size(0), method: size(1), object: size(2), reference_type: size(3), frame: size(4) };
+    let (set, cmd, payload) = (data[0], data[1], &data[3..]);
+    if let Ok(reply) = Reply::deserialize(set, cmd, payload, sizes) {
+        let bytes = reply.serialize(sizes);
+        let again = Reply::deserialize(set, cmd, &bytes, sizes).expect("re-encoded reply doesn't decode");
+        assert_eq!(again.serialize(sizes), bytes);
+    }
+    if let Ok(command) = Command::deserialize(set, cmd, payload, sizes) {
+        let (_, _, bytes) = command.serialize(sizes);
+        let again = Command::deserialize(set, cmd, &bytes, sizes).expect("re-encoded command doesn't decode");
+        assert_eq!(again.serialize(sizes).2, bytes);
+    }
+});
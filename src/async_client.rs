@@ -0,0 +1,145 @@
+//! Client that runs its reader as a tokio task instead of an OS thread.
+use crate::{Result,Error};
+use crate::client::{self,Event,Router,State,Waiter};
+use crate::jdwp;
+use crate::request::Request;
+use log::*;
+use std::sync::{Arc,MutexGuard,Weak};
+use tokio::io::{AsyncRead,AsyncReadExt,AsyncWrite,AsyncWriteExt};
+use tokio::sync::{mpsc,oneshot,Mutex};
+
+type Responder = oneshot::Sender<Result<jdwp::Reply>>;
+
+impl Waiter for Responder {
+    fn resolve(self, reply: Result<jdwp::Reply>) {
+        let _ = self.send(reply);
+    }
+}
+
+/// Async counterpart of `client::Pending`.
+pub struct AsyncPending<T = jdwp::Reply> {
+    id: u32,
+    rx: oneshot::Receiver<Result<jdwp::Reply>>,
+    unpack: fn(jdwp::Reply) -> std::result::Result<T, jdwp::Reply>,
+}
+
+impl<T> AsyncPending<T> {
+    pub fn id(&self) -> u32 {
+        return self.id;
+    }
+    pub async fn wait(self) -> Result<T> {
+        return match self.rx.await {
+            Ok(r) => r.and_then(|r| (self.unpack)(r).map_err(Error::unexpected)),
+            Err(_) => Err(Error::Disconnected),
+        };
+    }
+}
+
+pub struct AsyncClient {
+    router: Arc<Router<Responder>>,
+    // The reader task answers commands from the VM through a weak
+    // reference, so dropping the client still hangs up.
+    writer: Arc<Mutex<Box<dyn AsyncWrite + Send + Unpin>>>,
+}
+
+impl AsyncClient {
+    /// Handshakes over `stream` and spawns the reader onto the current runtime.
+    pub async fn connect<S>(stream: S) -> Result<(AsyncClient, mpsc::UnboundedReceiver<Event>)>
+    where S: AsyncRead + AsyncWrite + Send + Unpin + 'static
+    {
+        let mut stream = stream;
+        let mut handshake_buffer = [0u8; 14];
+        stream.write_all(client::HANDSHAKE).await?;
+        stream.flush().await?;
+        stream.read_exact(&mut handshake_buffer).await?;
+        if &handshake_buffer != client::HANDSHAKE {
+            error!("JDWP Handshake failed!");
+            return Err(Error::HandshakeFailed(handshake_buffer.to_vec()));
+        }
+        let (reader, writer) = tokio::io::split(stream);
+        let router = Arc::new(Router::new());
+        let writer: Arc<Mutex<Box<dyn AsyncWrite + Send + Unpin>>> = Arc::new(Mutex::new(Box::new(writer)));
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        tokio::spawn(reader_task(reader, router.clone(), Arc::downgrade(&writer), event_tx));
+        let client = AsyncClient {
+            router: router,
+            writer: writer,
+        };
+        return Ok((client, event_rx));
+    }
+    pub async fn send(&self, cmd: &jdwp::Command) -> Result<AsyncPending> {
+        return self.send_unpacked(cmd, Ok).await;
+    }
+    pub async fn send_and_wait(&self, cmd: &jdwp::Command) -> Result<jdwp::Reply> {
+        return self.send(cmd).await?.wait().await;
+    }
+    pub async fn send_typed<T>(&self, request: Request<T>) -> Result<AsyncPending<T>> {
+        return self.send_unpacked(&request.command, request.unpack).await;
+    }
+    pub async fn request<T>(&self, request: Request<T>) -> Result<T> {
+        return self.send_typed(request).await?.wait().await;
+    }
+    async fn send_unpacked<T>(&self, cmd: &jdwp::Command, unpack: fn(jdwp::Reply) -> std::result::Result<T, jdwp::Reply>) -> Result<AsyncPending<T>> {
+        let (tx, rx) = oneshot::channel();
+        let packet = self.router.register(cmd, tx)?;
+        let id = packet.id();
+        self.router.sent(&packet);
+        let res = {
+            let mut writer = self.writer.lock().await;
+            match writer.write_all(&packet.to_bytes()).await {
+                Ok(_) => writer.flush().await,
+                Err(e) => Err(e),
+            }
+        };
+        if let Err(e) = res {
+            self.router.cancel(id);
+            return Err(e.into());
+        }
+        return Ok(AsyncPending { id: id, rx: rx, unpack: unpack });
+    }
+    pub fn record(&self, recorder: Option<Arc<crate::capture::Recorder>>) {
+        self.router.set_recorder(recorder);
+    }
+    /// Do not hold the guard across an await point.
+    pub fn state(&self) -> MutexGuard<'_, State> {
+        return self.router.state();
+    }
+}
+
+async fn read_packet<R: AsyncRead + Unpin>(reader: &mut R, max: u32) -> std::io::Result<jdwp::Packet> {
+    let mut header = [0u8; 11];
+    reader.read_exact(&mut header).await?;
+    let datalen = jdwp::Packet::data_len(&header, max)?;
+    let mut data = Vec::new();
+    reader.take(datalen as u64).read_to_end(&mut data).await?;
+    if data.len() != datalen {
+        return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "JDWP packet data is truncated"));
+    }
+    return Ok(jdwp::Packet::from_parts(&header, data));
+}
+
+async fn reader_task<R: AsyncRead + Unpin>(reader: R, router: Arc<Router<Responder>>, writer: Weak<Mutex<Box<dyn AsyncWrite + Send + Unpin>>>, events: mpsc::UnboundedSender<Event>) {
+    let mut conn = reader;
+    loop {
+        let max = router.state().max_packet.unwrap_or(jdwp::MAX_PACKET_SIZE);
+        let packet = match read_packet(&mut conn, max).await {
+            Ok(p) => p,
+            Err(e) => { info!("JDWP connection closed: {:?}", e); break; },
+        };
+        let (event, reply) = router.dispatch(packet);
+        if let (Some(reply), Some(writer)) = (reply, writer.upgrade()) {
+            let mut writer = writer.lock().await;
+            let res = match writer.write_all(&reply.to_bytes()).await {
+                Ok(_) => writer.flush().await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = res {
+                warn!("Failed to answer VM command {}: {:?}", reply.id(), e);
+            }
+        }
+        if let Some(event) = event {
+            let _ = events.send(event);
+        }
+    }
+    router.close();
+}
@@ -0,0 +1,230 @@
+//! The class browser behind `classes`, `methods`, `fields` and `hierarchy`.
+//! What a class ID refers to can't change until the class is unloaded, so
+//! answers are cached until a ClassUnload event says otherwise.
+use crate::Result;
+use crate::client::{Client,Pending};
+use crate::eval;
+use crate::jdwp::{self,EventKind};
+use crate::lookup;
+use crate::request;
+use crate::signature;
+use log::*;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+const ACC_INTERFACE: i32 = 0x0200;
+const ACC_VARARGS: i32 = 0x0080;
+const ACC_ANNOTATION: i32 = 0x2000;
+const ACC_ENUM: i32 = 0x4000;
+/// Set by the class file or, in the high bits, by the VM.
+const SYNTHETIC: i32 = 0x1000 | 0xf000_0000u32 as i32;
+
+/// What a set of access flags belongs to. Some bits mean different things
+/// on fields and methods.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Member {
+    Class,
+    Field,
+    Method,
+}
+
+/// The Java keywords for `bits`, in the order javac would write them.
+pub fn modifiers(bits: i32, member: Member) -> Vec<&'static str> {
+    let keywords: &[(i32, &str)] = &[
+        (0x0001, "public"),
+        (0x0004, "protected"),
+        (0x0002, "private"),
+        (0x0400, "abstract"),
+        (0x0008, "static"),
+        (0x0010, "final"),
+        (0x0080, "transient"),
+        (0x0040, "volatile"),
+        (0x0020, "synchronized"),
+        (0x0100, "native"),
+        (0x0800, "strictfp"),
+    ];
+    let applies = |keyword: &str| match keyword {
+        "transient" | "volatile" => member == Member::Field,
+        "synchronized" | "native" => member == Member::Method,
+        // Every interface is abstract, so javac doesn't say so.
+        "abstract" => member != Member::Class || bits & ACC_INTERFACE == 0,
+        // Class files set ACC_STRICT only on methods.
+        "strictfp" => member == Member::Method,
+        _ => true,
+    };
+    return keywords.iter().filter(|(bit, keyword)| bits & bit != 0 && applies(keyword)).map(|(_, keyword)| *keyword).collect();
+}
+
+/// `class`, `interface`, `enum` or `@interface`.
+pub fn class_kind(bits: i32, ref_type_tag: u8) -> &'static str {
+    if bits & ACC_ANNOTATION != 0 {
+        return "@interface";
+    }
+    if bits & ACC_INTERFACE != 0 || ref_type_tag == jdwp::TYPE_TAG_INTERFACE {
+        return "interface";
+    }
+    if bits & ACC_ENUM != 0 {
+        return "enum";
+    }
+    return "class";
+}
+
+fn with_modifiers(bits: i32, member: Member, rest: String) -> String {
+    let mut words = modifiers(bits, member);
+    words.push(&rest);
+    let text = words.join(" ");
+    if bits & SYNTHETIC != 0 {
+        return format!("{} /* synthetic */", text);
+    }
+    return text;
+}
+
+/// A field as it would be declared, e.g. `private static final int LIMIT`.
+pub fn field_text(field: &jdwp::FieldInfo) -> String {
+    return with_modifiers(field.mod_bits, Member::Field, format!("{} {}", eval::type_name(&field.signature), field.name));
+}
+
+/// A method as it would be declared in `class`, with parameter types only.
+pub fn method_text(class: &str, method: &jdwp::MethodInfo) -> String {
+    if method.name == "<clinit>" {
+        return "static {}".to_string();
+    }
+    let parsed = match signature::parse_method(&method.signature) {
+        Some(parsed) => parsed,
+        None => { return with_modifiers(method.mod_bits, Member::Method, format!("{}{}", method.name, method.signature)); },
+    };
+    let mut params: Vec<String> = parsed.params.iter().map(|p| p.java()).collect();
+    if method.mod_bits & ACC_VARARGS != 0 {
+        if let Some(last) = params.last_mut() {
+            if let Some(element) = last.strip_suffix("[]") {
+                *last = format!("{}...", element);
+            }
+        }
+    }
+    let declaration = if method.name == "<init>" {
+        format!("{}({})", class.rsplit('.').next().unwrap_or(class), params.join(", "))
+    } else {
+        format!("{} {}({})", parsed.returns.java(), method.name, params.join(", "))
+    };
+    return with_modifiers(method.mod_bits, Member::Method, declaration);
+}
+
+#[derive(Default)]
+pub struct Browser {
+    classes: Option<Vec<jdwp::GenericClassInfo>>,
+    modifiers: HashMap<u64, i32>,
+    fields: HashMap<u64, Vec<jdwp::FieldInfo>>,
+    methods: HashMap<u64, Vec<jdwp::MethodInfo>>,
+    superclasses: HashMap<u64, u64>,
+    interfaces: HashMap<u64, Vec<u64>>,
+    /// The ClassUnload request that clears the cache, once set.
+    unload_request: Option<i32>,
+}
+
+impl Browser {
+    /// Every loaded class, including arrays and interfaces.
+    pub fn classes(&mut self, client: &Client) -> Result<&[jdwp::GenericClassInfo]> {
+        if self.unload_request.is_none() {
+            match client.request(request::event_request_set(EventKind::ClassUnload, jdwp::SUSPEND_NONE, Vec::new())) {
+                Ok(id) => self.unload_request = Some(id),
+                r => warn!("Can't hear about unloaded classes, the class cache may go stale: {:?}", r),
+            }
+        }
+        if self.classes.is_none() {
+            self.classes = Some(client.request(request::all_classes_with_generic())?);
+        }
+        return Ok(self.classes.as_deref().unwrap());
+    }
+    /// Drops the class list, which doesn't know about classes loaded since.
+    pub fn refresh(&mut self) {
+        self.classes = None;
+    }
+    /// The Java name of a loaded class.
+    pub fn name(&mut self, client: &Client, class: u64) -> Result<String> {
+        if let Some(c) = self.classes(client)?.iter().find(|c| c.type_id == class) {
+            return Ok(eval::type_name(&c.signature));
+        }
+        return lookup::class_name(client, class);
+    }
+    pub fn modifiers(&mut self, client: &Client, class: u64) -> Result<i32> {
+        if let Some(bits) = self.modifiers.get(&class) {
+            return Ok(*bits);
+        }
+        let bits = client.request(request::reference_type_modifiers(class))?;
+        self.modifiers.insert(class, bits);
+        return Ok(bits);
+    }
+    /// Fields declared by `class` itself.
+    pub fn fields(&mut self, client: &Client, class: u64) -> Result<&[jdwp::FieldInfo]> {
+        if let Entry::Vacant(entry) = self.fields.entry(class) {
+            entry.insert(client.request(request::reference_type_fields(class))?);
+        }
+        return Ok(&self.fields[&class]);
+    }
+    /// Methods and constructors declared by `class` itself.
+    pub fn methods(&mut self, client: &Client, class: u64) -> Result<&[jdwp::MethodInfo]> {
+        if let Entry::Vacant(entry) = self.methods.entry(class) {
+            entry.insert(client.request(request::reference_type_methods(class))?);
+        }
+        return Ok(&self.methods[&class]);
+    }
+    /// The direct superclass of a class, 0 for Object. Not for interfaces.
+    pub fn superclass(&mut self, client: &Client, class: u64) -> Result<u64> {
+        self.fetch_superclasses(client, &[class])?;
+        return Ok(self.superclasses[&class]);
+    }
+    /// Asks for every superclass not known yet at once, rather than waiting
+    /// for each in turn.
+    fn fetch_superclasses(&mut self, client: &Client, classes: &[u64]) -> Result<()> {
+        let mut pending: Vec<(u64, Pending<u64>)> = Vec::new();
+        for class in classes.iter().filter(|c| !self.superclasses.contains_key(c)) {
+            pending.push((*class, client.send_typed(request::class_type_superclass(*class))?));
+        }
+        for (class, reply) in pending {
+            self.superclasses.insert(class, reply.wait()?);
+        }
+        return Ok(());
+    }
+    /// The interfaces `class` implements directly, or that an interface extends.
+    pub fn interfaces(&mut self, client: &Client, class: u64) -> Result<Vec<u64>> {
+        self.fetch_interfaces(client, &[class])?;
+        return Ok(self.interfaces[&class].clone());
+    }
+    fn fetch_interfaces(&mut self, client: &Client, classes: &[u64]) -> Result<()> {
+        let mut pending: Vec<(u64, Pending<Vec<u64>>)> = Vec::new();
+        for class in classes.iter().filter(|c| !self.interfaces.contains_key(c)) {
+            pending.push((*class, client.send_typed(request::reference_type_interfaces(*class))?));
+        }
+        for (class, reply) in pending {
+            self.interfaces.insert(class, reply.wait()?);
+        }
+        return Ok(());
+    }
+    /// Loaded classes that extend `class` directly, or for an interface,
+    /// the loaded types that implement or extend it directly.
+    pub fn subclasses(&mut self, client: &Client, class: u64, interface: bool) -> Result<Vec<u64>> {
+        let candidates: Vec<(u64, u8)> = self.classes(client)?.iter()
+            .filter(|c| c.ref_type_tag != jdwp::TYPE_TAG_ARRAY && c.type_id != class)
+            .map(|c| (c.type_id, c.ref_type_tag))
+            .collect();
+        let ids: Vec<u64> = candidates.iter().map(|(id, _)| *id).collect();
+        if interface {
+            self.fetch_interfaces(client, &ids)?;
+            return Ok(ids.into_iter().filter(|id| self.interfaces[id].contains(&class)).collect());
+        }
+        let classes: Vec<u64> = candidates.iter().filter(|(_, tag)| *tag == jdwp::TYPE_TAG_CLASS).map(|(id, _)| *id).collect();
+        self.fetch_superclasses(client, &classes)?;
+        return Ok(classes.into_iter().filter(|id| self.superclasses[id] == class).collect());
+    }
+    /// Forgets everything when a class is unloaded. Returns true if the
+    /// event was this browser's.
+    pub fn on_event(&mut self, event: &jdwp::Event) -> bool {
+        match event {
+            jdwp::Event::ClassUnload { request_id, .. } if Some(*request_id) == self.unload_request => {
+                *self = Browser { unload_request: self.unload_request, ..Browser::default() };
+                return true;
+            },
+            _ => { return false; },
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! Recording JDWP sessions to disk and decoding them again later.
+//!
+//! A capture file is `MAGIC` followed by records of
+//! `u64 micros | u8 direction | u16 name length | name | packet`, all big
+//! endian, where the packet is exactly what went over the wire.
+use crate::client::{self,DeserializedPacket,State};
+use crate::jdwp;
+use crate::pcap;
+use std::io::{BufReader,BufWriter,Read,Write};
+use std::path::Path;
+use std::sync::Mutex;
+use log::*;
+
+pub const MAGIC: &[u8; 8] = b"DCDCAP1\n";
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum Direction {
+    /// Sent by the debugger.
+    ToVm,
+    /// Sent by the VM.
+    FromVm,
+}
+
+impl Direction {
+    pub fn arrow(&self) -> &'static str {
+        return match self {
+            Direction::ToVm => "->",
+            Direction::FromVm => "<-",
+        };
+    }
+}
+
+#[derive(Debug,Clone)]
+pub struct Record {
+    pub micros: u64,
+    pub direction: Direction,
+    pub name: String,
+    pub packet: jdwp::Packet,
+}
+
+pub fn now_micros() -> u64 {
+    return match std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        Ok(d) => d.as_micros() as u64,
+        Err(_) => 0,
+    };
+}
+
+pub struct Recorder {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Recorder {
+    pub fn new<W: Write + Send + 'static>(writer: W) -> std::io::Result<Recorder> {
+        let mut writer = writer;
+        writer.write_all(MAGIC)?;
+        return Ok(Recorder { out: Mutex::new(Box::new(writer)) });
+    }
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<Recorder> {
+        return Recorder::new(BufWriter::new(std::fs::File::create(path)?));
+    }
+    pub fn write_record(&self, record: &Record) -> std::io::Result<()> {
+        let mut out = self.out.lock().unwrap();
+        let name = record.name.as_bytes();
+        let name = &name[..std::cmp::min(name.len(), u16::MAX as usize)];
+        out.write_all(&record.micros.to_be_bytes())?;
+        out.write_all(&[match record.direction { Direction::ToVm => 0u8, Direction::FromVm => 1u8 }])?;
+        out.write_all(&(name.len() as u16).to_be_bytes())?;
+        out.write_all(name)?;
+        record.packet.write(&mut *out)?;
+        // Flushed per packet so a capture survives the debugger crashing.
+        return out.flush();
+    }
+    /// Like `write_record` but stamps the current time and only logs failures.
+    pub fn record(&self, direction: Direction, name: &str, packet: &jdwp::Packet) {
+        let record = Record {
+            micros: now_micros(),
+            direction: direction,
+            name: name.to_string(),
+            packet: packet.clone(),
+        };
+        if let Err(e) = self.write_record(&record) {
+            error!("Failed to record packet: {:?}", e);
+        }
+    }
+}
+
+pub struct CaptureReader<R: Read> {
+    reader: R,
+    max_packet: u32,
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Packets over `max_packet` bytes, or `jdwp::MAX_PACKET_SIZE`, are an error.
+    pub fn new(reader: R, max_packet: Option<u32>) -> std::io::Result<CaptureReader<R>> {
+        let mut reader = reader;
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "not a dcd capture"));
+        }
+        return Ok(CaptureReader { reader: reader, max_packet: max_packet.unwrap_or(jdwp::MAX_PACKET_SIZE) });
+    }
+    fn read_record(&mut self, first: u8) -> std::io::Result<Record> {
+        let mut micros = [0u8; 8];
+        micros[0] = first;
+        self.reader.read_exact(&mut micros[1..])?;
+        let mut header = [0u8; 3];
+        self.reader.read_exact(&mut header)?;
+        let direction = match header[0] {
+            0 => Direction::ToVm,
+            1 => Direction::FromVm,
+            _ => { return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "bad direction")); }
+        };
+        let mut name = vec![0u8; u16::from_be_bytes([header[1], header[2]]) as usize];
+        self.reader.read_exact(&mut name)?;
+        return Ok(Record {
+            micros: u64::from_be_bytes(micros),
+            direction: direction,
+            name: String::from_utf8_lossy(&name).into_owned(),
+            packet: jdwp::Packet::read_max(&mut self.reader, self.max_packet)?,
+        });
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = std::io::Result<Record>;
+    fn next(&mut self) -> Option<std::io::Result<Record>> {
+        // A clean EOF is only allowed between records.
+        let mut first = [0u8; 1];
+        return match self.reader.read(&mut first) {
+            Ok(0) => None,
+            Ok(_) => Some(self.read_record(first[0])),
+            Err(e) => Some(Err(e)),
+        };
+    }
+}
+
+/// Reads a dcd capture, or a pcap file of JDWP traffic on `port`.
+pub fn open<P: AsRef<Path>>(path: P, port: u16, max_packet: Option<u32>) -> std::io::Result<Vec<Record>> {
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    let chained = std::io::Cursor::new(magic).chain(reader);
+    if pcap::is_pcap(&magic) {
+        return pcap::import(chained, port, max_packet);
+    }
+    return CaptureReader::new(chained, max_packet)?.collect();
+}
+
+/// Decodes packets from both ends of a conversation. Each side gets its own
+/// pending table because the debugger's and the VM's packet IDs are
+/// independent, but ID sizes learned from either side apply to both.
+#[derive(Default)]
+pub struct Decoder {
+    debugger: State,
+    vm: State,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        return Default::default();
+    }
+    pub fn idsizes(&self) -> jdwp::IDSizes {
+        return self.debugger.idsizes;
+    }
+    /// Commands from either side still waiting for their reply.
+    pub fn replies_left(&self) -> usize {
+        return self.debugger.replies_left() + self.vm.replies_left();
+    }
+    /// The State owning the pending table this packet belongs to.
+    fn side(&mut self, direction: Direction, packet: &jdwp::Packet) -> &mut State {
+        return match (direction, packet) {
+            (Direction::ToVm, jdwp::Packet::Command { .. }) |
+            (Direction::FromVm, jdwp::Packet::Reply { .. }) => &mut self.debugger,
+            _ => &mut self.vm,
+        };
+    }
+    pub fn name(&mut self, direction: Direction, packet: &jdwp::Packet) -> String {
+        return match packet {
+            jdwp::Packet::Command { set, cmd, .. } => jdwp::command_name(*set, *cmd),
+            jdwp::Packet::Reply { id, error, .. } => {
+                let cmd = match self.side(direction, packet).pending_command(*id) {
+                    Some((set, cmd)) => jdwp::command_name(set, cmd),
+                    None => "Unmatched".to_string(),
+                };
+                if *error == 0 {
+                    format!("{} reply", cmd)
+                } else {
+                    format!("{} error {}", cmd, error)
+                }
+            },
+        };
+    }
+    pub fn decode(&mut self, direction: Direction, packet: &jdwp::Packet) -> jdwp::Result<DeserializedPacket> {
+        let side = self.side(direction, packet);
+        if let jdwp::Packet::Command { id, set, cmd, .. } = packet {
+            // Events and DDM chunks from the VM are never answered.
+            if direction == Direction::ToVm || client::expects_reply(*set, *cmd) {
+                side.expect_reply(*id, *set, *cmd);
+            }
+        }
+        let decoded = side.deserialize_packet(packet);
+        let idsizes = side.idsizes;
+        self.debugger.idsizes = idsizes;
+        self.vm.idsizes = idsizes;
+        return decoded;
+    }
+}
+
+fn describe(decoded: &jdwp::Result<DeserializedPacket>, packet: &jdwp::Packet) -> String {
+    return match decoded {
+        Ok(DeserializedPacket::Command(_, cmd)) => format!("{:?}", cmd),
+        Ok(DeserializedPacket::Reply(_, reply)) => format!("{:?}", reply),
+        Ok(DeserializedPacket::Error(_, e)) => format!("{:?}", e),
+        Err(e) => {
+            let len = match packet {
+                jdwp::Packet::Command { data, .. } | jdwp::Packet::Reply { data, .. } => data.len(),
+            };
+            format!("<undecodable: {:?}, {} bytes>", e, len)
+        },
+    };
+}
+
+/// One line of `dump` output. `micros` is relative to the start of the session.
+pub fn format_packet(decoder: &mut Decoder, micros: u64, direction: Direction, packet: &jdwp::Packet) -> String {
+    let name = decoder.name(direction, packet);
+    let decoded = decoder.decode(direction, packet);
+    return format!("{:>12.6} {} #{:<6} {}: {}",
+        micros as f64 / 1e6,
+        direction.arrow(),
+        packet.id(),
+        name,
+        describe(&decoded, packet));
+}
+
+/// Pretty-prints a capture, one packet per line.
+pub fn dump<W: Write>(records: &[Record], out: &mut W) -> std::io::Result<()> {
+    let mut decoder = Decoder::new();
+    let start = records.first().map(|r| r.micros).unwrap_or(0);
+    for record in records {
+        let line = format_packet(&mut decoder, record.micros.saturating_sub(start), record.direction, &record.packet);
+        writeln!(out, "{}", line)?;
+    }
+    return Ok(());
+}
+
+#[derive(Debug,Default,Clone,Copy,PartialEq,Eq)]
+pub struct ReplayStats {
+    pub packets: usize,
+    pub decoded: usize,
+    pub round_tripped: usize,
+}
+
+/// Feeds a capture back through the decoder and checks that every decoded
+/// packet serializes back to the bytes that were recorded.
+pub fn replay<W: Write>(records: &[Record], out: &mut W) -> std::io::Result<ReplayStats> {
+    let mut decoder = Decoder::new();
+    let mut stats: ReplayStats = Default::default();
+    for record in records {
+        stats.packets += 1;
+        let name = decoder.name(record.direction, &record.packet);
+        let decoded = decoder.decode(record.direction, &record.packet);
+        let (data, reencoded) = match (&record.packet, &decoded) {
+            (jdwp::Packet::Command { data, .. }, Ok(DeserializedPacket::Command(_, cmd))) => {
+                (data, cmd.serialize(decoder.idsizes()).2)
+            },
+            (jdwp::Packet::Reply { data, .. }, Ok(DeserializedPacket::Reply(_, reply))) => {
+                (data, reply.serialize(decoder.idsizes()))
+            },
+            (jdwp::Packet::Reply { data, .. }, Ok(DeserializedPacket::Error(..))) => (data, data.clone()),
+            (_, Err(e)) => {
+                writeln!(out, "#{} {} {}: failed to decode: {:?}", record.packet.id(), record.direction.arrow(), name, e)?;
+                continue;
+            },
+            _ => {
+                writeln!(out, "#{} {} {}: decoded as the wrong packet type", record.packet.id(), record.direction.arrow(), name)?;
+                continue;
+            },
+        };
+        stats.decoded += 1;
+        if *data == reencoded {
+            stats.round_tripped += 1;
+        } else {
+            writeln!(out, "#{} {} {}: re-encodes to {} bytes instead of {}",
+                record.packet.id(), record.direction.arrow(), name, reencoded.len(), data.len())?;
+        }
+    }
+    writeln!(out, "{} packets, {} decoded, {} round-tripped", stats.packets, stats.decoded, stats.round_tripped)?;
+    return Ok(stats);
+}
@@ -0,0 +1,455 @@
+use crate::{Result,Error};
+use std::sync::mpsc::{self,Sender,Receiver};
+use std::sync::{Arc,Mutex,MutexGuard,Weak};
+use std::io::{BufReader,BufWriter,Read,Write};
+use std::vec::Vec;
+use log::*;
+use std::collections::HashMap;
+use std::default::Default;
+use crate::jdwp;
+use crate::transport::Transport;
+use crate::capture::{Direction,Recorder};
+use crate::handles::Handles;
+use crate::ddm;
+use crate::request::Request;
+
+#[derive(Default)]
+pub struct State {
+    id: u32,
+    pub idsizes: jdwp::IDSizes,
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub major: i32,
+    pub minor: i32,
+    pub capabilities: jdwp::Capabilities,
+    /// The largest packet to accept from the VM, or `jdwp::MAX_PACKET_SIZE`.
+    pub max_packet: Option<u32>,
+    pending: HashMap<u32, (u8, u8)>,
+}
+
+pub enum DeserializedPacket {
+    Command(u32, jdwp::Command),
+    Reply(u32, jdwp::Reply),
+    Error(u32, jdwp::Error),
+}
+
+impl State {
+    /// Allocates an ID for `cmd` and records it as pending without writing anything.
+    pub fn prepare_command(&mut self, cmd: &jdwp::Command) -> jdwp::Packet {
+        let (set, cmd, data) = cmd.serialize(self.idsizes);
+        let id = self.id;
+        self.pending.insert(id, (set, cmd));
+        self.id += 1;
+        return jdwp::Packet::Command {
+            id: id,
+            set: set,
+            cmd: cmd,
+            data: data,
+        };
+    }
+    pub fn forget(&mut self, id: u32) {
+        self.pending.remove(&id);
+    }
+    /// Records a command that was sent by someone else so its reply can be decoded.
+    pub fn expect_reply(&mut self, id: u32, set: u8, cmd: u8) {
+        self.pending.insert(id, (set, cmd));
+    }
+    pub fn pending_command(&self, id: u32) -> Option<(u8, u8)> {
+        return self.pending.get(&id).copied();
+    }
+    pub fn send_command<W: Write>(&mut self, cmd: &jdwp::Command, writer: &mut W) -> std::io::Result<()> {
+        let packet = self.prepare_command(cmd);
+        let res = packet.write(writer);
+        if res.is_err() {
+            self.forget(packet.id());
+        }
+        return res;
+    }
+    /// The reply packet for command `id`. An error carries no data.
+    pub fn reply_packet(&self, id: u32, r: &jdwp::Result<jdwp::Reply>) -> jdwp::Packet {
+        return match r {
+            Ok(reply) => jdwp::Packet::Reply {
+                id: id,
+                error: 0,
+                data: reply.serialize(self.idsizes),
+            },
+            Err(e) => jdwp::Packet::Reply {
+                id: id,
+                error: e.serialize(),
+                data: Vec::with_capacity(0),
+            },
+        };
+    }
+    pub fn send_reply<W: Write>(&mut self, id: u32, r: &jdwp::Result<jdwp::Reply>, writer: &mut W) -> std::io::Result<()> {
+        return self.reply_packet(id, r).write(writer);
+    }
+    pub fn deserialize_packet(&mut self, packet: &jdwp::Packet) -> jdwp::Result<DeserializedPacket> {
+        let deserialized = match packet {
+            jdwp::Packet::Command { id, set, cmd, data } => {
+               DeserializedPacket::Command(*id, jdwp::Command::deserialize(*set, *cmd, data.as_slice(), self.idsizes)?)
+            },
+            jdwp::Packet::Reply {id, error, data} => {
+                if *error == 0u16 {
+                    match self.pending.remove(id) {
+                        Some((set, cmd)) => {
+                            DeserializedPacket::Reply(
+                                *id,
+                                jdwp::Reply::deserialize(set, cmd, data.as_slice(), self.idsizes)?
+                            )
+                        },
+                        None => {
+                            error!("Got a reply packet with no corresponding command!");
+                            return Err(jdwp::Error::IllegalArgument);
+                        },
+                    }
+                } else {
+                    if self.pending.remove(id).is_none() {
+                        warn!("Got an error packet with no corresponding command?");
+                    }
+                    match jdwp::Error::deserialize(*error) {
+                        Some(e) => DeserializedPacket::Error(*id, e),
+                        None => { return Err(jdwp::Error::Unimplemented); }
+                    }
+                }
+            }
+        };
+        // Some information is cached so I don't have to go through
+        // the pain of making another request for common inquiries.
+        if let DeserializedPacket::Reply(_, rply) = &deserialized {
+            match rply {
+                jdwp::Reply::Version { description, major, minor, version, name } => {
+                    self.name = name.clone();
+                    self.description = description.clone();
+                    self.version = version.clone();
+                    self.major = *major;
+                    self.minor = *minor;
+                },
+                jdwp::Reply::Capabilities(capabilities) => {
+                    let mut capbits = self.capabilities.bits();
+                    capbits = (capbits & !0x7f) | (capabilities.bits() & 0x7f);
+                    self.capabilities = jdwp::Capabilities::from_bits(capbits).unwrap();
+                },
+                jdwp::Reply::CapabilitiesNew(capabilities) => {
+                    self.capabilities = *capabilities;
+                },
+                jdwp::Reply::IDSizes { field, method, object, reference_type, frame } => {
+                    self.idsizes = jdwp::IDSizes {
+                        field: *field,
+                        method: *method,
+                        object: *object,
+                        reference_type: *reference_type,
+                        frame: *frame,
+                    }
+                },
+                _ => {},
+            }
+        }
+        return Ok(deserialized);
+    }
+    pub fn replies_left(&self) -> usize {
+        return self.pending.len();
+    }
+    pub fn supports_version(&self, major: i32, minor: i32) -> bool {
+        return (self.major, self.minor) >= (major, minor);
+    }
+}
+
+pub const HANDSHAKE: &[u8; 14] = b"JDWP-Handshake";
+
+pub fn handshake<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> Result<()> {
+    let handshake_str = HANDSHAKE;
+    let mut handshake_buffer: [u8; 14] = [0u8; 14];
+    info!("Conducting handshake...");
+    writer.write_all(handshake_str)?;
+    writer.flush()?;
+    reader.read_exact(&mut handshake_buffer)?;
+    if &handshake_buffer != handshake_str {
+        error!("JDWP Handshake failed! Exiting!");
+        return Err(Error::HandshakeFailed(handshake_buffer.to_vec()));
+    }
+    info!("Handshake successful!: {:?}", std::str::from_utf8(&handshake_buffer));
+    return Ok(());
+}
+
+/// A command sent by the VM, e.g. a composite event.
+pub type Event = (u32, jdwp::Command);
+
+/// Whether the VM waits for a reply to the command `set`/`cmd` it sent.
+/// JDWP only defines one command going that way, Event.Composite, and it
+/// takes no reply; Android's DDM chunks don't either. Anything else comes
+/// from a protocol extension we don't know, which may well block on us.
+pub fn expects_reply(set: u8, cmd: u8) -> bool {
+    return !matches!((set, cmd), (64, 100) | (ddm::DDM_SET, ddm::DDM_CHUNK));
+}
+
+/// What to answer the VM command `set`/`cmd` with, if anything. The only
+/// commands that get a reply are ones `expects_reply` knows nothing about,
+/// so the answer is always NOT_IMPLEMENTED (99): it is what the spec says
+/// to send for an unknown command, and it stops the VM from waiting forever.
+pub fn answer(set: u8, cmd: u8) -> Option<jdwp::Result<jdwp::Reply>> {
+    if !expects_reply(set, cmd) {
+        return None;
+    }
+    return Some(Err(jdwp::Error::Unimplemented));
+}
+
+/// Receives the outcome of exactly one command.
+pub(crate) trait Waiter {
+    fn resolve(self, reply: Result<jdwp::Reply>);
+}
+
+impl Waiter for Sender<Result<jdwp::Reply>> {
+    fn resolve(self, reply: Result<jdwp::Reply>) {
+        let _ = self.send(reply);
+    }
+}
+
+/// Reply correlation shared by the blocking and async clients.
+pub(crate) struct Router<T> {
+    state: Mutex<State>,
+    // None once the reader has hit EOF. Dropping the waiters wakes everybody.
+    waiters: Mutex<Option<HashMap<u32, T>>>,
+    recorder: Mutex<Option<Arc<Recorder>>>,
+}
+
+impl<T: Waiter> Router<T> {
+    pub fn new() -> Router<T> {
+        return Router {
+            state: Mutex::new(Default::default()),
+            waiters: Mutex::new(Some(HashMap::new())),
+            recorder: Mutex::new(None),
+        };
+    }
+    pub fn set_recorder(&self, recorder: Option<Arc<Recorder>>) {
+        *self.recorder.lock().unwrap() = recorder;
+    }
+    /// Called just before a packet is written so its reply can never be recorded first.
+    pub fn sent(&self, packet: &jdwp::Packet) {
+        if let Some(recorder) = self.recorder.lock().unwrap().as_ref() {
+            if let jdwp::Packet::Command { set, cmd, .. } = packet {
+                recorder.record(Direction::ToVm, &jdwp::command_name(*set, *cmd), packet);
+            }
+        }
+    }
+    fn answered(&self, set: u8, cmd: u8, packet: &jdwp::Packet) {
+        if let Some(recorder) = self.recorder.lock().unwrap().as_ref() {
+            recorder.record(Direction::ToVm, &format!("{} reply", jdwp::command_name(set, cmd)), packet);
+        }
+    }
+    fn received(&self, packet: &jdwp::Packet) {
+        if let Some(recorder) = self.recorder.lock().unwrap().as_ref() {
+            let name = match packet {
+                jdwp::Packet::Command { set, cmd, .. } => jdwp::command_name(*set, *cmd),
+                jdwp::Packet::Reply { id, .. } => match self.state.lock().unwrap().pending_command(*id) {
+                    Some((set, cmd)) => format!("{} reply", jdwp::command_name(set, cmd)),
+                    None => "Unmatched reply".to_string(),
+                },
+            };
+            recorder.record(Direction::FromVm, &name, packet);
+        }
+    }
+    pub fn state(&self) -> MutexGuard<'_, State> {
+        return self.state.lock().unwrap();
+    }
+    /// Assigns an ID to `cmd` and parks `waiter` under it. The caller writes the packet.
+    pub fn register(&self, cmd: &jdwp::Command, waiter: T) -> Result<jdwp::Packet> {
+        let mut state = self.state.lock().unwrap();
+        let mut waiters = self.waiters.lock().unwrap();
+        let waiters = match waiters.as_mut() {
+            Some(w) => w,
+            None => { return Err(Error::Disconnected); },
+        };
+        let packet = state.prepare_command(cmd);
+        waiters.insert(packet.id(), waiter);
+        return Ok(packet);
+    }
+    pub fn cancel(&self, id: u32) {
+        self.state.lock().unwrap().forget(id);
+        if let Some(w) = self.waiters.lock().unwrap().as_mut() {
+            w.remove(&id);
+        }
+    }
+    /// Hands a reply to its waiter. VM commands are decoded and returned,
+    /// along with the reply the caller must write back if one is due.
+    pub fn dispatch(&self, packet: jdwp::Packet) -> (Option<Event>, Option<jdwp::Packet>) {
+        self.received(&packet);
+        let deserialized = self.state.lock().unwrap().deserialize_packet(&packet);
+        match packet {
+            jdwp::Packet::Command { id, set, cmd, .. } => {
+                let event = match deserialized {
+                    Ok(DeserializedPacket::Command(_, command)) => Some((id, command)),
+                    Ok(_) => None,
+                    Err(e) => {
+                        error!("Failed to deserialize command {}/{}: {:?}", set, cmd, e);
+                        None
+                    },
+                };
+                let reply = answer(set, cmd).map(|r| self.state.lock().unwrap().reply_packet(id, &r));
+                if let Some(reply) = reply.as_ref() {
+                    self.answered(set, cmd, reply);
+                }
+                return (event, reply);
+            },
+            jdwp::Packet::Reply { id, .. } => {
+                let result = match deserialized {
+                    Ok(DeserializedPacket::Reply(_, reply)) => Ok(reply),
+                    Ok(DeserializedPacket::Error(_, e)) => Err(Error::Jdwp(e)),
+                    Ok(DeserializedPacket::Command(..)) => { return (None, None); },
+                    Err(e) => Err(Error::Jdwp(e)),
+                };
+                let waiter = self.waiters.lock().unwrap().as_mut().and_then(|w| w.remove(&id));
+                match waiter {
+                    Some(w) => w.resolve(result),
+                    None => { warn!("Reply {} has nobody waiting for it", id); },
+                }
+            },
+        }
+        return (None, None);
+    }
+    pub fn close(&self) {
+        *self.waiters.lock().unwrap() = None;
+    }
+}
+
+/// Handle for one in-flight command. Resolves to that command's reply only,
+/// unpacked to `T`; a reply of the wrong kind is `Error::UnexpectedReply`.
+pub struct Pending<T = jdwp::Reply> {
+    id: u32,
+    rx: Receiver<Result<jdwp::Reply>>,
+    unpack: fn(jdwp::Reply) -> std::result::Result<T, jdwp::Reply>,
+}
+
+impl<T> Pending<T> {
+    pub fn id(&self) -> u32 {
+        return self.id;
+    }
+    pub fn wait(self) -> Result<T> {
+        return match self.rx.recv() {
+            Ok(r) => r.and_then(|r| (self.unpack)(r).map_err(Error::unexpected)),
+            Err(_) => Err(Error::Disconnected),
+        };
+    }
+    pub fn poll(&self) -> Option<Result<T>> {
+        return match self.rx.try_recv() {
+            Ok(r) => Some(r.and_then(|r| (self.unpack)(r).map_err(Error::unexpected))),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => Some(Err(Error::Disconnected)),
+        };
+    }
+}
+
+pub struct Client {
+    router: Arc<Router<Sender<Result<jdwp::Reply>>>>,
+    // The reader thread answers commands from the VM through a weak
+    // reference, so dropping the client still hangs up.
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+    handles: Mutex<Handles>,
+}
+
+impl Client {
+    /// Takes both halves of an already handshaken connection. Replies are routed
+    /// to their `Pending` handles; VM commands go to the returned receiver.
+    pub fn new<R: Read + Send + 'static, W: Write + Send + 'static>(reader: R, writer: W) -> (Client, Receiver<Event>) {
+        let router = Arc::new(Router::new());
+        let writer: Arc<Mutex<Box<dyn Write + Send>>> = Arc::new(Mutex::new(Box::new(writer)));
+        let (event_tx, event_rx) = mpsc::channel();
+        let reader_router = router.clone();
+        let reader_writer = Arc::downgrade(&writer);
+        std::thread::spawn(move || {
+            reader_thread(reader, reader_router, reader_writer, event_tx);
+        });
+        let client = Client {
+            router: router,
+            writer: writer,
+            handles: Mutex::new(Handles::default()),
+        };
+        return (client, event_rx);
+    }
+    /// Handshakes over `transport` and starts the reader thread.
+    pub fn connect<T: Transport>(transport: T) -> Result<(Client, Receiver<Event>)> {
+        let (reader, writer) = transport.split()?;
+        let mut bufread = BufReader::new(reader);
+        let mut bufwrite = BufWriter::new(writer);
+        handshake(&mut bufread, &mut bufwrite)?;
+        return Ok(Client::new(bufread, bufwrite));
+    }
+    pub fn send(&self, cmd: &jdwp::Command) -> Result<Pending> {
+        return self.send_unpacked(cmd, Ok);
+    }
+    pub fn send_and_wait(&self, cmd: &jdwp::Command) -> Result<jdwp::Reply> {
+        return self.send(cmd)?.wait();
+    }
+    /// Like `send`, but the handle resolves to the reply already unpacked.
+    pub fn send_typed<T>(&self, request: Request<T>) -> Result<Pending<T>> {
+        return self.send_unpacked(&request.command, request.unpack);
+    }
+    /// Sends `request` and waits for its unpacked reply.
+    pub fn request<T>(&self, request: Request<T>) -> Result<T> {
+        return self.send_typed(request)?.wait();
+    }
+    fn send_unpacked<T>(&self, cmd: &jdwp::Command, unpack: fn(jdwp::Reply) -> std::result::Result<T, jdwp::Reply>) -> Result<Pending<T>> {
+        let (tx, rx) = mpsc::channel();
+        let packet = self.router.register(cmd, tx)?;
+        let id = packet.id();
+        self.router.sent(&packet);
+        // The state lock is not held while writing so the reader thread can
+        // keep draining replies even if the socket is backed up.
+        let res = {
+            let mut writer = self.writer.lock().unwrap();
+            packet.write(&mut *writer).and_then(|_| writer.flush())
+        };
+        if let Err(e) = res {
+            self.router.cancel(id);
+            return Err(e.into());
+        }
+        return Ok(Pending { id: id, rx: rx, unpack: unpack });
+    }
+    /// Learns the version, capabilities and ID sizes. Nothing that carries an
+    /// ID can be encoded until this has run.
+    pub fn initialize(&self) -> Result<()> {
+        self.send_and_wait(&jdwp::Command::Version)?;
+        let capabilities = if self.state().supports_version(1, 4) {
+            self.send(&jdwp::Command::CapabilitiesNew)?
+        } else {
+            self.send(&jdwp::Command::Capabilities)?
+        };
+        let idsizes = self.send(&jdwp::Command::IDSizes)?;
+        capabilities.wait()?;
+        idsizes.wait()?;
+        return Ok(());
+    }
+    pub fn state(&self) -> MutexGuard<'_, State> {
+        return self.router.state();
+    }
+    /// The objects this session has pinned as `$N`.
+    pub fn handles(&self) -> MutexGuard<'_, Handles> {
+        return self.handles.lock().unwrap();
+    }
+    /// Writes every packet from now on to `recorder`.
+    pub fn record(&self, recorder: Option<Arc<Recorder>>) {
+        self.router.set_recorder(recorder);
+    }
+}
+
+fn reader_thread<R: Read>(conn_data: R, router: Arc<Router<Sender<Result<jdwp::Reply>>>>, writer: Weak<Mutex<Box<dyn Write + Send>>>, events: Sender<Event>) {
+    let mut conn = conn_data;
+    loop {
+        let max = router.state().max_packet.unwrap_or(jdwp::MAX_PACKET_SIZE);
+        let packet = match jdwp::Packet::read_max(&mut conn, max) {
+            Ok(p) => p,
+            Err(e) => { info!("JDWP connection closed: {:?}", e); break; },
+        };
+        let (event, reply) = router.dispatch(packet);
+        if let (Some(reply), Some(writer)) = (reply, writer.upgrade()) {
+            let mut writer = writer.lock().unwrap();
+            if let Err(e) = reply.write(&mut *writer).and_then(|_| writer.flush()) {
+                warn!("Failed to answer VM command {}: {:?}", reply.id(), e);
+            }
+        }
+        if let Some(event) = event {
+            let _ = events.send(event);
+        }
+    }
+    router.close();
+}
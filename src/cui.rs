@@ -1,217 +1,1363 @@
 use crate::{Result,Error};
-use std::sync::mpsc::{self,Sender,Receiver,Iter};
-use std::io::{BufReader,BufWriter,Read,Write};
-use std::vec::Vec;
-use log::*;
-use std::collections::HashMap;
-use std::default::Default;
-use crate::jdwp;
+use crate::client::{Client,Event};
+use crate::browse::{self,Browser};
+use crate::ddm;
+use crate::eval::{self,Evaluator};
+use crate::hprof;
+use crate::instances;
+use crate::lookup::{self,class_name,location_text,reference_type,thread_name};
+use crate::redefine;
+use crate::request;
+use crate::signature;
+use crate::monitors;
+use crate::jdwp::{self,Command,Reply};
+use crate::trace::{self,Tracer};
+use crate::transport::Transport;
+use rustyline::error::ReadlineError;
+use std::collections::BTreeMap;
+use std::sync::{Arc,Mutex};
+use std::sync::mpsc::{self,Receiver};
+use std::time::{Duration,Instant};
 
+const HELP: &str = "commands:
+    threads                        list threads
+    thread ID                      select a thread
+    where                          backtrace of the selected thread
+    frame N                        select a frame of the selected thread
+    pop                            pop the selected frame and those above it, so the
+                                   call runs again when resumed
+    return [EXPR]                  make the innermost frame return EXPR when resumed
+    suspend                        suspend the VM
+    continue                       resume the VM
+    break CLASS:LINE [if EXPR]     stop at a line, only when EXPR is true if given
+    break CLASS.METHOD [if EXPR]   stop on entry to a method
+    watch CLASS.FIELD [EXPR|ID]    stop when a field is written, on one object if given
+    rwatch CLASS.FIELD [EXPR|ID]   stop when a field is read
+    awatch CLASS.FIELD [EXPR|ID]   stop when a field is read or written
+    catch throw [CLASS] [exclude PATTERN...]
+                                   stop when CLASS or a subclass is thrown, caught or not
+    catch uncaught [CLASS] [exclude PATTERN...]
+                                   stop when an exception won't be caught
+    deadlocks                      find threads deadlocked on monitors
+    monitor-trace on|off|[N]       time monitor contention, or show the N worst
+                                   locks and call sites so far
+    breakpoints                    list breakpoints, watchpoints and catchpoints
+    delete ID                      remove a breakpoint, watchpoint or catchpoint
+    trace [CLASS.METHOD [FILE]]    log calls and returns without stopping, or list traces;
+                                   both parts may contain *
+    untrace [ID]                   stop one trace, or all of them
+    redefine PATH                  replace loaded classes with those in a .class, .dex,
+                                   jar or apk file, or a directory of class files
+    redefine watch PATH|off        redefine from PATH whenever its files change
+    print EXPR                     evaluate a Java expression in the selected frame and
+                                   keep the result as $N; $_ is the newest, and the
+                                   last stop sets $thread, $frame, $exception, $retval
+    set var LVALUE = EXPR          assign to a local, field or array element
+    pin EXPR                       keep a value as $N, so it isn't garbage collected
+    unpin $N|all                   let pinned objects be collected again
+    pins                           list pinned values
+    classes [PATTERN]              loaded classes, * as wildcard
+    methods CLASS                  methods a class declares
+    fields CLASS                   fields a class declares
+    hierarchy CLASS                superclasses and interfaces of a class, and its
+                                   loaded subclasses
+    instances CLASS [N]            live instances of CLASS, the first N
+    instance-counts PATTERN [N]    live instance counts of matching classes, * as wildcard
+    referrers ID [DEPTH]           what refers to an object, back to GC roots
+    ddm hello                      VM and app identity
+    ddm features                   DDM features the VM supports
+    ddm threads [on|off]           thread list, or THCR/THDE notifications
+    ddm heap [now|gc|every-gc|off] request heap info
+    ddm segments [on|off] [merge|distinct]
+                                   heap segment dumps after every GC
+    ddm alloc [on|off|dump]        allocation tracking
+    ddm profile [start FILE [BUFSIZE]|stop|stream [BUFSIZE]|end OUT]
+                                   method profiling
+    ddm exit [STATUS]              make the VM exit
+    heap dump [FILE]               stream a heap dump from the VM and load it
+    heap dump-device PATH          write a heap dump to PATH on the device
+    heap load FILE                 load an HPROF file
+    heap histogram [N]             instance counts and sizes per class
+    heap instances CLASS           instances of CLASS with retained sizes
+    heap show ID                   fields of an object
+    heap dominators [ID|N]         objects ID dominates, or the N largest
+    help
+    quit";
+
+/// Default method trace buffer, the same as DDMS uses.
+const PROFILE_BUFFER: u32 = 8 * 1024 * 1024;
+
+/// How long a streamed heap dump may take to arrive.
+const HEAP_DUMP_TIMEOUT: Duration = Duration::from_secs(300);
+
+fn usage(msg: &str) -> Error {
+    return Error::Command(msg.to_string());
+}
+
+fn parse<T: std::str::FromStr>(arg: &str) -> Result<T> {
+    return arg.parse().map_err(|_| Error::Command(format!("bad number: {}", arg)));
+}
+
+fn parse_id(arg: &str) -> Result<u64> {
+    let parsed = match arg.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => arg.parse(),
+    };
+    return parsed.map_err(|_| Error::Command(format!("bad object id: {}", arg)));
+}
+
+/// How errors from a command are shown to the user.
+fn error_text(e: &Error) -> String {
+    return match e {
+        Error::Command(msg) | Error::Eval(msg) => msg.clone(),
+        Error::UnexpectedReply(r) => format!("unexpected reply {:?}", r),
+        Error::Jdwp(jdwp::Error::InvalidObject) => "object was collected".to_string(),
+        Error::Jdwp(e @ jdwp::Error::Field { .. }) => format!("bad packet from VM: {} is {:?}", e.field_path().unwrap(), e.root()),
+        Error::Jdwp(e) => format!("VM error: {:?}", e),
+        e => format!("Error: {:?}", e),
+    };
+}
+
+fn thread_status(status: i32) -> &'static str {
+    return match status {
+        0 => "zombie",
+        1 => "running",
+        2 => "sleeping",
+        3 => "monitor",
+        4 => "waiting",
+        _ => "not started",
+    };
+}
+
+fn frames(client: &Client, thread: u64, start: i32, length: i32) -> Result<Vec<jdwp::FrameInfo>> {
+    return client.request(request::thread_reference_frames(thread, start, length));
+}
+
+struct Breakpoint {
+    /// As the user wrote it.
+    location: String,
+    condition: Option<(String, eval::Expr)>,
+}
+
+struct Watch {
+    kind: jdwp::EventKind,
+    /// `Class.field`, for messages.
+    name: String,
+    /// The one object being watched, if not all of them.
+    object: Option<u64>,
+}
+
+/// State shared between the command loop and the event thread.
 #[derive(Default)]
-struct State {
-    id: u32,
-    pub idsizes: jdwp::IDSizes,
-    pub name: String,
-    pub version: String,
-    pub description: String,
-    pub major: i32,
-    pub minor: i32,
-    pub capabilities: jdwp::Capabilities,
-    pending: HashMap<u32, (u8, u8)>,
-}
-
-enum DeserializedPacket {
-    Command(u32, jdwp::Command),
-    Reply(u32, jdwp::Reply),
-    Error(u32, jdwp::Error),
-}
-
-impl State {
-    pub fn send_command<W: Write>(&mut self, cmd: &jdwp::Command, writer: &mut W) -> std::io::Result<()> {
-        let (set, cmd, data) = cmd.serialize(self.idsizes);
-        let id = self.id;
-        let packet = jdwp::Packet::Command {
-            id: id,
-            set: set,
-            cmd: cmd,
-            data: data,
-        };
-        let res = packet.write(writer);
-        if res.is_ok() {
-            self.pending.insert(id, (set, cmd));
-            self.id += 1;
-        }
-        return res;
-    }
-    pub fn send_reply<W: Write>(&mut self, id: u32, r: &jdwp::Result<jdwp::Reply>, writer: &mut W) -> std::io::Result<()> {
-        return match r {
-            Ok(reply) => {
-                let data = reply.serialize(self.idsizes);
-                let packet = jdwp::Packet::Reply {
-                    id: id,
-                    error: 0,
-                    data: data,
+struct Stop {
+    /// By request ID.
+    breakpoints: BTreeMap<i32, Breakpoint>,
+    watches: BTreeMap<i32, Watch>,
+    /// What each catchpoint catches, for listing.
+    catches: BTreeMap<i32, String>,
+    /// The thread commands look at, and which of its frames.
+    thread: Option<u64>,
+    frame: i32,
+}
+
+/// A value for an event message, which shouldn't fail just because part of it
+/// couldn't be looked up.
+fn value_text(client: &Client, value: Result<jdwp::Tag>) -> String {
+    return match value.and_then(|v| eval::format_value(client, &v)) {
+        Ok(text) => text,
+        Err(e) => format!("<{}>", error_text(&e)),
+    };
+}
+
+/// The current value of a field, from `object` unless it is static.
+fn field_value(client: &Client, class: u64, field: u64, object: &jdwp::Tag) -> Result<jdwp::Tag> {
+    let values = match object.object_id() {
+        Some(object) if object != 0 => client.request(request::object_reference_get_values(object, vec![field]))?,
+        _ => client.request(request::reference_type_get_values(class, vec![field]))?,
+    };
+    return match values.as_slice() {
+        [value] => Ok(*value),
+        _ => Err(Error::unexpected(Reply::Values(values))),
+    };
+}
+
+/// The class declaring `field` as seen from `class`, and the field itself.
+fn find_field(client: &Client, class: u64, field: &str) -> Result<Option<(u64, jdwp::FieldInfo)>> {
+    let mut class = class;
+    while class != 0 {
+        if let Some(f) = client.request(request::reference_type_fields(class))?.into_iter().find(|f| f.name == field) {
+            return Ok(Some((class, f)));
+        }
+        class = match client.request(request::class_type_superclass(class)) {
+            Ok(superclass) => superclass,
+            // Interfaces have no superclass.
+            Err(Error::Jdwp(_)) => 0,
+            Err(e) => { return Err(e); },
+        };
+    }
+    return Ok(None);
+}
+
+/// The `detailMessage` of a Throwable.
+fn exception_message(client: &Client, exception: u64) -> Result<Option<String>> {
+    let class = reference_type(client, exception)?;
+    let (_, field) = match find_field(client, class, "detailMessage")? {
+        Some(found) => found,
+        None => { return Ok(None); },
+    };
+    return match field_value(client, class, field.field_id, &jdwp::Tag::Object(exception))? {
+        jdwp::Tag::String(0) | jdwp::Tag::Object(0) => Ok(None),
+        jdwp::Tag::String(id) => Ok(Some(client.request(request::string_reference_value(id))?.text)),
+        v => Ok(Some(eval::format_value(client, &v)?)),
+    };
+}
+
+fn print_exception(client: &Client, thread: u64, location: &jdwp::Location, exception: &jdwp::Tag, catch_location: &jdwp::Location) {
+    let id = exception.object_id().unwrap_or(0);
+    let name = match reference_type(client, id).and_then(|class| class_name(client, class)) {
+        Ok(name) => name,
+        Err(_) => format!("{:#x}", id),
+    };
+    match exception_message(client, id) {
+        Ok(Some(message)) => println!("Exception {}: {}", name, message),
+        Ok(None) => println!("Exception {}", name),
+        Err(e) => println!("Exception {} (message unavailable: {})", name, error_text(&e)),
+    }
+    println!("    thrown in thread {} at {}", thread_name(client, thread), location_text(client, location));
+    if catch_location.class_id == 0 {
+        println!("    uncaught");
+    } else {
+        println!("    caught at {}", location_text(client, catch_location));
+    }
+    match frames(client, thread, 0, -1) {
+        Ok(frames) => {
+            for (i, frame) in frames.iter().enumerate() {
+                println!("    [{}] {}", i, location_text(client, &frame.location));
+            }
+        },
+        Err(e) => println!("    no backtrace: {}", error_text(&e)),
+    }
+}
+
+fn set_selected(client: &Client, thread: u64, frame: i32) {
+    let mut handles = client.handles();
+    handles.set_automatic("thread", Some(jdwp::Tag::Thread(thread)));
+    handles.set_automatic("frame", Some(jdwp::Tag::Int(frame)));
+}
+
+/// Fills `$thread`, `$frame`, `$exception` and `$retval` from a stopping
+/// event, clearing whatever it doesn't have.
+fn set_automatic(client: &Client, event: &jdwp::Event) {
+    let mut handles = client.handles();
+    handles.set_automatic("thread", event.thread().map(jdwp::Tag::Thread));
+    handles.set_automatic("frame", event.thread().map(|_| jdwp::Tag::Int(0)));
+    handles.set_automatic("exception", match event {
+        jdwp::Event::Exception { exception, .. } => Some(*exception),
+        _ => None,
+    });
+    handles.set_automatic("retval", match event {
+        jdwp::Event::MethodExitWithReturnValue { value, .. } => Some(*value),
+        _ => None,
+    });
+}
+
+/// Evaluates a breakpoint condition in the top frame of `thread`.
+fn condition_holds(client: &Client, thread: u64, condition: &eval::Expr) -> Result<bool> {
+    let frame = match frames(client, thread, 0, 1)?.first() {
+        Some(f) => *f,
+        None => { return Err(usage("thread has no frames")); },
+    };
+    return Evaluator::in_frame(client, thread, frame).test(condition);
+}
+
+/// Prints a composite event and selects the thread it stopped. Resumes
+/// straight away if every event in it was a trace or a breakpoint whose
+/// condition was false.
+fn on_events(client: &Client, stop: &Mutex<Stop>, tracer: &Mutex<Tracer>, contention: &Mutex<Option<monitors::Contention>>,
+        browser: &Mutex<Browser>, suspend_policy: u8, events: &[jdwp::Event]) {
+    let mut stopped = false;
+    let now = Instant::now();
+    for event in events {
+        if tracer.lock().unwrap().on_event(client, event) {
+            continue;
+        }
+        if contention.lock().unwrap().as_mut().map(|c| c.on_event(event, now)) == Some(true) {
+            continue;
+        }
+        if browser.lock().unwrap().on_event(event) {
+            continue;
+        }
+        match event {
+            jdwp::Event::Breakpoint { request_id, thread, location } => {
+                let condition = stop.lock().unwrap().breakpoints.get(request_id).and_then(|b| b.condition.clone());
+                if let Some((text, condition)) = condition {
+                    match condition_holds(client, *thread, &condition) {
+                        Ok(true) => {},
+                        Ok(false) => { continue; },
+                        Err(e) => println!("Condition {} failed: {}", text, error_text(&e)),
+                    }
+                }
+                println!("Breakpoint {} hit in thread {} at {}", request_id, thread_name(client, *thread), location_text(client, location));
+            },
+            jdwp::Event::FieldAccess { request_id, thread, location, type_id, field_id, object, .. } => {
+                let name = stop.lock().unwrap().watches.get(request_id).map(|w| w.name.clone()).unwrap_or_default();
+                println!("Watch {}: {} read, value {}, in thread {} at {}", request_id, name,
+                    value_text(client, field_value(client, *type_id, *field_id, object)),
+                    thread_name(client, *thread), location_text(client, location));
+            },
+            jdwp::Event::FieldModification { request_id, thread, location, type_id, field_id, object, value, .. } => {
+                // The event arrives before the write, so the field still holds the old value.
+                let name = stop.lock().unwrap().watches.get(request_id).map(|w| w.name.clone()).unwrap_or_default();
+                println!("Watch {}: {} changed from {} to {} in thread {} at {}", request_id, name,
+                    value_text(client, field_value(client, *type_id, *field_id, object)),
+                    value_text(client, Ok(*value)),
+                    thread_name(client, *thread), location_text(client, location));
+            },
+            jdwp::Event::Exception { thread, location, exception, catch_location, .. } => {
+                print_exception(client, *thread, location, exception, catch_location);
+            },
+            event => {
+                stopped = true;
+                println!("Event: {:?}", event);
+                set_automatic(client, event);
+                continue;
+            },
+        }
+        stopped = true;
+        if let Some(thread) = event.thread() {
+            let mut stop = stop.lock().unwrap();
+            stop.thread = Some(thread);
+            stop.frame = 0;
+        }
+        set_automatic(client, event);
+    }
+    if stopped {
+        return;
+    }
+    let resume = match (suspend_policy, events.first().and_then(|e| e.thread())) {
+        (jdwp::SUSPEND_ALL, _) => Command::Resume,
+        (jdwp::SUSPEND_EVENT_THREAD, Some(thread)) => Command::ThreadReferenceResume { thread: thread },
+        _ => { return; },
+    };
+    if let Err(e) = client.send_and_wait(&resume) {
+        println!("Failed to resume: {}", error_text(&e));
+    }
+}
+
+fn print_referrers(client: &Client, node: &instances::Referrers, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let arrow = if depth == 0 { "" } else { "<- " };
+    let note = match node.end {
+        instances::End::Expanded => "",
+        instances::End::Root => " (GC root: held by a stack frame, JNI or the VM)",
+        instances::End::Cycle => " (cycle)",
+        instances::End::DepthLimit => " ...",
+    };
+    println!("{}{}{}{}", indent, arrow, value_text(client, Ok(node.object)), note);
+    for referrer in node.referrers.iter() {
+        print_referrers(client, referrer, depth + 1);
+    }
+    if node.truncated {
+        println!("{}  <- ... more", indent);
+    }
+}
+
+fn print_redefined(outcome: &redefine::Outcome) {
+    if outcome.redefined.is_empty() {
+        println!("Nothing to redefine");
+    } else {
+        println!("Redefined {}", outcome.redefined.join(", "));
+    }
+    if !outcome.not_loaded.is_empty() {
+        println!("Not loaded yet: {}", outcome.not_loaded.join(", "));
+    }
+}
+
+pub struct Session {
+    client: Arc<Client>,
+    /// Heap dumps streamed back in HPDS chunks.
+    dumps: Receiver<Vec<u8>>,
+    heap: Option<(hprof::Heap, hprof::Dominators)>,
+    stop: Arc<Mutex<Stop>>,
+    tracer: Arc<Mutex<Tracer>>,
+    browser: Arc<Mutex<Browser>>,
+    contention: Arc<Mutex<Option<monitors::Contention>>>,
+    watcher: Option<redefine::Watcher>,
+}
+
+impl Session {
+    /// Learns about the VM, prints it, and starts printing events.
+    pub fn new(client: Client, events: Receiver<Event>) -> Result<Session> {
+        // We must know version, capabilities, and ID sizes before anything else
+        client.initialize()?;
+        {
+            let state = client.state();
+            println!("VM Version: {}.{}", state.major, state.minor);
+            println!("VM Name: {}", state.name);
+            println!("VM Capabilities: {:?}", state.capabilities);
+            println!("ID Sizes: {:?}", state.idsizes);
+        }
+        let client = Arc::new(client);
+        let stop = Arc::new(Mutex::new(Stop::default()));
+        let (dump_tx, dumps) = mpsc::channel();
+        let tracer = Arc::new(Mutex::new(Tracer::default()));
+        let browser = Arc::new(Mutex::new(Browser::default()));
+        let contention = Arc::new(Mutex::new(None));
+        let (event_client, event_stop, event_tracer) = (client.clone(), stop.clone(), tracer.clone());
+        let (event_contention, event_browser) = (contention.clone(), browser.clone());
+        std::thread::spawn(move || {
+            for (_, cmd) in events {
+                match cmd {
+                    Command::DdmChunk(chunk) if chunk.kind == ddm::HPDS && !chunk.data.is_empty() => {
+                        let _ = dump_tx.send(chunk.data);
+                    },
+                    Command::Composite { suspend_policy, events } => {
+                        on_events(&event_client, &event_stop, &event_tracer, &event_contention, &event_browser, suspend_policy, &events);
+                    },
+                    cmd => print_event(&cmd),
+                }
+            }
+        });
+        return Ok(Session { client: client, dumps: dumps, heap: None, stop: stop, tracer: tracer, browser: browser,
+            contention: contention, watcher: None });
+    }
+    /// Runs one command line. Returns false once the user asks to quit.
+    pub fn execute(&mut self, line: &str) -> Result<bool> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        // Everything after the command word, for commands that take expressions.
+        let rest = |n: usize| {
+            let mut rest = line.trim_start();
+            for word in words.iter().take(n) {
+                rest = rest[word.len()..].trim_start();
+            }
+            rest.trim_end()
+        };
+        match words.first() {
+            None => {},
+            Some(&"quit") | Some(&"q") => { return Ok(false); },
+            Some(&"help") => println!("{}", HELP),
+            Some(&"threads") => self.threads()?,
+            Some(&"thread") => match words.get(1) {
+                Some(id) => {
+                    let thread = self.object(id)?.object_id().unwrap_or(0);
+                    let mut stop = self.stop.lock().unwrap();
+                    stop.thread = Some(thread);
+                    stop.frame = 0;
+                    set_selected(&self.client, thread, 0);
+                },
+                None => { return Err(usage("thread ID")); },
+            },
+            Some(&"where") | Some(&"bt") => self.backtrace()?,
+            Some(&"frame") => match words.get(1) {
+                Some(n) => self.select_frame(parse(n)?)?,
+                None => { return Err(usage("frame N")); },
+            },
+            Some(&"pop") => self.pop()?,
+            Some(&"return") => self.force_return(rest(1))?,
+            Some(&"suspend") => { self.client.send_and_wait(&Command::Suspend)?; },
+            Some(&"continue") | Some(&"c") => {
+                self.client.send_and_wait(&Command::Resume)?;
+                self.stop.lock().unwrap().thread = None;
+            },
+            Some(&"break") => self.set_breakpoint(rest(1))?,
+            Some(&"watch") => self.watch(&[jdwp::EventKind::FieldModification], rest(1))?,
+            Some(&"rwatch") => self.watch(&[jdwp::EventKind::FieldAccess], rest(1))?,
+            Some(&"awatch") => self.watch(&[jdwp::EventKind::FieldAccess, jdwp::EventKind::FieldModification], rest(1))?,
+            Some(&"catch") => self.catch(&words[1..])?,
+            Some(&"deadlocks") => self.deadlocks()?,
+            Some(&"monitor-trace") => self.monitor_trace(&words[1..])?,
+            Some(&"breakpoints") => {
+                let stop = self.stop.lock().unwrap();
+                for (id, b) in stop.breakpoints.iter() {
+                    match &b.condition {
+                        Some((text, _)) => println!("{:>4} {} if {}", id, b.location, text),
+                        None => println!("{:>4} {}", id, b.location),
+                    }
+                }
+                for (id, what) in stop.catches.iter() {
+                    println!("{:>4} {}", id, what);
+                }
+                for (id, w) in stop.watches.iter() {
+                    let what = if w.kind == jdwp::EventKind::FieldAccess { "reads" } else { "writes" };
+                    match w.object {
+                        Some(object) => println!("{:>4} {} of {} on {:#x}", id, what, w.name, object),
+                        None => println!("{:>4} {} of {}", id, what, w.name),
+                    }
+                }
+            },
+            Some(&"delete") => match words.get(1) {
+                Some(id) => {
+                    let id = parse(id)?;
+                    let kind = {
+                        let mut stop = self.stop.lock().unwrap();
+                        if stop.breakpoints.remove(&id).is_some() {
+                            jdwp::EventKind::Breakpoint
+                        } else if stop.catches.remove(&id).is_some() {
+                            jdwp::EventKind::Exception
+                        } else {
+                            match stop.watches.remove(&id) {
+                                Some(w) => w.kind,
+                                None => { return Err(usage("no such breakpoint, watchpoint or catchpoint")); },
+                            }
+                        }
+                    };
+                    self.client.send_and_wait(&Command::EventRequestClear { event_kind: kind, request_id: id })?;
+                },
+                None => { return Err(usage("delete ID")); },
+            },
+            Some(&"trace") => match words.get(1) {
+                Some(spec) => {
+                    let id = self.tracer.lock().unwrap().start(&self.client, spec, words.get(2).cloned())?;
+                    println!("Trace {} set on {}", id, spec);
+                },
+                None => {
+                    for trace in self.tracer.lock().unwrap().traces.iter() {
+                        println!("{:>4} {} to {}", trace.id(), trace.spec, trace.file.as_deref().unwrap_or("stdout"));
+                    }
+                },
+            },
+            Some(&"untrace") => {
+                let id = match words.get(1) {
+                    Some(id) => Some(parse(id)?),
+                    None => None,
+                };
+                self.tracer.lock().unwrap().stop(&self.client, id)?;
+            },
+            Some(&"redefine") => self.redefine(&words[1..])?,
+            Some(&"classes") => self.classes(words.get(1).unwrap_or(&"*"))?,
+            Some(&"methods") => match words.get(1) {
+                Some(class) => self.members(class, true)?,
+                None => { return Err(usage("methods CLASS")); },
+            },
+            Some(&"fields") => match words.get(1) {
+                Some(class) => self.members(class, false)?,
+                None => { return Err(usage("fields CLASS")); },
+            },
+            Some(&"hierarchy") => match words.get(1) {
+                Some(class) => self.hierarchy(class)?,
+                None => { return Err(usage("hierarchy CLASS")); },
+            },
+            Some(&"instances") => match words.get(1) {
+                Some(class) => self.instances(class, match words.get(2) {
+                    Some(n) => parse(n)?,
+                    None => 20,
+                })?,
+                None => { return Err(usage("instances CLASS [N]")); },
+            },
+            Some(&"instance-counts") => match words.get(1) {
+                Some(pattern) => self.instance_counts(pattern, match words.get(2) {
+                    Some(n) => parse(n)?,
+                    None => 20,
+                })?,
+                None => { return Err(usage("instance-counts PATTERN [N]")); },
+            },
+            Some(&"referrers") => match words.get(1) {
+                Some(id) => {
+                    let depth = match words.get(2) {
+                        Some(n) => parse(n)?,
+                        None => 5,
+                    };
+                    let tree = instances::referrer_tree(&self.client, self.object(id)?, depth, 10)?;
+                    print_referrers(&self.client, &tree, 0);
+                },
+                None => { return Err(usage("referrers ID [DEPTH]")); },
+            },
+            Some(&"print") | Some(&"p") => {
+                let expr = eval::parse(rest(1))?;
+                let value = self.with_evaluator(|e| e.evaluate(&expr))?;
+                let number = self.client.handles().pin(&self.client, value)?;
+                println!("${} = {}", number, eval::format_value(&self.client, &value)?);
+            },
+            Some(&"set") if words.get(1) == Some(&"var") => {
+                let (target, expr) = eval::parse_assignment(rest(2))?;
+                let value = self.with_evaluator(|e| e.assign(&target, &expr).and_then(|v| e.format(&v)))?;
+                println!("{} = {}", rest(2).split('=').next().unwrap_or("").trim(), value);
+            },
+            Some(&"pin") => {
+                let value = self.with_evaluator(|e| e.evaluate(&eval::parse(rest(1))?))?;
+                let number = self.client.handles().pin(&self.client, value)?;
+                println!("${} = {}", number, value_text(&self.client, Ok(value)));
+            },
+            Some(&"unpin") => match words.get(1) {
+                Some(&"all") => self.client.handles().unpin_all(&self.client)?,
+                Some(arg) => {
+                    let number = arg.strip_prefix('$').map(parse).unwrap_or_else(|| Err(usage("unpin $N|all")))?;
+                    if !self.client.handles().unpin(&self.client, number)? {
+                        return Err(Error::Command(format!("no {}, try pins", arg)));
+                    }
+                },
+                None => { return Err(usage("unpin $N|all")); },
+            },
+            Some(&"pins") => {
+                let (pinned, automatic) = {
+                    let handles = self.client.handles();
+                    (handles.list(), handles.automatic())
+                };
+                for (number, value) in pinned {
+                    println!("${} = {}", number, value_text(&self.client, Ok(value)));
+                }
+                for (name, value) in automatic {
+                    println!("${} = {}", name, value_text(&self.client, Ok(value)));
+                }
+            },
+            Some(&"ddm") => self.ddm(&words[1..])?,
+            Some(&"heap") => self.heap(&words[1..])?,
+            Some(cmd) => { return Err(Error::Command(format!("unknown command {}, try help", cmd))); },
+        }
+        return Ok(true);
+    }
+    /// Runs `f` with an evaluator for the selected frame, or a frameless one
+    /// when no thread is selected.
+    fn with_evaluator<T, F: FnOnce(&Evaluator) -> Result<T>>(&self, f: F) -> Result<T> {
+        let (thread, frame) = {
+            let stop = self.stop.lock().unwrap();
+            (stop.thread, stop.frame)
+        };
+        return match thread {
+            Some(thread) => {
+                let frame = match frames(&self.client, thread, frame, 1)?.first() {
+                    Some(f) => *f,
+                    None => { return Err(usage("no such frame")); },
                 };
-                packet.write(writer)
+                f(&Evaluator::in_frame(&self.client, thread, frame))
             },
+            None => f(&Evaluator::new(&self.client)),
+        };
+    }
+    /// An object given as an ID or a pinned `$N`.
+    fn object(&self, arg: &str) -> Result<jdwp::Tag> {
+        return match arg.strip_prefix('$') {
+            Some(number) => match self.client.handles().get(parse(number)?) {
+                Some(value) => Ok(value),
+                None => Err(Error::Command(format!("no {}, try pins", arg))),
+            },
+            None => Ok(jdwp::Tag::Object(parse_id(arg)?)),
+        };
+    }
+    fn selected_thread(&self) -> Result<u64> {
+        return self.stop.lock().unwrap().thread.ok_or_else(|| usage("no thread selected, try threads"));
+    }
+    fn threads(&self) -> Result<()> {
+        let threads = self.client.request(request::all_threads())?;
+        let selected = self.stop.lock().unwrap().thread;
+        for thread in threads {
+            let name = self.client.request(request::thread_reference_name(thread))?;
+            let status = self.client.request(request::thread_reference_status(thread))?;
+            let (status, suspended) = (status.thread_status, status.suspend_status != 0);
+            println!("{} {:#x} {} {}{}", if selected == Some(thread) { "*" } else { " " }, thread, name,
+                thread_status(status), if suspended { " (suspended)" } else { "" });
+        }
+        return Ok(());
+    }
+    /// Suspends the VM long enough to read every thread's monitors, then
+    /// prints each cycle of threads waiting on each other.
+    fn deadlocks(&self) -> Result<()> {
+        self.client.send_and_wait(&Command::Suspend)?;
+        let found = monitors::collect(&self.client).and_then(|threads| {
+            let owners = monitors::owners(&self.client, &threads)?;
+            return Ok((monitors::deadlocks(&threads, &owners), threads));
+        });
+        let (cycles, threads) = match found {
+            Ok(found) => found,
             Err(e) => {
-                let packet = jdwp::Packet::Reply {
-                    id: id,
-                    error: e.serialize(),
-                    data: Vec::with_capacity(0),
+                self.client.send_and_wait(&Command::Resume)?;
+                return Err(e);
+            },
+        };
+        if cycles.is_empty() {
+            println!("No deadlocks");
+        }
+        for cycle in cycles.iter() {
+            println!("Deadlock between {} threads:", cycle.len());
+            for wait in cycle {
+                let monitor = value_text(&self.client, Ok(jdwp::Tag::Object(wait.monitor)));
+                println!("  \"{}\" waits for {} held by \"{}\"", thread_name(&self.client, wait.thread), monitor,
+                    thread_name(&self.client, wait.owner));
+                let waiter = threads.iter().find(|t| t.thread == wait.thread);
+                if let Some(location) = waiter.and_then(|t| t.location.as_ref()) {
+                    println!("      waiting at {}", location_text(&self.client, location));
+                }
+                let owner = threads.iter().find(|t| t.thread == wait.owner);
+                let owned = owner.and_then(|t| t.owned.iter().find(|o| o.object == wait.monitor));
+                if let Some(frame) = owned.and_then(|o| o.frame.as_ref()) {
+                    println!("      locked at {}", location_text(&self.client, &frame.location));
+                }
+            }
+        }
+        self.client.send_and_wait(&Command::Resume)?;
+        return Ok(());
+    }
+    fn monitor_trace(&self, args: &[&str]) -> Result<()> {
+        let mut contention = self.contention.lock().unwrap();
+        match args.first() {
+            Some(&"on") => {
+                if contention.is_some() {
+                    return Err(usage("monitor-trace is already on"));
+                }
+                *contention = Some(monitors::Contention::start(&self.client)?);
+                println!("Tracing monitor contention");
+            },
+            Some(&"off") => match contention.take() {
+                Some(mut c) => {
+                    c.stop(&self.client);
+                    self.print_contention(&c, 20);
+                },
+                None => { return Err(usage("monitor-trace isn't on")); },
+            },
+            arg => match contention.as_ref() {
+                Some(c) => self.print_contention(c, match arg {
+                    Some(n) => parse(n)?,
+                    None => 20,
+                }),
+                None => { return Err(usage("monitor-trace on|off|[N]")); },
+            },
+        }
+        return Ok(());
+    }
+    fn print_contention(&self, contention: &monitors::Contention, n: usize) {
+        let ms = |d: Duration| format!("{:.1}ms", d.as_secs_f64() * 1000.0);
+        let header = format!("{:>12} {:>6} {:>10} {:>12} {:>6}", "blocked", "times", "longest", "waited", "waits");
+        let row = |s: &monitors::Stats| format!("{:>12} {:>6} {:>10} {:>12} {:>6}", ms(s.blocked), s.contended, ms(s.longest), ms(s.waited), s.waits);
+        println!("{}  lock", header);
+        for (object, stats) in monitors::sorted(&contention.locks).iter().take(n) {
+            println!("{}  {}", row(stats), value_text(&self.client, Ok(jdwp::Tag::Object(*object))));
+        }
+        println!("{}  call site", header);
+        for (location, stats) in monitors::sorted(&contention.sites).iter().take(n) {
+            println!("{}  {}", row(stats), location_text(&self.client, location));
+        }
+    }
+    fn redefine(&mut self, args: &[&str]) -> Result<()> {
+        match args {
+            ["watch", "off"] => match self.watcher.take() {
+                Some(w) => println!("Stopped watching {}", w.path.display()),
+                None => { return Err(usage("not watching anything")); },
+            },
+            ["watch", path] => {
+                let path = std::path::Path::new(path);
+                if !path.exists() {
+                    return Err(Error::Command(format!("{} doesn't exist", path.display())));
+                }
+                let report = |result: Result<redefine::Outcome>| match result {
+                    Ok(outcome) => print_redefined(&outcome),
+                    Err(e) => println!("{}", error_text(&e)),
                 };
-                packet.write(writer)
+                self.watcher = Some(redefine::Watcher::start(self.client.clone(), path, Duration::from_millis(500), report));
+                println!("Watching {}", path.display());
+            },
+            [path] => {
+                let definitions = redefine::load(std::path::Path::new(path))?;
+                print_redefined(&redefine::redefine(&self.client, &definitions)?);
+            },
+            _ => { return Err(usage("redefine PATH | redefine watch PATH|off")); },
+        }
+        return Ok(());
+    }
+    fn backtrace(&self) -> Result<()> {
+        let thread = self.selected_thread()?;
+        let selected = self.stop.lock().unwrap().frame;
+        for (i, frame) in frames(&self.client, thread, 0, -1)?.iter().enumerate() {
+            println!("{} [{}] {}", if i as i32 == selected { "*" } else { " " }, i, location_text(&self.client, &frame.location));
+        }
+        return Ok(());
+    }
+    fn select_frame(&self, n: i32) -> Result<()> {
+        let thread = self.selected_thread()?;
+        let count = self.client.request(request::thread_reference_frame_count(thread))?;
+        if n < 0 || n >= count {
+            return Err(usage("no such frame"));
+        }
+        self.stop.lock().unwrap().frame = n;
+        set_selected(&self.client, thread, n);
+        return Ok(());
+    }
+    fn require(&self, needed: jdwp::Capabilities, what: &str) -> Result<()> {
+        if !self.client.state().capabilities.contains(needed) {
+            return Err(Error::Command(format!("this VM can't {} (no {:?} capability)", what, needed)));
+        }
+        return Ok(());
+    }
+    fn pop(&self) -> Result<()> {
+        self.require(jdwp::Capabilities::POP_FRAMES, "pop frames")?;
+        let thread = self.selected_thread()?;
+        let n = self.stop.lock().unwrap().frame;
+        let frame = match frames(&self.client, thread, n, 1)?.first() {
+            Some(f) => f.frame_id,
+            None => { return Err(usage("no such frame")); },
+        };
+        self.client.send_and_wait(&Command::StackFramePopFrames { thread: thread, frame: frame })?;
+        self.stop.lock().unwrap().frame = 0;
+        if let Some(top) = frames(&self.client, thread, 0, 1)?.first() {
+            println!("Now at {}", location_text(&self.client, &top.location));
+        }
+        return Ok(());
+    }
+    /// ForceEarlyReturn only acts on the innermost frame, so that must be
+    /// the one selected.
+    fn force_return(&self, text: &str) -> Result<()> {
+        self.require(jdwp::Capabilities::FORCE_EARLY_RETURN, "force early returns")?;
+        let thread = self.selected_thread()?;
+        if self.stop.lock().unwrap().frame != 0 {
+            return Err(usage("can only return from frame 0; pop the frames above first"));
+        }
+        let top = match frames(&self.client, thread, 0, 1)?.first() {
+            Some(f) => *f,
+            None => { return Err(usage("thread has no frames")); },
+        };
+        let signature = lookup::method(&self.client, &top.location)?
+            .map(|m| m.signature).ok_or_else(|| usage("can't find the frame's method"))?;
+        let expr = if text.is_empty() { None } else { Some(eval::parse(text)?) };
+        let evaluator = Evaluator::in_frame(&self.client, thread, top);
+        let value = evaluator.return_value(expr.as_ref(), &signature)?;
+        self.client.send_and_wait(&Command::ThreadReferenceForceEarlyReturn { thread: thread, value: value })?;
+        match value {
+            jdwp::Tag::Void => println!("{} will return when resumed", location_text(&self.client, &top.location)),
+            v => println!("{} will return {} when resumed", location_text(&self.client, &top.location), evaluator.format(&v)?),
+        }
+        return Ok(());
+    }
+    fn instances(&self, class: &str, n: i32) -> Result<()> {
+        let class = self.find_class(class)?;
+        let found = instances::instances(&self.client, class.type_id, n)?;
+        for object in found.iter() {
+            println!("  {}", value_text(&self.client, Ok(*object)));
+        }
+        println!("{} instance{}{}", found.len(), if found.len() == 1 { "" } else { "s" },
+            if found.len() as i32 == n { ", maybe more" } else { "" });
+        return Ok(());
+    }
+    fn instance_counts(&self, pattern: &str, n: usize) -> Result<()> {
+        let classes = self.client.request(request::all_classes())?;
+        let matching: Vec<(u64, String)> = classes.iter()
+            .map(|c| (c.type_id, eval::type_name(&c.signature)))
+            .filter(|(_, name)| trace::class_matches(pattern, name))
+            .collect();
+        let ids: Vec<u64> = matching.iter().map(|(id, _)| *id).collect();
+        let counts = instances::counts(&self.client, &ids)?;
+        let mut rows: Vec<(i64, &String)> = counts.into_iter().zip(matching.iter().map(|(_, name)| name))
+            .filter(|(count, _)| *count > 0)
+            .collect();
+        rows.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+        let most = rows.first().map(|r| r.0).unwrap_or(1);
+        for (count, name) in rows.iter().take(n) {
+            let bar = "#".repeat(((count * 40 + most - 1) / most) as usize);
+            println!("{:>10} {:<40} {}", count, bar, name);
+        }
+        return Ok(());
+    }
+    fn classes(&self, pattern: &str) -> Result<()> {
+        let mut browser = self.browser.lock().unwrap();
+        browser.refresh();
+        let mut found: Vec<(String, u8, String)> = browser.classes(&self.client)?.iter()
+            .map(|c| (eval::type_name(&c.signature), c.ref_type_tag, c.generic_signature.clone()))
+            .filter(|(name, _, _)| trace::class_matches(pattern, name))
+            .collect();
+        found.sort();
+        for (name, tag, generic) in found.iter() {
+            // Shown with its type parameters, like java.util.Map<K, V>.
+            let params = signature::parse_class(generic).map(|c| signature::type_params_java(&c.type_params)).unwrap_or_default();
+            let kind = match *tag {
+                jdwp::TYPE_TAG_INTERFACE => "interface",
+                jdwp::TYPE_TAG_ARRAY => "array",
+                _ => "class",
+            };
+            println!("{:<9} {}{}", kind, name, params);
+        }
+        println!("{} loaded type{}", found.len(), if found.len() == 1 { "" } else { "s" });
+        return Ok(());
+    }
+    /// The methods or fields a class declares itself.
+    fn members(&self, name: &str, methods: bool) -> Result<()> {
+        let class = self.find_class(name)?;
+        let class_name = eval::type_name(&class.signature);
+        let mut browser = self.browser.lock().unwrap();
+        if methods {
+            for method in browser.methods(&self.client, class.type_id)? {
+                println!("    {};", browse::method_text(&class_name, method));
+            }
+        } else {
+            for field in browser.fields(&self.client, class.type_id)? {
+                println!("    {};", browse::field_text(field));
             }
         }
+        return Ok(());
     }
-    pub fn deserialize_packet(&mut self, packet: &jdwp::Packet) -> jdwp::Result<DeserializedPacket> {
-        let deserialized = match packet {
-            jdwp::Packet::Command { id, set, cmd, data } => {
-               DeserializedPacket::Command(*id, jdwp::Command::deserialize(*set, *cmd, data.as_slice(), self.idsizes)?)
+    /// The superclass chain down to a class, with what each implements, then
+    /// the loaded classes that extend it.
+    fn hierarchy(&self, name: &str) -> Result<()> {
+        let class = self.find_class(name)?;
+        let client = &*self.client;
+        let mut browser = self.browser.lock().unwrap();
+        let interface = class.ref_type_tag == jdwp::TYPE_TAG_INTERFACE;
+        let mut chain = vec![class.type_id];
+        if !interface {
+            loop {
+                match browser.superclass(client, *chain.last().unwrap())? {
+                    0 => break,
+                    superclass => chain.push(superclass),
+                }
+            }
+        }
+        chain.reverse();
+        for (depth, id) in chain.iter().enumerate() {
+            let interfaces = browser.interfaces(client, *id)?;
+            let names = interfaces.iter().map(|i| browser.name(client, *i)).collect::<Result<Vec<String>>>()?;
+            let tag = if *id == class.type_id { class.ref_type_tag } else { jdwp::TYPE_TAG_CLASS };
+            let kind = browse::class_kind(browser.modifiers(client, *id)?, tag);
+            let mut line = format!("{}{} {}", "    ".repeat(depth), kind, browser.name(client, *id)?);
+            if !names.is_empty() {
+                line = format!("{} {} {}", line, if interface { "extends" } else { "implements" }, names.join(", "));
+            }
+            println!("{}", line);
+        }
+        let indent = "    ".repeat(chain.len());
+        let mut subclasses = Vec::new();
+        for id in browser.subclasses(client, class.type_id, interface)? {
+            // The modifiers tell interfaces apart.
+            subclasses.push((browser.name(client, id)?, browse::class_kind(browser.modifiers(client, id)?, jdwp::TYPE_TAG_CLASS)));
+        }
+        subclasses.sort();
+        for (name, kind) in subclasses.iter() {
+            println!("{}{} {}", indent, kind, name);
+        }
+        if subclasses.is_empty() {
+            println!("{}(no loaded subclasses)", indent);
+        }
+        return Ok(());
+    }
+    /// The loaded class called `name`, which may leave out the package.
+    fn find_class(&self, name: &str) -> Result<jdwp::ClassInfo> {
+        let classes = self.client.request(request::all_classes())?;
+        let signature = eval::class_signature(name);
+        if let Some(class) = classes.iter().find(|c| c.signature == signature) {
+            return Ok(class.clone());
+        }
+        let suffix = format!(".{}", name);
+        let mut matches = classes.into_iter().filter(|c| eval::type_name(&c.signature).ends_with(&suffix));
+        return match (matches.next(), matches.next()) {
+            (Some(class), None) => Ok(class),
+            (Some(_), Some(_)) => Err(Error::Command(format!("{} is ambiguous, give the package", name))),
+            (None, _) => Err(Error::Command(format!("class {} is not loaded", name))),
+        };
+    }
+    /// Where `Class:LINE` or `Class.method` starts.
+    fn resolve_location(&self, spec: &str) -> Result<jdwp::Location> {
+        let (class_name, line, method_name) = match spec.rsplit_once(':') {
+            Some((class, line)) => (class, Some(parse::<i32>(line)?), None),
+            None => match spec.rsplit_once('.') {
+                Some((class, method)) => (class, None, Some(method)),
+                None => { return Err(usage("break CLASS:LINE or break CLASS.METHOD")); },
             },
-            jdwp::Packet::Reply {id, error, data} => {
-                if *error == 0u16 {
-                    match self.pending.remove(id) {
-                        Some((set, cmd)) => {
-                            DeserializedPacket::Reply(
-                                *id,
-                                jdwp::Reply::deserialize(set, cmd, data.as_slice(), self.idsizes)?
-                            )
-                        },
-                        None => {
-                            error!("Got a reply packet with no corresponding command!");
-                            return Err(jdwp::Error::IllegalArgument); 
+        };
+        let class = self.find_class(class_name)?;
+        let methods = self.client.request(request::reference_type_methods(class.type_id))?;
+        let mut best: Option<(u64, i64)> = None;
+        for method in methods.iter() {
+            if method_name.is_some() && method_name != Some(method.name.as_str()) {
+                continue;
+            }
+            let (start, lines) = match self.client.request(request::method_line_table(class.type_id, method.method_id)) {
+                Ok(table) => (table.start, table.lines),
+                Err(Error::Jdwp(_)) => (0, Vec::new()),
+                Err(e) => { return Err(e); },
+            };
+            let index = match line {
+                None => Some(start),
+                Some(line) => lines.iter().filter(|l| l.line == line).map(|l| l.code_index).min(),
+            };
+            if let Some(index) = index {
+                best = Some((method.method_id, index));
+                break;
+            }
+        }
+        let (method, index) = match (best, line) {
+            (Some(found), _) => found,
+            (None, Some(line)) => { return Err(Error::Command(format!("no code at {}:{}", class_name, line))); },
+            (None, None) => { return Err(Error::Command(format!("no method {} in {}", method_name.unwrap(), class_name))); },
+        };
+        return Ok(jdwp::Location { type_tag: class.ref_type_tag, class_id: class.type_id, method_id: method, index: index as u64 });
+    }
+    fn set_breakpoint(&self, args: &str) -> Result<()> {
+        let (spec, condition) = match args.split_once(" if ") {
+            Some((spec, condition)) => (spec.trim(), Some(condition.trim())),
+            None => (args, None),
+        };
+        if spec.is_empty() {
+            return Err(usage("break CLASS:LINE [if EXPR] or break CLASS.METHOD [if EXPR]"));
+        }
+        // Parse first so a typo doesn't leave a breakpoint behind.
+        let condition = match condition {
+            Some(text) => Some((text.to_string(), eval::parse(text)?)),
+            None => None,
+        };
+        let location = self.resolve_location(spec)?;
+        let set = request::event_request_set(jdwp::EventKind::Breakpoint, jdwp::SUSPEND_ALL, vec![jdwp::Modifier::LocationOnly(location)]);
+        let id = self.client.request(set)?;
+        println!("Breakpoint {} at {}", id, location_text(&self.client, &location));
+        self.stop.lock().unwrap().breakpoints.insert(id, Breakpoint { location: spec.to_string(), condition: condition });
+        return Ok(());
+    }
+    fn catch(&self, args: &[&str]) -> Result<()> {
+        const USAGE: &str = "catch throw|uncaught [CLASS] [exclude PATTERN...]";
+        let (caught, args) = match args.split_first() {
+            Some((&"throw", rest)) => (true, rest),
+            Some((&"uncaught", rest)) => (false, rest),
+            _ => { return Err(usage(USAGE)); },
+        };
+        let (class, excludes) = match args {
+            [] => (None, &args[..0]),
+            ["exclude", patterns @ ..] => (None, patterns),
+            [class, "exclude", patterns @ ..] => (Some(*class), patterns),
+            [class] => (Some(*class), &args[..0]),
+            _ => { return Err(usage(USAGE)); },
+        };
+        if args.last() == Some(&"exclude") {
+            return Err(usage(USAGE));
+        }
+        let ref_type = match class {
+            Some(name) => self.find_class(name)?.type_id,
+            None => 0,
+        };
+        let mut modifiers = vec![jdwp::Modifier::ExceptionOnly { ref_type: ref_type, caught: caught, uncaught: true }];
+        modifiers.extend(excludes.iter().map(|p| jdwp::Modifier::ClassExclude(p.to_string())));
+        let id = self.client.request(request::event_request_set(jdwp::EventKind::Exception, jdwp::SUSPEND_ALL, modifiers))?;
+        let mut what = format!("{} {}", if caught { "throws of" } else { "uncaught" }, class.unwrap_or("any exception"));
+        if !excludes.is_empty() {
+            what.push_str(&format!(" except in {}", excludes.join(", ")));
+        }
+        println!("Catch {} on {}", id, what);
+        self.stop.lock().unwrap().catches.insert(id, what);
+        return Ok(());
+    }
+    fn watch(&self, kinds: &[jdwp::EventKind], args: &str) -> Result<()> {
+        let (spec, object) = match args.split_once(char::is_whitespace) {
+            Some((spec, object)) => (spec, Some(object.trim())),
+            None => (args, None),
+        };
+        let (class_name, field_name) = match spec.rsplit_once('.') {
+            Some(split) => split,
+            None => { return Err(usage("watch CLASS.FIELD [EXPR|ID]")); },
+        };
+        let capabilities = self.client.state().capabilities;
+        for kind in kinds {
+            let (needed, what) = match kind {
+                jdwp::EventKind::FieldAccess => (jdwp::Capabilities::WATCH_FIELD_ACCESS, "reads"),
+                _ => (jdwp::Capabilities::WATCH_FIELD_MODIFICATION, "writes"),
+            };
+            if !capabilities.contains(needed) {
+                return Err(Error::Command(format!("this VM can't watch field {} (no {:?} capability)", what, needed)));
+            }
+        }
+        let class = self.find_class(class_name)?;
+        let (declaring, field) = match find_field(&self.client, class.type_id, field_name)? {
+            Some(found) => found,
+            None => { return Err(Error::Command(format!("no field {} in {}", field_name, class_name))); },
+        };
+        let object = match object {
+            Some(text) if text.starts_with("0x") => Some(parse_id(text)?),
+            Some(text) => {
+                let expr = eval::parse(text)?;
+                let value = self.with_evaluator(|e| e.evaluate(&expr))?;
+                match value.object_id() {
+                    Some(id) if id != 0 => Some(id),
+                    _ => { return Err(Error::Command(format!("{} is not an object", text))); },
+                }
+            },
+            None => None,
+        };
+        let mut modifiers = vec![jdwp::Modifier::FieldOnly { declaring: declaring, field_id: field.field_id }];
+        if let Some(object) = object {
+            modifiers.push(jdwp::Modifier::InstanceOnly(object));
+        }
+        let name = format!("{}.{}", eval::type_name(&class.signature), field.name);
+        for kind in kinds {
+            let id = self.client.request(request::event_request_set(*kind, jdwp::SUSPEND_ALL, modifiers.clone()))?;
+            let what = if *kind == jdwp::EventKind::FieldAccess { "reads" } else { "writes" };
+            println!("Watch {} on {} of {}", id, what, name);
+            self.stop.lock().unwrap().watches.insert(id, Watch { kind: *kind, name: name.clone(), object: object });
+        }
+        return Ok(());
+    }
+    fn ddm_send(&self, chunk: ddm::Chunk) -> Result<Vec<ddm::Message>> {
+        let chunks = self.client.request(request::ddm_chunk(chunk))?;
+        return chunks.iter().map(|c| Ok(ddm::decode(c)?)).collect();
+    }
+    fn ddm_print(&self, chunk: ddm::Chunk) -> Result<()> {
+        for msg in self.ddm_send(chunk)? {
+            print_ddm(&msg);
+        }
+        return Ok(());
+    }
+    fn ddm(&mut self, args: &[&str]) -> Result<()> {
+        match args {
+            ["hello"] => self.ddm_print(ddm::hello())?,
+            ["features"] => self.ddm_print(ddm::features())?,
+            ["threads"] => self.ddm_print(ddm::thread_status())?,
+            ["threads", "on"] => self.ddm_print(ddm::thread_notifications(true))?,
+            ["threads", "off"] => self.ddm_print(ddm::thread_notifications(false))?,
+            ["heap", rest @ ..] => {
+                let when = match rest {
+                    [] | ["now"] => ddm::HPIF_NOW,
+                    ["gc"] => ddm::HPIF_NEXT_GC,
+                    ["every-gc"] => ddm::HPIF_EVERY_GC,
+                    ["off"] => ddm::HPIF_NEVER,
+                    _ => { return Err(usage("ddm heap [now|gc|every-gc|off]")); },
+                };
+                self.ddm_print(ddm::heap_info(when))?;
+            },
+            ["segments", rest @ ..] => {
+                let (when, what) = match rest {
+                    [] | ["on"] | ["on", "merge"] => (ddm::HPSG_EVERY_GC, ddm::HPSG_MERGE),
+                    ["on", "distinct"] => (ddm::HPSG_EVERY_GC, ddm::HPSG_DISTINCT),
+                    ["off"] => (ddm::HPSG_NEVER, ddm::HPSG_MERGE),
+                    _ => { return Err(usage("ddm segments [on|off] [merge|distinct]")); },
+                };
+                self.ddm_print(ddm::heap_segments(when, what))?;
+            },
+            ["alloc"] => self.ddm_print(ddm::alloc_query())?,
+            ["alloc", "on"] => self.ddm_print(ddm::alloc_tracking(true))?,
+            ["alloc", "off"] => self.ddm_print(ddm::alloc_tracking(false))?,
+            ["alloc", "dump"] => self.ddm_print(ddm::alloc_records())?,
+            ["profile"] => self.ddm_print(ddm::profiling_state())?,
+            ["profile", "start", file] => self.ddm_print(ddm::start_profiling(file, PROFILE_BUFFER, 0))?,
+            ["profile", "start", file, size] => self.ddm_print(ddm::start_profiling(file, parse(size)?, 0))?,
+            ["profile", "stop"] => self.ddm_print(ddm::stop_profiling())?,
+            ["profile", "stream"] => self.ddm_print(ddm::start_streaming(PROFILE_BUFFER, 0))?,
+            ["profile", "stream", size] => self.ddm_print(ddm::start_streaming(parse(size)?, 0))?,
+            ["profile", "end", out] => {
+                for msg in self.ddm_send(ddm::stop_streaming())? {
+                    match msg {
+                        ddm::Message::ProfileData(data) => {
+                            std::fs::write(out, &data)?;
+                            println!("Wrote {} bytes of trace to {}", data.len(), out);
                         },
+                        msg => print_ddm(&msg),
                     }
-                } else {
-                    if self.pending.remove(id).is_none() {
-                        warn!("Got an error packet with no corresponding command?");
+                }
+            },
+            ["exit", rest @ ..] => {
+                let status = match rest {
+                    [] => 0,
+                    [status] => parse(status)?,
+                    _ => { return Err(usage("ddm exit [STATUS]")); },
+                };
+                // The VM is gone before it could answer.
+                self.client.send(&Command::DdmChunk(ddm::exit(status)))?;
+            },
+            _ => { return Err(usage("unknown ddm command, try help")); },
+        }
+        return Ok(());
+    }
+    fn load_heap(&mut self, data: &[u8]) -> Result<()> {
+        let heap = hprof::parse_bytes(data)?;
+        let dominators = heap.dominators();
+        println!("{} objects in {} classes, {} GC roots, {} reachable",
+            heap.objects.len(), heap.classes.len(), heap.roots.len(), dominators.retained.len());
+        self.heap = Some((heap, dominators));
+        return Ok(());
+    }
+    fn loaded_heap(&self) -> Result<&(hprof::Heap, hprof::Dominators)> {
+        return self.heap.as_ref().ok_or_else(|| usage("no heap dump loaded, try heap dump"));
+    }
+    fn heap(&mut self, args: &[&str]) -> Result<()> {
+        match args {
+            ["dump", rest @ ..] => {
+                let file = match rest {
+                    [] => "heap.hprof",
+                    [file] => file,
+                    _ => { return Err(usage("heap dump [FILE]")); },
+                };
+                // Drop anything left over from an earlier dump that timed out.
+                while self.dumps.try_recv().is_ok() {}
+                let mut data = None;
+                for msg in self.ddm_send(ddm::heap_dump_stream())? {
+                    match msg {
+                        ddm::Message::HeapDump(d) => data = Some(d),
+                        ddm::Message::Empty(_) => {},
+                        msg => print_ddm(&msg),
                     }
-                    match jdwp::Error::deserialize(*error) {
-                        Some(e) => DeserializedPacket::Error(*id, e),
-                        None => { return Err(jdwp::Error::Unimplemented); }
+                }
+                let data = match data {
+                    Some(d) => d,
+                    None => self.dumps.recv_timeout(HEAP_DUMP_TIMEOUT)
+                        .map_err(|_| usage("the VM never sent the heap dump"))?,
+                };
+                std::fs::write(file, &data)?;
+                println!("Wrote {} bytes to {}", data.len(), file);
+                self.load_heap(&data)?;
+            },
+            ["dump-device", path] => self.ddm_print(ddm::heap_dump_file(path))?,
+            ["load", file] => {
+                let data = std::fs::read(file)?;
+                self.load_heap(&data)?;
+            },
+            ["histogram", rest @ ..] => {
+                let n = match rest {
+                    [] => 20,
+                    [n] => parse(n)?,
+                    _ => { return Err(usage("heap histogram [N]")); },
+                };
+                let (heap, _) = self.loaded_heap()?;
+                println!("{:>10} {:>12}  class", "count", "shallow");
+                for stats in heap.histogram().iter().take(n) {
+                    println!("{:>10} {:>12}  {}", stats.count, stats.shallow, stats.name);
+                }
+            },
+            ["instances", class] => {
+                let (heap, dominators) = self.loaded_heap()?;
+                let instances = heap.instances_of(class);
+                for object in instances.iter() {
+                    let retained = match dominators.retained(object.id) {
+                        Some(r) => r.to_string(),
+                        None => "unreachable".to_string(),
+                    };
+                    println!("{:#x} shallow {} retained {}", object.id, heap.shallow_size(object), retained);
+                }
+                println!("{} instances", instances.len());
+            },
+            ["show", id] => {
+                let (heap, dominators) = self.loaded_heap()?;
+                let id = parse_id(id)?;
+                let object = heap.objects.get(&id).ok_or_else(|| usage("no such object"))?;
+                println!("{:#x} {} on heap {}", id, heap.type_name(object),
+                    heap.heap_names.get(&object.heap).map(|s| s.as_str()).unwrap_or("default"));
+                if let Some(idom) = dominators.idom(id) {
+                    println!("retained {} bytes, dominated by {:#x}", dominators.retained(id).unwrap_or(0), idom);
+                }
+                for (name, value) in heap.fields(id) {
+                    match value {
+                        jdwp::Tag::Object(o) if o != 0 => {
+                            let ty = heap.objects.get(&o).map(|o| heap.type_name(o)).unwrap_or_default();
+                            println!("    {} = {:#x} {}", name, o, ty);
+                        },
+                        value => println!("    {} = {:?}", name, value),
                     }
                 }
-            }
-        };
-        // Some information is cached so I don't have to go through
-        // the pain of making another request for common inquiries.
-        match &deserialized {
-            DeserializedPacket::Reply(_, rply) => {
-                match rply {
-                    jdwp::Reply::Version { description, major, minor, version, name } => {
-                        self.name = name.clone();
-                        self.description = description.clone();
-                        self.version = version.clone();
-			self.major = *major;
-                        self.minor = *minor;
-                    },
-                    jdwp::Reply::Capabilities(capabilities) => {
-                        let mut capbits = self.capabilities.bits();
-                        capbits = (capbits & !0x7f) | (capabilities.bits() & 0x7f);
-                        self.capabilities = jdwp::Capabilities::from_bits(capbits).unwrap();
-                    },
-                    jdwp::Reply::CapabilitiesNew(capabilities) => {
-                        self.capabilities = *capabilities;
-                    },
-                    jdwp::Reply::IDSizes { field, method, object, reference_type, frame } => {
-                        self.idsizes = jdwp::IDSizes {
-                            field: *field,
-                            method: *method,
-                            object: *object,
-                            reference_type: *reference_type,
-                            frame: *frame,
-                        }
-                    },
-                    _ => {},
+            },
+            ["dominators", rest @ ..] => {
+                let (heap, dominators) = self.loaded_heap()?;
+                let rows = match rest {
+                    [] => dominators.largest(20),
+                    [arg] if arg.starts_with("0x") => dominators.children(parse_id(arg)?).into_iter()
+                        .map(|c| (c, dominators.retained(c).unwrap_or(0)))
+                        .collect(),
+                    [n] => dominators.largest(parse(n)?),
+                    _ => { return Err(usage("heap dominators [ID|N]")); },
+                };
+                for (id, retained) in rows {
+                    let ty = heap.objects.get(&id).map(|o| heap.type_name(o)).unwrap_or_default();
+                    println!("{:>12}  {:#x} {}", retained, id, ty);
                 }
             },
-            _ => {}
+            _ => { return Err(usage("unknown heap command, try help")); },
         }
-        return Ok(deserialized);
-    }
-    pub fn replies_left(&self) -> usize {
-        return self.pending.len();
-    }
-    pub fn supports_version(&self, major: i32, minor: i32) -> bool {
-        return self.major >= major && self.minor >= minor;
+        return Ok(());
     }
 }
 
-fn event_thread<R: Read>(conn_data: R, vm_channel: Sender<jdwp::Packet>) -> Result<()> {
-    let mut conn = conn_data;
-    loop {
-        let packet = jdwp::Packet::read(&mut conn);
-        if packet.is_err() {
-            error!("JDWP Read Error: {:?}", packet);
-            break;
-        }
-        vm_channel.send(packet.unwrap());
+fn print_event(cmd: &Command) {
+    match cmd {
+        Command::Composite { events, .. } => {
+            for event in events {
+                println!("Event: {:?}", event);
+            }
+        },
+        Command::DdmChunk(chunk) => match ddm::decode(chunk) {
+            Ok(msg) => print_ddm(&msg),
+            Err(e) => println!("Bad {} chunk: {:?}", chunk.name(), e),
+        },
+        cmd => println!("VM sent {:?}", cmd),
     }
-    Ok(())
 }
 
-fn recv_until_all_replied(vm_channel: &mut Receiver<jdwp::Packet>, state: &mut State) -> Vec<DeserializedPacket> {
-    let mut packets: Vec<DeserializedPacket> = vec![];
-    while state.replies_left() > 0 {
-        let reply_packet = vm_channel.recv().expect("JDWP server closed connection while we were waiting for replies!");
-        let deserialized = match state.deserialize_packet(&reply_packet) {
-            Ok(p) => p,
-            Err(e) => { error!("Failed to deserialize packet: {:?}", e); continue; },
-        };
-        packets.push(deserialized);
+fn print_ddm(msg: &ddm::Message) {
+    match msg {
+        ddm::Message::Hello { protocol, pid, vm, app, abi, package, .. } => {
+            println!("pid {} running {} on {}, DDM protocol {}", pid, app, vm, protocol);
+            if let Some(package) = package {
+                println!("package {}", package);
+            }
+            if let Some(abi) = abi {
+                println!("abi {}", abi);
+            }
+        },
+        ddm::Message::Features(features) => {
+            for feature in features {
+                println!("{}", feature);
+            }
+        },
+        ddm::Message::AppName { app, .. } => println!("App name is now {}", app),
+        ddm::Message::Wait { .. } => println!("App is waiting for a debugger"),
+        ddm::Message::Threads(threads) => {
+            println!("{:>8} {:>8} {:>6} {:>8} {:>8}", "id", "tid", "status", "utime", "stime");
+            for t in threads {
+                println!("{:>8} {:>8} {:>6} {:>8} {:>8}{}", t.thread, t.tid, t.status, t.utime, t.stime,
+                    if t.daemon == Some(true) { " daemon" } else { "" });
+            }
+        },
+        ddm::Message::ThreadCreated { thread, name } => println!("Thread {} created: {}", thread, name),
+        ddm::Message::ThreadDied { thread } => println!("Thread {} died", thread),
+        ddm::Message::HeapInfo(heaps) => {
+            for h in heaps {
+                println!("Heap {}: {} of {} bytes used ({} max), {} objects",
+                    h.heap, h.bytes_allocated, h.size, h.max_size, h.objects_allocated);
+            }
+        },
+        ddm::Message::HeapSegment { heap, unit_size, start, units, runs, .. } => {
+            println!("Heap {} segment at {:#x}: {} units of {} bytes in {} runs", heap, start, units, unit_size, runs.len());
+        },
+        ddm::Message::AllocTracking(on) => println!("Allocation tracking is {}", if *on { "on" } else { "off" }),
+        ddm::Message::Allocations(allocations) => {
+            for a in allocations {
+                println!("{} bytes of {} on thread {}", a.size, a.class, a.thread);
+                for f in a.frames.iter() {
+                    println!("    at {}.{}({}:{})", f.class, f.method, f.file, f.line);
+                }
+            }
+        },
+        ddm::Message::ProfilingState(state) => println!("Method profiling: {}", match state {
+            0 => "off",
+            1 => "to a file",
+            2 => "streaming",
+            _ => "unknown",
+        }),
+        ddm::Message::ProfileData(data) => println!("{} bytes of method trace", data.len()),
+        ddm::Message::HeapDumpResult(0) => println!("Heap dump written"),
+        ddm::Message::HeapDumpResult(e) => println!("Heap dump failed ({})", e),
+        ddm::Message::HeapDump(data) => println!("{} bytes of heap dump", data.len()),
+        ddm::Message::Fail { code, message } => println!("VM refused: {} ({})", message, code),
+        ddm::Message::Empty(kind) => println!("{} ok", ddm::type_name(*kind)),
+        ddm::Message::Unknown(chunk) => println!("{} chunk, {} bytes", chunk.name(), chunk.data.len()),
     }
-    return packets;
 }
 
-fn prompt_thread<W: Write>(conn_const: W, vm_channel_const: Receiver<jdwp::Packet>) -> Result<()> {
-    let mut conn = conn_const;
-    let mut vm_channel = vm_channel_const;
-    let mut state: State = Default::default();
+fn readline_error(e: ReadlineError) -> Error {
+    return Error::Io(std::io::Error::other(e.to_string()));
+}
 
-    // We must know version, capabilities, and ID sizes before anything else
-    state.send_command(&jdwp::Command::Version, &mut conn).unwrap();
-    conn.flush();
-    let _ = recv_until_all_replied(&mut vm_channel, &mut state);
-    if state.supports_version(1, 4) {
-        state.send_command(&jdwp::Command::CapabilitiesNew, &mut conn).unwrap();
-    } else {
-        state.send_command(&jdwp::Command::Capabilities, &mut conn).unwrap();
-    }
-    state.send_command(&jdwp::Command::IDSizes, &mut conn).unwrap();
-    conn.flush();
-    let _ = recv_until_all_replied(&mut vm_channel, &mut state);
-    println!("VM Version: {}.{}", state.major, state.minor);
-    println!("VM Name: {}", state.name);
-    println!("VM Capabilities: {:?}", state.capabilities);
-    println!("ID Sizes: {:?}", state.idsizes);
-    
+pub fn run(client: Client, events: Receiver<Event>) -> Result<()> {
+    let mut session = Session::new(client, events)?;
+    let mut editor = rustyline::Editor::<()>::new().map_err(readline_error)?;
     loop {
-        break;
+        let line = match editor.readline("(dcd) ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => { return Err(readline_error(e)); },
+        };
+        editor.add_history_entry(line.as_str());
+        match session.execute(&line) {
+            Ok(true) => {},
+            Ok(false) => break,
+            Err(Error::Disconnected) => { return Err(Error::Disconnected); },
+            Err(e) => println!("{}", error_text(&e)),
+        }
     }
     Ok(())
 }
 
-pub fn main<R: Read + Send + 'static, W: Write + Send + 'static>(r: R, w: W) -> Result<()> {
-    let mut bufread = BufReader::new(r);
-    let mut bufwrite = BufWriter::new(w);
-    let handshake_str = b"JDWP-Handshake";
-    let mut handshake_buffer: [u8; 14] = [0u8; 14];
-    let _ = &mut handshake_buffer.copy_from_slice(handshake_str);
-    info!("Conducting handshake...");
-    bufwrite.write_all(&handshake_buffer)?;
-    bufwrite.flush()?;
-    bufread.read_exact(&mut handshake_buffer)?;
-    if &handshake_buffer != handshake_str {
-        error!("JDWP Handshake failed! Exiting!");
-        return Err(Error::HandshakeFailed(handshake_buffer.to_vec()));
-    }
-    info!("Handshake successful!: {:?}", std::str::from_utf8(&handshake_buffer));
+pub fn main<T: Transport>(transport: T) -> Result<()> {
+    let (client, events) = Client::connect(transport)?;
     println!("Connected to JVM");
-    let (event, prompt) = mpsc::channel();
-    let evthread_handle = std::thread::spawn(move || {
-        event_thread(bufread, event);
-    });
-    let _ = prompt_thread(bufwrite, prompt);
-    Ok(())
+    run(client, events)
 }
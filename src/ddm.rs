@@ -0,0 +1,462 @@
+//! Android's DDM traffic, which rides inside JDWP command set 199.
+//!
+//! Every DDM packet carries a chunk: a four character type, a length and a
+//! payload, all big endian. DDM strings are UTF-16 and prefixed with their
+//! length in characters rather than bytes.
+use crate::jdwp::{self,Deserializer,Serializer};
+use std::io::Read;
+
+pub const DDM_SET: u8 = 199;
+pub const DDM_CHUNK: u8 = 1;
+
+pub const fn chunk_type(name: &[u8; 4]) -> u32 {
+    return u32::from_be_bytes(*name);
+}
+
+pub const HELO: u32 = chunk_type(b"HELO");
+pub const FEAT: u32 = chunk_type(b"FEAT");
+pub const APNM: u32 = chunk_type(b"APNM");
+pub const WAIT: u32 = chunk_type(b"WAIT");
+pub const THEN: u32 = chunk_type(b"THEN");
+pub const THST: u32 = chunk_type(b"THST");
+pub const THCR: u32 = chunk_type(b"THCR");
+pub const THDE: u32 = chunk_type(b"THDE");
+pub const HPIF: u32 = chunk_type(b"HPIF");
+pub const HPSG: u32 = chunk_type(b"HPSG");
+pub const REAE: u32 = chunk_type(b"REAE");
+pub const REAQ: u32 = chunk_type(b"REAQ");
+pub const REAL: u32 = chunk_type(b"REAL");
+pub const MPRS: u32 = chunk_type(b"MPRS");
+pub const MPRE: u32 = chunk_type(b"MPRE");
+pub const MPSS: u32 = chunk_type(b"MPSS");
+pub const MPSE: u32 = chunk_type(b"MPSE");
+pub const MPRQ: u32 = chunk_type(b"MPRQ");
+pub const HPDU: u32 = chunk_type(b"HPDU");
+pub const HPDS: u32 = chunk_type(b"HPDS");
+pub const EXIT: u32 = chunk_type(b"EXIT");
+pub const FAIL: u32 = chunk_type(b"FAIL");
+
+/// The DDM protocol version we speak in HELO.
+pub const PROTOCOL_VERSION: i32 = 1;
+
+/// When the VM should send HPIF chunks.
+pub const HPIF_NEVER: u8 = 0;
+pub const HPIF_NOW: u8 = 1;
+pub const HPIF_NEXT_GC: u8 = 2;
+pub const HPIF_EVERY_GC: u8 = 3;
+
+/// When the VM should send HPSG chunks, and how much detail they carry.
+pub const HPSG_NEVER: u8 = 0;
+pub const HPSG_EVERY_GC: u8 = 1;
+pub const HPSG_MERGE: u8 = 0;
+pub const HPSG_DISTINCT: u8 = 1;
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Chunk {
+    pub kind: u32,
+    pub data: Vec<u8>,
+}
+
+impl Chunk {
+    pub fn new(kind: u32, data: Vec<u8>) -> Chunk {
+        return Chunk { kind: kind, data: data };
+    }
+    pub fn name(&self) -> String {
+        return type_name(self.kind);
+    }
+    pub fn serialize(&self, serializer: &mut Serializer) {
+        serializer.serialize_int(self.kind as i32);
+        serializer.serialize_int(self.data.len() as i32);
+        serializer.0.extend_from_slice(&self.data);
+    }
+    pub fn deserialize<R: Read>(deserializer: &mut Deserializer<R>) -> jdwp::Result<Chunk> {
+        let kind = deserializer.deserialize_int()? as u32;
+        let len = deserializer.deserialize_int()?;
+        if len < 0 {
+            return Err(jdwp::Error::InvalidLength);
+        }
+        let mut data = Vec::new();
+        let read = deserializer.0.by_ref().take(len as u64).read_to_end(&mut data);
+        if read.is_err() || data.len() != len as usize {
+            return Err(jdwp::Error::InvalidLength);
+        }
+        return Ok(Chunk { kind: kind, data: data });
+    }
+}
+
+pub fn type_name(kind: u32) -> String {
+    return kind.to_be_bytes().iter()
+        .map(|b| if b.is_ascii_graphic() { *b as char } else { '?' })
+        .collect();
+}
+
+/// Big endian reader over a chunk payload. Running off the end is an
+/// `InvalidLength` error rather than a panic.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> jdwp::Result<&'a [u8]> {
+        if n > self.data.len() {
+            return Err(jdwp::Error::InvalidLength);
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        return Ok(head);
+    }
+    fn u8(&mut self) -> jdwp::Result<u8> {
+        return Ok(self.bytes(1)?[0]);
+    }
+    fn u16(&mut self) -> jdwp::Result<u16> {
+        let b = self.bytes(2)?;
+        return Ok(u16::from_be_bytes([b[0], b[1]]));
+    }
+    fn u32(&mut self) -> jdwp::Result<u32> {
+        let b = self.bytes(4)?;
+        return Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+    }
+    fn u64(&mut self) -> jdwp::Result<u64> {
+        return Ok((self.u32()? as u64) << 32 | self.u32()? as u64);
+    }
+    fn utf16(&mut self, len: usize) -> jdwp::Result<String> {
+        let units = self.bytes(len.checked_mul(2).ok_or(jdwp::Error::InvalidLength)?)?
+            .chunks(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect::<Vec<_>>();
+        return Ok(String::from_utf16_lossy(&units));
+    }
+    fn string(&mut self) -> jdwp::Result<String> {
+        let len = self.u32()? as usize;
+        return self.utf16(len);
+    }
+    fn is_empty(&self) -> bool {
+        return self.data.is_empty();
+    }
+}
+
+fn put_string(out: &mut Vec<u8>, s: &str) {
+    let units: Vec<u16> = s.encode_utf16().collect();
+    out.extend_from_slice(&(units.len() as u32).to_be_bytes());
+    for u in units {
+        out.extend_from_slice(&u.to_be_bytes());
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct ThreadInfo {
+    pub thread: u32,
+    pub status: u8,
+    pub tid: u32,
+    pub utime: u32,
+    pub stime: u32,
+    pub daemon: Option<bool>,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct HeapInfo {
+    pub heap: u32,
+    pub timestamp: u64,
+    pub reason: u8,
+    pub max_size: u32,
+    pub size: u32,
+    pub bytes_allocated: u32,
+    pub objects_allocated: u32,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct AllocFrame {
+    pub class: String,
+    pub method: String,
+    pub file: String,
+    /// -1 without line information, -2 for native methods.
+    pub line: i16,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Allocation {
+    pub size: u32,
+    pub thread: u16,
+    pub class: String,
+    pub frames: Vec<AllocFrame>,
+}
+
+/// A chunk payload, decoded.
+#[derive(Debug,Clone,PartialEq)]
+pub enum Message {
+    Hello {
+        protocol: u32,
+        pid: u32,
+        vm: String,
+        app: String,
+        user: Option<u32>,
+        abi: Option<String>,
+        jvm_flags: Option<String>,
+        native_debuggable: Option<bool>,
+        package: Option<String>,
+    },
+    Features(Vec<String>),
+    AppName { app: String, user: Option<u32>, package: Option<String> },
+    /// The app is waiting for a debugger.
+    Wait { reason: u8 },
+    Threads(Vec<ThreadInfo>),
+    ThreadCreated { thread: u32, name: String },
+    ThreadDied { thread: u32 },
+    HeapInfo(Vec<HeapInfo>),
+    /// `runs` are (state, length - 1) pairs in allocation units.
+    HeapSegment { heap: u32, unit_size: u8, start: u32, offset: u32, units: u32, runs: Vec<(u8, u8)> },
+    AllocTracking(bool),
+    Allocations(Vec<Allocation>),
+    /// 0 when idle, 1 when profiling to a file, 2 when streaming.
+    ProfilingState(u8),
+    ProfileData(Vec<u8>),
+    /// 0 if the VM managed to write the heap dump file.
+    HeapDumpResult(u8),
+    /// A streamed HPROF heap dump.
+    HeapDump(Vec<u8>),
+    Fail { code: u32, message: String },
+    /// An acknowledgement with no payload.
+    Empty(u32),
+    Unknown(Chunk),
+}
+
+fn decode_allocations(data: &[u8]) -> jdwp::Result<Vec<Allocation>> {
+    let mut r = Reader { data: data };
+    let header_len = r.u8()? as usize;
+    let entry_len = r.u8()? as usize;
+    let frame_len = r.u8()? as usize;
+    let count = r.u16()?;
+    let strings = r.u32()? as usize;
+    let class_names = r.u16()?;
+    let method_names = r.u16()?;
+    let file_names = r.u16()?;
+    if header_len < 15 || entry_len < 9 || frame_len < 8 || strings > data.len() {
+        return Err(jdwp::Error::InvalidLength);
+    }
+    let mut table = Reader { data: &data[strings..] };
+    let mut read_table = |n: u16| -> jdwp::Result<Vec<String>> {
+        return (0..n).map(|_| table.string()).collect();
+    };
+    let class_names = read_table(class_names)?;
+    let method_names = read_table(method_names)?;
+    let file_names = read_table(file_names)?;
+    let lookup = |table: &[String], i: u16| -> jdwp::Result<String> {
+        return table.get(i as usize).cloned().ok_or(jdwp::Error::InvalidIndex);
+    };
+
+    let mut r = Reader { data: &data[..strings] };
+    r.bytes(header_len)?;
+    let mut allocations = Vec::new();
+    for _ in 0..count {
+        let mut entry = Reader { data: r.bytes(entry_len)? };
+        let size = entry.u32()?;
+        let thread = entry.u16()?;
+        let class = lookup(&class_names, entry.u16()?)?;
+        let depth = entry.u8()?;
+        let mut frames = Vec::new();
+        for _ in 0..depth {
+            let mut frame = Reader { data: r.bytes(frame_len)? };
+            frames.push(AllocFrame {
+                class: lookup(&class_names, frame.u16()?)?,
+                method: lookup(&method_names, frame.u16()?)?,
+                file: lookup(&file_names, frame.u16()?)?,
+                line: frame.u16()? as i16,
+            });
+        }
+        allocations.push(Allocation { size: size, thread: thread, class: class, frames: frames });
+    }
+    return Ok(allocations);
+}
+
+pub fn decode(chunk: &Chunk) -> jdwp::Result<Message> {
+    let mut r = Reader { data: &chunk.data };
+    if r.is_empty() && chunk.kind != FEAT && chunk.kind != REAL && chunk.kind != MPSE {
+        return Ok(Message::Empty(chunk.kind));
+    }
+    return Ok(match chunk.kind {
+        HELO => {
+            let protocol = r.u32()?;
+            let pid = r.u32()?;
+            let vm_len = r.u32()? as usize;
+            let app_len = r.u32()? as usize;
+            let vm = r.utf16(vm_len)?;
+            let app = r.utf16(app_len)?;
+            // Newer VMs keep appending fields, so each one is optional.
+            let user = if r.is_empty() { None } else { Some(r.u32()?) };
+            let abi = if r.is_empty() { None } else { Some(r.string()?) };
+            let jvm_flags = if r.is_empty() { None } else { Some(r.string()?) };
+            let native_debuggable = if r.is_empty() { None } else { Some(r.u8()? != 0) };
+            let package = if r.is_empty() { None } else { Some(r.string()?) };
+            Message::Hello {
+                protocol: protocol,
+                pid: pid,
+                vm: vm,
+                app: app,
+                user: user,
+                abi: abi,
+                jvm_flags: jvm_flags,
+                native_debuggable: native_debuggable,
+                package: package,
+            }
+        },
+        FEAT => {
+            let count = if r.is_empty() { 0 } else { r.u32()? };
+            Message::Features((0..count).map(|_| r.string()).collect::<jdwp::Result<_>>()?)
+        },
+        APNM => {
+            let app = r.string()?;
+            let user = if r.is_empty() { None } else { Some(r.u32()?) };
+            let package = if r.is_empty() { None } else { Some(r.string()?) };
+            Message::AppName { app: app, user: user, package: package }
+        },
+        WAIT => Message::Wait { reason: r.u8()? },
+        THST => {
+            let header_len = r.u8()? as usize;
+            let entry_len = r.u8()? as usize;
+            let count = r.u16()?;
+            if header_len < 4 || entry_len < 17 {
+                return Err(jdwp::Error::InvalidLength);
+            }
+            r.bytes(header_len - 4)?;
+            let mut threads = Vec::new();
+            for _ in 0..count {
+                let mut e = Reader { data: r.bytes(entry_len)? };
+                threads.push(ThreadInfo {
+                    thread: e.u32()?,
+                    status: e.u8()?,
+                    tid: e.u32()?,
+                    utime: e.u32()?,
+                    stime: e.u32()?,
+                    daemon: if e.is_empty() { None } else { Some(e.u8()? != 0) },
+                });
+            }
+            Message::Threads(threads)
+        },
+        THCR => Message::ThreadCreated { thread: r.u32()?, name: r.string()? },
+        THDE => Message::ThreadDied { thread: r.u32()? },
+        HPIF => {
+            let count = r.u32()?;
+            let mut heaps = Vec::new();
+            for _ in 0..count {
+                heaps.push(HeapInfo {
+                    heap: r.u32()?,
+                    timestamp: r.u64()?,
+                    reason: r.u8()?,
+                    max_size: r.u32()?,
+                    size: r.u32()?,
+                    bytes_allocated: r.u32()?,
+                    objects_allocated: r.u32()?,
+                });
+            }
+            Message::HeapInfo(heaps)
+        },
+        HPSG => {
+            let heap = r.u32()?;
+            let unit_size = r.u8()?;
+            let start = r.u32()?;
+            let offset = r.u32()?;
+            let units = r.u32()?;
+            let mut runs = Vec::new();
+            while !r.is_empty() {
+                runs.push((r.u8()?, r.u8()?));
+            }
+            Message::HeapSegment { heap: heap, unit_size: unit_size, start: start, offset: offset, units: units, runs: runs }
+        },
+        REAQ => Message::AllocTracking(r.u8()? != 0),
+        REAL => {
+            if r.is_empty() {
+                Message::Allocations(Vec::new())
+            } else {
+                Message::Allocations(decode_allocations(&chunk.data)?)
+            }
+        },
+        MPRQ => Message::ProfilingState(r.u8()?),
+        MPSE => Message::ProfileData(chunk.data.clone()),
+        HPDU => Message::HeapDumpResult(r.u8()?),
+        HPDS => Message::HeapDump(chunk.data.clone()),
+        FAIL => Message::Fail { code: r.u32()?, message: r.string()? },
+        _ => Message::Unknown(chunk.clone()),
+    });
+}
+
+pub fn hello() -> Chunk {
+    return Chunk::new(HELO, PROTOCOL_VERSION.to_be_bytes().to_vec());
+}
+
+pub fn features() -> Chunk {
+    return Chunk::new(FEAT, Vec::new());
+}
+
+/// Turns THCR/THDE notifications on or off.
+pub fn thread_notifications(enable: bool) -> Chunk {
+    return Chunk::new(THEN, vec![enable as u8]);
+}
+
+pub fn thread_status() -> Chunk {
+    return Chunk::new(THST, Vec::new());
+}
+
+pub fn heap_info(when: u8) -> Chunk {
+    return Chunk::new(HPIF, vec![when]);
+}
+
+pub fn heap_segments(when: u8, what: u8) -> Chunk {
+    return Chunk::new(HPSG, vec![when, what]);
+}
+
+pub fn alloc_tracking(enable: bool) -> Chunk {
+    return Chunk::new(REAE, vec![enable as u8]);
+}
+
+pub fn alloc_query() -> Chunk {
+    return Chunk::new(REAQ, Vec::new());
+}
+
+pub fn alloc_records() -> Chunk {
+    return Chunk::new(REAL, Vec::new());
+}
+
+/// Starts method tracing into `file` on the device.
+pub fn start_profiling(file: &str, buffer_size: u32, flags: u32) -> Chunk {
+    let mut data = Vec::new();
+    data.extend_from_slice(&buffer_size.to_be_bytes());
+    data.extend_from_slice(&flags.to_be_bytes());
+    put_string(&mut data, file);
+    return Chunk::new(MPRS, data);
+}
+
+pub fn stop_profiling() -> Chunk {
+    return Chunk::new(MPRE, Vec::new());
+}
+
+/// Starts method tracing with the trace sent back over DDM.
+pub fn start_streaming(buffer_size: u32, flags: u32) -> Chunk {
+    let mut data = Vec::new();
+    data.extend_from_slice(&buffer_size.to_be_bytes());
+    data.extend_from_slice(&flags.to_be_bytes());
+    return Chunk::new(MPSS, data);
+}
+
+pub fn stop_streaming() -> Chunk {
+    return Chunk::new(MPSE, Vec::new());
+}
+
+pub fn profiling_state() -> Chunk {
+    return Chunk::new(MPRQ, Vec::new());
+}
+
+/// Makes the VM write an HPROF heap dump to `file` on the device.
+pub fn heap_dump_file(file: &str) -> Chunk {
+    let mut data = Vec::new();
+    put_string(&mut data, file);
+    return Chunk::new(HPDU, data);
+}
+
+/// Makes the VM send an HPROF heap dump back in an HPDS chunk.
+pub fn heap_dump_stream() -> Chunk {
+    return Chunk::new(HPDS, Vec::new());
+}
+
+pub fn exit(status: i32) -> Chunk {
+    return Chunk::new(EXIT, status.to_be_bytes().to_vec());
+}
@@ -0,0 +1,1168 @@
+//! A small Java-like expression language, evaluated against a suspended
+//! frame over JDWP. Used by `print`, `set var` and breakpoint conditions.
+use crate::{Result,Error};
+use crate::client::Client;
+use crate::handles;
+use crate::jdwp::{self,Command,Reply,Tag};
+use crate::lookup::{class_name,reference_type,signature};
+use crate::request::{self,Request};
+use crate::signature;
+
+/// Java's ACC_STATIC modifier bit.
+const ACC_STATIC: i32 = 0x0008;
+
+/// How many array elements `format_value` shows before eliding the rest.
+const ARRAY_PREVIEW: i32 = 10;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Expr {
+    /// Primitives and null.
+    Literal(Tag),
+    Str(String),
+    /// A local, a field of `this`, `this` itself, or a class name.
+    Name(String),
+    Field(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    /// A method call, on `this` or the current class when there is no target.
+    Call(Option<Box<Expr>>, String, Vec<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    InstanceOf(Box<Expr>, String),
+}
+
+fn error<T>(msg: String) -> Result<T> {
+    return Err(Error::Eval(msg));
+}
+
+#[derive(Debug,Clone,PartialEq)]
+enum Token {
+    Ident(String),
+    /// Kept as written until the parser knows whether a minus applies, since
+    /// `2147483648` is only an int as `-2147483648`.
+    Number(String),
+    Literal(Tag),
+    Str(String),
+    Op(&'static str),
+}
+
+const OPS: [&str; 21] = [
+    "&&", "||", "==", "!=", "<=", ">=",
+    ".", ",", "(", ")", "[", "]", "+", "-", "*", "/", "%", "!", "<", ">", "=",
+];
+
+fn escape(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<char> {
+    return Ok(match chars.next() {
+        Some('n') => '\n',
+        Some('t') => '\t',
+        Some('r') => '\r',
+        Some('b') => '\u{8}',
+        Some('f') => '\u{c}',
+        Some('0') => '\0',
+        Some('\\') => '\\',
+        Some('\'') => '\'',
+        Some('"') => '"',
+        Some('u') => {
+            let hex: String = (0..4).filter_map(|_| chars.next()).collect();
+            let code = u32::from_str_radix(&hex, 16).map_err(|_| Error::Eval(format!("bad escape \\u{}", hex)))?;
+            char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+        },
+        c => { return error(format!("bad escape \\{}", c.map(String::from).unwrap_or_default())); },
+    });
+}
+
+/// The literal `text`, negated first if a minus sign precedes it. Decimal
+/// magnitudes are range-checked after negating so the minimum values parse.
+fn number(text: &str, negative: bool) -> Result<Tag> {
+    let bad = || Error::Eval(format!("bad number {}{}", if negative { "-" } else { "" }, text));
+    let clean = text.replace('_', "");
+    let lower = clean.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        // Hex literals are bit patterns, and negating one wraps like Java.
+        return Ok(match hex.strip_suffix('l') {
+            Some(hex) => {
+                let bits = u64::from_str_radix(hex, 16).map_err(|_| bad())? as i64;
+                Tag::Long(if negative { bits.wrapping_neg() } else { bits })
+            },
+            None => {
+                let bits = u32::from_str_radix(hex, 16).map_err(|_| bad())? as i32;
+                Tag::Int(if negative { bits.wrapping_neg() } else { bits })
+            },
+        });
+    }
+    let signed = |magnitude: &str| -> Result<i128> {
+        let magnitude: u64 = magnitude.parse().map_err(|_| bad())?;
+        return Ok(if negative { -(magnitude as i128) } else { magnitude as i128 });
+    };
+    if let Some(n) = lower.strip_suffix('l') {
+        return Ok(Tag::Long(i64::try_from(signed(n)?).map_err(|_| bad())?));
+    }
+    let sign = if negative { -1.0 } else { 1.0 };
+    if let Some(n) = lower.strip_suffix('f') {
+        return Ok(Tag::Float(sign as f32 * n.parse::<f32>().map_err(|_| bad())?));
+    }
+    if let Some(n) = lower.strip_suffix('d') {
+        return Ok(Tag::Double(sign * n.parse::<f64>().map_err(|_| bad())?));
+    }
+    if lower.contains('.') || lower.contains('e') {
+        return Ok(Tag::Double(sign * lower.parse::<f64>().map_err(|_| bad())?));
+    }
+    return Ok(Tag::Int(i32::try_from(signed(&lower)?).map_err(|_| bad())?));
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() {
+            let mut lexeme = String::new();
+            while let Some(&c) = chars.peek() {
+                let exponent = matches!(lexeme.chars().last(), Some('e') | Some('E')) && !lexeme.starts_with("0x");
+                if c.is_ascii_alphanumeric() || c == '_' || c == '.' || (exponent && (c == '-' || c == '+')) {
+                    lexeme.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            tokens.push(Token::Number(lexeme));
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            let mut ident = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_alphanumeric() || c == '_' || c == '$' {
+                    ident.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            tokens.push(Token::Ident(ident));
+        } else if c == '"' {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => s.push(escape(&mut chars)?),
+                    Some(c) => s.push(c),
+                    None => { return error("unterminated string".to_string()); },
+                }
+            }
+            tokens.push(Token::Str(s));
+        } else if c == '\'' {
+            chars.next();
+            let value = match chars.next() {
+                Some('\\') => escape(&mut chars)?,
+                Some(c) => c,
+                None => { return error("unterminated character".to_string()); },
+            };
+            if chars.next() != Some('\'') {
+                return error("unterminated character".to_string());
+            }
+            let mut utf16 = [0u16; 2];
+            tokens.push(Token::Literal(Tag::Char(value.encode_utf16(&mut utf16)[0])));
+        } else {
+            let rest: String = chars.clone().take(2).collect();
+            let op = match OPS.iter().find(|op| rest.starts_with(*op)) {
+                Some(op) => *op,
+                None => { return error(format!("unexpected {}", c)); },
+            };
+            for _ in 0..op.len() {
+                chars.next();
+            }
+            tokens.push(Token::Op(op));
+        }
+    }
+    return Ok(tokens);
+}
+
+/// How deep parentheses, unary operators and calls may nest, so a long run
+/// of `(` or `!` in a condition is an error rather than a stack overflow.
+const MAX_DEPTH: usize = 64;
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Unary expressions being parsed, which every level of nesting goes through.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        return self.tokens.get(self.pos);
+    }
+    fn eat(&mut self, op: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Op(o)) if *o == op) {
+            self.pos += 1;
+            return true;
+        }
+        return false;
+    }
+    fn eat_ident(&mut self, word: &str) -> bool {
+        if let Some(Token::Ident(i)) = self.peek() {
+            if i == word {
+                self.pos += 1;
+                return true;
+            }
+        }
+        return false;
+    }
+    fn expect(&mut self, op: &str) -> Result<()> {
+        if !self.eat(op) {
+            return error(format!("expected {} {}", op, self.position()));
+        }
+        return Ok(());
+    }
+    fn ident(&mut self) -> Result<String> {
+        if let Some(Token::Ident(i)) = self.peek() {
+            let i = i.clone();
+            self.pos += 1;
+            return Ok(i);
+        }
+        return error(format!("expected a name {}", self.position()));
+    }
+    fn position(&self) -> String {
+        return match self.peek() {
+            None => "at end of expression".to_string(),
+            Some(Token::Ident(i)) => format!("before {}", i),
+            Some(Token::Number(n)) => format!("before {}", n),
+            Some(Token::Op(op)) => format!("before {}", op),
+            Some(Token::Str(s)) => format!("before {:?}", s),
+            Some(Token::Literal(t)) => format!("before {:?}", t),
+        };
+    }
+    fn binary<F: Fn(&mut Parser) -> Result<Expr>>(&mut self, ops: &[(&str, BinaryOp)], next: F) -> Result<Expr> {
+        let mut left = next(self)?;
+        'outer: loop {
+            for (text, op) in ops {
+                if self.eat(text) {
+                    let right = next(self)?;
+                    left = Expr::Binary(*op, Box::new(left), Box::new(right));
+                    continue 'outer;
+                }
+            }
+            return Ok(left);
+        }
+    }
+    fn expr(&mut self) -> Result<Expr> {
+        return self.binary(&[("||", BinaryOp::Or)], |p| {
+            p.binary(&[("&&", BinaryOp::And)], |p| {
+                p.binary(&[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)], Parser::relational)
+            })
+        });
+    }
+    fn relational(&mut self) -> Result<Expr> {
+        let mut left = self.additive()?;
+        loop {
+            let op = if self.eat("<=") {
+                BinaryOp::Le
+            } else if self.eat(">=") {
+                BinaryOp::Ge
+            } else if self.eat("<") {
+                BinaryOp::Lt
+            } else if self.eat(">") {
+                BinaryOp::Gt
+            } else if self.eat_ident("instanceof") {
+                left = Expr::InstanceOf(Box::new(left), self.type_name()?);
+                continue;
+            } else {
+                return Ok(left);
+            };
+            left = Expr::Binary(op, Box::new(left), Box::new(self.additive()?));
+        }
+    }
+    fn additive(&mut self) -> Result<Expr> {
+        return self.binary(&[("+", BinaryOp::Add), ("-", BinaryOp::Sub)], |p| {
+            p.binary(&[("*", BinaryOp::Mul), ("/", BinaryOp::Div), ("%", BinaryOp::Rem)], Parser::unary)
+        });
+    }
+    fn unary(&mut self) -> Result<Expr> {
+        if self.depth >= MAX_DEPTH {
+            return Err(Error::Command(format!("expression nested more than {} deep", MAX_DEPTH)));
+        }
+        self.depth += 1;
+        let expr = self.unary_inner();
+        self.depth -= 1;
+        return expr;
+    }
+    fn unary_inner(&mut self) -> Result<Expr> {
+        if self.eat("-") {
+            if let Some(Token::Number(n)) = self.peek().cloned() {
+                self.pos += 1;
+                return Ok(Expr::Literal(number(&n, true)?));
+            }
+            return Ok(match self.unary()? {
+                Expr::Literal(Tag::Int(i)) => Expr::Literal(Tag::Int(i.wrapping_neg())),
+                Expr::Literal(Tag::Long(l)) => Expr::Literal(Tag::Long(l.wrapping_neg())),
+                e => Expr::Unary(UnaryOp::Neg, Box::new(e)),
+            });
+        }
+        if self.eat("!") {
+            return Ok(Expr::Unary(UnaryOp::Not, Box::new(self.unary()?)));
+        }
+        if self.eat("+") {
+            return self.unary();
+        }
+        return self.postfix();
+    }
+    fn args(&mut self) -> Result<Vec<Expr>> {
+        let mut args = Vec::new();
+        if self.eat(")") {
+            return Ok(args);
+        }
+        loop {
+            args.push(self.expr()?);
+            if self.eat(")") {
+                return Ok(args);
+            }
+            self.expect(",")?;
+        }
+    }
+    fn postfix(&mut self) -> Result<Expr> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.eat(".") {
+                let name = self.ident()?;
+                expr = if self.eat("(") {
+                    Expr::Call(Some(Box::new(expr)), name, self.args()?)
+                } else {
+                    Expr::Field(Box::new(expr), name)
+                };
+            } else if self.eat("[") {
+                let index = self.expr()?;
+                self.expect("]")?;
+                expr = Expr::Index(Box::new(expr), Box::new(index));
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+    fn primary(&mut self) -> Result<Expr> {
+        let token = match self.peek() {
+            Some(t) => t.clone(),
+            None => { return error("unexpected end of expression".to_string()); },
+        };
+        self.pos += 1;
+        return Ok(match token {
+            Token::Number(n) => Expr::Literal(number(&n, false)?),
+            Token::Literal(t) => Expr::Literal(t),
+            Token::Str(s) => Expr::Str(s),
+            Token::Op("(") => {
+                let expr = self.expr()?;
+                self.expect(")")?;
+                expr
+            },
+            Token::Ident(i) => match i.as_str() {
+                "true" => Expr::Literal(Tag::Boolean(true)),
+                "false" => Expr::Literal(Tag::Boolean(false)),
+                "null" => Expr::Literal(Tag::Object(0)),
+                _ if self.eat("(") => Expr::Call(None, i, self.args()?),
+                _ => Expr::Name(i),
+            },
+            Token::Op(op) => { return error(format!("unexpected {}", op)); },
+        });
+    }
+    /// A dotted class name with optional `[]` suffixes.
+    fn type_name(&mut self) -> Result<String> {
+        let mut name = self.ident()?;
+        while self.eat(".") {
+            name.push('.');
+            name.push_str(&self.ident()?);
+        }
+        while self.eat("[") {
+            self.expect("]")?;
+            name.push_str("[]");
+        }
+        return Ok(name);
+    }
+    fn finish(&self) -> Result<()> {
+        if self.pos < self.tokens.len() {
+            return error(format!("unexpected input {}", self.position()));
+        }
+        return Ok(());
+    }
+}
+
+pub fn parse(text: &str) -> Result<Expr> {
+    let mut parser = Parser { tokens: tokenize(text)?, pos: 0, depth: 0 };
+    let expr = parser.expr()?;
+    parser.finish()?;
+    return Ok(expr);
+}
+
+/// Parses `LVALUE = EXPR` for `set var`.
+pub fn parse_assignment(text: &str) -> Result<(Expr, Expr)> {
+    let mut parser = Parser { tokens: tokenize(text)?, pos: 0, depth: 0 };
+    let target = parser.postfix()?;
+    if !matches!(target, Expr::Name(_) | Expr::Field(..) | Expr::Index(..)) {
+        return error("can only assign to a variable, field or array element".to_string());
+    }
+    parser.expect("=")?;
+    let value = parser.expr()?;
+    parser.finish()?;
+    return Ok((target, value));
+}
+
+/// `Ljava/lang/String;` as `java.lang.String`, `[I` as `int[]`. Anything
+/// that doesn't parse is shown as it is.
+pub fn type_name(signature: &str) -> String {
+    if signature == "V" {
+        return "void".to_string();
+    }
+    return match signature::parse_type(signature) {
+        Some(parsed) => parsed.java(),
+        None => signature.to_string(),
+    };
+}
+
+/// The inverse of `type_name`.
+pub fn class_signature(name: &str) -> String {
+    if let Some(element) = name.strip_suffix("[]") {
+        return format!("[{}", class_signature(element));
+    }
+    return match name {
+        "boolean" => "Z",
+        "byte" => "B",
+        "char" => "C",
+        "short" => "S",
+        "int" => "I",
+        "long" => "J",
+        "float" => "F",
+        "double" => "D",
+        "void" => "V",
+        _ => { return format!("L{};", name.replace('.', "/")); },
+    }.to_string();
+}
+
+/// The tag StackFrame.GetValues wants for a variable with this signature.
+fn signature_tag(signature: &str) -> u8 {
+    return signature.as_bytes().first().cloned().unwrap_or(b'L');
+}
+
+fn is_reference(value: &Tag) -> bool {
+    return value.object_id().is_some();
+}
+
+/// Numbers after Java's binary numeric promotion.
+#[derive(Debug,Clone,Copy)]
+enum Num {
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+}
+
+impl Num {
+    fn of(value: &Tag) -> Option<Num> {
+        return Some(match value {
+            Tag::Byte(b) => Num::Int(*b as i8 as i32),
+            Tag::Short(s) => Num::Int(*s as i32),
+            Tag::Char(c) => Num::Int(*c as i32),
+            Tag::Int(i) => Num::Int(*i),
+            Tag::Long(l) => Num::Long(*l),
+            Tag::Float(f) => Num::Float(*f),
+            Tag::Double(d) => Num::Double(*d),
+            _ => { return None; },
+        });
+    }
+    fn rank(&self) -> u8 {
+        return match self {
+            Num::Int(_) => 0,
+            Num::Long(_) => 1,
+            Num::Float(_) => 2,
+            Num::Double(_) => 3,
+        };
+    }
+    fn long(&self) -> i64 {
+        return match self {
+            Num::Int(i) => *i as i64,
+            Num::Long(l) => *l,
+            Num::Float(f) => *f as i64,
+            Num::Double(d) => *d as i64,
+        };
+    }
+    fn double(&self) -> f64 {
+        return match self {
+            Num::Int(i) => *i as f64,
+            Num::Long(l) => *l as f64,
+            Num::Float(f) => *f as f64,
+            Num::Double(d) => *d,
+        };
+    }
+}
+
+/// Converts a value for storing into something declared as `signature`.
+/// Numbers convert like a Java cast; everything else must already match.
+fn cast(value: &Tag, signature: &str) -> Result<Tag> {
+    let num = Num::of(value);
+    let converted = match (signature_tag(signature), num) {
+        (b'B', Some(n)) => Some(Tag::Byte(n.long() as u8)),
+        (b'C', Some(n)) => Some(Tag::Char(n.long() as u16)),
+        (b'S', Some(n)) => Some(Tag::Short(n.long() as i16)),
+        (b'I', Some(n)) => Some(Tag::Int(n.long() as i32)),
+        (b'J', Some(n)) => Some(Tag::Long(n.long())),
+        (b'F', Some(n)) => Some(Tag::Float(n.double() as f32)),
+        (b'D', Some(n)) => Some(Tag::Double(n.double())),
+        (b'Z', _) => match value {
+            Tag::Boolean(_) => Some(*value),
+            _ => None,
+        },
+        (b'L', _) | (b'[', _) if is_reference(value) => Some(*value),
+        _ => None,
+    };
+    return converted.ok_or_else(|| Error::Eval(format!("can't convert {:?} to {}", value, type_name(signature))));
+}
+
+/// Like `cast`, but only allows the conversions a Java method call would.
+fn widen(value: &Tag, signature: &str) -> Option<Tag> {
+    let target = signature_tag(signature);
+    let allowed = match (value.tag(), target) {
+        (a, b) if a == b => true,
+        (b'B', b'S') | (b'B', b'I') | (b'S', b'I') | (b'C', b'I') => true,
+        (b'B' | b'S' | b'C' | b'I', b'J' | b'F' | b'D') => true,
+        (b'J', b'F' | b'D') | (b'F', b'D') => true,
+        (_, b'L') | (_, b'[') => is_reference(value),
+        _ => false,
+    };
+    return if allowed { cast(value, signature).ok() } else { None };
+}
+
+/// `value` passed as a parameter of type `param`. A plain object reference
+/// takes the declared type's tag, so a String parameter gets a string.
+fn argument(value: &Tag, param: &signature::Type) -> Option<Tag> {
+    let widened = widen(value, &param.jni())?;
+    return match widened {
+        Tag::Object(id) => signature::reference(param.tag(), id).or(Some(widened)),
+        _ => Some(widened),
+    };
+}
+
+/// Java's Double.toString for the common cases.
+fn java_double(d: f64) -> String {
+    if d.is_nan() {
+        return "NaN".to_string();
+    }
+    if d.is_infinite() {
+        return if d > 0.0 { "Infinity" } else { "-Infinity" }.to_string();
+    }
+    if d.fract() == 0.0 && d.abs() < 1e7 {
+        return format!("{:.1}", d);
+    }
+    return format!("{}", d);
+}
+
+/// A primitive as Java's String.valueOf would print it.
+fn primitive_text(value: &Tag) -> Option<String> {
+    return Some(match value {
+        Tag::Boolean(b) => b.to_string(),
+        Tag::Byte(b) => (*b as i8).to_string(),
+        Tag::Char(c) => char::from_u32(*c as u32).unwrap_or(char::REPLACEMENT_CHARACTER).to_string(),
+        Tag::Short(s) => s.to_string(),
+        Tag::Int(i) => i.to_string(),
+        Tag::Long(l) => l.to_string(),
+        Tag::Float(f) => java_double(*f as f64),
+        Tag::Double(d) => java_double(*d),
+        Tag::Void => "void".to_string(),
+        _ => { return None; },
+    });
+}
+
+/// The one value a get-values command asked for.
+fn single(values: Vec<Tag>) -> Result<Tag> {
+    return match values.as_slice() {
+        [value] => Ok(*value),
+        _ => Err(Error::unexpected(Reply::Values(values))),
+    };
+}
+
+fn string_value(client: &Client, string: u64) -> Result<String> {
+    return Ok(client.request(request::string_reference_value(string))?.text);
+}
+
+/// Renders a value for the user: strings quoted, objects as `Type@id`, and
+/// the first few elements of arrays.
+pub fn format_value(client: &Client, value: &Tag) -> Result<String> {
+    if let Some(text) = primitive_text(value) {
+        return Ok(match value {
+            Tag::Char(_) => format!("{:?}", text.chars().next().unwrap()),
+            _ => text,
+        });
+    }
+    let id = value.object_id().unwrap_or(0);
+    if id == 0 {
+        return Ok("null".to_string());
+    }
+    if let Tag::String(_) = value {
+        return Ok(format!("{:?}", string_value(client, id)?));
+    }
+    let ty = class_name(client, reference_type(client, id)?)?;
+    if let Tag::Array(_) = value {
+        let length = client.request(request::array_reference_length(id))?;
+        let shown = std::cmp::min(length, ARRAY_PREVIEW);
+        let values = client.request(request::array_reference_get_values(id, 0, shown))?;
+        let mut elements = Vec::new();
+        for v in values.iter() {
+            elements.push(match v {
+                Tag::String(_) => format_value(client, v)?,
+                v if is_reference(v) && v.object_id() != Some(0) => format!("@{:#x}", v.object_id().unwrap()),
+                v => format_value(client, v)?,
+            });
+        }
+        if length > shown {
+            elements.push("...".to_string());
+        }
+        let element_type = ty.strip_suffix("[]").unwrap_or(&ty);
+        return Ok(format!("{}[{}] {{{}}}", element_type, length, elements.join(", ")));
+    }
+    return Ok(format!("{}@{:#x}", ty, id));
+}
+
+/// What a name or dotted path resolved to.
+enum Place {
+    Value(Tag),
+    Type(u64),
+}
+
+/// Evaluates expressions in one frame of a suspended thread, or with no
+/// frame at all, in which case only static members are reachable.
+pub struct Evaluator<'a> {
+    client: &'a Client,
+    thread: u64,
+    frame: Option<jdwp::FrameInfo>,
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(client: &'a Client) -> Evaluator<'a> {
+        return Evaluator { client: client, thread: 0, frame: None };
+    }
+    pub fn in_frame(client: &'a Client, thread: u64, frame: jdwp::FrameInfo) -> Evaluator<'a> {
+        return Evaluator { client: client, thread: thread, frame: Some(frame) };
+    }
+    fn request<T>(&self, request: Request<T>) -> Result<T> {
+        return self.client.request(request);
+    }
+    /// For the commands with an empty reply.
+    fn send(&self, cmd: Command) -> Result<()> {
+        self.client.send_and_wait(&cmd)?;
+        return Ok(());
+    }
+    pub fn format(&self, value: &Tag) -> Result<String> {
+        return format_value(self.client, value);
+    }
+    pub fn evaluate(&self, expr: &Expr) -> Result<Tag> {
+        return match expr {
+            Expr::Literal(t) => Ok(*t),
+            Expr::Str(s) => self.create_string(s),
+            Expr::Name(_) | Expr::Field(..) => match self.place(expr)? {
+                Place::Value(v) => Ok(v),
+                Place::Type(class) => error(format!("{} is a type, not a value", class_name(self.client, class)?)),
+            },
+            Expr::Index(array, index) => {
+                let array = self.array(&self.evaluate(array)?)?;
+                let index = self.index(&self.evaluate(index)?)?;
+                self.bounds_check(array, index)?;
+                single(self.request(request::array_reference_get_values(array, index, 1))?)
+            },
+            Expr::Call(target, name, args) => {
+                let args = args.iter().map(|a| self.evaluate(a)).collect::<Result<Vec<_>>>()?;
+                let place = match target {
+                    Some(target) => self.place(target)?,
+                    None => match self.this_object()? {
+                        Some(this) => Place::Value(Tag::Object(this)),
+                        None => Place::Type(self.frame_class()?),
+                    },
+                };
+                match place {
+                    Place::Type(class) => self.invoke(None, class, name, &args),
+                    Place::Value(v) => {
+                        let object = self.non_null(&v, name)?;
+                        self.invoke(Some(object), reference_type(self.client, object)?, name, &args)
+                    },
+                }
+            },
+            Expr::Unary(UnaryOp::Not, e) => Ok(Tag::Boolean(!self.boolean(&self.evaluate(e)?)?)),
+            Expr::Unary(UnaryOp::Neg, e) => {
+                let value = self.evaluate(e)?;
+                Ok(match Num::of(&value) {
+                    Some(Num::Int(i)) => Tag::Int(i.wrapping_neg()),
+                    Some(Num::Long(l)) => Tag::Long(l.wrapping_neg()),
+                    Some(Num::Float(f)) => Tag::Float(-f),
+                    Some(Num::Double(d)) => Tag::Double(-d),
+                    None => { return error(format!("can't negate {}", self.format(&value)?)); },
+                })
+            },
+            Expr::Binary(BinaryOp::And, a, b) => {
+                Ok(Tag::Boolean(self.boolean(&self.evaluate(a)?)? && self.boolean(&self.evaluate(b)?)?))
+            },
+            Expr::Binary(BinaryOp::Or, a, b) => {
+                Ok(Tag::Boolean(self.boolean(&self.evaluate(a)?)? || self.boolean(&self.evaluate(b)?)?))
+            },
+            Expr::Binary(op, a, b) => {
+                let a = self.evaluate(a)?;
+                let b = self.evaluate(b)?;
+                self.binary(*op, &a, &b)
+            },
+            Expr::InstanceOf(e, ty) => {
+                let id = match self.evaluate(e)?.object_id() {
+                    Some(id) => id,
+                    None => { return error("instanceof needs an object".to_string()); },
+                };
+                Ok(Tag::Boolean(id != 0 && self.is_instance(reference_type(self.client, id)?, ty)?))
+            },
+        };
+    }
+    /// Evaluates a condition, which must come out as a boolean.
+    pub fn test(&self, expr: &Expr) -> Result<bool> {
+        return self.boolean(&self.evaluate(expr)?);
+    }
+    /// Stores `value` into a local, field or array element and returns what
+    /// was stored after conversion.
+    pub fn assign(&self, target: &Expr, value: &Expr) -> Result<Tag> {
+        let value = self.evaluate(value)?;
+        return match target {
+            Expr::Name(name) if name != "this" => {
+                if let Some(var) = self.local(name)? {
+                    let value = cast(&value, &var.signature)?;
+                    let frame = self.frame.unwrap().frame_id;
+                    self.send(Command::StackFrameSetValues { thread: self.thread, frame: frame, values: vec![(var.slot, value)] })?;
+                    return Ok(value);
+                }
+                match self.this_object()? {
+                    Some(this) => self.set_field(Some(this), reference_type(self.client, this)?, name, &value),
+                    None => self.set_field(None, self.frame_class()?, name, &value),
+                }
+            },
+            Expr::Field(inner, name) => match self.place(inner)? {
+                Place::Type(class) => self.set_field(None, class, name, &value),
+                Place::Value(v) => {
+                    let object = self.non_null(&v, name)?;
+                    self.set_field(Some(object), reference_type(self.client, object)?, name, &value)
+                },
+            },
+            Expr::Index(array, index) => {
+                let array = self.array(&self.evaluate(array)?)?;
+                let index = self.index(&self.evaluate(index)?)?;
+                self.bounds_check(array, index)?;
+                let element = signature(self.client, reference_type(self.client, array)?)?;
+                let value = cast(&value, &element[1..])?;
+                self.send(Command::ArrayReferenceSetValues { array: array, first: index, values: vec![value] })?;
+                Ok(value)
+            },
+            _ => error("can only assign to a variable, field or array element".to_string()),
+        };
+    }
+    /// The value for returning early from a method with this signature,
+    /// checked the way javac checks a return statement.
+    pub fn return_value(&self, expr: Option<&Expr>, signature: &str) -> Result<Tag> {
+        let returns = signature.rsplit(')').next().unwrap_or("V");
+        let value = match (expr, returns) {
+            (None, "V") => { return Ok(Tag::Void); },
+            (Some(_), "V") => { return error("the method is void, so there's nothing to return".to_string()); },
+            (None, _) => { return error(format!("the method returns {}, so give a value", type_name(returns))); },
+            (Some(expr), _) => self.evaluate(expr)?,
+        };
+        let converted = match (value, signature_tag(returns)) {
+            // Int constants narrow, as in `byte b = 1;`.
+            (Tag::Int(i), b'B') if i8::try_from(i).is_ok() => Some(Tag::Byte(i as u8)),
+            (Tag::Int(i), b'S') if i16::try_from(i).is_ok() => Some(Tag::Short(i as i16)),
+            (Tag::Int(i), b'C') if u16::try_from(i).is_ok() => Some(Tag::Char(i as u16)),
+            (v, b'L' | b'[') if v.object_id() == Some(0) => Some(v),
+            (v, b'L' | b'[') if is_reference(&v) => {
+                let class = reference_type(self.client, v.object_id().unwrap())?;
+                if self.is_instance(class, &type_name(returns))? { Some(v) } else { None }
+            },
+            (v, _) => widen(&v, returns),
+        };
+        return match converted {
+            Some(v) => Ok(v),
+            None => error(format!("can't return {} from a method returning {}", self.format(&value)?, type_name(returns))),
+        };
+    }
+    fn create_string(&self, s: &str) -> Result<Tag> {
+        return Ok(Tag::String(self.request(request::create_string(s.to_string()))?));
+    }
+    fn boolean(&self, value: &Tag) -> Result<bool> {
+        return match value {
+            Tag::Boolean(b) => Ok(*b),
+            v => error(format!("expected a boolean, got {}", self.format(v)?)),
+        };
+    }
+    fn array(&self, value: &Tag) -> Result<u64> {
+        return match value {
+            Tag::Array(0) => error("NullPointerException: array is null".to_string()),
+            Tag::Array(id) => Ok(*id),
+            v => error(format!("{} is not an array", self.format(v)?)),
+        };
+    }
+    fn index(&self, value: &Tag) -> Result<i32> {
+        return match value {
+            Tag::Byte(_) | Tag::Short(_) | Tag::Char(_) | Tag::Int(_) => Ok(Num::of(value).unwrap().long() as i32),
+            v => error(format!("array index must be an int, got {}", self.format(v)?)),
+        };
+    }
+    fn bounds_check(&self, array: u64, index: i32) -> Result<()> {
+        let length = self.request(request::array_reference_length(array))?;
+        if index < 0 || index >= length {
+            return error(format!("ArrayIndexOutOfBoundsException: index {} out of bounds for length {}", index, length));
+        }
+        return Ok(());
+    }
+    fn non_null(&self, value: &Tag, member: &str) -> Result<u64> {
+        return match value.object_id() {
+            Some(0) => error(format!("NullPointerException: {} on null", member)),
+            Some(id) => Ok(id),
+            None => error(format!("{} is not an object", self.format(value)?)),
+        };
+    }
+    fn frame_class(&self) -> Result<u64> {
+        return match self.frame {
+            Some(f) => Ok(f.location.class_id),
+            None => error("no current frame, stop at a breakpoint or pick a thread first".to_string()),
+        };
+    }
+    fn this_object(&self) -> Result<Option<u64>> {
+        let frame = match self.frame {
+            Some(f) => f,
+            None => { return Ok(None); },
+        };
+        let this = self.request(request::stack_frame_this_object(self.thread, frame.frame_id))?;
+        return Ok(this.object_id().filter(|id| *id != 0));
+    }
+    /// The innermost local called `name` that is live at the frame's location.
+    fn local(&self, name: &str) -> Result<Option<jdwp::VariableInfo>> {
+        let frame = match self.frame {
+            Some(f) => f,
+            None => { return Ok(None); },
+        };
+        let variables = match self.request(request::method_variable_table(frame.location.class_id, frame.location.method_id)) {
+            Ok(table) => table.variables,
+            // Compiled without -g, or native.
+            Err(Error::Jdwp(jdwp::Error::AbsentInformation)) | Err(Error::Jdwp(jdwp::Error::NativeMethod)) => { return Ok(None); },
+            Err(e) => { return Err(e); },
+        };
+        let index = frame.location.index as i64;
+        return Ok(variables.into_iter()
+            .filter(|v| v.name == name && v.code_index <= index && index < v.code_index + v.length as i64)
+            .max_by_key(|v| v.code_index));
+    }
+    /// A local, `this`, or a field reachable from the frame.
+    fn variable(&self, name: &str) -> Result<Option<Tag>> {
+        if let Some(var) = name.strip_prefix('$').filter(|v| handles::reserved(v)) {
+            return match self.client.handles().variable(var) {
+                Some(value) => Ok(Some(value)),
+                None => error(format!("{} isn't set, try pins", name)),
+            };
+        }
+        if name == "this" {
+            return match self.this_object()? {
+                Some(this) => Ok(Some(Tag::Object(this))),
+                None => error("no this in a static context".to_string()),
+            };
+        }
+        if let Some(var) = self.local(name)? {
+            let frame = self.frame.unwrap().frame_id;
+            let slots = vec![(var.slot, signature_tag(&var.signature))];
+            return Ok(Some(single(self.request(request::stack_frame_get_values(self.thread, frame, slots))?)?));
+        }
+        let (object, class) = match (self.this_object()?, self.frame) {
+            (Some(this), _) => (Some(this), reference_type(self.client, this)?),
+            (None, Some(frame)) => (None, frame.location.class_id),
+            (None, None) => { return Ok(None); },
+        };
+        return match self.find_field(class, name)? {
+            Some((declaring, field)) => Ok(Some(self.read_field(object, declaring, &field)?)),
+            None => Ok(None),
+        };
+    }
+    fn superclass(&self, class: u64) -> Result<u64> {
+        return match self.request(request::class_type_superclass(class)) {
+            Ok(s) => Ok(s),
+            // Interfaces and array types have no superclass to ask about.
+            Err(Error::Jdwp(_)) => Ok(0),
+            Err(e) => Err(e),
+        };
+    }
+    fn find_field(&self, class: u64, name: &str) -> Result<Option<(u64, jdwp::FieldInfo)>> {
+        let mut class = class;
+        while class != 0 {
+            let fields = self.request(request::reference_type_fields(class))?;
+            if let Some(field) = fields.into_iter().find(|f| f.name == name) {
+                return Ok(Some((class, field)));
+            }
+            class = self.superclass(class)?;
+        }
+        return Ok(None);
+    }
+    fn read_field(&self, object: Option<u64>, declaring: u64, field: &jdwp::FieldInfo) -> Result<Tag> {
+        let values = if field.mod_bits & ACC_STATIC != 0 {
+            self.request(request::reference_type_get_values(declaring, vec![field.field_id]))?
+        } else {
+            match object {
+                Some(object) => self.request(request::object_reference_get_values(object, vec![field.field_id]))?,
+                None => { return error(format!("{} is not static", field.name)); },
+            }
+        };
+        return single(values);
+    }
+    fn set_field(&self, object: Option<u64>, class: u64, name: &str, value: &Tag) -> Result<Tag> {
+        let (declaring, field) = match self.find_field(class, name)? {
+            Some(found) => found,
+            None => { return error(format!("no field {} in {}", name, class_name(self.client, class)?)); },
+        };
+        let value = cast(value, &field.signature)?;
+        let cmd = if field.mod_bits & ACC_STATIC != 0 {
+            Command::ClassTypeSetValues { class: declaring, values: vec![(field.field_id, value)] }
+        } else {
+            match object {
+                Some(object) => Command::ObjectReferenceSetValues { object: object, values: vec![(field.field_id, value)] },
+                None => { return error(format!("{} is not static", name)); },
+            }
+        };
+        self.send(cmd)?;
+        return Ok(value);
+    }
+    /// The class called `name`, trying the frame's package and java.lang for
+    /// simple names.
+    fn find_class(&self, name: &str) -> Result<Option<u64>> {
+        let mut candidates = vec![class_signature(name)];
+        if !name.contains('.') {
+            if let Some(frame) = self.frame {
+                let current = signature(self.client, frame.location.class_id)?;
+                if let Some(slash) = current.rfind('/') {
+                    candidates.push(format!("{}/{};", &current[..slash], name));
+                }
+            }
+            candidates.push(format!("Ljava/lang/{};", name));
+        }
+        for candidate in candidates {
+            if let Some(class) = self.request(request::classes_by_signature(candidate))?.first() {
+                return Ok(Some(class.type_id));
+            }
+        }
+        return Ok(None);
+    }
+    fn member(&self, place: Place, name: &str) -> Result<Tag> {
+        let (object, class) = match place {
+            Place::Type(class) => (None, class),
+            Place::Value(Tag::Array(array)) if name == "length" && array != 0 => {
+                return Ok(Tag::Int(self.request(request::array_reference_length(array))?));
+            },
+            Place::Value(v) => {
+                let object = self.non_null(&v, name)?;
+                (Some(object), reference_type(self.client, object)?)
+            },
+        };
+        return match self.find_field(class, name)? {
+            Some((declaring, field)) => self.read_field(object, declaring, &field),
+            None => error(format!("no field {} in {}", name, class_name(self.client, class)?)),
+        };
+    }
+    /// `a.b.c` as its parts, if the expression is only names and fields.
+    fn dotted(expr: &Expr) -> Option<Vec<String>> {
+        return match expr {
+            Expr::Name(n) => Some(vec![n.clone()]),
+            Expr::Field(inner, name) => {
+                let mut parts = Evaluator::dotted(inner)?;
+                parts.push(name.clone());
+                Some(parts)
+            },
+            _ => None,
+        };
+    }
+    fn place(&self, expr: &Expr) -> Result<Place> {
+        if let Some(parts) = Evaluator::dotted(expr) {
+            let (mut place, rest) = match self.variable(&parts[0])? {
+                Some(v) => (Place::Value(v), &parts[1..]),
+                None => {
+                    let mut found = None;
+                    for i in 1..=parts.len() {
+                        if let Some(class) = self.find_class(&parts[..i].join("."))? {
+                            found = Some((Place::Type(class), &parts[i..]));
+                            break;
+                        }
+                    }
+                    match found {
+                        Some(found) => found,
+                        None => { return error(format!("unknown name {}", parts[0])); },
+                    }
+                },
+            };
+            for name in rest {
+                place = Place::Value(self.member(place, name)?);
+            }
+            return Ok(place);
+        }
+        return match expr {
+            Expr::Field(inner, name) => Ok(Place::Value(self.member(self.place(inner)?, name)?)),
+            e => Ok(Place::Value(self.evaluate(e)?)),
+        };
+    }
+    fn invoke(&self, object: Option<u64>, class: u64, name: &str, args: &[Tag]) -> Result<Tag> {
+        if self.thread == 0 {
+            return error("calling methods needs a suspended thread".to_string());
+        }
+        let mut current = class;
+        while current != 0 {
+            let methods = self.request(request::reference_type_methods(current))?;
+            for method in methods.iter().filter(|m| m.name == name) {
+                let params = match signature::parse_method(&method.signature) {
+                    Some(parsed) => parsed.params,
+                    None => { continue; },
+                };
+                if params.len() != args.len() {
+                    continue;
+                }
+                let converted: Option<Vec<Tag>> = args.iter().zip(params.iter()).map(|(a, p)| argument(a, p)).collect();
+                let converted = match converted {
+                    Some(c) => c,
+                    None => { continue; },
+                };
+                let options = jdwp::INVOKE_SINGLE_THREADED;
+                let invoked = match (method.mod_bits & ACC_STATIC != 0, object) {
+                    (true, _) => self.request(request::class_type_invoke_method(current, self.thread, method.method_id, converted, options))?,
+                    (false, Some(object)) => self.request(request::object_reference_invoke_method(object, self.thread, current, method.method_id, converted, options))?,
+                    (false, None) => { return error(format!("{} is not static", name)); },
+                };
+                return match invoked.exception.object_id().unwrap_or(0) {
+                    0 => Ok(invoked.return_value),
+                    exception => {
+                        let thrown = class_name(self.client, reference_type(self.client, exception)?)?;
+                        error(format!("{} threw {}", name, thrown))
+                    },
+                };
+            }
+            current = self.superclass(current)?;
+        }
+        return error(format!("no method {} taking {} arguments in {}", name, args.len(), class_name(self.client, class)?));
+    }
+    /// The value as string concatenation would show it.
+    fn text(&self, value: &Tag) -> Result<String> {
+        if let Some(text) = primitive_text(value) {
+            return Ok(text);
+        }
+        return match value {
+            Tag::String(id) if *id != 0 => string_value(self.client, *id),
+            v if v.object_id() == Some(0) => Ok("null".to_string()),
+            v if self.thread != 0 => {
+                let object = v.object_id().unwrap();
+                let s = self.invoke(Some(object), reference_type(self.client, object)?, "toString", &[])?;
+                self.text(&s)
+            },
+            v => self.format(v),
+        };
+    }
+    fn is_instance(&self, class: u64, ty: &str) -> Result<bool> {
+        let wanted = class_signature(ty);
+        if wanted == "Ljava/lang/Object;" {
+            return Ok(true);
+        }
+        let mut queue = vec![class];
+        while let Some(class) = queue.pop() {
+            let sig = signature(self.client, class)?;
+            // Simple names match any package.
+            let simple = !ty.contains('.') && type_name(&sig).rsplit('.').next() == Some(ty);
+            if sig == wanted || simple {
+                return Ok(true);
+            }
+            let superclass = self.superclass(class)?;
+            if superclass != 0 {
+                queue.push(superclass);
+            }
+            queue.extend(self.request(request::reference_type_interfaces(class))?);
+        }
+        return Ok(false);
+    }
+    fn binary(&self, op: BinaryOp, a: &Tag, b: &Tag) -> Result<Tag> {
+        if op == BinaryOp::Add && (matches!(a, Tag::String(_)) || matches!(b, Tag::String(_))) {
+            let joined = self.text(a)? + &self.text(b)?;
+            return self.create_string(&joined);
+        }
+        if op == BinaryOp::Eq || op == BinaryOp::Ne {
+            let equal = match (a, b, Num::of(a), Num::of(b)) {
+                (_, _, Some(x), Some(y)) if x.rank() >= 2 || y.rank() >= 2 => x.double() == y.double(),
+                (_, _, Some(x), Some(y)) => x.long() == y.long(),
+                (Tag::Boolean(x), Tag::Boolean(y), _, _) => x == y,
+                (x, y, _, _) if is_reference(x) && is_reference(y) => x.object_id() == y.object_id(),
+                _ => { return error(format!("can't compare {} and {}", self.format(a)?, self.format(b)?)); },
+            };
+            return Ok(Tag::Boolean(equal == (op == BinaryOp::Eq)));
+        }
+        let (x, y) = match (Num::of(a), Num::of(b)) {
+            (Some(x), Some(y)) => (x, y),
+            _ => { return error(format!("{:?} needs numbers, got {} and {}", op, self.format(a)?, self.format(b)?)); },
+        };
+        let rank = std::cmp::max(x.rank(), y.rank());
+        if let BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge = op {
+            let ordering = if rank >= 2 {
+                x.double().partial_cmp(&y.double())
+            } else {
+                Some(x.long().cmp(&y.long()))
+            };
+            use std::cmp::Ordering::*;
+            return Ok(Tag::Boolean(match (op, ordering) {
+                (_, None) => false,
+                (BinaryOp::Lt, Some(o)) => o == Less,
+                (BinaryOp::Le, Some(o)) => o != Greater,
+                (BinaryOp::Gt, Some(o)) => o == Greater,
+                (_, Some(o)) => o != Less,
+            }));
+        }
+        if rank >= 2 {
+            let (x, y) = (x.double(), y.double());
+            let r = match op {
+                BinaryOp::Add => x + y,
+                BinaryOp::Sub => x - y,
+                BinaryOp::Mul => x * y,
+                BinaryOp::Div => x / y,
+                _ => x % y,
+            };
+            return Ok(if rank == 2 { Tag::Float(r as f32) } else { Tag::Double(r) });
+        }
+        let (x, y) = (x.long(), y.long());
+        if y == 0 && (op == BinaryOp::Div || op == BinaryOp::Rem) {
+            return error("ArithmeticException: / by zero".to_string());
+        }
+        return Ok(if rank == 1 {
+            Tag::Long(match op {
+                BinaryOp::Add => x.wrapping_add(y),
+                BinaryOp::Sub => x.wrapping_sub(y),
+                BinaryOp::Mul => x.wrapping_mul(y),
+                BinaryOp::Div => x.wrapping_div(y),
+                _ => x.wrapping_rem(y),
+            })
+        } else {
+            let (x, y) = (x as i32, y as i32);
+            Tag::Int(match op {
+                BinaryOp::Add => x.wrapping_add(y),
+                BinaryOp::Sub => x.wrapping_sub(y),
+                BinaryOp::Mul => x.wrapping_mul(y),
+                BinaryOp::Div => x.wrapping_div(y),
+                _ => x.wrapping_rem(y),
+            })
+        });
+    }
+}
@@ -0,0 +1,129 @@
+//! Values the session holds on to: the `$N` value history and the `$thread`
+//! style variables set by events. The VM may collect any object the debugger
+//! hasn't pinned, so objects in the history have collection disabled until
+//! they're released. Released IDs go back to the VM in batched
+//! DisposeObjects commands.
+use crate::Result;
+use crate::client::Client;
+use crate::jdwp::{Command,Tag};
+use std::collections::{BTreeMap,HashMap};
+
+/// How many released objects are queued before they're disposed.
+pub const DISPOSE_BATCH: usize = 32;
+
+/// Variables the last event sets, named without the `$`.
+pub const AUTOMATIC: &[&str] = &["thread", "frame", "exception", "retval"];
+
+/// Whether `$name` belongs to the history rather than the program.
+pub fn reserved(name: &str) -> bool {
+    return name == "_" || AUTOMATIC.contains(&name) || name.parse::<usize>().is_ok();
+}
+
+#[derive(Default)]
+pub struct Handles {
+    numbered: BTreeMap<usize, Tag>,
+    last: usize,
+    /// How many handles refer to each object, and how many times its ID was
+    /// handed to us, which is what the VM counts.
+    pins: HashMap<u64, (i32, i32)>,
+    /// Released objects and how many times each ID was handed to us.
+    released: Vec<(u64, i32)>,
+    automatic: HashMap<&'static str, Tag>,
+}
+
+fn pinnable(value: &Tag) -> Option<u64> {
+    return match value.object_id() {
+        Some(0) | None => None,
+        id => id,
+    };
+}
+
+impl Handles {
+    /// Numbers `value` as the next `$N`, disabling collection the first
+    /// time an object is pinned.
+    pub fn pin(&mut self, client: &Client, value: Tag) -> Result<usize> {
+        if let Some(object) = pinnable(&value) {
+            if !self.pins.contains_key(&object) {
+                client.send_and_wait(&Command::ObjectReferenceDisableCollection { object: object })?;
+                // Still ours if it was released but not disposed yet.
+                let references = match self.released.iter().position(|(o, _)| *o == object) {
+                    Some(i) => self.released.remove(i).1,
+                    None => 0,
+                };
+                self.pins.insert(object, (0, references));
+            }
+            let (handles, references) = self.pins.get_mut(&object).unwrap();
+            *handles += 1;
+            *references += 1;
+        }
+        self.last += 1;
+        self.numbered.insert(self.last, value);
+        return Ok(self.last);
+    }
+    pub fn get(&self, number: usize) -> Option<Tag> {
+        return self.numbered.get(&number).copied();
+    }
+    /// `$name` without the `$`: a history number, `_` for the newest value
+    /// in the history, or an automatic variable.
+    pub fn variable(&self, name: &str) -> Option<Tag> {
+        if name == "_" {
+            return self.numbered.values().next_back().copied();
+        }
+        if let Ok(number) = name.parse() {
+            return self.get(number);
+        }
+        return self.automatic.get(name).copied();
+    }
+    pub fn set_automatic(&mut self, name: &'static str, value: Option<Tag>) {
+        match value {
+            Some(value) => self.automatic.insert(name, value),
+            None => self.automatic.remove(name),
+        };
+    }
+    /// The automatic variables that are set, in `AUTOMATIC` order.
+    pub fn automatic(&self) -> Vec<(&'static str, Tag)> {
+        return AUTOMATIC.iter().filter_map(|n| self.automatic.get(n).map(|v| (*n, *v))).collect();
+    }
+    /// Every handle, lowest number first.
+    pub fn list(&self) -> Vec<(usize, Tag)> {
+        return self.numbered.iter().map(|(n, v)| (*n, *v)).collect();
+    }
+    /// Drops `$number`. Once nothing refers to its object, collection is
+    /// enabled again and the ID is queued for disposal.
+    pub fn unpin(&mut self, client: &Client, number: usize) -> Result<bool> {
+        let value = match self.numbered.remove(&number) {
+            Some(value) => value,
+            None => { return Ok(false); },
+        };
+        if let Some(object) = pinnable(&value) {
+            let (handles, references) = self.pins.get_mut(&object).unwrap();
+            *handles -= 1;
+            if *handles == 0 {
+                let references = *references;
+                self.pins.remove(&object);
+                client.send_and_wait(&Command::ObjectReferenceEnableCollection { object: object })?;
+                self.released.push((object, references));
+                if self.released.len() >= DISPOSE_BATCH {
+                    self.dispose(client)?;
+                }
+            }
+        }
+        return Ok(true);
+    }
+    pub fn unpin_all(&mut self, client: &Client) -> Result<()> {
+        let numbers: Vec<usize> = self.numbered.keys().copied().collect();
+        for number in numbers {
+            self.unpin(client, number)?;
+        }
+        return self.dispose(client);
+    }
+    /// Sends the queued IDs back to the VM now.
+    pub fn dispose(&mut self, client: &Client) -> Result<()> {
+        if self.released.is_empty() {
+            return Ok(());
+        }
+        let requests = std::mem::take(&mut self.released);
+        client.send_and_wait(&Command::DisposeObjects { requests: requests })?;
+        return Ok(());
+    }
+}
@@ -0,0 +1,566 @@
+//! Parser for HPROF heap dumps, including the tags Android adds.
+//!
+//! The whole dump is parsed into a `Heap` that can be queried for a class
+//! histogram, the instances of a class, an object's fields and, through
+//! `Heap::dominators`, the retained size of every reachable object.
+use crate::jdwp::Tag;
+use std::collections::HashMap;
+use std::io::Read;
+
+const STRING: u8 = 0x01;
+const LOAD_CLASS: u8 = 0x02;
+const HEAP_DUMP: u8 = 0x0c;
+const HEAP_DUMP_SEGMENT: u8 = 0x1c;
+
+const ROOT_UNKNOWN: u8 = 0xff;
+const ROOT_JNI_GLOBAL: u8 = 0x01;
+const ROOT_JNI_LOCAL: u8 = 0x02;
+const ROOT_JAVA_FRAME: u8 = 0x03;
+const ROOT_NATIVE_STACK: u8 = 0x04;
+const ROOT_STICKY_CLASS: u8 = 0x05;
+const ROOT_THREAD_BLOCK: u8 = 0x06;
+const ROOT_MONITOR_USED: u8 = 0x07;
+const ROOT_THREAD_OBJECT: u8 = 0x08;
+const CLASS_DUMP: u8 = 0x20;
+const INSTANCE_DUMP: u8 = 0x21;
+const OBJECT_ARRAY_DUMP: u8 = 0x22;
+const PRIMITIVE_ARRAY_DUMP: u8 = 0x23;
+// Android additions.
+const HEAP_DUMP_INFO: u8 = 0xfe;
+const ROOT_INTERNED_STRING: u8 = 0x89;
+const ROOT_FINALIZING: u8 = 0x8a;
+const ROOT_DEBUGGER: u8 = 0x8b;
+const ROOT_REFERENCE_CLEANUP: u8 = 0x8c;
+const ROOT_VM_INTERNAL: u8 = 0x8d;
+const ROOT_JNI_MONITOR: u8 = 0x8e;
+const UNREACHABLE: u8 = 0x90;
+const PRIMITIVE_ARRAY_NODATA: u8 = 0xc3;
+
+/// HPROF basic type codes.
+pub const TYPE_OBJECT: u8 = 2;
+pub const TYPE_BOOLEAN: u8 = 4;
+pub const TYPE_CHAR: u8 = 5;
+pub const TYPE_FLOAT: u8 = 6;
+pub const TYPE_DOUBLE: u8 = 7;
+pub const TYPE_BYTE: u8 = 8;
+pub const TYPE_SHORT: u8 = 9;
+pub const TYPE_INT: u8 = 10;
+pub const TYPE_LONG: u8 = 11;
+
+fn invalid(msg: &str) -> std::io::Error {
+    return std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string());
+}
+
+fn type_size(ty: u8, id_size: usize) -> std::io::Result<usize> {
+    return Ok(match ty {
+        TYPE_OBJECT => id_size,
+        TYPE_BOOLEAN | TYPE_BYTE => 1,
+        TYPE_CHAR | TYPE_SHORT => 2,
+        TYPE_FLOAT | TYPE_INT => 4,
+        TYPE_DOUBLE | TYPE_LONG => 8,
+        _ => { return Err(invalid("bad basic type")); },
+    });
+}
+
+pub fn type_name(ty: u8) -> &'static str {
+    return match ty {
+        TYPE_OBJECT => "Object",
+        TYPE_BOOLEAN => "boolean",
+        TYPE_CHAR => "char",
+        TYPE_FLOAT => "float",
+        TYPE_DOUBLE => "double",
+        TYPE_BYTE => "byte",
+        TYPE_SHORT => "short",
+        TYPE_INT => "int",
+        TYPE_LONG => "long",
+        _ => "?",
+    };
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+    id_size: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn bytes(&mut self, n: usize) -> std::io::Result<&'a [u8]> {
+        if n > self.data.len() {
+            return Err(invalid("truncated heap dump"));
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        return Ok(head);
+    }
+    fn uint(&mut self, n: usize) -> std::io::Result<u64> {
+        return Ok(self.bytes(n)?.iter().fold(0u64, |v, b| v << 8 | *b as u64));
+    }
+    fn u8(&mut self) -> std::io::Result<u8> {
+        return Ok(self.bytes(1)?[0]);
+    }
+    fn u16(&mut self) -> std::io::Result<u16> {
+        return Ok(self.uint(2)? as u16);
+    }
+    fn u32(&mut self) -> std::io::Result<u32> {
+        return Ok(self.uint(4)? as u32);
+    }
+    fn id(&mut self) -> std::io::Result<u64> {
+        return self.uint(self.id_size);
+    }
+    fn value(&mut self, ty: u8) -> std::io::Result<Tag> {
+        return Ok(match ty {
+            TYPE_OBJECT => Tag::Object(self.id()?),
+            TYPE_BOOLEAN => Tag::Boolean(self.u8()? != 0),
+            TYPE_CHAR => Tag::Char(self.u16()?),
+            TYPE_FLOAT => Tag::Float(f32::from_bits(self.u32()?)),
+            TYPE_DOUBLE => Tag::Double(f64::from_bits(self.uint(8)?)),
+            TYPE_BYTE => Tag::Byte(self.u8()?),
+            TYPE_SHORT => Tag::Short(self.u16()? as i16),
+            TYPE_INT => Tag::Int(self.u32()? as i32),
+            TYPE_LONG => Tag::Long(self.uint(8)? as i64),
+            _ => { return Err(invalid("bad basic type")); },
+        });
+    }
+}
+
+#[derive(Debug,Clone)]
+pub struct Class {
+    pub id: u64,
+    pub name: String,
+    pub super_id: u64,
+    pub loader: u64,
+    pub instance_size: u32,
+    pub statics: Vec<(String, Tag)>,
+    /// Declared instance fields, not including the superclass's.
+    pub fields: Vec<(String, u8)>,
+}
+
+#[derive(Debug,Clone)]
+pub enum ObjectData {
+    /// Field values as laid out in the dump: this class's fields first,
+    /// then the superclass's.
+    Instance(Vec<u8>),
+    ObjectArray(Vec<u64>),
+    /// `data` is empty for Android's no-data arrays.
+    PrimitiveArray { ty: u8, len: u32, data: Vec<u8> },
+    Class,
+}
+
+#[derive(Debug,Clone)]
+pub struct Object {
+    pub id: u64,
+    /// The class, or for a class object the class itself.
+    pub class: u64,
+    pub heap: u32,
+    pub data: ObjectData,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Root {
+    pub kind: u8,
+    pub id: u64,
+}
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct ClassStats {
+    pub class: u64,
+    pub name: String,
+    pub count: usize,
+    pub shallow: u64,
+}
+
+#[derive(Debug,Default)]
+pub struct Heap {
+    pub id_size: usize,
+    pub timestamp: u64,
+    pub strings: HashMap<u64, String>,
+    pub classes: HashMap<u64, Class>,
+    pub objects: HashMap<u64, Object>,
+    pub roots: Vec<Root>,
+    pub heap_names: HashMap<u32, String>,
+}
+
+pub fn parse<R: Read>(reader: R) -> std::io::Result<Heap> {
+    let mut reader = reader;
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    return parse_bytes(&data);
+}
+
+pub fn parse_bytes(data: &[u8]) -> std::io::Result<Heap> {
+    let nul = data.iter().position(|b| *b == 0).ok_or_else(|| invalid("no HPROF header"))?;
+    if !data[..nul].starts_with(b"JAVA PROFILE ") {
+        return Err(invalid("not an HPROF file"));
+    }
+    let mut c = Cursor { data: &data[nul + 1..], id_size: 4 };
+    let id_size = c.u32()? as usize;
+    if !(1..=8).contains(&id_size) {
+        return Err(invalid("bad identifier size"));
+    }
+    c.id_size = id_size;
+    let mut heap = Heap { id_size: id_size, timestamp: c.uint(8)?, ..Default::default() };
+    let mut class_names: HashMap<u64, u64> = HashMap::new();
+    // Android's heap info tags stay in effect across segments.
+    let mut current_heap = 0u32;
+    while !c.data.is_empty() {
+        let tag = c.u8()?;
+        c.u32()?;
+        let len = c.u32()? as usize;
+        let mut body = Cursor { data: c.bytes(len)?, id_size: id_size };
+        match tag {
+            STRING => {
+                let id = body.id()?;
+                heap.strings.insert(id, String::from_utf8_lossy(body.data).into_owned());
+            },
+            LOAD_CLASS => {
+                body.u32()?;
+                let class = body.id()?;
+                body.u32()?;
+                class_names.insert(class, body.id()?);
+            },
+            HEAP_DUMP | HEAP_DUMP_SEGMENT => heap.parse_dump(&mut body, &mut current_heap)?,
+            _ => {},
+        }
+    }
+    for class in heap.classes.values_mut() {
+        if let Some(name) = class_names.get(&class.id).and_then(|s| heap.strings.get(s)) {
+            class.name = name.clone();
+        }
+    }
+    return Ok(heap);
+}
+
+impl Heap {
+    fn string(&self, id: u64) -> String {
+        return self.strings.get(&id).cloned().unwrap_or_else(|| format!("<string {:#x}>", id));
+    }
+    fn parse_dump(&mut self, c: &mut Cursor, current_heap: &mut u32) -> std::io::Result<()> {
+        while !c.data.is_empty() {
+            let tag = c.u8()?;
+            match tag {
+                ROOT_UNKNOWN | ROOT_STICKY_CLASS | ROOT_MONITOR_USED | ROOT_INTERNED_STRING |
+                ROOT_FINALIZING | ROOT_DEBUGGER | ROOT_REFERENCE_CLEANUP | ROOT_VM_INTERNAL => {
+                    self.roots.push(Root { kind: tag, id: c.id()? });
+                },
+                ROOT_JNI_GLOBAL => {
+                    self.roots.push(Root { kind: tag, id: c.id()? });
+                    c.id()?;
+                },
+                ROOT_JNI_LOCAL | ROOT_JAVA_FRAME | ROOT_THREAD_OBJECT | ROOT_JNI_MONITOR => {
+                    self.roots.push(Root { kind: tag, id: c.id()? });
+                    c.u32()?;
+                    c.u32()?;
+                },
+                ROOT_NATIVE_STACK | ROOT_THREAD_BLOCK => {
+                    self.roots.push(Root { kind: tag, id: c.id()? });
+                    c.u32()?;
+                },
+                UNREACHABLE => { c.id()?; },
+                HEAP_DUMP_INFO => {
+                    *current_heap = c.u32()?;
+                    let name = c.id()?;
+                    let name = self.string(name);
+                    self.heap_names.insert(*current_heap, name);
+                },
+                CLASS_DUMP => {
+                    let id = c.id()?;
+                    c.u32()?;
+                    let super_id = c.id()?;
+                    let loader = c.id()?;
+                    for _ in 0..4 {
+                        c.id()?;
+                    }
+                    let instance_size = c.u32()?;
+                    for _ in 0..c.u16()? {
+                        c.u16()?;
+                        let ty = c.u8()?;
+                        c.value(ty)?;
+                    }
+                    let mut statics = Vec::new();
+                    for _ in 0..c.u16()? {
+                        let name = c.id()?;
+                        let ty = c.u8()?;
+                        statics.push((self.string(name), c.value(ty)?));
+                    }
+                    let mut fields = Vec::new();
+                    for _ in 0..c.u16()? {
+                        let name = c.id()?;
+                        let ty = c.u8()?;
+                        type_size(ty, self.id_size)?;
+                        fields.push((self.string(name), ty));
+                    }
+                    self.classes.insert(id, Class {
+                        id: id,
+                        name: format!("<class {:#x}>", id),
+                        super_id: super_id,
+                        loader: loader,
+                        instance_size: instance_size,
+                        statics: statics,
+                        fields: fields,
+                    });
+                    self.objects.insert(id, Object { id: id, class: id, heap: *current_heap, data: ObjectData::Class });
+                },
+                INSTANCE_DUMP => {
+                    let id = c.id()?;
+                    c.u32()?;
+                    let class = c.id()?;
+                    let len = c.u32()? as usize;
+                    let data = c.bytes(len)?.to_vec();
+                    self.objects.insert(id, Object { id: id, class: class, heap: *current_heap, data: ObjectData::Instance(data) });
+                },
+                OBJECT_ARRAY_DUMP => {
+                    let id = c.id()?;
+                    c.u32()?;
+                    let len = c.u32()?;
+                    let class = c.id()?;
+                    let elements = (0..len).map(|_| c.id()).collect::<std::io::Result<Vec<_>>>()?;
+                    self.objects.insert(id, Object { id: id, class: class, heap: *current_heap, data: ObjectData::ObjectArray(elements) });
+                },
+                PRIMITIVE_ARRAY_DUMP | PRIMITIVE_ARRAY_NODATA => {
+                    let id = c.id()?;
+                    c.u32()?;
+                    let len = c.u32()?;
+                    let ty = c.u8()?;
+                    let data = if tag == PRIMITIVE_ARRAY_DUMP {
+                        let size = (len as usize).checked_mul(type_size(ty, self.id_size)?)
+                            .ok_or_else(|| invalid("array too large"))?;
+                        c.bytes(size)?.to_vec()
+                    } else {
+                        Vec::new()
+                    };
+                    let array = ObjectData::PrimitiveArray { ty: ty, len: len, data: data };
+                    self.objects.insert(id, Object { id: id, class: 0, heap: *current_heap, data: array });
+                },
+                _ => { return Err(invalid(&format!("unknown heap dump tag {:#x}", tag))); },
+            }
+        }
+        return Ok(());
+    }
+    pub fn class_name(&self, class: u64) -> &str {
+        return self.classes.get(&class).map(|c| c.name.as_str()).unwrap_or("?");
+    }
+    /// The type name of any object, including primitive arrays which have
+    /// no class in the dump.
+    pub fn type_name(&self, object: &Object) -> String {
+        return match &object.data {
+            ObjectData::PrimitiveArray { ty, .. } => format!("{}[]", type_name(*ty)),
+            ObjectData::Class => format!("class {}", self.class_name(object.id)),
+            _ => self.class_name(object.class).to_string(),
+        };
+    }
+    /// Every instance field of `class`, the class's own before its superclass's.
+    pub fn layout(&self, class: u64) -> Vec<(String, u8)> {
+        let mut fields = Vec::new();
+        let mut class = self.classes.get(&class);
+        while let Some(c) = class {
+            fields.extend(c.fields.iter().cloned());
+            class = self.classes.get(&c.super_id);
+        }
+        return fields;
+    }
+    pub fn fields(&self, id: u64) -> Vec<(String, Tag)> {
+        let object = match self.objects.get(&id) {
+            Some(o) => o,
+            None => { return Vec::new(); },
+        };
+        return match &object.data {
+            ObjectData::Instance(data) => {
+                let mut c = Cursor { data: data, id_size: self.id_size };
+                self.layout(object.class).into_iter()
+                    .map_while(|(name, ty)| c.value(ty).ok().map(|v| (name, v)))
+                    .collect()
+            },
+            ObjectData::Class => self.classes[&id].statics.clone(),
+            ObjectData::ObjectArray(elements) => elements.iter().enumerate()
+                .map(|(i, e)| (format!("[{}]", i), Tag::Object(*e)))
+                .collect(),
+            ObjectData::PrimitiveArray { ty, data, .. } => {
+                let mut c = Cursor { data: data, id_size: self.id_size };
+                let mut values = Vec::new();
+                while let Ok(v) = c.value(*ty) {
+                    values.push((format!("[{}]", values.len()), v));
+                }
+                values
+            },
+        };
+    }
+    /// Objects directly referenced by `id`.
+    pub fn references(&self, id: u64) -> Vec<u64> {
+        return self.fields(id).into_iter()
+            .filter_map(|(_, v)| match v {
+                Tag::Object(o) if o != 0 => Some(o),
+                _ => None,
+            })
+            .collect();
+    }
+    pub fn shallow_size(&self, object: &Object) -> u64 {
+        return match &object.data {
+            ObjectData::Instance(data) => self.classes.get(&object.class)
+                .map(|c| c.instance_size as u64)
+                .unwrap_or(data.len() as u64),
+            ObjectData::ObjectArray(elements) => (elements.len() * self.id_size) as u64,
+            ObjectData::PrimitiveArray { ty, len, .. } => *len as u64 * type_size(*ty, self.id_size).unwrap_or(0) as u64,
+            ObjectData::Class => self.classes[&object.id].statics.iter()
+                .map(|(_, v)| type_size(value_type(v), self.id_size).unwrap_or(0) as u64)
+                .sum(),
+        };
+    }
+    pub fn instances_of(&self, name: &str) -> Vec<&Object> {
+        let mut instances: Vec<&Object> = self.objects.values()
+            .filter(|o| !matches!(o.data, ObjectData::Class) && self.type_name(o) == name)
+            .collect();
+        instances.sort_by_key(|o| o.id);
+        return instances;
+    }
+    /// Instance count and shallow size per type, largest first.
+    pub fn histogram(&self) -> Vec<ClassStats> {
+        let mut stats: HashMap<String, ClassStats> = HashMap::new();
+        for object in self.objects.values() {
+            if let ObjectData::Class = object.data {
+                continue;
+            }
+            let name = self.type_name(object);
+            let entry = stats.entry(name.clone()).or_insert(ClassStats { class: object.class, name: name, count: 0, shallow: 0 });
+            entry.count += 1;
+            entry.shallow += self.shallow_size(object);
+        }
+        let mut stats: Vec<ClassStats> = stats.into_values().collect();
+        stats.sort_by(|a, b| b.shallow.cmp(&a.shallow).then(a.name.cmp(&b.name)));
+        return stats;
+    }
+    /// Builds the dominator tree of everything reachable from the GC roots.
+    pub fn dominators(&self) -> Dominators {
+        // Node 0 is a synthetic root pointing at every GC root.
+        let mut ids = vec![0u64];
+        let mut index: HashMap<u64, usize> = HashMap::new();
+        for id in self.objects.keys() {
+            index.insert(*id, ids.len());
+            ids.push(*id);
+        }
+        let mut roots: Vec<usize> = self.roots.iter().filter_map(|r| index.get(&r.id).cloned()).collect();
+        roots.sort_unstable();
+        roots.dedup();
+        let successors = |n: usize| -> Vec<usize> {
+            if n == 0 {
+                return roots.clone();
+            }
+            return self.references(ids[n]).iter().filter_map(|r| index.get(r).cloned()).collect();
+        };
+
+        // Iterative DFS for a postorder numbering and the predecessor lists.
+        let mut order = vec![usize::MAX; ids.len()];
+        let mut postorder = Vec::new();
+        let mut preds: Vec<Vec<usize>> = vec![Vec::new(); ids.len()];
+        let mut visited = vec![false; ids.len()];
+        let mut stack = vec![(0usize, successors(0), 0usize)];
+        visited[0] = true;
+        while let Some((node, succ, next)) = stack.last_mut() {
+            let node = *node;
+            if *next == succ.len() {
+                order[node] = postorder.len();
+                postorder.push(node);
+                stack.pop();
+                continue;
+            }
+            let s = succ[*next];
+            *next += 1;
+            preds[s].push(node);
+            if !visited[s] {
+                visited[s] = true;
+                stack.push((s, successors(s), 0));
+            }
+        }
+
+        // Cooper, Harvey and Kennedy, working in postorder numbers.
+        let n = postorder.len();
+        let root = n - 1;
+        let mut idom = vec![usize::MAX; n];
+        idom[root] = root;
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in (0..root).rev() {
+                let mut new_idom = usize::MAX;
+                for p in preds[postorder[b]].iter().map(|p| order[*p]) {
+                    if idom[p] == usize::MAX {
+                        continue;
+                    }
+                    new_idom = if new_idom == usize::MAX {
+                        p
+                    } else {
+                        let (mut x, mut y) = (p, new_idom);
+                        while x != y {
+                            while x < y { x = idom[x]; }
+                            while y < x { y = idom[y]; }
+                        }
+                        x
+                    };
+                }
+                if idom[b] != new_idom {
+                    idom[b] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        // A dominator always finishes after the nodes it dominates.
+        let mut retained: Vec<u64> = (0..n)
+            .map(|b| if b == root { 0 } else { self.shallow_size(&self.objects[&ids[postorder[b]]]) })
+            .collect();
+        for b in 0..root {
+            retained[idom[b]] += retained[b];
+        }
+        let mut result = Dominators { idom: HashMap::new(), retained: HashMap::new() };
+        for b in 0..root {
+            let id = ids[postorder[b]];
+            result.idom.insert(id, ids[postorder[idom[b]]]);
+            result.retained.insert(id, retained[b]);
+        }
+        return result;
+    }
+}
+
+fn value_type(value: &Tag) -> u8 {
+    return match value {
+        Tag::Boolean(_) => TYPE_BOOLEAN,
+        Tag::Char(_) => TYPE_CHAR,
+        Tag::Float(_) => TYPE_FLOAT,
+        Tag::Double(_) => TYPE_DOUBLE,
+        Tag::Byte(_) => TYPE_BYTE,
+        Tag::Short(_) => TYPE_SHORT,
+        Tag::Int(_) => TYPE_INT,
+        Tag::Long(_) => TYPE_LONG,
+        _ => TYPE_OBJECT,
+    };
+}
+
+/// Immediate dominators and retained sizes. Objects unreachable from the
+/// GC roots appear in neither.
+#[derive(Debug,Default)]
+pub struct Dominators {
+    /// Immediate dominator of each object, 0 for objects only the
+    /// synthetic root dominates.
+    pub idom: HashMap<u64, u64>,
+    pub retained: HashMap<u64, u64>,
+}
+
+impl Dominators {
+    pub fn retained(&self, id: u64) -> Option<u64> {
+        return self.retained.get(&id).cloned();
+    }
+    pub fn idom(&self, id: u64) -> Option<u64> {
+        return self.idom.get(&id).cloned();
+    }
+    /// Objects with the largest retained sizes, largest first.
+    pub fn largest(&self, n: usize) -> Vec<(u64, u64)> {
+        let mut all: Vec<(u64, u64)> = self.retained.iter().map(|(id, size)| (*id, *size)).collect();
+        all.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        all.truncate(n);
+        return all;
+    }
+    /// Objects immediately dominated by `id`.
+    pub fn children(&self, id: u64) -> Vec<u64> {
+        let mut children: Vec<u64> = self.idom.iter().filter(|(_, d)| **d == id).map(|(c, _)| *c).collect();
+        children.sort_by_key(|c| std::cmp::Reverse(self.retained(*c)));
+        return children;
+    }
+}
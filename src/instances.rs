@@ -0,0 +1,95 @@
+//! Live heap queries that don't need a heap dump: instances of a class,
+//! instance counts, and chains of referring objects.
+use crate::{Result,Error};
+use crate::client::Client;
+use crate::jdwp::{Capabilities,Reply,Tag};
+use crate::request;
+use std::collections::HashSet;
+
+fn require_instance_info(client: &Client) -> Result<()> {
+    if !client.state().capabilities.contains(Capabilities::GET_INSTANCE_INFO) {
+        return Err(Error::Command("this VM can't list instances (no GET_INSTANCE_INFO capability)".to_string()));
+    }
+    return Ok(());
+}
+
+/// Up to `max` live instances of exactly `class`, or all of them if 0.
+pub fn instances(client: &Client, class: u64, max: i32) -> Result<Vec<Tag>> {
+    require_instance_info(client)?;
+    return client.request(request::reference_type_instances(class, max));
+}
+
+/// The number of live instances of each class.
+pub fn counts(client: &Client, classes: &[u64]) -> Result<Vec<i64>> {
+    require_instance_info(client)?;
+    let counts = client.request(request::instance_counts(classes.to_vec()))?;
+    if counts.len() != classes.len() {
+        return Err(Error::unexpected(Reply::InstanceCounts(counts)));
+    }
+    return Ok(counts);
+}
+
+/// Up to `max` objects that refer to `object`, or all of them if 0.
+pub fn referrers(client: &Client, object: u64, max: i32) -> Result<Vec<Tag>> {
+    require_instance_info(client)?;
+    return client.request(request::object_reference_referring_objects(object, max));
+}
+
+/// Why a branch of a referrer tree stops.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum End {
+    /// Its referrers are the children.
+    Expanded,
+    /// Nothing on the heap refers to it, so it's held by a GC root: a stack
+    /// frame, a JNI reference, or the VM itself.
+    Root,
+    /// Already shown further up this branch.
+    Cycle,
+    /// Not followed any further.
+    DepthLimit,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Referrers {
+    pub object: Tag,
+    pub end: End,
+    pub referrers: Vec<Referrers>,
+    /// Set when there were more than the width allowed.
+    pub truncated: bool,
+}
+
+/// The objects referring to `object`, then what refers to those, and so on
+/// up to `depth` levels and `width` referrers per object.
+pub fn referrer_tree(client: &Client, object: Tag, depth: usize, width: usize) -> Result<Referrers> {
+    require_instance_info(client)?;
+    return expand(client, object, depth, width, &mut HashSet::new());
+}
+
+fn expand(client: &Client, object: Tag, depth: usize, width: usize, path: &mut HashSet<u64>) -> Result<Referrers> {
+    let id = object.object_id().unwrap_or(0);
+    let mut node = Referrers { object: object, end: End::Expanded, referrers: Vec::new(), truncated: false };
+    if path.contains(&id) {
+        node.end = End::Cycle;
+        return Ok(node);
+    }
+    if depth == 0 {
+        node.end = End::DepthLimit;
+        return Ok(node);
+    }
+    // One more than the width says whether any were left out.
+    let mut found = referrers(client, id, width as i32 + 1)?;
+    if found.is_empty() {
+        node.end = End::Root;
+        return Ok(node);
+    }
+    if found.len() > width {
+        found.truncate(width);
+        node.truncated = true;
+    }
+    path.insert(id);
+    for referrer in found {
+        node.referrers.push(expand(client, referrer, depth - 1, width, path)?);
+    }
+    path.remove(&id);
+    return Ok(node);
+}
@@ -2,40 +2,219 @@
 use bitflags::bitflags;
 use std::vec::Vec;
 use std::io::{Read,Write};
-use log::*;
+use crate::ddm::{self,Chunk};
+use crate::mutf8;
 
-#[derive(Debug)]
+#[derive(Debug,Clone,PartialEq,Eq)]
 pub enum Error {
-    IllegalArgument,
+    InvalidThread,
+    InvalidThreadGroup,
+    InvalidPriority,
+    ThreadNotSuspended,
+    ThreadSuspended,
+    ThreadNotAlive,
+    InvalidObject,
+    InvalidClass,
+    ClassNotPrepared,
+    InvalidMethodId,
+    InvalidLocation,
+    InvalidFieldId,
+    InvalidFrameId,
+    NoMoreFrames,
+    OpaqueFrame,
+    NotCurrentFrame,
+    TypeMismatch,
+    InvalidSlot,
+    Duplicate,
+    NotFound,
+    InvalidMonitor,
+    NotMonitorOwner,
+    Interrupt,
+    InvalidClassFormat,
+    CircularClassDefinition,
+    FailsVerification,
+    AddMethodNotImplemented,
+    SchemaChangeNotImplemented,
+    InvalidTypestate,
+    HierarchyChangeNotImplemented,
+    DeleteMethodNotImplemented,
+    UnsupportedVersion,
+    NamesDontMatch,
+    ClassModifiersChangeNotImplemented,
+    MethodModifiersChangeNotImplemented,
+    ClassAttributeChangeNotImplemented,
+    Unimplemented,
+    NullPointer,
     AbsentInformation,
+    InvalidEventType,
+    IllegalArgument,
+    OutOfMemory,
+    AccessDenied,
+    VmDead,
+    Internal,
+    UnattachedThread,
+    InvalidTag,
+    AlreadyInvoking,
+    InvalidIndex,
     InvalidLength,
     InvalidString,
-    Unimplemented,
+    InvalidClassLoader,
+    InvalidArray,
+    TransportLoad,
+    TransportInit,
+    NativeMethod,
+    InvalidCount,
+    /// Decoding the packet field `name` failed. Only our deserializers make
+    /// these; on the wire it is whatever `source` is.
+    Field { name: &'static str, source: Box<Error> },
 }
 
 impl Error {
     pub fn serialize(&self) -> u16 {
         return match self {
-            Error::IllegalArgument => 103,
+            Error::InvalidThread => 10,
+            Error::InvalidThreadGroup => 11,
+            Error::InvalidPriority => 12,
+            Error::ThreadNotSuspended => 13,
+            Error::ThreadSuspended => 14,
+            Error::ThreadNotAlive => 15,
+            Error::InvalidObject => 20,
+            Error::InvalidClass => 21,
+            Error::ClassNotPrepared => 22,
+            Error::InvalidMethodId => 23,
+            Error::InvalidLocation => 24,
+            Error::InvalidFieldId => 25,
+            Error::InvalidFrameId => 30,
+            Error::NoMoreFrames => 31,
+            Error::OpaqueFrame => 32,
+            Error::NotCurrentFrame => 33,
+            Error::TypeMismatch => 34,
+            Error::InvalidSlot => 35,
+            Error::Duplicate => 40,
+            Error::NotFound => 41,
+            Error::InvalidMonitor => 50,
+            Error::NotMonitorOwner => 51,
+            Error::Interrupt => 52,
+            Error::InvalidClassFormat => 60,
+            Error::CircularClassDefinition => 61,
+            Error::FailsVerification => 62,
+            Error::AddMethodNotImplemented => 63,
+            Error::SchemaChangeNotImplemented => 64,
+            Error::InvalidTypestate => 65,
+            Error::HierarchyChangeNotImplemented => 66,
+            Error::DeleteMethodNotImplemented => 67,
+            Error::UnsupportedVersion => 68,
+            Error::NamesDontMatch => 69,
+            Error::ClassModifiersChangeNotImplemented => 70,
+            Error::MethodModifiersChangeNotImplemented => 71,
+            Error::ClassAttributeChangeNotImplemented => 72,
+            Error::Unimplemented => 99,
+            Error::NullPointer => 100,
             Error::AbsentInformation => 101,
+            Error::InvalidEventType => 102,
+            Error::IllegalArgument => 103,
+            Error::OutOfMemory => 110,
+            Error::AccessDenied => 111,
+            Error::VmDead => 112,
+            Error::Internal => 113,
+            Error::UnattachedThread => 115,
+            Error::InvalidTag => 500,
+            Error::AlreadyInvoking => 502,
+            Error::InvalidIndex => 503,
             Error::InvalidLength => 504,
             Error::InvalidString => 506,
-            Error::Unimplemented => 99,
+            Error::InvalidClassLoader => 507,
+            Error::InvalidArray => 508,
+            Error::TransportLoad => 509,
+            Error::TransportInit => 510,
+            Error::NativeMethod => 511,
+            Error::InvalidCount => 512,
+            Error::Field { source, .. } => source.serialize(),
+        };
+    }
+    /// The error behind any `Field` wrapping.
+    pub fn root(&self) -> &Error {
+        return match self {
+            Error::Field { source, .. } => source.root(),
+            e => e,
         };
     }
+    /// The fields being decoded when this went wrong, outermost first and
+    /// joined with dots, e.g. `events.location.class_id`.
+    pub fn field_path(&self) -> Option<String> {
+        let mut names = Vec::new();
+        let mut e = self;
+        while let Error::Field { name, source } = e {
+            names.push(*name);
+            e = source;
+        }
+        return if names.is_empty() { None } else { Some(names.join(".")) };
+    }
     pub fn deserialize(data: u16) -> Option<Error> {
         return Some(match data {
-            103 => Error::IllegalArgument,
+            10 => Error::InvalidThread,
+            11 => Error::InvalidThreadGroup,
+            12 => Error::InvalidPriority,
+            13 => Error::ThreadNotSuspended,
+            14 => Error::ThreadSuspended,
+            15 => Error::ThreadNotAlive,
+            20 => Error::InvalidObject,
+            21 => Error::InvalidClass,
+            22 => Error::ClassNotPrepared,
+            23 => Error::InvalidMethodId,
+            24 => Error::InvalidLocation,
+            25 => Error::InvalidFieldId,
+            30 => Error::InvalidFrameId,
+            31 => Error::NoMoreFrames,
+            32 => Error::OpaqueFrame,
+            33 => Error::NotCurrentFrame,
+            34 => Error::TypeMismatch,
+            35 => Error::InvalidSlot,
+            40 => Error::Duplicate,
+            41 => Error::NotFound,
+            50 => Error::InvalidMonitor,
+            51 => Error::NotMonitorOwner,
+            52 => Error::Interrupt,
+            60 => Error::InvalidClassFormat,
+            61 => Error::CircularClassDefinition,
+            62 => Error::FailsVerification,
+            63 => Error::AddMethodNotImplemented,
+            64 => Error::SchemaChangeNotImplemented,
+            65 => Error::InvalidTypestate,
+            66 => Error::HierarchyChangeNotImplemented,
+            67 => Error::DeleteMethodNotImplemented,
+            68 => Error::UnsupportedVersion,
+            69 => Error::NamesDontMatch,
+            70 => Error::ClassModifiersChangeNotImplemented,
+            71 => Error::MethodModifiersChangeNotImplemented,
+            72 => Error::ClassAttributeChangeNotImplemented,
+            99 => Error::Unimplemented,
+            100 => Error::NullPointer,
             101 => Error::AbsentInformation,
+            102 => Error::InvalidEventType,
+            103 => Error::IllegalArgument,
+            110 => Error::OutOfMemory,
+            111 => Error::AccessDenied,
+            112 => Error::VmDead,
+            113 => Error::Internal,
+            115 => Error::UnattachedThread,
+            500 => Error::InvalidTag,
+            502 => Error::AlreadyInvoking,
+            503 => Error::InvalidIndex,
             504 => Error::InvalidLength,
             506 => Error::InvalidString,
-            99 => Error::Unimplemented,
+            507 => Error::InvalidClassLoader,
+            508 => Error::InvalidArray,
+            509 => Error::TransportLoad,
+            510 => Error::TransportInit,
+            511 => Error::NativeMethod,
+            512 => Error::InvalidCount,
             _ => { return None; }
         });
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug,Clone,Copy,PartialEq)]
 pub enum Tag {
     Array(u64),
     Byte(u8),
@@ -54,6 +233,37 @@ pub enum Tag {
     ClassLoader(u64),
     ClassObject(u64),
 }
+
+impl Tag {
+    pub fn tag(&self) -> u8 {
+        return match self {
+            Tag::Array(_) => b'[',
+            Tag::Byte(_) => b'B',
+            Tag::Char(_) => b'C',
+            Tag::Object(_) => b'L',
+            Tag::Float(_) => b'F',
+            Tag::Double(_) => b'D',
+            Tag::Int(_) => b'I',
+            Tag::Long(_) => b'J',
+            Tag::Short(_) => b'S',
+            Tag::Void => b'V',
+            Tag::Boolean(_) => b'Z',
+            Tag::String(_) => b's',
+            Tag::Thread(_) => b't',
+            Tag::ThreadGroup(_) => b'g',
+            Tag::ClassLoader(_) => b'l',
+            Tag::ClassObject(_) => b'c',
+        };
+    }
+    /// The object ID if this is any kind of reference.
+    pub fn object_id(&self) -> Option<u64> {
+        return match self {
+            Tag::Array(id) | Tag::Object(id) | Tag::String(id) | Tag::Thread(id) |
+            Tag::ThreadGroup(id) | Tag::ClassLoader(id) | Tag::ClassObject(id) => Some(*id),
+            _ => None,
+        };
+    }
+}
 /*
 pub enum ArrayRegion {
     Array(Vec<u64>),
@@ -77,6 +287,17 @@ pub enum ArrayRegion {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Names the packet field a deserializer result is for.
+pub trait FieldResult<T> {
+    fn field(self, name: &'static str) -> Result<T>;
+}
+
+impl<T> FieldResult<T> for Result<T> {
+    fn field(self, name: &'static str) -> Result<T> {
+        return self.map_err(|e| Error::Field { name: name, source: Box::new(e) });
+    }
+}
+
 #[derive(Clone,Copy,Default,Debug)]
 pub struct IDSizes {
     pub field: i32,
@@ -96,10 +317,6 @@ impl Serializer {
             self.0.push(b);
         }
     }
-    pub fn write_array<T>(&mut self, data: &[T]) {
-        let (_, data_u8, _) = unsafe { data.align_to() };
-        self.0.extend_from_slice(data_u8);
-    }
     pub fn write_ids(&mut self, data: &[u64], size: i32) {
         for d in data {
             self.write_untagged(*d, size);
@@ -124,16 +341,69 @@ impl Serializer {
         self.write_untagged(data.to_bits() as u64, 4);
     }
     pub fn serialize_double(&mut self, data: f64) {
-        self.write_untagged(data.to_bits() as u64, 8);
+        self.write_untagged(data.to_bits(), 8);
     }
     pub fn serialize_object(&mut self, id: u64) {
         self.write_untagged(id, self.1.object);
     }
-    pub fn serialize_string(&mut self, s: &String) {
-        let sbytes = s.as_bytes();
-        let slen = sbytes.len();
-        self.write_untagged(slen as u64, 4);
-        self.write_array(sbytes);
+    pub fn serialize_string(&mut self, s: &str) {
+        self.serialize_bytes(&mutf8::encode(s));
+    }
+    /// Writes the bytes the string came in as, if decoding it was lossy.
+    pub fn serialize_java_string(&mut self, s: &JavaString) {
+        match &s.raw {
+            Some(raw) => self.serialize_bytes(raw),
+            None => self.serialize_string(&s.text),
+        }
+    }
+    /// A length-prefixed byte array.
+    pub fn serialize_bytes(&mut self, data: &[u8]) {
+        self.serialize_int(data.len() as i32);
+        self.0.extend_from_slice(data);
+    }
+    pub fn serialize_reference_type(&mut self, id: u64) {
+        self.write_untagged(id, self.1.reference_type);
+    }
+    pub fn serialize_method(&mut self, id: u64) {
+        self.write_untagged(id, self.1.method);
+    }
+    pub fn serialize_field(&mut self, id: u64) {
+        self.write_untagged(id, self.1.field);
+    }
+    pub fn serialize_frame(&mut self, id: u64) {
+        self.write_untagged(id, self.1.frame);
+    }
+    pub fn serialize_location(&mut self, location: &Location) {
+        self.serialize_byte(location.type_tag);
+        self.serialize_reference_type(location.class_id);
+        self.serialize_method(location.method_id);
+        self.write_untagged(location.index, 8);
+    }
+    pub fn serialize_untagged_value(&mut self, value: &Tag) {
+        match value {
+            Tag::Array(id) | Tag::Object(id) | Tag::String(id) | Tag::Thread(id) |
+            Tag::ThreadGroup(id) | Tag::ClassLoader(id) | Tag::ClassObject(id) => self.serialize_object(*id),
+            Tag::Byte(b) => self.serialize_byte(*b),
+            Tag::Char(c) => self.serialize_char(*c),
+            Tag::Float(f) => self.serialize_float(*f),
+            Tag::Double(d) => self.serialize_double(*d),
+            Tag::Int(i) => self.serialize_int(*i),
+            Tag::Long(l) => self.serialize_long(*l),
+            Tag::Short(s) => self.write_untagged(*s as u16 as u64, 2),
+            Tag::Void => {},
+            Tag::Boolean(b) => self.serialize_bool(*b),
+        }
+    }
+    pub fn serialize_value(&mut self, value: &Tag) {
+        self.serialize_byte(value.tag());
+        self.serialize_untagged_value(value);
+    }
+    /// Writes an int count followed by each item.
+    pub fn serialize_list<T, F: FnMut(&mut Serializer, &T)>(&mut self, items: &[T], mut f: F) {
+        self.serialize_int(items.len() as i32);
+        for item in items {
+            f(self, item);
+        }
     }
 }
 
@@ -172,15 +442,6 @@ impl<R: Read> Deserializer<R> {
         }
     }
     */
-    pub fn read_array<T>(&mut self, size: usize) -> Result<Vec<T>> {
-        let mut data: Vec<T> = Vec::with_capacity(size);
-        unsafe { data.set_len(size); }
-        let (_, data_u8, _) = unsafe { data.align_to_mut() };
-        if self.0.read_exact(data_u8).is_err() {
-            return Err(Error::AbsentInformation);
-        }
-        return Ok(data);
-    }
     pub fn deserialize_byte(&mut self) -> Result<u8> {
         return Ok(self.read_untagged(1)? as u8);
     }
@@ -200,21 +461,91 @@ impl<R: Read> Deserializer<R> {
         return Ok(f32::from_bits(self.read_untagged(4)? as u32));
     }
     pub fn deserialize_double(&mut self) -> Result<f64> {
-        return Ok(f64::from_bits(self.read_untagged(8)? as u64));
+        return Ok(f64::from_bits(self.read_untagged(8)?));
     }
     pub fn deserialize_object(&mut self) -> Result<u64> {
-        return Ok(self.read_untagged(self.1.object)?);
+        return self.read_untagged(self.1.object);
     }
+    /// A string, with U+FFFD for whatever Rust can't hold, such as an
+    /// unpaired surrogate. Use `deserialize_java_string` to keep those.
     pub fn deserialize_string(&mut self) -> Result<String> {
-        let length = self.read_untagged(4)? as i32;
-        if length < 0 {
+        return Ok(self.deserialize_java_string()?.text);
+    }
+    pub fn deserialize_java_string(&mut self) -> Result<JavaString> {
+        let data = self.deserialize_bytes()?;
+        return Ok(match mutf8::decode(&data) {
+            (text, true) => JavaString { text: text, raw: None },
+            (text, false) => JavaString { text: text, raw: Some(data) },
+        });
+    }
+    pub fn deserialize_bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.deserialize_int()?;
+        if len < 0 {
             return Err(Error::InvalidLength);
         }
-        let data = self.read_array(length as usize)?;
-        return match String::from_utf8(data) {
-            Ok(s) => Ok(s),
-            Err(_) => Err(Error::InvalidString),
-        };
+        let mut data = Vec::new();
+        let read = self.0.by_ref().take(len as u64).read_to_end(&mut data);
+        if read.is_err() || data.len() != len as usize {
+            return Err(Error::InvalidLength);
+        }
+        return Ok(data);
+    }
+    pub fn deserialize_reference_type(&mut self) -> Result<u64> {
+        return self.read_untagged(self.1.reference_type);
+    }
+    pub fn deserialize_method(&mut self) -> Result<u64> {
+        return self.read_untagged(self.1.method);
+    }
+    pub fn deserialize_field(&mut self) -> Result<u64> {
+        return self.read_untagged(self.1.field);
+    }
+    pub fn deserialize_frame(&mut self) -> Result<u64> {
+        return self.read_untagged(self.1.frame);
+    }
+    pub fn deserialize_location(&mut self) -> Result<Location> {
+        return Ok(Location {
+            type_tag: self.deserialize_byte().field("type_tag")?,
+            class_id: self.deserialize_reference_type().field("class_id")?,
+            method_id: self.deserialize_method().field("method_id")?,
+            index: self.read_untagged(8).field("index")?,
+        });
+    }
+    pub fn deserialize_untagged_value(&mut self, tag: u8) -> Result<Tag> {
+        return Ok(match tag {
+            b'[' => Tag::Array(self.deserialize_object()?),
+            b'B' => Tag::Byte(self.deserialize_byte()?),
+            b'C' => Tag::Char(self.deserialize_char()?),
+            b'L' => Tag::Object(self.deserialize_object()?),
+            b'F' => Tag::Float(self.deserialize_float()?),
+            b'D' => Tag::Double(self.deserialize_double()?),
+            b'I' => Tag::Int(self.deserialize_int()?),
+            b'J' => Tag::Long(self.deserialize_long()?),
+            b'S' => Tag::Short(self.read_untagged(2)? as u16 as i16),
+            b'V' => Tag::Void,
+            b'Z' => Tag::Boolean(self.deserialize_boolean()?),
+            b's' => Tag::String(self.deserialize_object()?),
+            b't' => Tag::Thread(self.deserialize_object()?),
+            b'g' => Tag::ThreadGroup(self.deserialize_object()?),
+            b'l' => Tag::ClassLoader(self.deserialize_object()?),
+            b'c' => Tag::ClassObject(self.deserialize_object()?),
+            _ => { return Err(Error::InvalidTag); }
+        });
+    }
+    pub fn deserialize_value(&mut self) -> Result<Tag> {
+        let tag = self.deserialize_byte()?;
+        return self.deserialize_untagged_value(tag);
+    }
+    /// Reads an int count followed by that many items.
+    pub fn deserialize_list<T, F: FnMut(&mut Self) -> Result<T>>(&mut self, mut f: F) -> Result<Vec<T>> {
+        let count = self.deserialize_int()?;
+        if count < 0 {
+            return Err(Error::InvalidLength);
+        }
+        let mut items = Vec::new();
+        for _ in 0..count {
+            items.push(f(self)?);
+        }
+        return Ok(items);
     }
 }
 
@@ -246,8 +577,702 @@ bitflags! {
     }
 }
 
-#[derive(Debug)]
+pub const TYPE_TAG_CLASS: u8 = 1;
+pub const TYPE_TAG_INTERFACE: u8 = 2;
+pub const TYPE_TAG_ARRAY: u8 = 3;
+
+pub const SUSPEND_NONE: u8 = 0;
+pub const SUSPEND_EVENT_THREAD: u8 = 1;
+pub const SUSPEND_ALL: u8 = 2;
+
+/// InvokeMethod options.
+pub const INVOKE_SINGLE_THREADED: i32 = 1;
+pub const INVOKE_NONVIRTUAL: i32 = 2;
+
+/// Whether an array region with this tag holds untagged primitives.
+pub fn is_primitive_tag(tag: u8) -> bool {
+    return matches!(tag, b'B' | b'C' | b'F' | b'D' | b'I' | b'J' | b'S' | b'Z');
+}
+
+/// A string value from the VM. Java strings can hold things a `String`
+/// can't, so when `text` is a lossy copy `raw` keeps the original bytes.
+#[derive(Debug,Clone,PartialEq,Eq,Default)]
+pub struct JavaString {
+    pub text: String,
+    pub raw: Option<Vec<u8>>,
+}
+
+impl From<&str> for JavaString {
+    fn from(text: &str) -> JavaString {
+        return JavaString { text: text.to_string(), raw: None };
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash,Default)]
+pub struct Location {
+    pub type_tag: u8,
+    pub class_id: u64,
+    pub method_id: u64,
+    pub index: u64,
+}
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct ClassRef {
+    pub ref_type_tag: u8,
+    pub type_id: u64,
+    pub status: i32,
+}
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct ClassInfo {
+    pub ref_type_tag: u8,
+    pub type_id: u64,
+    pub signature: String,
+    pub status: i32,
+}
+
+/// A class from AllClassesWithGeneric. `generic_signature` is empty for
+/// classes that aren't generic.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct GenericClassInfo {
+    pub ref_type_tag: u8,
+    pub type_id: u64,
+    pub signature: String,
+    pub generic_signature: String,
+    pub status: i32,
+}
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct FieldInfo {
+    pub field_id: u64,
+    pub name: String,
+    pub signature: String,
+    pub mod_bits: i32,
+}
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct MethodInfo {
+    pub method_id: u64,
+    pub name: String,
+    pub signature: String,
+    pub mod_bits: i32,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct LineEntry {
+    pub code_index: i64,
+    pub line: i32,
+}
+
+/// A local variable, live for `length` code indices from `code_index`.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct VariableInfo {
+    pub code_index: i64,
+    pub name: String,
+    pub signature: String,
+    pub length: i32,
+    pub slot: i32,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct FrameInfo {
+    pub frame_id: u64,
+    pub location: Location,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum EventKind {
+    SingleStep = 1,
+    Breakpoint = 2,
+    FramePop = 3,
+    Exception = 4,
+    UserDefined = 5,
+    ThreadStart = 6,
+    ThreadDeath = 7,
+    ClassPrepare = 8,
+    ClassUnload = 9,
+    ClassLoad = 10,
+    FieldAccess = 20,
+    FieldModification = 21,
+    ExceptionCatch = 30,
+    MethodEntry = 40,
+    MethodExit = 41,
+    MethodExitWithReturnValue = 42,
+    MonitorContendedEnter = 43,
+    MonitorContendedEntered = 44,
+    MonitorWait = 45,
+    MonitorWaited = 46,
+    VMStart = 90,
+    VMDeath = 99,
+    VMDisconnected = 100,
+}
+
+impl EventKind {
+    pub fn deserialize(data: u8) -> Option<EventKind> {
+        return Some(match data {
+            1 => EventKind::SingleStep,
+            2 => EventKind::Breakpoint,
+            3 => EventKind::FramePop,
+            4 => EventKind::Exception,
+            5 => EventKind::UserDefined,
+            6 => EventKind::ThreadStart,
+            7 => EventKind::ThreadDeath,
+            8 => EventKind::ClassPrepare,
+            9 => EventKind::ClassUnload,
+            10 => EventKind::ClassLoad,
+            20 => EventKind::FieldAccess,
+            21 => EventKind::FieldModification,
+            30 => EventKind::ExceptionCatch,
+            40 => EventKind::MethodEntry,
+            41 => EventKind::MethodExit,
+            42 => EventKind::MethodExitWithReturnValue,
+            43 => EventKind::MonitorContendedEnter,
+            44 => EventKind::MonitorContendedEntered,
+            45 => EventKind::MonitorWait,
+            46 => EventKind::MonitorWaited,
+            90 => EventKind::VMStart,
+            99 => EventKind::VMDeath,
+            100 => EventKind::VMDisconnected,
+            _ => { return None; }
+        });
+    }
+}
+
+/// Filters attached to an EventRequest.Set command.
+#[derive(Debug,Clone,PartialEq)]
+pub enum Modifier {
+    Count(i32),
+    Conditional(i32),
+    ThreadOnly(u64),
+    ClassOnly(u64),
+    ClassMatch(String),
+    ClassExclude(String),
+    LocationOnly(Location),
+    ExceptionOnly { ref_type: u64, caught: bool, uncaught: bool },
+    FieldOnly { declaring: u64, field_id: u64 },
+    Step { thread: u64, size: i32, depth: i32 },
+    InstanceOnly(u64),
+    SourceNameMatch(String),
+}
+
+impl Modifier {
+    pub fn serialize(&self, serializer: &mut Serializer) {
+        match self {
+            Modifier::Count(count) => {
+                serializer.serialize_byte(1);
+                serializer.serialize_int(*count);
+            },
+            Modifier::Conditional(expr_id) => {
+                serializer.serialize_byte(2);
+                serializer.serialize_int(*expr_id);
+            },
+            Modifier::ThreadOnly(thread) => {
+                serializer.serialize_byte(3);
+                serializer.serialize_object(*thread);
+            },
+            Modifier::ClassOnly(clazz) => {
+                serializer.serialize_byte(4);
+                serializer.serialize_reference_type(*clazz);
+            },
+            Modifier::ClassMatch(pattern) => {
+                serializer.serialize_byte(5);
+                serializer.serialize_string(pattern);
+            },
+            Modifier::ClassExclude(pattern) => {
+                serializer.serialize_byte(6);
+                serializer.serialize_string(pattern);
+            },
+            Modifier::LocationOnly(location) => {
+                serializer.serialize_byte(7);
+                serializer.serialize_location(location);
+            },
+            Modifier::ExceptionOnly { ref_type, caught, uncaught } => {
+                serializer.serialize_byte(8);
+                serializer.serialize_reference_type(*ref_type);
+                serializer.serialize_bool(*caught);
+                serializer.serialize_bool(*uncaught);
+            },
+            Modifier::FieldOnly { declaring, field_id } => {
+                serializer.serialize_byte(9);
+                serializer.serialize_reference_type(*declaring);
+                serializer.serialize_field(*field_id);
+            },
+            Modifier::Step { thread, size, depth } => {
+                serializer.serialize_byte(10);
+                serializer.serialize_object(*thread);
+                serializer.serialize_int(*size);
+                serializer.serialize_int(*depth);
+            },
+            Modifier::InstanceOnly(instance) => {
+                serializer.serialize_byte(11);
+                serializer.serialize_object(*instance);
+            },
+            Modifier::SourceNameMatch(pattern) => {
+                serializer.serialize_byte(12);
+                serializer.serialize_string(pattern);
+            },
+        }
+    }
+    pub fn deserialize<R: Read>(deserializer: &mut Deserializer<R>) -> Result<Modifier> {
+        return Ok(match deserializer.deserialize_byte().field("mod_kind")? {
+            1 => Modifier::Count(deserializer.deserialize_int().field("count")?),
+            2 => Modifier::Conditional(deserializer.deserialize_int().field("expr_id")?),
+            3 => Modifier::ThreadOnly(deserializer.deserialize_object().field("thread")?),
+            4 => Modifier::ClassOnly(deserializer.deserialize_reference_type().field("clazz")?),
+            5 => Modifier::ClassMatch(deserializer.deserialize_string().field("class_pattern")?),
+            6 => Modifier::ClassExclude(deserializer.deserialize_string().field("class_pattern")?),
+            7 => Modifier::LocationOnly(deserializer.deserialize_location().field("loc")?),
+            8 => Modifier::ExceptionOnly {
+                ref_type: deserializer.deserialize_reference_type().field("ref_type")?,
+                caught: deserializer.deserialize_boolean().field("caught")?,
+                uncaught: deserializer.deserialize_boolean().field("uncaught")?,
+            },
+            9 => Modifier::FieldOnly {
+                declaring: deserializer.deserialize_reference_type().field("declaring")?,
+                field_id: deserializer.deserialize_field().field("field_id")?,
+            },
+            10 => Modifier::Step {
+                thread: deserializer.deserialize_object().field("thread")?,
+                size: deserializer.deserialize_int().field("size")?,
+                depth: deserializer.deserialize_int().field("depth")?,
+            },
+            11 => Modifier::InstanceOnly(deserializer.deserialize_object().field("instance")?),
+            12 => Modifier::SourceNameMatch(deserializer.deserialize_string().field("source_name_pattern")?),
+            _ => { return Err(Error::IllegalArgument); }
+        });
+    }
+}
+
+/// One entry of an Event.Composite command.
+#[derive(Debug,Clone,PartialEq)]
+pub enum Event {
+    VMStart { request_id: i32, thread: u64 },
+    VMDeath { request_id: i32 },
+    SingleStep { request_id: i32, thread: u64, location: Location },
+    Breakpoint { request_id: i32, thread: u64, location: Location },
+    MethodEntry { request_id: i32, thread: u64, location: Location },
+    MethodExit { request_id: i32, thread: u64, location: Location },
+    MethodExitWithReturnValue { request_id: i32, thread: u64, location: Location, value: Tag },
+    MonitorContendedEnter { request_id: i32, thread: u64, object: Tag, location: Location },
+    MonitorContendedEntered { request_id: i32, thread: u64, object: Tag, location: Location },
+    MonitorWait { request_id: i32, thread: u64, object: Tag, location: Location, timeout: i64 },
+    MonitorWaited { request_id: i32, thread: u64, object: Tag, location: Location, timed_out: bool },
+    Exception { request_id: i32, thread: u64, location: Location, exception: Tag, catch_location: Location },
+    ThreadStart { request_id: i32, thread: u64 },
+    ThreadDeath { request_id: i32, thread: u64 },
+    ClassPrepare { request_id: i32, thread: u64, ref_type_tag: u8, type_id: u64, signature: String, status: i32 },
+    ClassUnload { request_id: i32, signature: String },
+    FieldAccess { request_id: i32, thread: u64, location: Location, ref_type_tag: u8, type_id: u64, field_id: u64, object: Tag },
+    FieldModification { request_id: i32, thread: u64, location: Location, ref_type_tag: u8, type_id: u64, field_id: u64, object: Tag, value: Tag },
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        return match self {
+            Event::VMStart { .. } => EventKind::VMStart,
+            Event::VMDeath { .. } => EventKind::VMDeath,
+            Event::SingleStep { .. } => EventKind::SingleStep,
+            Event::Breakpoint { .. } => EventKind::Breakpoint,
+            Event::MethodEntry { .. } => EventKind::MethodEntry,
+            Event::MethodExit { .. } => EventKind::MethodExit,
+            Event::MethodExitWithReturnValue { .. } => EventKind::MethodExitWithReturnValue,
+            Event::MonitorContendedEnter { .. } => EventKind::MonitorContendedEnter,
+            Event::MonitorContendedEntered { .. } => EventKind::MonitorContendedEntered,
+            Event::MonitorWait { .. } => EventKind::MonitorWait,
+            Event::MonitorWaited { .. } => EventKind::MonitorWaited,
+            Event::Exception { .. } => EventKind::Exception,
+            Event::ThreadStart { .. } => EventKind::ThreadStart,
+            Event::ThreadDeath { .. } => EventKind::ThreadDeath,
+            Event::ClassPrepare { .. } => EventKind::ClassPrepare,
+            Event::ClassUnload { .. } => EventKind::ClassUnload,
+            Event::FieldAccess { .. } => EventKind::FieldAccess,
+            Event::FieldModification { .. } => EventKind::FieldModification,
+        };
+    }
+    pub fn request_id(&self) -> i32 {
+        return match self {
+            Event::VMStart { request_id, .. } | Event::VMDeath { request_id } |
+            Event::SingleStep { request_id, .. } | Event::Breakpoint { request_id, .. } |
+            Event::MethodEntry { request_id, .. } | Event::MethodExit { request_id, .. } |
+            Event::MethodExitWithReturnValue { request_id, .. } |
+            Event::MonitorContendedEnter { request_id, .. } | Event::MonitorContendedEntered { request_id, .. } |
+            Event::MonitorWait { request_id, .. } | Event::MonitorWaited { request_id, .. } |
+            Event::Exception { request_id, .. } | Event::ThreadStart { request_id, .. } |
+            Event::ThreadDeath { request_id, .. } | Event::ClassPrepare { request_id, .. } |
+            Event::ClassUnload { request_id, .. } | Event::FieldAccess { request_id, .. } |
+            Event::FieldModification { request_id, .. } => *request_id,
+        };
+    }
+    pub fn thread(&self) -> Option<u64> {
+        return match self {
+            Event::VMDeath { .. } | Event::ClassUnload { .. } => None,
+            Event::VMStart { thread, .. } | Event::SingleStep { thread, .. } |
+            Event::Breakpoint { thread, .. } | Event::MethodEntry { thread, .. } |
+            Event::MethodExit { thread, .. } | Event::MethodExitWithReturnValue { thread, .. } |
+            Event::MonitorContendedEnter { thread, .. } | Event::MonitorContendedEntered { thread, .. } |
+            Event::MonitorWait { thread, .. } | Event::MonitorWaited { thread, .. } |
+            Event::Exception { thread, .. } | Event::ThreadStart { thread, .. } |
+            Event::ThreadDeath { thread, .. } | Event::ClassPrepare { thread, .. } |
+            Event::FieldAccess { thread, .. } | Event::FieldModification { thread, .. } => Some(*thread),
+        };
+    }
+    pub fn location(&self) -> Option<Location> {
+        return match self {
+            Event::SingleStep { location, .. } | Event::Breakpoint { location, .. } |
+            Event::MethodEntry { location, .. } | Event::MethodExit { location, .. } |
+            Event::MethodExitWithReturnValue { location, .. } |
+            Event::MonitorContendedEnter { location, .. } | Event::MonitorContendedEntered { location, .. } |
+            Event::MonitorWait { location, .. } | Event::MonitorWaited { location, .. } |
+            Event::Exception { location, .. } | Event::FieldAccess { location, .. } |
+            Event::FieldModification { location, .. } => Some(*location),
+            _ => None,
+        };
+    }
+    pub fn serialize(&self, serializer: &mut Serializer) {
+        serializer.serialize_byte(self.kind() as u8);
+        serializer.serialize_int(self.request_id());
+        match self {
+            Event::VMDeath { .. } => {},
+            Event::VMStart { thread, .. } | Event::ThreadStart { thread, .. } | Event::ThreadDeath { thread, .. } => {
+                serializer.serialize_object(*thread);
+            },
+            Event::SingleStep { thread, location, .. } | Event::Breakpoint { thread, location, .. } |
+            Event::MethodEntry { thread, location, .. } | Event::MethodExit { thread, location, .. } => {
+                serializer.serialize_object(*thread);
+                serializer.serialize_location(location);
+            },
+            Event::MethodExitWithReturnValue { thread, location, value, .. } => {
+                serializer.serialize_object(*thread);
+                serializer.serialize_location(location);
+                serializer.serialize_value(value);
+            },
+            Event::MonitorContendedEnter { thread, object, location, .. } |
+            Event::MonitorContendedEntered { thread, object, location, .. } => {
+                serializer.serialize_object(*thread);
+                serializer.serialize_value(object);
+                serializer.serialize_location(location);
+            },
+            Event::MonitorWait { thread, object, location, timeout, .. } => {
+                serializer.serialize_object(*thread);
+                serializer.serialize_value(object);
+                serializer.serialize_location(location);
+                serializer.serialize_long(*timeout);
+            },
+            Event::MonitorWaited { thread, object, location, timed_out, .. } => {
+                serializer.serialize_object(*thread);
+                serializer.serialize_value(object);
+                serializer.serialize_location(location);
+                serializer.serialize_bool(*timed_out);
+            },
+            Event::Exception { thread, location, exception, catch_location, .. } => {
+                serializer.serialize_object(*thread);
+                serializer.serialize_location(location);
+                serializer.serialize_value(exception);
+                serializer.serialize_location(catch_location);
+            },
+            Event::ClassPrepare { thread, ref_type_tag, type_id, signature, status, .. } => {
+                serializer.serialize_object(*thread);
+                serializer.serialize_byte(*ref_type_tag);
+                serializer.serialize_reference_type(*type_id);
+                serializer.serialize_string(signature);
+                serializer.serialize_int(*status);
+            },
+            Event::ClassUnload { signature, .. } => {
+                serializer.serialize_string(signature);
+            },
+            Event::FieldAccess { thread, location, ref_type_tag, type_id, field_id, object, .. } => {
+                serializer.serialize_object(*thread);
+                serializer.serialize_location(location);
+                serializer.serialize_byte(*ref_type_tag);
+                serializer.serialize_reference_type(*type_id);
+                serializer.serialize_field(*field_id);
+                serializer.serialize_value(object);
+            },
+            Event::FieldModification { thread, location, ref_type_tag, type_id, field_id, object, value, .. } => {
+                serializer.serialize_object(*thread);
+                serializer.serialize_location(location);
+                serializer.serialize_byte(*ref_type_tag);
+                serializer.serialize_reference_type(*type_id);
+                serializer.serialize_field(*field_id);
+                serializer.serialize_value(object);
+                serializer.serialize_value(value);
+            },
+        }
+    }
+    pub fn deserialize<R: Read>(deserializer: &mut Deserializer<R>) -> Result<Event> {
+        let kind = match EventKind::deserialize(deserializer.deserialize_byte().field("event_kind")?) {
+            Some(k) => k,
+            None => { return Err(Error::InvalidEventType); }
+        };
+        let request_id = deserializer.deserialize_int().field("request_id")?;
+        let d = deserializer;
+        return Ok(match kind {
+            EventKind::VMStart => Event::VMStart { request_id: request_id, thread: d.deserialize_object().field("thread")? },
+            EventKind::VMDeath => Event::VMDeath { request_id: request_id },
+            EventKind::ThreadStart => Event::ThreadStart { request_id: request_id, thread: d.deserialize_object().field("thread")? },
+            EventKind::ThreadDeath => Event::ThreadDeath { request_id: request_id, thread: d.deserialize_object().field("thread")? },
+            EventKind::SingleStep => Event::SingleStep {
+                request_id: request_id,
+                thread: d.deserialize_object().field("thread")?,
+                location: d.deserialize_location().field("location")?,
+            },
+            EventKind::Breakpoint => Event::Breakpoint {
+                request_id: request_id,
+                thread: d.deserialize_object().field("thread")?,
+                location: d.deserialize_location().field("location")?,
+            },
+            EventKind::MethodEntry => Event::MethodEntry {
+                request_id: request_id,
+                thread: d.deserialize_object().field("thread")?,
+                location: d.deserialize_location().field("location")?,
+            },
+            EventKind::MethodExit => Event::MethodExit {
+                request_id: request_id,
+                thread: d.deserialize_object().field("thread")?,
+                location: d.deserialize_location().field("location")?,
+            },
+            EventKind::MethodExitWithReturnValue => Event::MethodExitWithReturnValue {
+                request_id: request_id,
+                thread: d.deserialize_object().field("thread")?,
+                location: d.deserialize_location().field("location")?,
+                value: d.deserialize_value().field("value")?,
+            },
+            EventKind::MonitorContendedEnter => Event::MonitorContendedEnter {
+                request_id: request_id,
+                thread: d.deserialize_object().field("thread")?,
+                object: d.deserialize_value().field("object")?,
+                location: d.deserialize_location().field("location")?,
+            },
+            EventKind::MonitorContendedEntered => Event::MonitorContendedEntered {
+                request_id: request_id,
+                thread: d.deserialize_object().field("thread")?,
+                object: d.deserialize_value().field("object")?,
+                location: d.deserialize_location().field("location")?,
+            },
+            EventKind::MonitorWait => Event::MonitorWait {
+                request_id: request_id,
+                thread: d.deserialize_object().field("thread")?,
+                object: d.deserialize_value().field("object")?,
+                location: d.deserialize_location().field("location")?,
+                timeout: d.deserialize_long().field("timeout")?,
+            },
+            EventKind::MonitorWaited => Event::MonitorWaited {
+                request_id: request_id,
+                thread: d.deserialize_object().field("thread")?,
+                object: d.deserialize_value().field("object")?,
+                location: d.deserialize_location().field("location")?,
+                timed_out: d.deserialize_boolean().field("timed_out")?,
+            },
+            EventKind::Exception => Event::Exception {
+                request_id: request_id,
+                thread: d.deserialize_object().field("thread")?,
+                location: d.deserialize_location().field("location")?,
+                exception: d.deserialize_value().field("exception")?,
+                catch_location: d.deserialize_location().field("catch_location")?,
+            },
+            EventKind::ClassPrepare => Event::ClassPrepare {
+                request_id: request_id,
+                thread: d.deserialize_object().field("thread")?,
+                ref_type_tag: d.deserialize_byte().field("ref_type_tag")?,
+                type_id: d.deserialize_reference_type().field("type_id")?,
+                signature: d.deserialize_string().field("signature")?,
+                status: d.deserialize_int().field("status")?,
+            },
+            EventKind::ClassUnload => Event::ClassUnload {
+                request_id: request_id,
+                signature: d.deserialize_string().field("signature")?,
+            },
+            EventKind::FieldAccess => Event::FieldAccess {
+                request_id: request_id,
+                thread: d.deserialize_object().field("thread")?,
+                location: d.deserialize_location().field("location")?,
+                ref_type_tag: d.deserialize_byte().field("ref_type_tag")?,
+                type_id: d.deserialize_reference_type().field("type_id")?,
+                field_id: d.deserialize_field().field("field_id")?,
+                object: d.deserialize_value().field("object")?,
+            },
+            EventKind::FieldModification => Event::FieldModification {
+                request_id: request_id,
+                thread: d.deserialize_object().field("thread")?,
+                location: d.deserialize_location().field("location")?,
+                ref_type_tag: d.deserialize_byte().field("ref_type_tag")?,
+                type_id: d.deserialize_reference_type().field("type_id")?,
+                field_id: d.deserialize_field().field("field_id")?,
+                object: d.deserialize_value().field("object")?,
+                value: d.deserialize_value().field("value")?,
+            },
+            _ => { return Err(Error::InvalidEventType); }
+        });
+    }
+}
+
+/// Human readable "Set.Command" name, also for commands we cannot decode.
+pub fn command_name(set: u8, cmd: u8) -> String {
+    let (set_name, cmd_name) = match set {
+        1 => ("VirtualMachine", match cmd {
+            1 => "Version",
+            2 => "ClassesBySignature",
+            3 => "AllClasses",
+            4 => "AllThreads",
+            5 => "TopLevelThreadGroups",
+            6 => "Dispose",
+            7 => "IDSizes",
+            8 => "Suspend",
+            9 => "Resume",
+            10 => "Exit",
+            11 => "CreateString",
+            12 => "Capabilities",
+            13 => "ClassPaths",
+            14 => "DisposeObjects",
+            15 => "HoldEvents",
+            16 => "ReleaseEvents",
+            17 => "CapabilitiesNew",
+            18 => "RedefineClasses",
+            19 => "SetDefaultStratum",
+            20 => "AllClassesWithGeneric",
+            21 => "InstanceCounts",
+            22 => "AllModules",
+            _ => "",
+        }),
+        2 => ("ReferenceType", match cmd {
+            1 => "Signature",
+            2 => "ClassLoader",
+            3 => "Modifiers",
+            4 => "Fields",
+            5 => "Methods",
+            6 => "GetValues",
+            7 => "SourceFile",
+            8 => "NestedTypes",
+            9 => "Status",
+            10 => "Interfaces",
+            11 => "ClassObject",
+            12 => "SourceDebugExtension",
+            13 => "SignatureWithGeneric",
+            14 => "FieldsWithGeneric",
+            15 => "MethodsWithGeneric",
+            16 => "Instances",
+            17 => "ClassFileVersion",
+            18 => "ConstantPool",
+            19 => "Module",
+            _ => "",
+        }),
+        3 => ("ClassType", match cmd {
+            1 => "Superclass",
+            2 => "SetValues",
+            3 => "InvokeMethod",
+            4 => "NewInstance",
+            _ => "",
+        }),
+        4 => ("ArrayType", match cmd {
+            1 => "NewInstance",
+            _ => "",
+        }),
+        5 => ("InterfaceType", match cmd {
+            1 => "InvokeMethod",
+            _ => "",
+        }),
+        6 => ("Method", match cmd {
+            1 => "LineTable",
+            2 => "VariableTable",
+            3 => "Bytecodes",
+            4 => "IsObsolete",
+            5 => "VariableTableWithGeneric",
+            _ => "",
+        }),
+        9 => ("ObjectReference", match cmd {
+            1 => "ReferenceType",
+            2 => "GetValues",
+            3 => "SetValues",
+            5 => "MonitorInfo",
+            6 => "InvokeMethod",
+            7 => "DisableCollection",
+            8 => "EnableCollection",
+            9 => "IsCollected",
+            10 => "ReferringObjects",
+            _ => "",
+        }),
+        10 => ("StringReference", match cmd {
+            1 => "Value",
+            _ => "",
+        }),
+        11 => ("ThreadReference", match cmd {
+            1 => "Name",
+            2 => "Suspend",
+            3 => "Resume",
+            4 => "Status",
+            5 => "ThreadGroup",
+            6 => "Frames",
+            7 => "FrameCount",
+            8 => "OwnedMonitors",
+            9 => "CurrentContendedMonitor",
+            10 => "Stop",
+            11 => "Interrupt",
+            12 => "SuspendCount",
+            13 => "OwnedMonitorsStackDepthInfo",
+            14 => "ForceEarlyReturn",
+            _ => "",
+        }),
+        12 => ("ThreadGroupReference", match cmd {
+            1 => "Name",
+            2 => "Parent",
+            3 => "Children",
+            _ => "",
+        }),
+        13 => ("ArrayReference", match cmd {
+            1 => "Length",
+            2 => "GetValues",
+            3 => "SetValues",
+            _ => "",
+        }),
+        14 => ("ClassLoaderReference", match cmd {
+            1 => "VisibleClasses",
+            _ => "",
+        }),
+        15 => ("EventRequest", match cmd {
+            1 => "Set",
+            2 => "Clear",
+            3 => "ClearAllBreakpoints",
+            _ => "",
+        }),
+        16 => ("StackFrame", match cmd {
+            1 => "GetValues",
+            2 => "SetValues",
+            3 => "ThisObject",
+            4 => "PopFrames",
+            _ => "",
+        }),
+        17 => ("ClassObjectReference", match cmd {
+            1 => "ReflectedType",
+            _ => "",
+        }),
+        18 => ("ModuleReference", match cmd {
+            1 => "Name",
+            2 => "ClassLoader",
+            _ => "",
+        }),
+        64 => ("Event", match cmd {
+            100 => "Composite",
+            _ => "",
+        }),
+        199 => ("DDM", match cmd {
+            1 => "Chunk",
+            _ => "",
+        }),
+        _ => ("", ""),
+    };
+    if set_name.is_empty() {
+        return format!("Unknown({}/{})", set, cmd);
+    }
+    if cmd_name.is_empty() {
+        return format!("{}.Unknown({})", set_name, cmd);
+    }
+    return format!("{}.{}", set_name, cmd_name);
+}
+
+#[derive(Debug,Clone,PartialEq)]
 pub enum Reply {
+    /// Any command whose reply carries no data.
+    Empty,
     Version {
         description: String,
         major: i32,
@@ -255,6 +1280,10 @@ pub enum Reply {
         version: String,
         name: String,
     },
+    ClassesBySignature(Vec<ClassRef>),
+    AllClasses(Vec<ClassInfo>),
+    AllClassesWithGeneric(Vec<GenericClassInfo>),
+    AllThreads(Vec<u64>),
     Capabilities(Capabilities),
     CapabilitiesNew(Capabilities),
     IDSizes {
@@ -264,12 +1293,59 @@ pub enum Reply {
         reference_type: i32,
         frame: i32,
     },
+    ReferenceTypeSignature(String),
+    /// Access flags as in the class file.
+    ReferenceTypeModifiers(i32),
+    ReferenceTypeFields(Vec<FieldInfo>),
+    ReferenceTypeMethods(Vec<MethodInfo>),
+    ReferenceTypeSourceFile(String),
+    MethodLineTable {
+        start: i64,
+        end: i64,
+        lines: Vec<LineEntry>,
+    },
+    ThreadReferenceName(String),
+    ThreadReferenceStatus {
+        thread_status: i32,
+        suspend_status: i32,
+    },
+    ThreadReferenceFrames(Vec<FrameInfo>),
+    ThreadReferenceFrameCount(i32),
+    EventRequestSet(i32),
+    CreateString(u64),
+    /// Tagged values from ReferenceType, ObjectReference or StackFrame GetValues.
+    Values(Vec<Tag>),
+    ReferenceTypeInterfaces(Vec<u64>),
+    ClassTypeSuperclass(u64),
+    /// From either ClassType or ObjectReference InvokeMethod.
+    InvokeMethod { return_value: Tag, exception: Tag },
+    MethodVariableTable { arg_count: i32, variables: Vec<VariableInfo> },
+    ObjectReferenceReferenceType { ref_type_tag: u8, type_id: u64 },
+    StringReferenceValue(JavaString),
+    ArrayReferenceLength(i32),
+    /// `tag` says whether the values went over the wire untagged.
+    ArrayReferenceGetValues { tag: u8, values: Vec<Tag> },
+    StackFrameThisObject(Tag),
+    ObjectReferenceMonitorInfo { owner: u64, entry_count: i32, waiters: Vec<u64> },
+    /// ObjectReference.IsCollected.
+    IsCollected(bool),
+    /// Tagged objects from ReferenceType.Instances or ObjectReference.ReferringObjects.
+    Objects(Vec<Tag>),
+    InstanceCounts(Vec<i64>),
+    ThreadReferenceOwnedMonitors(Vec<Tag>),
+    /// The monitor and the stack depth of the frame that locked it.
+    ThreadReferenceOwnedMonitorsStackDepthInfo(Vec<(Tag, i32)>),
+    /// A null object if the thread isn't waiting for a monitor.
+    ThreadReferenceCurrentContendedMonitor(Tag),
+    /// Zero or more DDM chunks sent back for a DDM.Chunk command.
+    DdmChunks(Vec<Chunk>),
 }
 
 impl Reply {
     pub fn serialize(&self, sizes: IDSizes) -> Vec<u8> {
         let mut serializer = Serializer(Vec::new(), sizes);
         match self {
+            Reply::Empty => {},
             Reply::Version { description, major, minor, version, name } => {
                 serializer.serialize_string(description);
                 serializer.serialize_int(*major);
@@ -277,6 +1353,33 @@ impl Reply {
                 serializer.serialize_string(version);
                 serializer.serialize_string(name);
             },
+            Reply::ClassesBySignature(classes) => {
+                serializer.serialize_list(classes, |s, c| {
+                    s.serialize_byte(c.ref_type_tag);
+                    s.serialize_reference_type(c.type_id);
+                    s.serialize_int(c.status);
+                });
+            },
+            Reply::AllClasses(classes) => {
+                serializer.serialize_list(classes, |s, c| {
+                    s.serialize_byte(c.ref_type_tag);
+                    s.serialize_reference_type(c.type_id);
+                    s.serialize_string(&c.signature);
+                    s.serialize_int(c.status);
+                });
+            },
+            Reply::AllClassesWithGeneric(classes) => {
+                serializer.serialize_list(classes, |s, c| {
+                    s.serialize_byte(c.ref_type_tag);
+                    s.serialize_reference_type(c.type_id);
+                    s.serialize_string(&c.signature);
+                    s.serialize_string(&c.generic_signature);
+                    s.serialize_int(c.status);
+                });
+            },
+            Reply::AllThreads(threads) => {
+                serializer.serialize_list(threads, |s, t| s.serialize_object(*t));
+            },
             Reply::Capabilities(capabilities) => {
                 let bits = capabilities.bits();
                 for i in 0..7 { serializer.serialize_byte((bits >> i & 1) as u8); }
@@ -284,6 +1387,8 @@ impl Reply {
             Reply::CapabilitiesNew(capabilities) => {
                  let bits = capabilities.bits();
                  for i in 0..21 { serializer.serialize_byte((bits >> i & 1) as u8); }
+                 // Reserved capabilities, always false.
+                 for _ in 21..32 { serializer.serialize_byte(0); }
             }
             Reply::IDSizes {field, method, object, reference_type, frame } => {
                 serializer.serialize_int(*field);
@@ -291,25 +1396,162 @@ impl Reply {
                 serializer.serialize_int(*object);
                 serializer.serialize_int(*reference_type);
                 serializer.serialize_int(*frame);
-            }
+            },
+            Reply::ReferenceTypeSignature(s) | Reply::ReferenceTypeSourceFile(s) | Reply::ThreadReferenceName(s) => {
+                serializer.serialize_string(s);
+            },
+            Reply::ReferenceTypeFields(fields) => {
+                serializer.serialize_list(fields, |s, f| {
+                    s.serialize_field(f.field_id);
+                    s.serialize_string(&f.name);
+                    s.serialize_string(&f.signature);
+                    s.serialize_int(f.mod_bits);
+                });
+            },
+            Reply::ReferenceTypeMethods(methods) => {
+                serializer.serialize_list(methods, |s, m| {
+                    s.serialize_method(m.method_id);
+                    s.serialize_string(&m.name);
+                    s.serialize_string(&m.signature);
+                    s.serialize_int(m.mod_bits);
+                });
+            },
+            Reply::MethodLineTable { start, end, lines } => {
+                serializer.serialize_long(*start);
+                serializer.serialize_long(*end);
+                serializer.serialize_list(lines, |s, l| {
+                    s.serialize_long(l.code_index);
+                    s.serialize_int(l.line);
+                });
+            },
+            Reply::ThreadReferenceStatus { thread_status, suspend_status } => {
+                serializer.serialize_int(*thread_status);
+                serializer.serialize_int(*suspend_status);
+            },
+            Reply::ThreadReferenceFrames(frames) => {
+                serializer.serialize_list(frames, |s, f| {
+                    s.serialize_frame(f.frame_id);
+                    s.serialize_location(&f.location);
+                });
+            },
+            Reply::ThreadReferenceFrameCount(n) | Reply::EventRequestSet(n) => {
+                serializer.serialize_int(*n);
+            },
+            Reply::DdmChunks(chunks) => {
+                for chunk in chunks {
+                    chunk.serialize(&mut serializer);
+                }
+            },
+            Reply::CreateString(id) => {
+                serializer.serialize_object(*id);
+            },
+            Reply::ClassTypeSuperclass(id) => {
+                serializer.serialize_reference_type(*id);
+            },
+            Reply::ReferenceTypeModifiers(bits) => serializer.serialize_int(*bits),
+            Reply::Values(values) => {
+                serializer.serialize_list(values, |s, v| s.serialize_value(v));
+            },
+            Reply::ReferenceTypeInterfaces(interfaces) => {
+                serializer.serialize_list(interfaces, |s, i| s.serialize_reference_type(*i));
+            },
+            Reply::InvokeMethod { return_value, exception } => {
+                serializer.serialize_value(return_value);
+                serializer.serialize_value(exception);
+            },
+            Reply::MethodVariableTable { arg_count, variables } => {
+                serializer.serialize_int(*arg_count);
+                serializer.serialize_list(variables, |s, v| {
+                    s.serialize_long(v.code_index);
+                    s.serialize_string(&v.name);
+                    s.serialize_string(&v.signature);
+                    s.serialize_int(v.length);
+                    s.serialize_int(v.slot);
+                });
+            },
+            Reply::ObjectReferenceReferenceType { ref_type_tag, type_id } => {
+                serializer.serialize_byte(*ref_type_tag);
+                serializer.serialize_reference_type(*type_id);
+            },
+            Reply::StringReferenceValue(s) => {
+                serializer.serialize_java_string(s);
+            },
+            Reply::ArrayReferenceLength(n) => {
+                serializer.serialize_int(*n);
+            },
+            Reply::ArrayReferenceGetValues { tag, values } => {
+                serializer.serialize_byte(*tag);
+                if is_primitive_tag(*tag) {
+                    serializer.serialize_list(values, |s, v| s.serialize_untagged_value(v));
+                } else {
+                    serializer.serialize_list(values, |s, v| s.serialize_value(v));
+                }
+            },
+            Reply::StackFrameThisObject(value) | Reply::ThreadReferenceCurrentContendedMonitor(value) => {
+                serializer.serialize_value(value);
+            },
+            Reply::ObjectReferenceMonitorInfo { owner, entry_count, waiters } => {
+                serializer.serialize_object(*owner);
+                serializer.serialize_int(*entry_count);
+                serializer.serialize_list(waiters, |s, t| s.serialize_object(*t));
+            },
+            Reply::Objects(objects) => {
+                serializer.serialize_list(objects, |s, o| s.serialize_value(o));
+            },
+            Reply::InstanceCounts(counts) => {
+                serializer.serialize_list(counts, |s, c| s.serialize_long(*c));
+            },
+            Reply::ThreadReferenceOwnedMonitors(monitors) => {
+                serializer.serialize_list(monitors, |s, m| s.serialize_value(m));
+            },
+            Reply::ThreadReferenceOwnedMonitorsStackDepthInfo(monitors) => {
+                serializer.serialize_list(monitors, |s, (m, depth)| {
+                    s.serialize_value(m);
+                    s.serialize_int(*depth);
+                });
+            },
+            Reply::IsCollected(collected) => serializer.serialize_bool(*collected),
         }
         return serializer.0; 
     }
     pub fn deserialize(set: u8, cmd: u8, data: &[u8], sizes: IDSizes) -> Result<Reply> {
         let mut deserializer = Deserializer(data, sizes);
+        let d = &mut deserializer;
         return Ok(match set {
             1 => match cmd {
                 1 => Reply::Version {
-                    description: deserializer.deserialize_string()?,
-                    major: deserializer.deserialize_int()?,
-                    minor: deserializer.deserialize_int()?,
-                    version: deserializer.deserialize_string()?,
-                    name: deserializer.deserialize_string()?,
+                    description: d.deserialize_string().field("description")?,
+                    major: d.deserialize_int().field("major")?,
+                    minor: d.deserialize_int().field("minor")?,
+                    version: d.deserialize_string().field("version")?,
+                    name: d.deserialize_string().field("name")?,
                 },
+                2 => Reply::ClassesBySignature(d.deserialize_list(|d| Ok(ClassRef {
+                    ref_type_tag: d.deserialize_byte().field("ref_type_tag")?,
+                    type_id: d.deserialize_reference_type().field("type_id")?,
+                    status: d.deserialize_int().field("status")?,
+                })).field("classes")?),
+                3 => Reply::AllClasses(d.deserialize_list(|d| Ok(ClassInfo {
+                    ref_type_tag: d.deserialize_byte().field("ref_type_tag")?,
+                    type_id: d.deserialize_reference_type().field("type_id")?,
+                    signature: d.deserialize_string().field("signature")?,
+                    status: d.deserialize_int().field("status")?,
+                })).field("classes")?),
+                4 => Reply::AllThreads(d.deserialize_list(|d| d.deserialize_object()).field("threads")?),
+                20 => Reply::AllClassesWithGeneric(d.deserialize_list(|d| Ok(GenericClassInfo {
+                    ref_type_tag: d.deserialize_byte().field("ref_type_tag")?,
+                    type_id: d.deserialize_reference_type().field("type_id")?,
+                    signature: d.deserialize_string().field("signature")?,
+                    generic_signature: d.deserialize_string().field("generic_signature")?,
+                    status: d.deserialize_int().field("status")?,
+                })).field("classes")?),
+                6 | 8 | 9 | 10 | 14 | 18 => Reply::Empty,
+                21 => Reply::InstanceCounts(d.deserialize_list(|d| d.deserialize_long()).field("counts")?),
+                11 => Reply::CreateString(d.deserialize_object().field("string_object")?),
                 12 => { 
                     let mut capabilities = 0u32;
                     for i in 0..7 {
-                        if deserializer.deserialize_boolean()? {
+                        if d.deserialize_boolean().field("capabilities")? {
                             capabilities |= 1 << i;
                         }
                     }
@@ -318,18 +1560,149 @@ impl Reply {
                 17 => {
                     let mut capabilities = 0u32;
                     for i in 0..21 {
-                        if deserializer.deserialize_boolean()? {
+                        if d.deserialize_boolean().field("capabilities")? {
                             capabilities |= 1 << i;
                         }
                     }
                     Reply::CapabilitiesNew(Capabilities::from_bits(capabilities).unwrap())
                 },
                 7 => Reply::IDSizes {
-                    field: deserializer.deserialize_int()?,
-                    method: deserializer.deserialize_int()?,
-                    object: deserializer.deserialize_int()?,
-                    reference_type: deserializer.deserialize_int()?,
-                    frame: deserializer.deserialize_int()?,
+                    field: d.deserialize_int().field("field")?,
+                    method: d.deserialize_int().field("method")?,
+                    object: d.deserialize_int().field("object")?,
+                    reference_type: d.deserialize_int().field("reference_type")?,
+                    frame: d.deserialize_int().field("frame")?,
+                },
+                _ => { return Err(Error::Unimplemented); },
+            },
+            2 => match cmd {
+                1 => Reply::ReferenceTypeSignature(d.deserialize_string().field("signature")?),
+                4 => Reply::ReferenceTypeFields(d.deserialize_list(|d| Ok(FieldInfo {
+                    field_id: d.deserialize_field().field("field_id")?,
+                    name: d.deserialize_string().field("name")?,
+                    signature: d.deserialize_string().field("signature")?,
+                    mod_bits: d.deserialize_int().field("mod_bits")?,
+                })).field("declared")?),
+                5 => Reply::ReferenceTypeMethods(d.deserialize_list(|d| Ok(MethodInfo {
+                    method_id: d.deserialize_method().field("method_id")?,
+                    name: d.deserialize_string().field("name")?,
+                    signature: d.deserialize_string().field("signature")?,
+                    mod_bits: d.deserialize_int().field("mod_bits")?,
+                })).field("declared")?),
+                6 => Reply::Values(d.deserialize_list(|d| d.deserialize_value()).field("values")?),
+                3 => Reply::ReferenceTypeModifiers(d.deserialize_int().field("mod_bits")?),
+                7 => Reply::ReferenceTypeSourceFile(d.deserialize_string().field("source_file")?),
+                10 => Reply::ReferenceTypeInterfaces(d.deserialize_list(|d| d.deserialize_reference_type()).field("interfaces")?),
+                16 => Reply::Objects(d.deserialize_list(|d| d.deserialize_value()).field("instances")?),
+                _ => { return Err(Error::Unimplemented); },
+            },
+            3 => match cmd {
+                1 => Reply::ClassTypeSuperclass(d.deserialize_reference_type().field("superclass")?),
+                2 => Reply::Empty,
+                3 => Reply::InvokeMethod {
+                    return_value: d.deserialize_value().field("return_value")?,
+                    exception: d.deserialize_value().field("exception")?,
+                },
+                _ => { return Err(Error::Unimplemented); },
+            },
+            6 => match cmd {
+                1 => Reply::MethodLineTable {
+                    start: d.deserialize_long().field("start")?,
+                    end: d.deserialize_long().field("end")?,
+                    lines: d.deserialize_list(|d| Ok(LineEntry {
+                        code_index: d.deserialize_long().field("code_index")?,
+                        line: d.deserialize_int().field("line")?,
+                    })).field("lines")?,
+                },
+                2 => Reply::MethodVariableTable {
+                    arg_count: d.deserialize_int().field("arg_count")?,
+                    variables: d.deserialize_list(|d| Ok(VariableInfo {
+                        code_index: d.deserialize_long().field("code_index")?,
+                        name: d.deserialize_string().field("name")?,
+                        signature: d.deserialize_string().field("signature")?,
+                        length: d.deserialize_int().field("length")?,
+                        slot: d.deserialize_int().field("slot")?,
+                    })).field("variables")?,
+                },
+                _ => { return Err(Error::Unimplemented); },
+            },
+            9 => match cmd {
+                1 => Reply::ObjectReferenceReferenceType {
+                    ref_type_tag: d.deserialize_byte().field("ref_type_tag")?,
+                    type_id: d.deserialize_reference_type().field("type_id")?,
+                },
+                2 => Reply::Values(d.deserialize_list(|d| d.deserialize_value()).field("values")?),
+                3 | 7 | 8 => Reply::Empty,
+                9 => Reply::IsCollected(d.deserialize_boolean().field("is_collected")?),
+                5 => Reply::ObjectReferenceMonitorInfo {
+                    owner: d.deserialize_object().field("owner")?,
+                    entry_count: d.deserialize_int().field("entry_count")?,
+                    waiters: d.deserialize_list(|d| d.deserialize_object()).field("waiters")?,
+                },
+                6 => Reply::InvokeMethod {
+                    return_value: d.deserialize_value().field("return_value")?,
+                    exception: d.deserialize_value().field("exception")?,
+                },
+                10 => Reply::Objects(d.deserialize_list(|d| d.deserialize_value()).field("instances")?),
+                _ => { return Err(Error::Unimplemented); },
+            },
+            10 => match cmd {
+                1 => Reply::StringReferenceValue(d.deserialize_java_string().field("string_value")?),
+                _ => { return Err(Error::Unimplemented); },
+            },
+            11 => match cmd {
+                1 => Reply::ThreadReferenceName(d.deserialize_string().field("thread_name")?),
+                2 | 3 => Reply::Empty,
+                4 => Reply::ThreadReferenceStatus {
+                    thread_status: d.deserialize_int().field("thread_status")?,
+                    suspend_status: d.deserialize_int().field("suspend_status")?,
+                },
+                6 => Reply::ThreadReferenceFrames(d.deserialize_list(|d| Ok(FrameInfo {
+                    frame_id: d.deserialize_frame().field("frame_id")?,
+                    location: d.deserialize_location().field("location")?,
+                })).field("frames")?),
+                7 => Reply::ThreadReferenceFrameCount(d.deserialize_int().field("frame_count")?),
+                8 => Reply::ThreadReferenceOwnedMonitors(d.deserialize_list(|d| d.deserialize_value()).field("owned")?),
+                9 => Reply::ThreadReferenceCurrentContendedMonitor(d.deserialize_value().field("monitor")?),
+                13 => Reply::ThreadReferenceOwnedMonitorsStackDepthInfo(
+                    d.deserialize_list(|d| Ok((d.deserialize_value().field("monitor")?, d.deserialize_int().field("stack_depth")?))).field("owned")?,
+                ),
+                14 => Reply::Empty,
+                _ => { return Err(Error::Unimplemented); },
+            },
+            13 => match cmd {
+                1 => Reply::ArrayReferenceLength(d.deserialize_int().field("array_length")?),
+                2 => {
+                    let tag = d.deserialize_byte().field("tag")?;
+                    let values = if is_primitive_tag(tag) {
+                        d.deserialize_list(|d| d.deserialize_untagged_value(tag)).field("values")?
+                    } else {
+                        d.deserialize_list(|d| d.deserialize_value()).field("values")?
+                    };
+                    Reply::ArrayReferenceGetValues { tag: tag, values: values }
+                },
+                3 => Reply::Empty,
+                _ => { return Err(Error::Unimplemented); },
+            },
+            16 => match cmd {
+                1 => Reply::Values(d.deserialize_list(|d| d.deserialize_value()).field("values")?),
+                2 => Reply::Empty,
+                3 => Reply::StackFrameThisObject(d.deserialize_value().field("object_this")?),
+                4 => Reply::Empty,
+                _ => { return Err(Error::Unimplemented); },
+            },
+            15 => match cmd {
+                1 => Reply::EventRequestSet(d.deserialize_int().field("request_id")?),
+                2 | 3 => Reply::Empty,
+                _ => { return Err(Error::Unimplemented); },
+            },
+            ddm::DDM_SET => match cmd {
+                ddm::DDM_CHUNK => {
+                    let mut chunks = Vec::new();
+                    while !d.0.is_empty() {
+                        chunks.push(Chunk::deserialize(d)?);
+                    }
+                    Reply::DdmChunks(chunks)
                 },
                 _ => { return Err(Error::Unimplemented); },
             },
@@ -338,47 +1711,572 @@ impl Reply {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug,Clone,PartialEq)]
 pub enum Command {
     Version,
+    ClassesBySignature { signature: String },
+    AllClasses,
+    AllClassesWithGeneric,
+    AllThreads,
+    Dispose,
+    IDSizes,
+    Suspend,
+    Resume,
+    Exit { exit_code: i32 },
+    CreateString { utf: String },
+    /// Drops `count` references to each object, as counted by the VM.
+    DisposeObjects { requests: Vec<(u64, i32)> },
     Capabilities,
     CapabilitiesNew,
-    IDSizes,
+    /// New class file bytes (dex on Android) for each class.
+    RedefineClasses { classes: Vec<(u64, Vec<u8>)> },
+    InstanceCounts { ref_types: Vec<u64> },
+    ReferenceTypeSignature { ref_type: u64 },
+    ReferenceTypeModifiers { ref_type: u64 },
+    ReferenceTypeFields { ref_type: u64 },
+    ReferenceTypeMethods { ref_type: u64 },
+    ReferenceTypeGetValues { ref_type: u64, fields: Vec<u64> },
+    ReferenceTypeSourceFile { ref_type: u64 },
+    ReferenceTypeInterfaces { ref_type: u64 },
+    /// At most `max_instances` of them, or all if 0.
+    ReferenceTypeInstances { ref_type: u64, max_instances: i32 },
+    ClassTypeSuperclass { class: u64 },
+    /// Values go over the wire untagged, so this can only be serialized.
+    ClassTypeSetValues { class: u64, values: Vec<(u64, Tag)> },
+    ClassTypeInvokeMethod { class: u64, thread: u64, method: u64, args: Vec<Tag>, options: i32 },
+    MethodLineTable { ref_type: u64, method: u64 },
+    MethodVariableTable { ref_type: u64, method: u64 },
+    ObjectReferenceReferenceType { object: u64 },
+    ObjectReferenceGetValues { object: u64, fields: Vec<u64> },
+    /// Values go over the wire untagged, so this can only be serialized.
+    ObjectReferenceSetValues { object: u64, values: Vec<(u64, Tag)> },
+    ObjectReferenceMonitorInfo { object: u64 },
+    ObjectReferenceDisableCollection { object: u64 },
+    ObjectReferenceEnableCollection { object: u64 },
+    ObjectReferenceIsCollected { object: u64 },
+    /// At most `max_referrers` of them, or all if 0.
+    ObjectReferenceReferringObjects { object: u64, max_referrers: i32 },
+    ObjectReferenceInvokeMethod { object: u64, thread: u64, class: u64, method: u64, args: Vec<Tag>, options: i32 },
+    StringReferenceValue { string: u64 },
+    ThreadReferenceName { thread: u64 },
+    ThreadReferenceSuspend { thread: u64 },
+    ThreadReferenceResume { thread: u64 },
+    ThreadReferenceStatus { thread: u64 },
+    ThreadReferenceFrames { thread: u64, start: i32, length: i32 },
+    ThreadReferenceFrameCount { thread: u64 },
+    ThreadReferenceOwnedMonitors { thread: u64 },
+    ThreadReferenceCurrentContendedMonitor { thread: u64 },
+    ThreadReferenceOwnedMonitorsStackDepthInfo { thread: u64 },
+    /// Takes effect when the thread resumes.
+    ThreadReferenceForceEarlyReturn { thread: u64, value: Tag },
+    ArrayReferenceLength { array: u64 },
+    ArrayReferenceGetValues { array: u64, first: i32, length: i32 },
+    /// Values go over the wire untagged, so this can only be serialized.
+    ArrayReferenceSetValues { array: u64, first: i32, values: Vec<Tag> },
+    StackFrameGetValues { thread: u64, frame: u64, slots: Vec<(i32, u8)> },
+    StackFrameSetValues { thread: u64, frame: u64, values: Vec<(i32, Tag)> },
+    StackFrameThisObject { thread: u64, frame: u64 },
+    /// Pops `frame` and every frame above it.
+    StackFramePopFrames { thread: u64, frame: u64 },
+    EventRequestSet { event_kind: EventKind, suspend_policy: u8, modifiers: Vec<Modifier> },
+    EventRequestClear { event_kind: EventKind, request_id: i32 },
+    EventRequestClearAllBreakpoints,
+    Composite { suspend_policy: u8, events: Vec<Event> },
+    /// Android DDM traffic. Either side may send these.
+    DdmChunk(Chunk),
 }
 
 impl Command {
     pub fn deserialize(set: u8, cmd: u8, data: &[u8], sizes: IDSizes) -> Result<Command> { 
-        let _deserializer = Deserializer(data, sizes);
+        let mut deserializer = Deserializer(data, sizes);
+        let d = &mut deserializer;
         return Ok(match set {
             1 => match cmd {
                 1 => Command::Version,
+                2 => Command::ClassesBySignature { signature: d.deserialize_string().field("signature")? },
+                3 => Command::AllClasses,
+                20 => Command::AllClassesWithGeneric,
+                4 => Command::AllThreads,
+                6 => Command::Dispose,
+                7 => Command::IDSizes,
+                8 => Command::Suspend,
+                9 => Command::Resume,
+                10 => Command::Exit { exit_code: d.deserialize_int().field("exit_code")? },
+                11 => Command::CreateString { utf: d.deserialize_string().field("utf")? },
                 12 => Command::Capabilities,
+                14 => Command::DisposeObjects {
+                    requests: d.deserialize_list(|d| Ok((d.deserialize_object().field("object")?, d.deserialize_int().field("ref_cnt")?))).field("requests")?,
+                },
                 17 => Command::CapabilitiesNew,
-                7 => Command::IDSizes,
+                18 => Command::RedefineClasses {
+                    classes: d.deserialize_list(|d| Ok((d.deserialize_reference_type().field("ref_type")?, d.deserialize_bytes().field("classfile")?))).field("classes")?,
+                },
+                21 => Command::InstanceCounts { ref_types: d.deserialize_list(|d| d.deserialize_reference_type()).field("ref_types")? },
+                _ => { return Err(Error::Unimplemented) },
+            },
+            2 => {
+                let ref_type = d.deserialize_reference_type().field("ref_type")?;
+                match cmd {
+                    1 => Command::ReferenceTypeSignature { ref_type: ref_type },
+                    3 => Command::ReferenceTypeModifiers { ref_type: ref_type },
+                    4 => Command::ReferenceTypeFields { ref_type: ref_type },
+                    5 => Command::ReferenceTypeMethods { ref_type: ref_type },
+                    6 => Command::ReferenceTypeGetValues {
+                        ref_type: ref_type,
+                        fields: d.deserialize_list(|d| d.deserialize_field()).field("fields")?,
+                    },
+                    7 => Command::ReferenceTypeSourceFile { ref_type: ref_type },
+                    10 => Command::ReferenceTypeInterfaces { ref_type: ref_type },
+                    16 => Command::ReferenceTypeInstances { ref_type: ref_type, max_instances: d.deserialize_int().field("max_instances")? },
+                    _ => { return Err(Error::Unimplemented) },
+                }
+            },
+            3 => match cmd {
+                1 => Command::ClassTypeSuperclass { class: d.deserialize_reference_type().field("class")? },
+                3 => Command::ClassTypeInvokeMethod {
+                    class: d.deserialize_reference_type().field("class")?,
+                    thread: d.deserialize_object().field("thread")?,
+                    method: d.deserialize_method().field("method")?,
+                    args: d.deserialize_list(|d| d.deserialize_value()).field("args")?,
+                    options: d.deserialize_int().field("options")?,
+                },
+                _ => { return Err(Error::Unimplemented) },
+            },
+            6 => match cmd {
+                1 => Command::MethodLineTable {
+                    ref_type: d.deserialize_reference_type().field("ref_type")?,
+                    method: d.deserialize_method().field("method")?,
+                },
+                2 => Command::MethodVariableTable {
+                    ref_type: d.deserialize_reference_type().field("ref_type")?,
+                    method: d.deserialize_method().field("method")?,
+                },
+                _ => { return Err(Error::Unimplemented) },
+            },
+            9 => {
+                let object = d.deserialize_object().field("object")?;
+                match cmd {
+                    1 => Command::ObjectReferenceReferenceType { object: object },
+                    2 => Command::ObjectReferenceGetValues {
+                        object: object,
+                        fields: d.deserialize_list(|d| d.deserialize_field()).field("fields")?,
+                    },
+                    5 => Command::ObjectReferenceMonitorInfo { object: object },
+                    7 => Command::ObjectReferenceDisableCollection { object: object },
+                    8 => Command::ObjectReferenceEnableCollection { object: object },
+                    9 => Command::ObjectReferenceIsCollected { object: object },
+                    10 => Command::ObjectReferenceReferringObjects { object: object, max_referrers: d.deserialize_int().field("max_referrers")? },
+                    6 => Command::ObjectReferenceInvokeMethod {
+                        object: object,
+                        thread: d.deserialize_object().field("thread")?,
+                        class: d.deserialize_reference_type().field("class")?,
+                        method: d.deserialize_method().field("method")?,
+                        args: d.deserialize_list(|d| d.deserialize_value()).field("args")?,
+                        options: d.deserialize_int().field("options")?,
+                    },
+                    _ => { return Err(Error::Unimplemented) },
+                }
+            },
+            10 => match cmd {
+                1 => Command::StringReferenceValue { string: d.deserialize_object().field("string")? },
+                _ => { return Err(Error::Unimplemented) },
+            },
+            11 => {
+                let thread = d.deserialize_object().field("thread")?;
+                match cmd {
+                    1 => Command::ThreadReferenceName { thread: thread },
+                    2 => Command::ThreadReferenceSuspend { thread: thread },
+                    3 => Command::ThreadReferenceResume { thread: thread },
+                    4 => Command::ThreadReferenceStatus { thread: thread },
+                    6 => Command::ThreadReferenceFrames {
+                        thread: thread,
+                        start: d.deserialize_int().field("start")?,
+                        length: d.deserialize_int().field("length")?,
+                    },
+                    7 => Command::ThreadReferenceFrameCount { thread: thread },
+                    8 => Command::ThreadReferenceOwnedMonitors { thread: thread },
+                    9 => Command::ThreadReferenceCurrentContendedMonitor { thread: thread },
+                    13 => Command::ThreadReferenceOwnedMonitorsStackDepthInfo { thread: thread },
+                    14 => Command::ThreadReferenceForceEarlyReturn { thread: thread, value: d.deserialize_value().field("value")? },
+                    _ => { return Err(Error::Unimplemented) },
+                }
+            },
+            13 => {
+                let array = d.deserialize_object().field("array")?;
+                match cmd {
+                    1 => Command::ArrayReferenceLength { array: array },
+                    2 => Command::ArrayReferenceGetValues {
+                        array: array,
+                        first: d.deserialize_int().field("first")?,
+                        length: d.deserialize_int().field("length")?,
+                    },
+                    _ => { return Err(Error::Unimplemented) },
+                }
+            },
+            16 => {
+                let thread = d.deserialize_object().field("thread")?;
+                let frame = d.deserialize_frame().field("frame")?;
+                match cmd {
+                    1 => Command::StackFrameGetValues {
+                        thread: thread,
+                        frame: frame,
+                        slots: d.deserialize_list(|d| Ok((d.deserialize_int().field("slot")?, d.deserialize_byte().field("sig_byte")?))).field("slots")?,
+                    },
+                    2 => Command::StackFrameSetValues {
+                        thread: thread,
+                        frame: frame,
+                        values: d.deserialize_list(|d| Ok((d.deserialize_int().field("slot")?, d.deserialize_value().field("slot_value")?))).field("values")?,
+                    },
+                    3 => Command::StackFrameThisObject { thread: thread, frame: frame },
+                    4 => Command::StackFramePopFrames { thread: thread, frame: frame },
+                    _ => { return Err(Error::Unimplemented) },
+                }
+            },
+            15 => match cmd {
+                1 => Command::EventRequestSet {
+                    event_kind: EventKind::deserialize(d.deserialize_byte().field("event_kind")?).ok_or(Error::InvalidEventType)?,
+                    suspend_policy: d.deserialize_byte().field("suspend_policy")?,
+                    modifiers: d.deserialize_list(Modifier::deserialize).field("modifiers")?,
+                },
+                2 => Command::EventRequestClear {
+                    event_kind: EventKind::deserialize(d.deserialize_byte().field("event_kind")?).ok_or(Error::InvalidEventType)?,
+                    request_id: d.deserialize_int().field("request_id")?,
+                },
+                3 => Command::EventRequestClearAllBreakpoints,
+                _ => { return Err(Error::Unimplemented) },
+            },
+            ddm::DDM_SET => match cmd {
+                ddm::DDM_CHUNK => Command::DdmChunk(Chunk::deserialize(d)?),
+                _ => { return Err(Error::Unimplemented) },
+            },
+            64 => match cmd {
+                100 => Command::Composite {
+                    suspend_policy: d.deserialize_byte().field("suspend_policy")?,
+                    events: d.deserialize_list(Event::deserialize).field("events")?,
+                },
                 _ => { return Err(Error::Unimplemented) },
             },
             _ => { return Err(Error::Unimplemented); },
         });
     }
     pub fn serialize(&self, sizes: IDSizes) -> (u8, u8, Vec<u8>) {
-        let serializer = Serializer(Vec::new(), sizes);
+        let mut serializer = Serializer(Vec::new(), sizes);
+        let s = &mut serializer;
         let (set, cmd) = match self {
             Command::Version => (1, 1),
+            Command::ClassesBySignature { signature } => {
+                s.serialize_string(signature);
+                (1, 2)
+            },
+            Command::AllClasses => (1, 3),
+            Command::AllClassesWithGeneric => (1, 20),
+            Command::AllThreads => (1, 4),
+            Command::Dispose => (1, 6),
+            Command::IDSizes => (1, 7),
+            Command::Suspend => (1, 8),
+            Command::Resume => (1, 9),
+            Command::Exit { exit_code } => {
+                s.serialize_int(*exit_code);
+                (1, 10)
+            },
+            Command::CreateString { utf } => {
+                s.serialize_string(utf);
+                (1, 11)
+            },
             Command::Capabilities => (1, 12),
+            Command::DisposeObjects { requests } => {
+                s.serialize_list(requests, |s, (object, count)| {
+                    s.serialize_object(*object);
+                    s.serialize_int(*count);
+                });
+                (1, 14)
+            },
             Command::CapabilitiesNew => (1, 17),
-            Command::IDSizes => (1, 7),
+            Command::RedefineClasses { classes } => {
+                s.serialize_list(classes, |s, (class, bytes)| {
+                    s.serialize_reference_type(*class);
+                    s.serialize_bytes(bytes);
+                });
+                (1, 18)
+            },
+            Command::InstanceCounts { ref_types } => {
+                s.serialize_list(ref_types, |s, t| s.serialize_reference_type(*t));
+                (1, 21)
+            },
+            Command::ReferenceTypeSignature { ref_type } => {
+                s.serialize_reference_type(*ref_type);
+                (2, 1)
+            },
+            Command::ReferenceTypeModifiers { ref_type } => {
+                s.serialize_reference_type(*ref_type);
+                (2, 3)
+            },
+            Command::ReferenceTypeFields { ref_type } => {
+                s.serialize_reference_type(*ref_type);
+                (2, 4)
+            },
+            Command::ReferenceTypeMethods { ref_type } => {
+                s.serialize_reference_type(*ref_type);
+                (2, 5)
+            },
+            Command::ReferenceTypeGetValues { ref_type, fields } => {
+                s.serialize_reference_type(*ref_type);
+                s.serialize_list(fields, |s, f| s.serialize_field(*f));
+                (2, 6)
+            },
+            Command::ReferenceTypeSourceFile { ref_type } => {
+                s.serialize_reference_type(*ref_type);
+                (2, 7)
+            },
+            Command::ReferenceTypeInterfaces { ref_type } => {
+                s.serialize_reference_type(*ref_type);
+                (2, 10)
+            },
+            Command::ReferenceTypeInstances { ref_type, max_instances } => {
+                s.serialize_reference_type(*ref_type);
+                s.serialize_int(*max_instances);
+                (2, 16)
+            },
+            Command::ClassTypeSuperclass { class } => {
+                s.serialize_reference_type(*class);
+                (3, 1)
+            },
+            Command::ClassTypeSetValues { class, values } => {
+                s.serialize_reference_type(*class);
+                s.serialize_list(values, |s, (field, value)| {
+                    s.serialize_field(*field);
+                    s.serialize_untagged_value(value);
+                });
+                (3, 2)
+            },
+            Command::ClassTypeInvokeMethod { class, thread, method, args, options } => {
+                s.serialize_reference_type(*class);
+                s.serialize_object(*thread);
+                s.serialize_method(*method);
+                s.serialize_list(args, |s, a| s.serialize_value(a));
+                s.serialize_int(*options);
+                (3, 3)
+            },
+            Command::MethodLineTable { ref_type, method } => {
+                s.serialize_reference_type(*ref_type);
+                s.serialize_method(*method);
+                (6, 1)
+            },
+            Command::MethodVariableTable { ref_type, method } => {
+                s.serialize_reference_type(*ref_type);
+                s.serialize_method(*method);
+                (6, 2)
+            },
+            Command::ObjectReferenceReferenceType { object } => {
+                s.serialize_object(*object);
+                (9, 1)
+            },
+            Command::ObjectReferenceGetValues { object, fields } => {
+                s.serialize_object(*object);
+                s.serialize_list(fields, |s, f| s.serialize_field(*f));
+                (9, 2)
+            },
+            Command::ObjectReferenceSetValues { object, values } => {
+                s.serialize_object(*object);
+                s.serialize_list(values, |s, (field, value)| {
+                    s.serialize_field(*field);
+                    s.serialize_untagged_value(value);
+                });
+                (9, 3)
+            },
+            Command::ObjectReferenceMonitorInfo { object } => {
+                s.serialize_object(*object);
+                (9, 5)
+            },
+            Command::ObjectReferenceDisableCollection { object } => {
+                s.serialize_object(*object);
+                (9, 7)
+            },
+            Command::ObjectReferenceEnableCollection { object } => {
+                s.serialize_object(*object);
+                (9, 8)
+            },
+            Command::ObjectReferenceIsCollected { object } => {
+                s.serialize_object(*object);
+                (9, 9)
+            },
+            Command::ObjectReferenceReferringObjects { object, max_referrers } => {
+                s.serialize_object(*object);
+                s.serialize_int(*max_referrers);
+                (9, 10)
+            },
+            Command::ObjectReferenceInvokeMethod { object, thread, class, method, args, options } => {
+                s.serialize_object(*object);
+                s.serialize_object(*thread);
+                s.serialize_reference_type(*class);
+                s.serialize_method(*method);
+                s.serialize_list(args, |s, a| s.serialize_value(a));
+                s.serialize_int(*options);
+                (9, 6)
+            },
+            Command::StringReferenceValue { string } => {
+                s.serialize_object(*string);
+                (10, 1)
+            },
+            Command::ThreadReferenceName { thread } => {
+                s.serialize_object(*thread);
+                (11, 1)
+            },
+            Command::ThreadReferenceSuspend { thread } => {
+                s.serialize_object(*thread);
+                (11, 2)
+            },
+            Command::ThreadReferenceResume { thread } => {
+                s.serialize_object(*thread);
+                (11, 3)
+            },
+            Command::ThreadReferenceStatus { thread } => {
+                s.serialize_object(*thread);
+                (11, 4)
+            },
+            Command::ThreadReferenceFrames { thread, start, length } => {
+                s.serialize_object(*thread);
+                s.serialize_int(*start);
+                s.serialize_int(*length);
+                (11, 6)
+            },
+            Command::ThreadReferenceFrameCount { thread } => {
+                s.serialize_object(*thread);
+                (11, 7)
+            },
+            Command::ThreadReferenceOwnedMonitors { thread } => {
+                s.serialize_object(*thread);
+                (11, 8)
+            },
+            Command::ThreadReferenceCurrentContendedMonitor { thread } => {
+                s.serialize_object(*thread);
+                (11, 9)
+            },
+            Command::ThreadReferenceOwnedMonitorsStackDepthInfo { thread } => {
+                s.serialize_object(*thread);
+                (11, 13)
+            },
+            Command::ThreadReferenceForceEarlyReturn { thread, value } => {
+                s.serialize_object(*thread);
+                s.serialize_value(value);
+                (11, 14)
+            },
+            Command::ArrayReferenceLength { array } => {
+                s.serialize_object(*array);
+                (13, 1)
+            },
+            Command::ArrayReferenceGetValues { array, first, length } => {
+                s.serialize_object(*array);
+                s.serialize_int(*first);
+                s.serialize_int(*length);
+                (13, 2)
+            },
+            Command::ArrayReferenceSetValues { array, first, values } => {
+                s.serialize_object(*array);
+                s.serialize_int(*first);
+                s.serialize_list(values, |s, v| s.serialize_untagged_value(v));
+                (13, 3)
+            },
+            Command::StackFrameGetValues { thread, frame, slots } => {
+                s.serialize_object(*thread);
+                s.serialize_frame(*frame);
+                s.serialize_list(slots, |s, (slot, tag)| {
+                    s.serialize_int(*slot);
+                    s.serialize_byte(*tag);
+                });
+                (16, 1)
+            },
+            Command::StackFrameSetValues { thread, frame, values } => {
+                s.serialize_object(*thread);
+                s.serialize_frame(*frame);
+                s.serialize_list(values, |s, (slot, value)| {
+                    s.serialize_int(*slot);
+                    s.serialize_value(value);
+                });
+                (16, 2)
+            },
+            Command::StackFrameThisObject { thread, frame } => {
+                s.serialize_object(*thread);
+                s.serialize_frame(*frame);
+                (16, 3)
+            },
+            Command::StackFramePopFrames { thread, frame } => {
+                s.serialize_object(*thread);
+                s.serialize_frame(*frame);
+                (16, 4)
+            },
+            Command::EventRequestSet { event_kind, suspend_policy, modifiers } => {
+                s.serialize_byte(*event_kind as u8);
+                s.serialize_byte(*suspend_policy);
+                s.serialize_list(modifiers, |s, m| m.serialize(s));
+                (15, 1)
+            },
+            Command::EventRequestClear { event_kind, request_id } => {
+                s.serialize_byte(*event_kind as u8);
+                s.serialize_int(*request_id);
+                (15, 2)
+            },
+            Command::EventRequestClearAllBreakpoints => (15, 3),
+            Command::Composite { suspend_policy, events } => {
+                s.serialize_byte(*suspend_policy);
+                s.serialize_list(events, |s, e| e.serialize(s));
+                (64, 100)
+            },
+            Command::DdmChunk(chunk) => {
+                chunk.serialize(s);
+                (ddm::DDM_SET, ddm::DDM_CHUNK)
+            },
         };
         return (set, cmd, serializer.0);
     }
 }
 
-pub struct Header {
-    len: u32,
-    id: u32,
-    flags: u8,
+/// The largest packet `Packet::read` accepts, header included. Heap dumps
+/// come in one packet, so this is generous.
+pub const MAX_PACKET_SIZE: u32 = 256 << 20;
+
+const HEADER_SIZE: u32 = 11;
+const FLAG_REPLY: u8 = 0x80;
+
+/// Why a packet header was rejected.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum PacketError {
+    /// The length is less than the header alone.
+    LengthTooShort(u32),
+    LengthTooLong { length: u32, max: u32 },
+    /// Flags other than the reply bit, which no JDWP version defines.
+    UnknownFlags(u8),
 }
 
-#[derive(Debug)]
+impl PacketError {
+    /// The header field at fault.
+    pub fn field(&self) -> &'static str {
+        return match self {
+            PacketError::LengthTooShort(_) | PacketError::LengthTooLong { .. } => "length",
+            PacketError::UnknownFlags(_) => "flags",
+        };
+    }
+}
+
+impl std::fmt::Display for PacketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            PacketError::LengthTooShort(length) => write!(f, "JDWP packet length {} is shorter than the header", length),
+            PacketError::LengthTooLong { length, max } => write!(f, "JDWP packet length {} is over the limit of {}", length, max),
+            PacketError::UnknownFlags(flags) => write!(f, "JDWP packet flags {:#04x} are unknown", flags),
+        };
+    }
+}
+
+impl std::error::Error for PacketError {}
+
+impl From<PacketError> for std::io::Error {
+    fn from(e: PacketError) -> std::io::Error {
+        return std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
 pub enum Packet {
     Command {
         id: u32,
@@ -394,6 +2292,12 @@ pub enum Packet {
 }
 
 impl Packet {
+    pub fn id(&self) -> u32 {
+        return match self {
+            Packet::Command { id, .. } => *id,
+            Packet::Reply { id, .. } => *id,
+        };
+    }
     pub fn write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
         match self {
             Packet::Command { id, set, cmd, data } => {
@@ -412,20 +2316,25 @@ impl Packet {
         }
         return Ok(());
     }
-    pub fn read<R: Read>(reader: &mut R) -> std::io::Result<Packet> {
-        let mut header = [0u8; 11];
-        reader.read_exact(&mut header)?;
-        let len = u32::from_be_bytes(header[0..4].try_into().unwrap());
+    /// Length of the data following an 11 byte header, checked against
+    /// `max` for the whole packet.
+    pub fn data_len(header: &[u8; 11], max: u32) -> std::result::Result<usize, PacketError> {
+        let length = u32::from_be_bytes(header[0..4].try_into().unwrap());
+        if length < HEADER_SIZE {
+            return Err(PacketError::LengthTooShort(length));
+        }
+        if length > max {
+            return Err(PacketError::LengthTooLong { length: length, max: max });
+        }
+        if header[8] & !FLAG_REPLY != 0 {
+            return Err(PacketError::UnknownFlags(header[8]));
+        }
+        return Ok((length - HEADER_SIZE) as usize);
+    }
+    pub fn from_parts(header: &[u8; 11], data: Vec<u8>) -> Packet {
         let id = u32::from_be_bytes(header[4..8].try_into().unwrap());
         let flags = header[8];
-        if len < 11 {
-            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "JDWP packet length < 4"));
-        }
-        let datalen = (len - 11) as usize;
-        let mut data = Vec::with_capacity(datalen);
-        unsafe { data.set_len(datalen); }
-        reader.read_exact(&mut data.as_mut_slice())?;
-        return Ok(if (flags & 0x80u8) != 0u8 {
+        return if (flags & FLAG_REPLY) != 0u8 {
             Packet::Reply {
                 id: id,
                 error: ((header[9] as u16) << 8 | header[10] as u16),
@@ -438,6 +2347,28 @@ impl Packet {
                 cmd: header[10],
                 data: data,
             }
-        });
+        };
+    }
+    pub fn read<R: Read>(reader: &mut R) -> std::io::Result<Packet> {
+        return Packet::read_max(reader, MAX_PACKET_SIZE);
+    }
+    /// Reads a packet of at most `max` bytes. The buffer grows as data
+    /// arrives, so a peer can't make us allocate more than it sends.
+    pub fn read_max<R: Read>(reader: &mut R, max: u32) -> std::io::Result<Packet> {
+        let mut header = [0u8; 11];
+        reader.read_exact(&mut header)?;
+        let datalen = Packet::data_len(&header, max)?;
+        let mut data = Vec::new();
+        reader.take(datalen as u64).read_to_end(&mut data)?;
+        if data.len() != datalen {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "JDWP packet data is truncated"));
+        }
+        return Ok(Packet::from_parts(&header, data));
+    }
+    /// Serialized form of the packet, header included.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write(&mut bytes).unwrap();
+        return bytes;
     }
 }
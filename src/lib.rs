@@ -0,0 +1,73 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+pub mod jdwp;
+pub mod mutf8;
+pub mod ddm;
+pub mod hprof;
+pub mod eval;
+pub mod signature;
+pub mod browse;
+pub mod monitors;
+pub mod instances;
+pub mod transport;
+pub mod client;
+pub mod request;
+pub mod lookup;
+pub mod handles;
+#[cfg(feature = "async")]
+pub mod async_client;
+pub mod capture;
+pub mod pcap;
+pub mod proxy;
+pub mod mux;
+pub mod trace;
+pub mod redefine;
+pub mod cui;
+// Tests get this through the dev-dependency on ourselves with `mock` on.
+#[cfg(feature = "mock")]
+pub mod mock;
+use std::net::*;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+#[derive(Debug)]
+pub enum Error {
+    HandshakeFailed(Vec<u8>),
+    Io(std::io::Error),
+    Jdwp(jdwp::Error),
+    Disconnected,
+    /// A CUI command was malformed or named something that doesn't exist.
+    Command(String),
+    /// An expression didn't parse, or failed while being evaluated.
+    Eval(String),
+    /// The VM answered a command with some other command's reply.
+    UnexpectedReply(Box<jdwp::Reply>),
+}
+
+impl Error {
+    pub fn unexpected(reply: jdwp::Reply) -> Error {
+        return Error::UnexpectedReply(Box::new(reply));
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        return Error::Io(e);
+    }
+}
+
+impl From<jdwp::Error> for Error {
+    fn from(e: jdwp::Error) -> Error {
+        return Error::Jdwp(e);
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+pub fn tcp<A: ToSocketAddrs>(addr: A) -> Result<TcpStream> {
+    return Ok(TcpStream::connect(addr)?);
+}
+
+#[cfg(unix)]
+pub fn unix<P: AsRef<std::path::Path>>(path: P) -> Result<UnixStream> {
+    return Ok(UnixStream::connect(path)?);
+}
@@ -0,0 +1,57 @@
+//! Turning the IDs in events and replies into names to show. Shared by the
+//! CUI, the evaluator, tracing and the class browser.
+use crate::Result;
+use crate::client::Client;
+use crate::eval;
+use crate::jdwp;
+use crate::request;
+
+/// The runtime type of `object`.
+pub fn reference_type(client: &Client, object: u64) -> Result<u64> {
+    let (_, type_id) = client.request(request::object_reference_reference_type(object))?;
+    return Ok(type_id);
+}
+
+/// The type's JNI signature, like `Ljava/lang/String;`.
+pub fn signature(client: &Client, ref_type: u64) -> Result<String> {
+    return client.request(request::reference_type_signature(ref_type));
+}
+
+/// The type's name as Java source writes it.
+pub fn class_name(client: &Client, ref_type: u64) -> Result<String> {
+    return Ok(eval::type_name(&signature(client, ref_type)?));
+}
+
+/// The thread's name, or its ID if the VM won't say.
+pub fn thread_name(client: &Client, thread: u64) -> String {
+    return client.request(request::thread_reference_name(thread)).unwrap_or_else(|_| format!("{:#x}", thread));
+}
+
+/// The method `location` is in, if its class still lists it.
+pub fn method(client: &Client, location: &jdwp::Location) -> Result<Option<jdwp::MethodInfo>> {
+    let methods = client.request(request::reference_type_methods(location.class_id))?;
+    return Ok(methods.into_iter().find(|m| m.method_id == location.method_id));
+}
+
+/// The source line `location` is on. None for methods without a line table.
+pub fn line(client: &Client, location: &jdwp::Location) -> Option<i32> {
+    let table = client.request(request::method_line_table(location.class_id, location.method_id)).ok()?;
+    return table.lines.iter()
+        .filter(|l| l.code_index <= location.index as i64)
+        .max_by_key(|l| l.code_index)
+        .map(|l| l.line);
+}
+
+/// `Class.method:line`, falling back to raw IDs for whatever can't be looked up.
+pub fn location_text(client: &Client, location: &jdwp::Location) -> String {
+    let class = class_name(client, location.class_id).unwrap_or_else(|_| format!("{:#x}", location.class_id));
+    let method = match method(client, location) {
+        Ok(Some(m)) => m.name,
+        _ => format!("{:#x}", location.method_id),
+    };
+    let line = match line(client, location) {
+        Some(line) => line.to_string(),
+        None => format!("@{}", location.index),
+    };
+    return format!("{}.{}:{}", class, method, line);
+}
@@ -1,39 +1,112 @@
-pub mod jdwp;
-pub mod cui;
-use std::net::*;
-use std::io::{Write,Read};
-
-#[derive(Debug)]
-pub enum Error {
-    HandshakeFailed(Vec<u8>),
-    Io(std::io::Error),
-    Jdwp(jdwp::Error),
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+use dcd::*;
+use dcd::client::Client;
+use std::sync::Arc;
+
+const USAGE: &str = "usage:
+    dcd [--record FILE] [--max-packet BYTES] [HOST:PORT | unix:PATH]
+    dcd dump FILE [--port PORT] [--max-packet BYTES]
+    dcd replay FILE [--port PORT] [--max-packet BYTES]
+    dcd import PCAP OUT [--port PORT] [--max-packet BYTES]
+    dcd proxy --listen HOST:PORT --target HOST:PORT [--record FILE] [--max-packet BYTES]
+    dcd mux --listen HOST:PORT --target HOST:PORT [--max-packet BYTES]";
+
+const DEFAULT_PORT: u16 = 4444;
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
 }
 
-impl From<std::io::Error> for Error {
-    fn from(e: std::io::Error) -> Error {
-        return Error::Io(e);
+/// Splits `--flag value` pairs out of the positional arguments.
+fn parse_args(args: &[String]) -> (Vec<String>, Vec<(String, String)>) {
+    let mut positional = Vec::new();
+    let mut flags = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg.starts_with("--") {
+            flags.push((arg.clone(), iter.next().cloned().unwrap_or_default()));
+        } else {
+            positional.push(arg.clone());
+        }
     }
+    return (positional, flags);
 }
 
-impl From<jdwp::Error> for Error {
-    fn from(e: jdwp::Error) -> Error {
-        return Error::Jdwp(e);
-    }
+fn flag<'a>(flags: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    return flags.iter().find(|(f, _)| f == name).map(|(_, v)| v.as_str());
 }
 
-type Result<T> = std::result::Result<T, Error>;
+fn port(flags: &[(String, String)]) -> u16 {
+    return flag(flags, "--port").and_then(|p| p.parse().ok()).unwrap_or(DEFAULT_PORT);
+}
 
-pub fn tcp<A: ToSocketAddrs>(addr: A) -> Result<(TcpStream, TcpStream)> {
-    let recv_end = TcpStream::connect(addr)?;
-    let send_end = recv_end.try_clone()?;
-    return Ok((recv_end,send_end));
+fn max_packet(flags: &[(String, String)]) -> Option<u32> {
+    return flag(flags, "--max-packet").map(|max| max.parse().unwrap_or_else(|_| usage()));
+}
+
+fn connect(addr: &str, flags: &[(String, String)]) -> Result<()> {
+    println!("Opening connection to {}!", addr);
+    let (client, events) = match addr.strip_prefix("unix:") {
+        #[cfg(unix)]
+        Some(path) => Client::connect(unix(path)?)?,
+        _ => Client::connect(tcp(addr)?)?,
+    };
+    println!("Connected to JVM");
+    client.state().max_packet = max_packet(flags);
+    if let Some(path) = flag(flags, "--record") {
+        client.record(Some(Arc::new(capture::Recorder::create(path)?)));
+    }
+    return cui::run(client, events);
 }
 
 fn main() -> Result<()> {
     env_logger::init();
-    println!("Opening connection to localhost:4444!");
-    let (r,w) = tcp("127.0.0.1:4444")?;
-    cui::main(r,w)?; 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (positional, flags) = parse_args(&args);
+    let mut stdout = std::io::stdout();
+    match positional.first().map(|s| s.as_str()) {
+        Some("dump") if positional.len() == 2 => {
+            let records = capture::open(&positional[1], port(&flags), max_packet(&flags))?;
+            capture::dump(&records, &mut stdout)?;
+        },
+        Some("replay") if positional.len() == 2 => {
+            let records = capture::open(&positional[1], port(&flags), max_packet(&flags))?;
+            let stats = capture::replay(&records, &mut stdout)?;
+            if stats.round_tripped != stats.packets {
+                std::process::exit(1);
+            }
+        },
+        Some("import") if positional.len() == 3 => {
+            let records = capture::open(&positional[1], port(&flags), max_packet(&flags))?;
+            let recorder = capture::Recorder::create(&positional[2])?;
+            for record in records.iter() {
+                recorder.write_record(record)?;
+            }
+            println!("Imported {} packets", records.len());
+        },
+        Some("proxy") if positional.len() == 1 => {
+            let (listen, target) = match (flag(&flags, "--listen"), flag(&flags, "--target")) {
+                (Some(l), Some(t)) => (l, t),
+                _ => usage(),
+            };
+            let recorder = match flag(&flags, "--record") {
+                Some(path) => Some(Arc::new(capture::Recorder::create(path)?)),
+                None => None,
+            };
+            proxy::listen(listen, target, recorder, max_packet(&flags))?;
+        },
+        Some("mux") if positional.len() == 1 => {
+            let (listen, target) = match (flag(&flags, "--listen"), flag(&flags, "--target")) {
+                (Some(l), Some(t)) => (l, t),
+                _ => usage(),
+            };
+            mux::listen(listen, target, max_packet(&flags))?;
+        },
+        Some("dump") | Some("replay") | Some("import") | Some("proxy") | Some("mux") | Some("help") => usage(),
+        Some(addr) if positional.len() == 1 => connect(addr, &flags)?,
+        None => connect("127.0.0.1:4444", &flags)?,
+        _ => usage(),
+    }
     Ok(())
 }
@@ -0,0 +1,842 @@
+//! A scriptable in-process VM that speaks JDWP, for tests that have no device.
+use crate::client::{self,Client,State};
+use crate::jdwp;
+use crate::transport::{self,Transport};
+use std::collections::HashMap;
+use std::io::{BufReader,BufWriter,Read,Write};
+use std::sync::{Arc,Mutex,MutexGuard};
+use std::sync::mpsc::Receiver;
+use std::thread::JoinHandle;
+use log::*;
+
+pub const THREAD_STATUS_RUNNING: i32 = 1;
+pub const THREAD_STATUS_MONITOR: i32 = 3;
+pub const SUSPEND_STATUS_SUSPENDED: i32 = 1;
+pub const CLASS_STATUS_INITIALIZED: i32 = 7;
+
+#[derive(Clone,Debug)]
+pub struct MockField {
+    pub id: u64,
+    pub name: String,
+    pub signature: String,
+    pub mod_bits: i32,
+}
+
+#[derive(Clone,Debug)]
+pub struct MockMethod {
+    pub id: u64,
+    pub name: String,
+    pub signature: String,
+    pub mod_bits: i32,
+    pub lines: Vec<jdwp::LineEntry>,
+    pub arg_count: i32,
+    pub variables: Vec<jdwp::VariableInfo>,
+}
+
+#[derive(Clone,Debug)]
+pub struct MockClass {
+    pub id: u64,
+    pub ref_type_tag: u8,
+    pub signature: String,
+    /// Empty unless the class is generic.
+    pub generic_signature: String,
+    pub source_file: String,
+    pub status: i32,
+    pub mod_bits: i32,
+    pub superclass: u64,
+    pub interfaces: Vec<u64>,
+    pub fields: Vec<MockField>,
+    pub methods: Vec<MockMethod>,
+    /// Values of static fields by field ID.
+    pub statics: HashMap<u64, jdwp::Tag>,
+}
+
+#[derive(Clone,Debug)]
+pub enum MockData {
+    /// Instance field values by field ID.
+    Fields(HashMap<u64, jdwp::Tag>),
+    Array(Vec<jdwp::Tag>),
+    String(String),
+}
+
+#[derive(Clone,Debug)]
+pub struct MockObject {
+    pub id: u64,
+    pub class: u64,
+    pub data: MockData,
+}
+
+#[derive(Clone,Debug)]
+pub struct MockMonitor {
+    pub owner: u64,
+    pub entry_count: i32,
+    /// Stack depth of the owner's frame that locked it.
+    pub depth: i32,
+    /// Threads in Object.wait on it.
+    pub waiters: Vec<u64>,
+}
+
+#[derive(Clone,Debug)]
+pub struct MockThread {
+    pub id: u64,
+    pub name: String,
+    pub status: i32,
+    pub suspend_count: i32,
+    /// Innermost frame first, as ThreadReference.Frames reports them.
+    pub frames: Vec<jdwp::FrameInfo>,
+    /// The object whose monitor it's blocked on, or 0.
+    pub contended: u64,
+}
+
+#[derive(Clone,Debug)]
+pub struct MockRequest {
+    pub id: i32,
+    pub event_kind: jdwp::EventKind,
+    pub suspend_policy: u8,
+    pub modifiers: Vec<jdwp::Modifier>,
+}
+
+/// Everything the mock VM knows about. Tests build one up front and can
+/// keep editing it through `MockVm::model` while the VM is serving.
+pub struct Model {
+    pub description: String,
+    pub major: i32,
+    pub minor: i32,
+    pub version: String,
+    pub name: String,
+    pub capabilities: jdwp::Capabilities,
+    pub idsizes: jdwp::IDSizes,
+    pub classes: Vec<MockClass>,
+    pub threads: Vec<MockThread>,
+    pub requests: Vec<MockRequest>,
+    pub objects: HashMap<u64, MockObject>,
+    /// Local variable values by frame ID and slot.
+    pub locals: HashMap<u64, HashMap<i32, jdwp::Tag>>,
+    /// The `this` object of each frame that has one.
+    pub this_objects: HashMap<u64, u64>,
+    /// Owned monitors by object ID.
+    pub monitors: HashMap<u64, MockMonitor>,
+    /// Bytes from each RedefineClasses, by class.
+    pub redefined: Vec<(u64, Vec<u8>)>,
+    /// Values forced by ForceEarlyReturn, by thread.
+    pub early_returns: Vec<(u64, jdwp::Tag)>,
+    /// Objects with collection disabled, and how many times.
+    pub uncollectable: HashMap<u64, u32>,
+    /// Every DisposeObjects request, in order.
+    pub disposed: Vec<Vec<(u64, i32)>>,
+    next_id: u64,
+    next_request: i32,
+}
+
+impl Default for Model {
+    fn default() -> Model {
+        return Model {
+            description: "Mock JDWP VM".to_string(),
+            major: 1,
+            minor: 8,
+            version: "1.8.0".to_string(),
+            name: "MockVM".to_string(),
+            capabilities: Default::default(),
+            idsizes: jdwp::IDSizes { field: 8, method: 8, object: 8, reference_type: 8, frame: 8 },
+            classes: Vec::new(),
+            threads: Vec::new(),
+            requests: Vec::new(),
+            objects: HashMap::new(),
+            locals: HashMap::new(),
+            this_objects: HashMap::new(),
+            monitors: HashMap::new(),
+            redefined: Vec::new(),
+            early_returns: Vec::new(),
+            uncollectable: HashMap::new(),
+            disposed: Vec::new(),
+            next_id: 0x100,
+            next_request: 1,
+        };
+    }
+}
+
+impl Model {
+    pub fn new_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        return id;
+    }
+    pub fn add_class(&mut self, signature: &str, source_file: &str) -> u64 {
+        let id = self.new_id();
+        self.classes.push(MockClass {
+            id: id,
+            ref_type_tag: jdwp::TYPE_TAG_CLASS,
+            signature: signature.to_string(),
+            generic_signature: String::new(),
+            source_file: source_file.to_string(),
+            status: CLASS_STATUS_INITIALIZED,
+            mod_bits: 1,
+            superclass: 0,
+            interfaces: Vec::new(),
+            fields: Vec::new(),
+            methods: Vec::new(),
+            statics: HashMap::new(),
+        });
+        return id;
+    }
+    fn class_by_signature(&mut self, signature: &str, ref_type_tag: u8) -> u64 {
+        return match self.classes.iter().find(|c| c.signature == signature) {
+            Some(c) => c.id,
+            None => {
+                let id = self.add_class(signature, "");
+                self.class_mut(id).unwrap().ref_type_tag = ref_type_tag;
+                id
+            },
+        };
+    }
+    /// `lines` maps code indices to source lines.
+    pub fn add_method(&mut self, class: u64, name: &str, signature: &str, lines: &[(i64, i32)]) -> u64 {
+        let id = self.new_id();
+        let method = MockMethod {
+            id: id,
+            name: name.to_string(),
+            signature: signature.to_string(),
+            mod_bits: 1,
+            lines: lines.iter().map(|(i, l)| jdwp::LineEntry { code_index: *i, line: *l }).collect(),
+            arg_count: 0,
+            variables: Vec::new(),
+        };
+        self.class_mut(class).expect("no such mock class").methods.push(method);
+        return id;
+    }
+    pub fn add_field(&mut self, class: u64, name: &str, signature: &str) -> u64 {
+        let id = self.new_id();
+        let field = MockField {
+            id: id,
+            name: name.to_string(),
+            signature: signature.to_string(),
+            mod_bits: 1,
+        };
+        self.class_mut(class).expect("no such mock class").fields.push(field);
+        return id;
+    }
+    /// Declares a local variable live across the whole method.
+    pub fn add_variable(&mut self, class: u64, method: u64, name: &str, signature: &str, slot: i32) {
+        let class = self.class_mut(class).expect("no such mock class");
+        let method = class.methods.iter_mut().find(|m| m.id == method).expect("no such mock method");
+        method.variables.push(jdwp::VariableInfo {
+            code_index: 0,
+            name: name.to_string(),
+            signature: signature.to_string(),
+            length: i32::MAX,
+            slot: slot,
+        });
+    }
+    pub fn add_object(&mut self, class: u64, fields: &[(u64, jdwp::Tag)]) -> u64 {
+        let id = self.new_id();
+        let data = MockData::Fields(fields.iter().cloned().collect());
+        self.objects.insert(id, MockObject { id: id, class: class, data: data });
+        return id;
+    }
+    pub fn add_string(&mut self, value: &str) -> u64 {
+        let class = self.class_by_signature("Ljava/lang/String;", jdwp::TYPE_TAG_CLASS);
+        let id = self.new_id();
+        self.objects.insert(id, MockObject { id: id, class: class, data: MockData::String(value.to_string()) });
+        return id;
+    }
+    pub fn add_array(&mut self, signature: &str, values: &[jdwp::Tag]) -> u64 {
+        let class = self.class_by_signature(signature, jdwp::TYPE_TAG_ARRAY);
+        let id = self.new_id();
+        self.objects.insert(id, MockObject { id: id, class: class, data: MockData::Array(values.to_vec()) });
+        return id;
+    }
+    pub fn add_thread(&mut self, name: &str) -> u64 {
+        let id = self.new_id();
+        self.threads.push(MockThread {
+            id: id,
+            name: name.to_string(),
+            status: THREAD_STATUS_RUNNING,
+            suspend_count: 0,
+            frames: Vec::new(),
+            contended: 0,
+        });
+        return id;
+    }
+    /// Pushes a new innermost frame onto `thread` and returns its frame ID.
+    pub fn push_frame(&mut self, thread: u64, class: u64, method: u64, index: u64) -> u64 {
+        let id = self.new_id();
+        let frame = jdwp::FrameInfo {
+            frame_id: id,
+            location: jdwp::Location {
+                type_tag: jdwp::TYPE_TAG_CLASS,
+                class_id: class,
+                method_id: method,
+                index: index,
+            },
+        };
+        self.thread_mut(thread).expect("no such mock thread").frames.insert(0, frame);
+        return id;
+    }
+    pub fn class(&self, id: u64) -> Option<&MockClass> {
+        return self.classes.iter().find(|c| c.id == id);
+    }
+    pub fn class_mut(&mut self, id: u64) -> Option<&mut MockClass> {
+        return self.classes.iter_mut().find(|c| c.id == id);
+    }
+    pub fn thread(&self, id: u64) -> Option<&MockThread> {
+        return self.threads.iter().find(|t| t.id == id);
+    }
+    pub fn thread_mut(&mut self, id: u64) -> Option<&mut MockThread> {
+        return self.threads.iter_mut().find(|t| t.id == id);
+    }
+    pub fn set_local(&mut self, frame: u64, slot: i32, value: jdwp::Tag) {
+        self.locals.entry(frame).or_default().insert(slot, value);
+    }
+    /// Makes `thread` the owner of `object`'s monitor, locked by the frame
+    /// `depth` frames from the top.
+    pub fn lock(&mut self, object: u64, thread: u64, depth: i32) {
+        self.monitors.insert(object, MockMonitor { owner: thread, entry_count: 1, depth: depth, waiters: Vec::new() });
+    }
+    /// Blocks `thread` trying to enter `object`'s monitor.
+    pub fn contend(&mut self, thread: u64, object: u64) {
+        self.thread_mut(thread).expect("no such mock thread").contended = object;
+        self.thread_mut(thread).unwrap().status = THREAD_STATUS_MONITOR;
+    }
+    fn owned_monitors(&self, thread: u64) -> Vec<(jdwp::Tag, i32)> {
+        let mut owned: Vec<(u64, i32)> = self.monitors.iter().filter(|(_, m)| m.owner == thread).map(|(o, m)| (*o, m.depth)).collect();
+        owned.sort();
+        return owned.into_iter().map(|(o, depth)| (jdwp::Tag::Object(o), depth)).collect();
+    }
+    /// An object ID tagged the way the VM would tag it.
+    fn tagged(&self, object: &MockObject) -> jdwp::Tag {
+        return match object.data {
+            MockData::String(_) => jdwp::Tag::String(object.id),
+            MockData::Array(_) => jdwp::Tag::Array(object.id),
+            MockData::Fields(_) => jdwp::Tag::Object(object.id),
+        };
+    }
+    /// Objects in ID order, so replies listing them are stable.
+    fn sorted_objects(&self) -> Vec<&MockObject> {
+        let mut objects: Vec<&MockObject> = self.objects.values().collect();
+        objects.sort_by_key(|o| o.id);
+        return objects;
+    }
+    /// Garbage collects `id` unless collection of it is disabled. Returns
+    /// whether it went.
+    pub fn collect(&mut self, id: u64) -> bool {
+        if self.uncollectable.contains_key(&id) {
+            return false;
+        }
+        return self.objects.remove(&id).is_some();
+    }
+    pub fn object(&self, id: u64) -> jdwp::Result<&MockObject> {
+        return self.objects.get(&id).ok_or(jdwp::Error::InvalidObject);
+    }
+    pub fn requests_for(&self, kind: jdwp::EventKind) -> Vec<MockRequest> {
+        return self.requests.iter().filter(|r| r.event_kind == kind).cloned().collect();
+    }
+    fn suspended_thread(&self, id: u64) -> jdwp::Result<&MockThread> {
+        let thread = self.thread(id).ok_or(jdwp::Error::InvalidThread)?;
+        if thread.suspend_count == 0 {
+            return Err(jdwp::Error::ThreadNotSuspended);
+        }
+        return Ok(thread);
+    }
+    fn frame(&self, thread: u64, frame: u64) -> jdwp::Result<&jdwp::FrameInfo> {
+        return self.suspended_thread(thread)?.frames.iter().find(|f| f.frame_id == frame).ok_or(jdwp::Error::InvalidFrameId);
+    }
+    fn array(&self, id: u64) -> jdwp::Result<&Vec<jdwp::Tag>> {
+        return match &self.object(id)?.data {
+            MockData::Array(values) => Ok(values),
+            _ => Err(jdwp::Error::InvalidArray),
+        };
+    }
+    /// The built-in answer to `cmd`, used when no scripted handler claims it.
+    pub fn handle(&mut self, cmd: &jdwp::Command) -> jdwp::Result<jdwp::Reply> {
+        use jdwp::Command as C;
+        use jdwp::Reply as R;
+        return Ok(match cmd {
+            C::Version => R::Version {
+                description: self.description.clone(),
+                major: self.major,
+                minor: self.minor,
+                version: self.version.clone(),
+                name: self.name.clone(),
+            },
+            C::ClassesBySignature { signature } => R::ClassesBySignature(
+                self.classes.iter().filter(|c| &c.signature == signature).map(|c| jdwp::ClassRef {
+                    ref_type_tag: c.ref_type_tag,
+                    type_id: c.id,
+                    status: c.status,
+                }).collect()
+            ),
+            C::AllClasses => R::AllClasses(self.classes.iter().map(|c| jdwp::ClassInfo {
+                ref_type_tag: c.ref_type_tag,
+                type_id: c.id,
+                signature: c.signature.clone(),
+                status: c.status,
+            }).collect()),
+            C::AllClassesWithGeneric => R::AllClassesWithGeneric(self.classes.iter().map(|c| jdwp::GenericClassInfo {
+                ref_type_tag: c.ref_type_tag,
+                type_id: c.id,
+                signature: c.signature.clone(),
+                generic_signature: c.generic_signature.clone(),
+                status: c.status,
+            }).collect()),
+            C::AllThreads => R::AllThreads(self.threads.iter().map(|t| t.id).collect()),
+            C::Dispose | C::Exit { .. } => R::Empty,
+            C::IDSizes => R::IDSizes {
+                field: self.idsizes.field,
+                method: self.idsizes.method,
+                object: self.idsizes.object,
+                reference_type: self.idsizes.reference_type,
+                frame: self.idsizes.frame,
+            },
+            C::Suspend => {
+                for t in self.threads.iter_mut() {
+                    t.suspend_count += 1;
+                }
+                R::Empty
+            },
+            C::Resume => {
+                for t in self.threads.iter_mut() {
+                    t.suspend_count = std::cmp::max(0, t.suspend_count - 1);
+                }
+                R::Empty
+            },
+            C::Capabilities => R::Capabilities(self.capabilities & jdwp::Capabilities::from_bits_truncate(0x7f)),
+            C::CapabilitiesNew => R::CapabilitiesNew(self.capabilities),
+            C::InstanceCounts { ref_types } => {
+                if ref_types.iter().any(|t| self.class(*t).is_none()) {
+                    return Err(jdwp::Error::InvalidClass);
+                }
+                R::InstanceCounts(ref_types.iter().map(|t| self.objects.values().filter(|o| o.class == *t).count() as i64).collect())
+            },
+            C::ReferenceTypeInstances { ref_type, max_instances } => {
+                self.class(*ref_type).ok_or(jdwp::Error::InvalidClass)?;
+                let instances = self.sorted_objects().into_iter().filter(|o| o.class == *ref_type);
+                let limit = if *max_instances > 0 { *max_instances as usize } else { usize::MAX };
+                R::Objects(instances.take(limit).map(|o| self.tagged(o)).collect())
+            },
+            C::RedefineClasses { classes } => {
+                if !self.capabilities.contains(jdwp::Capabilities::REDEFINE_CLASSES) {
+                    return Err(jdwp::Error::Unimplemented);
+                }
+                if classes.iter().any(|(class, _)| self.class(*class).is_none()) {
+                    return Err(jdwp::Error::InvalidClass);
+                }
+                self.redefined.extend(classes.iter().cloned());
+                R::Empty
+            },
+            C::ReferenceTypeSignature { ref_type } => {
+                R::ReferenceTypeSignature(self.class(*ref_type).ok_or(jdwp::Error::InvalidClass)?.signature.clone())
+            },
+            C::ReferenceTypeModifiers { ref_type } => {
+                R::ReferenceTypeModifiers(self.class(*ref_type).ok_or(jdwp::Error::InvalidClass)?.mod_bits)
+            },
+            C::ReferenceTypeFields { ref_type } => {
+                let class = self.class(*ref_type).ok_or(jdwp::Error::InvalidClass)?;
+                R::ReferenceTypeFields(class.fields.iter().map(|f| jdwp::FieldInfo {
+                    field_id: f.id,
+                    name: f.name.clone(),
+                    signature: f.signature.clone(),
+                    mod_bits: f.mod_bits,
+                }).collect())
+            },
+            C::ReferenceTypeMethods { ref_type } => {
+                let class = self.class(*ref_type).ok_or(jdwp::Error::InvalidClass)?;
+                R::ReferenceTypeMethods(class.methods.iter().map(|m| jdwp::MethodInfo {
+                    method_id: m.id,
+                    name: m.name.clone(),
+                    signature: m.signature.clone(),
+                    mod_bits: m.mod_bits,
+                }).collect())
+            },
+            C::ReferenceTypeSourceFile { ref_type } => {
+                let class = self.class(*ref_type).ok_or(jdwp::Error::InvalidClass)?;
+                if class.source_file.is_empty() {
+                    return Err(jdwp::Error::AbsentInformation);
+                }
+                R::ReferenceTypeSourceFile(class.source_file.clone())
+            },
+            C::CreateString { utf } => R::CreateString(self.add_string(utf)),
+            C::DisposeObjects { requests } => {
+                self.disposed.push(requests.clone());
+                R::Empty
+            },
+            C::ObjectReferenceDisableCollection { object } => {
+                self.object(*object)?;
+                *self.uncollectable.entry(*object).or_insert(0) += 1;
+                R::Empty
+            },
+            C::ObjectReferenceEnableCollection { object } => {
+                if let Some(count) = self.uncollectable.get_mut(object) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.uncollectable.remove(object);
+                    }
+                }
+                R::Empty
+            },
+            C::ObjectReferenceIsCollected { object } => R::IsCollected(!self.objects.contains_key(object)),
+            C::ReferenceTypeGetValues { ref_type, fields } => {
+                let class = self.class(*ref_type).ok_or(jdwp::Error::InvalidClass)?;
+                R::Values(fields.iter().map(|f| class.statics.get(f).cloned().ok_or(jdwp::Error::InvalidFieldId)).collect::<jdwp::Result<_>>()?)
+            },
+            C::ReferenceTypeInterfaces { ref_type } => {
+                R::ReferenceTypeInterfaces(self.class(*ref_type).ok_or(jdwp::Error::InvalidClass)?.interfaces.clone())
+            },
+            C::ClassTypeSuperclass { class } => {
+                R::ClassTypeSuperclass(self.class(*class).ok_or(jdwp::Error::InvalidClass)?.superclass)
+            },
+            C::MethodVariableTable { ref_type, method } => {
+                let class = self.class(*ref_type).ok_or(jdwp::Error::InvalidClass)?;
+                let method = class.methods.iter().find(|m| m.id == *method).ok_or(jdwp::Error::InvalidMethodId)?;
+                if method.variables.is_empty() {
+                    return Err(jdwp::Error::AbsentInformation);
+                }
+                R::MethodVariableTable { arg_count: method.arg_count, variables: method.variables.clone() }
+            },
+            C::ObjectReferenceReferenceType { object } => {
+                let class = self.object(*object)?.class;
+                R::ObjectReferenceReferenceType {
+                    ref_type_tag: self.class(class).ok_or(jdwp::Error::InvalidClass)?.ref_type_tag,
+                    type_id: class,
+                }
+            },
+            C::ObjectReferenceReferringObjects { object, max_referrers } => {
+                self.object(*object)?;
+                let refers = |v: &jdwp::Tag| v.object_id() == Some(*object);
+                let referrers = self.sorted_objects().into_iter().filter(|o| match &o.data {
+                    MockData::Fields(fields) => fields.values().any(refers),
+                    MockData::Array(values) => values.iter().any(refers),
+                    MockData::String(_) => false,
+                });
+                let limit = if *max_referrers > 0 { *max_referrers as usize } else { usize::MAX };
+                R::Objects(referrers.take(limit).map(|o| self.tagged(o)).collect())
+            },
+            C::ObjectReferenceMonitorInfo { object } => {
+                self.object(*object)?;
+                match self.monitors.get(object) {
+                    Some(m) => R::ObjectReferenceMonitorInfo { owner: m.owner, entry_count: m.entry_count, waiters: m.waiters.clone() },
+                    None => R::ObjectReferenceMonitorInfo { owner: 0, entry_count: 0, waiters: Vec::new() },
+                }
+            },
+            C::ObjectReferenceGetValues { object, fields: ids } => match &self.object(*object)?.data {
+                MockData::Fields(values) => {
+                    R::Values(ids.iter().map(|f| values.get(f).cloned().ok_or(jdwp::Error::InvalidFieldId)).collect::<jdwp::Result<_>>()?)
+                },
+                _ => { return Err(jdwp::Error::InvalidFieldId); },
+            },
+            C::StringReferenceValue { string } => match &self.object(*string)?.data {
+                MockData::String(value) => R::StringReferenceValue(value.as_str().into()),
+                _ => { return Err(jdwp::Error::InvalidString); },
+            },
+            C::ArrayReferenceLength { array } => R::ArrayReferenceLength(self.array(*array)?.len() as i32),
+            C::ArrayReferenceGetValues { array, first, length } => {
+                let signature = &self.class(self.object(*array)?.class).ok_or(jdwp::Error::InvalidClass)?.signature;
+                let tag = signature.as_bytes().get(1).cloned().unwrap_or(b'L');
+                let values = self.array(*array)?;
+                if *first < 0 || *length < 0 || (*first + *length) as usize > values.len() {
+                    return Err(jdwp::Error::InvalidLength);
+                }
+                R::ArrayReferenceGetValues {
+                    tag: tag,
+                    values: values[*first as usize..(*first + *length) as usize].to_vec(),
+                }
+            },
+            C::StackFrameGetValues { thread, frame, slots } => {
+                self.frame(*thread, *frame)?;
+                let locals = self.locals.get(frame);
+                R::Values(slots.iter().map(|(slot, _)| {
+                    locals.and_then(|l| l.get(slot)).cloned().ok_or(jdwp::Error::InvalidSlot)
+                }).collect::<jdwp::Result<_>>()?)
+            },
+            C::StackFrameSetValues { thread, frame, values } => {
+                self.frame(*thread, *frame)?;
+                for (slot, value) in values {
+                    self.set_local(*frame, *slot, *value);
+                }
+                R::Empty
+            },
+            C::StackFramePopFrames { thread, frame } => {
+                let frames = &self.suspended_thread(*thread)?.frames;
+                let index = frames.iter().position(|f| f.frame_id == *frame).ok_or(jdwp::Error::InvalidFrameId)?;
+                // A thread's last frame has no caller to return to.
+                if index + 1 == frames.len() {
+                    return Err(jdwp::Error::NoMoreFrames);
+                }
+                self.thread_mut(*thread).unwrap().frames.drain(..=index);
+                R::Empty
+            },
+            C::StackFrameThisObject { thread, frame } => {
+                self.frame(*thread, *frame)?;
+                R::StackFrameThisObject(match self.this_objects.get(frame) {
+                    Some(id) => jdwp::Tag::Object(*id),
+                    None => jdwp::Tag::Object(0),
+                })
+            },
+            C::MethodLineTable { ref_type, method } => {
+                let class = self.class(*ref_type).ok_or(jdwp::Error::InvalidClass)?;
+                let method = class.methods.iter().find(|m| m.id == *method).ok_or(jdwp::Error::InvalidMethodId)?;
+                if method.lines.is_empty() {
+                    return Err(jdwp::Error::AbsentInformation);
+                }
+                R::MethodLineTable {
+                    start: method.lines.iter().map(|l| l.code_index).min().unwrap(),
+                    end: method.lines.iter().map(|l| l.code_index).max().unwrap(),
+                    lines: method.lines.clone(),
+                }
+            },
+            C::ThreadReferenceName { thread } => {
+                R::ThreadReferenceName(self.thread(*thread).ok_or(jdwp::Error::InvalidThread)?.name.clone())
+            },
+            C::ThreadReferenceSuspend { thread } => {
+                self.thread_mut(*thread).ok_or(jdwp::Error::InvalidThread)?.suspend_count += 1;
+                R::Empty
+            },
+            C::ThreadReferenceResume { thread } => {
+                let thread = self.thread_mut(*thread).ok_or(jdwp::Error::InvalidThread)?;
+                thread.suspend_count = std::cmp::max(0, thread.suspend_count - 1);
+                R::Empty
+            },
+            C::ThreadReferenceStatus { thread } => {
+                let thread = self.thread(*thread).ok_or(jdwp::Error::InvalidThread)?;
+                R::ThreadReferenceStatus {
+                    thread_status: thread.status,
+                    suspend_status: if thread.suspend_count > 0 { SUSPEND_STATUS_SUSPENDED } else { 0 },
+                }
+            },
+            C::ThreadReferenceFrames { thread, start, length } => {
+                let frames = &self.suspended_thread(*thread)?.frames;
+                if *start < 0 || *start as usize > frames.len() {
+                    return Err(jdwp::Error::InvalidIndex);
+                }
+                let start = *start as usize;
+                let end = if *length == -1 { frames.len() } else { start + *length as usize };
+                if *length < -1 || end > frames.len() {
+                    return Err(jdwp::Error::InvalidLength);
+                }
+                R::ThreadReferenceFrames(frames[start..end].to_vec())
+            },
+            C::ThreadReferenceFrameCount { thread } => {
+                R::ThreadReferenceFrameCount(self.suspended_thread(*thread)?.frames.len() as i32)
+            },
+            C::ThreadReferenceOwnedMonitors { thread } => {
+                self.suspended_thread(*thread)?;
+                R::ThreadReferenceOwnedMonitors(self.owned_monitors(*thread).into_iter().map(|(m, _)| m).collect())
+            },
+            C::ThreadReferenceOwnedMonitorsStackDepthInfo { thread } => {
+                self.suspended_thread(*thread)?;
+                R::ThreadReferenceOwnedMonitorsStackDepthInfo(self.owned_monitors(*thread))
+            },
+            C::ThreadReferenceForceEarlyReturn { thread, value } => {
+                self.suspended_thread(*thread)?;
+                self.early_returns.push((*thread, *value));
+                R::Empty
+            },
+            C::ThreadReferenceCurrentContendedMonitor { thread } => {
+                R::ThreadReferenceCurrentContendedMonitor(jdwp::Tag::Object(self.suspended_thread(*thread)?.contended))
+            },
+            C::EventRequestSet { event_kind, suspend_policy, modifiers } => {
+                let id = self.next_request;
+                self.next_request += 1;
+                self.requests.push(MockRequest {
+                    id: id,
+                    event_kind: *event_kind,
+                    suspend_policy: *suspend_policy,
+                    modifiers: modifiers.clone(),
+                });
+                R::EventRequestSet(id)
+            },
+            C::EventRequestClear { event_kind, request_id } => {
+                self.requests.retain(|r| !(r.event_kind == *event_kind && r.id == *request_id));
+                R::Empty
+            },
+            C::EventRequestClearAllBreakpoints => {
+                self.requests.retain(|r| r.event_kind != jdwp::EventKind::Breakpoint);
+                R::Empty
+            },
+            _ => { return Err(jdwp::Error::Unimplemented); },
+        });
+    }
+}
+
+/// A scripted reply. Returning None falls through to the next handler and
+/// finally to `Model::handle`.
+pub type Handler = Box<dyn FnMut(&jdwp::Command, &mut Model) -> Option<jdwp::Result<jdwp::Reply>> + Send>;
+
+struct Shared {
+    model: Mutex<Model>,
+    handlers: Mutex<Vec<Handler>>,
+    received: Mutex<Vec<jdwp::Command>>,
+    packets: Mutex<Vec<(u8, u8)>>,
+    replies: Mutex<Vec<jdwp::Packet>>,
+    writer: Mutex<Option<Box<dyn Write + Send>>>,
+    next_id: Mutex<u32>,
+}
+
+pub struct MockVm {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockVm {
+    pub fn new(model: Model) -> MockVm {
+        return MockVm {
+            shared: Arc::new(Shared {
+                model: Mutex::new(model),
+                handlers: Mutex::new(Vec::new()),
+                received: Mutex::new(Vec::new()),
+                packets: Mutex::new(Vec::new()),
+                replies: Mutex::new(Vec::new()),
+                writer: Mutex::new(None),
+                // Kept well away from the debugger's IDs to make traces easier to read.
+                next_id: Mutex::new(0x4000_0000),
+            }),
+            thread: None,
+        };
+    }
+    /// Starts answering on `transport` from a background thread.
+    pub fn serve<T: Transport>(&mut self, transport: T) -> std::io::Result<()> {
+        let (reader, writer) = transport.split()?;
+        let shared = self.shared.clone();
+        self.thread = Some(std::thread::spawn(move || {
+            if let Err(e) = serve(reader, writer, shared) {
+                info!("Mock VM stopped: {:?}", e);
+            }
+        }));
+        return Ok(());
+    }
+    pub fn on<F>(&self, handler: F)
+    where F: FnMut(&jdwp::Command, &mut Model) -> Option<jdwp::Result<jdwp::Reply>> + Send + 'static
+    {
+        self.shared.handlers.lock().unwrap().push(Box::new(handler));
+    }
+    pub fn model(&self) -> MutexGuard<'_, Model> {
+        return self.shared.model.lock().unwrap();
+    }
+    /// Every command the debugger has sent so far, in order.
+    pub fn received(&self) -> Vec<jdwp::Command> {
+        return self.shared.received.lock().unwrap().clone();
+    }
+    /// The set and command number of every command packet, as they came
+    /// off the wire, including ones that failed to decode.
+    pub fn packets(&self) -> Vec<(u8, u8)> {
+        return self.shared.packets.lock().unwrap().clone();
+    }
+    /// Sends a composite event, suspending threads the way a real VM would.
+    pub fn emit(&self, suspend_policy: u8, events: Vec<jdwp::Event>) -> std::io::Result<()> {
+        let sizes = {
+            let mut model = self.shared.model.lock().unwrap();
+            for event in events.iter() {
+                match (suspend_policy, event.thread()) {
+                    (jdwp::SUSPEND_ALL, _) => model.threads.iter_mut().for_each(|t| t.suspend_count += 1),
+                    (jdwp::SUSPEND_EVENT_THREAD, Some(thread)) => {
+                        if let Some(t) = model.thread_mut(thread) {
+                            t.suspend_count += 1;
+                        }
+                    },
+                    _ => {},
+                }
+            }
+            model.idsizes
+        };
+        let cmd = jdwp::Command::Composite { suspend_policy: suspend_policy, events: events };
+        return self.send_command(&cmd, sizes);
+    }
+    /// Sends an arbitrary command to the debugger. Its reply, if any, shows up in `replies`.
+    pub fn send_command(&self, cmd: &jdwp::Command, sizes: jdwp::IDSizes) -> std::io::Result<()> {
+        let (set, command, data) = cmd.serialize(sizes);
+        return self.send_raw(set, command, data).map(|_| ());
+    }
+    /// Sends a command packet as is, even one no VM would send. Returns its ID.
+    pub fn send_raw(&self, set: u8, cmd: u8, data: Vec<u8>) -> std::io::Result<u32> {
+        let id = {
+            let mut next_id = self.shared.next_id.lock().unwrap();
+            *next_id += 1;
+            *next_id
+        };
+        let packet = jdwp::Packet::Command { id: id, set: set, cmd: cmd, data: data };
+        let mut writer = self.shared.writer.lock().unwrap();
+        return match writer.as_mut() {
+            Some(w) => packet.write(w).and_then(|_| w.flush()).map(|_| id),
+            None => Err(std::io::Error::new(std::io::ErrorKind::NotConnected, "mock VM is not serving")),
+        };
+    }
+    /// Every reply the debugger has sent to commands from the VM, in order.
+    pub fn replies(&self) -> Vec<jdwp::Packet> {
+        return self.shared.replies.lock().unwrap().clone();
+    }
+    /// Waits for the debugger to hang up.
+    pub fn join(mut self) {
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
+/// Hangs up on the debugger when the serving thread exits, even by panicking.
+struct HangUp(Arc<Shared>);
+
+impl Drop for HangUp {
+    fn drop(&mut self) {
+        if let Ok(mut writer) = self.0.writer.lock() {
+            *writer = None;
+        }
+    }
+}
+
+fn serve<R: Read, W: Write + Send + 'static>(reader: R, writer: W, shared: Arc<Shared>) -> std::io::Result<()> {
+    let _hangup = HangUp(shared.clone());
+    let mut reader = BufReader::new(reader);
+    let mut handshake = [0u8; 14];
+    reader.read_exact(&mut handshake)?;
+    if &handshake != client::HANDSHAKE {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "bad JDWP handshake"));
+    }
+    {
+        let mut writer = BufWriter::new(writer);
+        writer.write_all(client::HANDSHAKE)?;
+        writer.flush()?;
+        *shared.writer.lock().unwrap() = Some(Box::new(writer));
+    }
+    loop {
+        let packet = jdwp::Packet::read(&mut reader)?;
+        let (id, set, cmd, data) = match packet {
+            jdwp::Packet::Command { id, set, cmd, data } => (id, set, cmd, data),
+            reply => {
+                shared.replies.lock().unwrap().push(reply);
+                continue;
+            },
+        };
+        shared.packets.lock().unwrap().push((set, cmd));
+        let mut model = shared.model.lock().unwrap();
+        let mut state = State::default();
+        state.idsizes = model.idsizes;
+        let reply = match jdwp::Command::deserialize(set, cmd, data.as_slice(), model.idsizes) {
+            Ok(command) => {
+                debug!("Mock VM got {:?}", command);
+                shared.received.lock().unwrap().push(command.clone());
+                let mut scripted = None;
+                for handler in shared.handlers.lock().unwrap().iter_mut() {
+                    scripted = handler(&command, &mut model);
+                    if scripted.is_some() {
+                        break;
+                    }
+                }
+                scripted.unwrap_or_else(|| model.handle(&command))
+            },
+            Err(e) => Err(e),
+        };
+        drop(model);
+        let mut writer = shared.writer.lock().unwrap();
+        let writer = writer.as_mut().unwrap();
+        state.send_reply(id, &reply, writer)?;
+        writer.flush()?;
+    }
+}
+
+/// Starts a mock VM on an in-memory duplex and connects a client to it.
+pub fn connect(model: Model) -> crate::Result<(MockVm, Client, Receiver<client::Event>)> {
+    let (vm_end, client_end) = transport::duplex();
+    let mut vm = MockVm::new(model);
+    vm.serve(vm_end)?;
+    let (client, events) = Client::connect(client_end)?;
+    return Ok((vm, client, events));
+}
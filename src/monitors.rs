@@ -0,0 +1,239 @@
+//! Who holds which monitor, deadlocks among the threads waiting for them,
+//! and how long threads spend blocked on them.
+use crate::{Result,Error};
+use crate::client::Client;
+use crate::jdwp::{self,Capabilities,Command,EventKind,Tag};
+use crate::request;
+use std::collections::{BTreeMap,HashMap};
+use std::hash::Hash;
+use std::time::{Duration,Instant};
+
+/// A monitor a thread holds.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Owned {
+    pub object: u64,
+    /// The frame that locked it, if the VM can tell.
+    pub frame: Option<jdwp::FrameInfo>,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct ThreadMonitors {
+    pub thread: u64,
+    pub owned: Vec<Owned>,
+    /// The monitor it's blocked on, if any.
+    pub contended: Option<u64>,
+    /// Where it's blocked.
+    pub location: Option<jdwp::Location>,
+}
+
+/// One edge of a deadlock: `thread` waits for `monitor`, which `owner` holds.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Wait {
+    pub thread: u64,
+    pub monitor: u64,
+    pub owner: u64,
+}
+
+fn object(tag: &Tag) -> Option<u64> {
+    return match tag {
+        Tag::Object(0) => None,
+        tag => tag.object_id(),
+    };
+}
+
+fn frame_at(client: &Client, thread: u64, depth: i32) -> Result<Option<jdwp::FrameInfo>> {
+    if depth < 0 {
+        return Ok(None);
+    }
+    return Ok(client.request(request::thread_reference_frames(thread, depth, 1))?.into_iter().next());
+}
+
+/// Monitors owned and waited for by every thread. The VM must be suspended.
+pub fn collect(client: &Client) -> Result<Vec<ThreadMonitors>> {
+    let capabilities = client.state().capabilities;
+    for needed in [Capabilities::GET_OWNED_MONITOR_INFO, Capabilities::GET_CURRENT_CONTENDED_MONITOR] {
+        if !capabilities.contains(needed) {
+            return Err(Error::Command(format!("this VM can't report monitors (no {:?} capability)", needed)));
+        }
+    }
+    let threads = client.request(request::all_threads())?;
+    let mut result = Vec::new();
+    for thread in threads {
+        let owned = if capabilities.contains(Capabilities::GET_MONITOR_FRAME_INFO) {
+            let mut owned = Vec::new();
+            for (monitor, depth) in client.request(request::thread_reference_owned_monitors_stack_depth_info(thread))? {
+                if let Some(id) = object(&monitor) {
+                    owned.push(Owned { object: id, frame: frame_at(client, thread, depth)? });
+                }
+            }
+            owned
+        } else {
+            let monitors = client.request(request::thread_reference_owned_monitors(thread))?;
+            monitors.iter().filter_map(object).map(|id| Owned { object: id, frame: None }).collect()
+        };
+        let contended = object(&client.request(request::thread_reference_current_contended_monitor(thread))?);
+        let location = match contended {
+            Some(_) => frame_at(client, thread, 0)?.map(|f| f.location),
+            None => None,
+        };
+        result.push(ThreadMonitors { thread: thread, owned: owned, contended: contended, location: location });
+    }
+    return Ok(result);
+}
+
+/// The owner of each monitor. MonitorInfo, where the VM has it, overrides
+/// the owned lists for contended monitors since it comes from the monitor
+/// itself.
+pub fn owners(client: &Client, threads: &[ThreadMonitors]) -> Result<HashMap<u64, u64>> {
+    let mut owners = HashMap::new();
+    for t in threads {
+        for owned in t.owned.iter() {
+            owners.insert(owned.object, t.thread);
+        }
+    }
+    if client.state().capabilities.contains(Capabilities::GET_MONITOR_INFO) {
+        for monitor in threads.iter().filter_map(|t| t.contended) {
+            let owner = client.request(request::object_reference_monitor_info(monitor))?.owner;
+            if owner != 0 {
+                owners.insert(monitor, owner);
+            }
+        }
+    }
+    return Ok(owners);
+}
+
+/// Cycles in the wait-for graph, each starting from its lowest thread ID.
+/// A thread waits for at most one monitor, so each cycle is found by
+/// following the only edge out of every thread.
+pub fn deadlocks(threads: &[ThreadMonitors], owners: &HashMap<u64, u64>) -> Vec<Vec<Wait>> {
+    let mut edges: BTreeMap<u64, Wait> = BTreeMap::new();
+    for t in threads {
+        if let Some(monitor) = t.contended {
+            if let Some(owner) = owners.get(&monitor) {
+                edges.insert(t.thread, Wait { thread: t.thread, monitor: monitor, owner: *owner });
+            }
+        }
+    }
+    let mut cycles = Vec::new();
+    for start in edges.keys() {
+        let mut path: Vec<&Wait> = Vec::new();
+        let mut at = *start;
+        while let Some(wait) = edges.get(&at) {
+            if path.iter().any(|w| w.thread == at) {
+                break;
+            }
+            path.push(wait);
+            at = wait.owner;
+        }
+        // Only report the cycle from its lowest thread, so once.
+        if at == *start && path.iter().all(|w| w.thread >= *start) {
+            cycles.push(path.into_iter().cloned().collect());
+        }
+    }
+    return cycles;
+}
+
+/// Time spent on one lock or at one call site.
+#[derive(Debug,Clone,Copy,PartialEq,Default)]
+pub struct Stats {
+    /// Times a thread had to wait to enter the monitor.
+    pub contended: u32,
+    pub blocked: Duration,
+    pub longest: Duration,
+    /// Times a thread called Object.wait on it, and how long that took.
+    pub waits: u32,
+    pub waited: Duration,
+}
+
+struct Pending {
+    object: u64,
+    location: jdwp::Location,
+    since: Instant,
+}
+
+/// Contention recorded from monitor events. The events are requested with
+/// SUSPEND_NONE so the program runs at speed, which means times are when
+/// the events reached us rather than when they happened in the VM.
+#[derive(Default)]
+pub struct Contention {
+    pub requests: Vec<(EventKind, i32)>,
+    /// Threads blocked entering, and threads in Object.wait.
+    entering: HashMap<u64, Pending>,
+    waiting: HashMap<u64, Pending>,
+    pub locks: HashMap<u64, Stats>,
+    pub sites: HashMap<jdwp::Location, Stats>,
+}
+
+fn add<K: Eq + Hash>(stats: &mut HashMap<K, Stats>, key: K, wait: bool, time: Duration) {
+    let stats = stats.entry(key).or_default();
+    if wait {
+        stats.waits += 1;
+        stats.waited += time;
+    } else {
+        stats.contended += 1;
+        stats.blocked += time;
+        stats.longest = std::cmp::max(stats.longest, time);
+    }
+}
+
+/// Most blocked first.
+pub fn sorted<K: Clone>(stats: &HashMap<K, Stats>) -> Vec<(K, Stats)> {
+    let mut sorted: Vec<(K, Stats)> = stats.iter().map(|(k, s)| (k.clone(), *s)).collect();
+    sorted.sort_by(|a, b| b.1.blocked.cmp(&a.1.blocked).then(b.1.waited.cmp(&a.1.waited)));
+    return sorted;
+}
+
+impl Contention {
+    /// Asks for all four monitor events.
+    pub fn start(client: &Client) -> Result<Contention> {
+        if !client.state().capabilities.contains(Capabilities::REQUEST_MONITOR_EVENTS) {
+            return Err(Error::Command("this VM can't report monitor events (no REQUEST_MONITOR_EVENTS capability)".to_string()));
+        }
+        let mut contention = Contention::default();
+        let kinds = [EventKind::MonitorContendedEnter, EventKind::MonitorContendedEntered, EventKind::MonitorWait, EventKind::MonitorWaited];
+        for kind in kinds {
+            match client.request(request::event_request_set(kind, jdwp::SUSPEND_NONE, Vec::new())) {
+                Ok(id) => contention.requests.push((kind, id)),
+                Err(e) => {
+                    contention.stop(client);
+                    return Err(e);
+                },
+            }
+        }
+        return Ok(contention);
+    }
+    pub fn stop(&mut self, client: &Client) {
+        for (kind, id) in self.requests.drain(..) {
+            let _ = client.send_and_wait(&Command::EventRequestClear { event_kind: kind, request_id: id });
+        }
+    }
+    /// Records a monitor event that arrived at `at`. Returns false for
+    /// events that aren't ours.
+    pub fn on_event(&mut self, event: &jdwp::Event, at: Instant) -> bool {
+        if !self.requests.iter().any(|(_, id)| event.request_id() == *id) {
+            return false;
+        }
+        match event {
+            jdwp::Event::MonitorContendedEnter { thread, object, location, .. } => {
+                let object = object.object_id().unwrap_or(0);
+                self.entering.insert(*thread, Pending { object: object, location: *location, since: at });
+            },
+            jdwp::Event::MonitorWait { thread, object, location, .. } => {
+                let object = object.object_id().unwrap_or(0);
+                self.waiting.insert(*thread, Pending { object: object, location: *location, since: at });
+            },
+            jdwp::Event::MonitorContendedEntered { thread, .. } | jdwp::Event::MonitorWaited { thread, .. } => {
+                let wait = matches!(event, jdwp::Event::MonitorWaited { .. });
+                let pending = if wait { self.waiting.remove(thread) } else { self.entering.remove(thread) };
+                // Without the start, e.g. if it began before tracing did, there's nothing to time.
+                if let Some(p) = pending {
+                    let time = at.saturating_duration_since(p.since);
+                    add(&mut self.locks, p.object, wait, time);
+                    add(&mut self.sites, p.location, wait, time);
+                }
+            },
+            _ => {},
+        }
+        return true;
+    }
+}
@@ -0,0 +1,78 @@
+//! Modified UTF-8, the encoding of every JDWP string. It is CESU-8 with NUL
+//! written as `C0 80`: characters outside the BMP are a surrogate pair with
+//! each half encoded on its own, in three bytes.
+
+/// `text` as modified UTF-8.
+pub fn encode(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut units = [0u16; 2];
+    for c in text.chars() {
+        for unit in c.encode_utf16(&mut units).iter() {
+            let u = *unit as u32;
+            if u != 0 && u < 0x80 {
+                bytes.push(u as u8);
+            } else if u < 0x800 {
+                bytes.push(0xc0 | (u >> 6) as u8);
+                bytes.push(0x80 | (u & 0x3f) as u8);
+            } else {
+                bytes.push(0xe0 | (u >> 12) as u8);
+                bytes.push(0x80 | ((u >> 6) & 0x3f) as u8);
+                bytes.push(0x80 | (u & 0x3f) as u8);
+            }
+        }
+    }
+    return bytes;
+}
+
+fn continuation(bytes: &[u8], at: usize, n: usize) -> Option<u32> {
+    let mut value = 0;
+    for i in 1..=n {
+        let b = *bytes.get(at + i)?;
+        if b & 0xc0 != 0x80 {
+            return None;
+        }
+        value = (value << 6) | (b & 0x3f) as u32;
+    }
+    return Some(value);
+}
+
+/// The UTF-16 a Java String would hold. Plain UTF-8 four byte sequences
+/// are accepted too, and anything malformed becomes U+FFFD.
+fn utf16(bytes: &[u8]) -> Vec<u16> {
+    let mut units = Vec::with_capacity(bytes.len());
+    let mut at = 0;
+    while at < bytes.len() {
+        let b = bytes[at] as u32;
+        let (value, n) = match b {
+            0x00..=0x7f => (Some(b), 0),
+            0xc0..=0xdf => (continuation(bytes, at, 1).map(|v| ((b & 0x1f) << 6) | v), 1),
+            0xe0..=0xef => (continuation(bytes, at, 2).map(|v| ((b & 0x0f) << 12) | v), 2),
+            0xf0..=0xf7 => (continuation(bytes, at, 3).map(|v| ((b & 0x07) << 18) | v), 3),
+            _ => (None, 0),
+        };
+        // Up to three bytes is one UTF-16 unit, which may be half a pair.
+        match (value, n) {
+            (Some(v), 3) if char::from_u32(v).is_some() => {
+                let mut pair = [0u16; 2];
+                units.extend_from_slice(char::from_u32(v).unwrap().encode_utf16(&mut pair));
+            },
+            (Some(v), 0..=2) => units.push(v as u16),
+            _ => {
+                units.push(0xfffd);
+                at += 1;
+                continue;
+            },
+        }
+        at += 1 + n;
+    }
+    return units;
+}
+
+/// Decodes modified UTF-8. The flag is false if the text is only a lossy
+/// copy, i.e. encoding it again wouldn't give back `bytes`: unpaired
+/// surrogates, malformed sequences, or non-canonical forms like a raw NUL.
+pub fn decode(bytes: &[u8]) -> (String, bool) {
+    let text = String::from_utf16_lossy(&utf16(bytes));
+    let exact = encode(&text) == bytes;
+    return (text, exact);
+}
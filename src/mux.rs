@@ -0,0 +1,462 @@
+//! Lets several debuggers share the one JDWP connection a VM accepts.
+//!
+//! Command IDs are remapped so clients can pick whatever IDs they like, event
+//! requests are remembered per client so composite events only reach the
+//! client that asked for them, and the commands every debugger opens with
+//! (Version, IDSizes, Capabilities) are answered from the cache in `State`.
+use crate::{Result,Error};
+use crate::client::{self,State};
+use crate::jdwp;
+use crate::transport::Transport;
+use std::collections::HashMap;
+use std::io::{BufReader,BufWriter,Read,Write};
+use std::net::{TcpListener,TcpStream};
+use std::sync::{Arc,Mutex};
+use std::time::Duration;
+use log::*;
+
+/// A command the mux sent to the VM on someone's behalf.
+struct InFlight {
+    /// None for the mux's own bookkeeping commands.
+    client: Option<usize>,
+    id: u32,
+    set: u8,
+    cmd: u8,
+    kind: Option<jdwp::EventKind>,
+}
+
+type ClientWriter = Arc<Mutex<Box<dyn Write + Send>>>;
+
+struct Inner {
+    state: State,
+    next_id: u32,
+    next_client: usize,
+    /// Each client's writer has its own lock so nothing writes to a
+    /// socket while holding `Inner`.
+    clients: HashMap<usize, ClientWriter>,
+    in_flight: HashMap<u32, InFlight>,
+    /// Event request ID -> owning client.
+    requests: HashMap<i32, (usize, jdwp::EventKind)>,
+    /// Automatic events (VMStart) that arrived before anybody connected.
+    held: Vec<jdwp::Packet>,
+}
+
+pub struct Mux {
+    inner: Mutex<Inner>,
+    vm: Mutex<Box<dyn Write + Send>>,
+    /// The largest packet accepted from the VM or any client.
+    max_packet: u32,
+}
+
+/// How long a debugger that connected over TCP has to send the handshake.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The debugger speaks first, the mux echoes.
+fn answer_handshake<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> Result<()> {
+    let mut handshake = [0u8; 14];
+    reader.read_exact(&mut handshake)?;
+    if &handshake != client::HANDSHAKE {
+        return Err(Error::HandshakeFailed(handshake.to_vec()));
+    }
+    writer.write_all(client::HANDSHAKE)?;
+    writer.flush()?;
+    return Ok(());
+}
+
+fn write_packet(writer: &mut Box<dyn Write + Send>, packet: &jdwp::Packet) -> std::io::Result<()> {
+    packet.write(writer)?;
+    return writer.flush();
+}
+
+impl Mux {
+    /// Handshakes with the VM, caches its version, capabilities and ID sizes
+    /// and starts relaying. Packets over `max_packet` bytes, or
+    /// `jdwp::MAX_PACKET_SIZE`, drop the connection they came from.
+    pub fn connect<T: Transport>(vm: T, max_packet: Option<u32>) -> Result<Arc<Mux>> {
+        let max_packet = max_packet.unwrap_or(jdwp::MAX_PACKET_SIZE);
+        let (reader, writer) = vm.split()?;
+        let mut reader = BufReader::new(reader);
+        let mut writer = BufWriter::new(writer);
+        client::handshake(&mut reader, &mut writer)?;
+        let mut state = State::default();
+        let mut held = Vec::new();
+        let mut query = |state: &mut State, cmd: jdwp::Command| -> Result<()> {
+            state.send_command(&cmd, &mut writer)?;
+            writer.flush()?;
+            loop {
+                let packet = jdwp::Packet::read_max(&mut reader, max_packet)?;
+                match state.deserialize_packet(&packet) {
+                    Ok(client::DeserializedPacket::Reply(..)) => { return Ok(()); },
+                    Ok(client::DeserializedPacket::Error(_, e)) => { return Err(Error::Jdwp(e)); },
+                    // A VM started with suspend=y greets us with VMStart.
+                    Ok(client::DeserializedPacket::Command(..)) => { held.push(packet); },
+                    Err(e) => { return Err(Error::Jdwp(e)); },
+                }
+            }
+        };
+        query(&mut state, jdwp::Command::Version)?;
+        if state.supports_version(1, 4) {
+            query(&mut state, jdwp::Command::CapabilitiesNew)?;
+        } else {
+            query(&mut state, jdwp::Command::Capabilities)?;
+        }
+        query(&mut state, jdwp::Command::IDSizes)?;
+        let mux = Arc::new(Mux {
+            inner: Mutex::new(Inner {
+                state: state,
+                next_id: 1,
+                next_client: 1,
+                clients: HashMap::new(),
+                in_flight: HashMap::new(),
+                requests: HashMap::new(),
+                held: held,
+            }),
+            vm: Mutex::new(Box::new(writer)),
+            max_packet: max_packet,
+        });
+        let vm_mux = mux.clone();
+        std::thread::spawn(move || {
+            loop {
+                match jdwp::Packet::read_max(&mut reader, max_packet) {
+                    Ok(packet) => vm_mux.vm_packet(packet),
+                    Err(e) => { info!("VM connection closed: {:?}", e); break; },
+                }
+            }
+            // Hanging up on every client tells them the VM is gone.
+            vm_mux.inner.lock().unwrap().clients.clear();
+        });
+        return Ok(mux);
+    }
+    pub fn client_count(&self) -> usize {
+        return self.inner.lock().unwrap().clients.len();
+    }
+    /// Handshakes with a new debugger and starts serving it.
+    pub fn add_client<T: Transport>(self: &Arc<Self>, transport: T) -> Result<usize> {
+        let (reader, writer) = transport.split()?;
+        let mut reader = BufReader::new(reader);
+        let mut writer = BufWriter::new(writer);
+        answer_handshake(&mut reader, &mut writer)?;
+        return self.register(reader, Box::new(writer));
+    }
+    /// Starts serving a debugger that has already handshaked.
+    fn register<R: Read + Send + 'static>(self: &Arc<Self>, mut reader: BufReader<R>, writer: Box<dyn Write + Send>) -> Result<usize> {
+        let writer = Arc::new(Mutex::new(writer));
+        // Holding the new writer's lock until the held events are out keeps
+        // anything routed to this client meanwhile behind them.
+        let (id, held, mut guard) = {
+            let mut inner = self.inner.lock().unwrap();
+            let held = inner.held.drain(..).collect::<Vec<_>>();
+            let id = inner.next_client;
+            inner.next_client += 1;
+            inner.clients.insert(id, writer.clone());
+            (id, held, writer.lock().unwrap())
+        };
+        for packet in held.iter() {
+            if let Err(e) = write_packet(&mut guard, packet) {
+                drop(guard);
+                self.drop_client(id);
+                return Err(e.into());
+            }
+        }
+        drop(guard);
+        let mux = self.clone();
+        std::thread::spawn(move || {
+            loop {
+                match jdwp::Packet::read_max(&mut reader, mux.max_packet) {
+                    Ok(packet) => {
+                        if !mux.client_packet(id, packet) {
+                            break;
+                        }
+                    },
+                    Err(e) => { info!("Client {} closed: {:?}", id, e); break; },
+                }
+            }
+            mux.drop_client(id);
+        });
+        return Ok(id);
+    }
+    /// Handshakes with a debugger that connected over TCP. The handshake
+    /// must arrive within `HANDSHAKE_TIMEOUT`; after that reads block again.
+    fn accept(self: &Arc<Self>, stream: TcpStream) -> Result<usize> {
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        let (reader, writer) = stream.split()?;
+        let mut reader = BufReader::new(reader);
+        let mut writer = BufWriter::new(writer);
+        answer_handshake(&mut reader, &mut writer)?;
+        reader.get_ref().set_read_timeout(None)?;
+        return self.register(reader, Box::new(writer));
+    }
+    /// Serves every debugger that connects to `listener`, each on its own
+    /// thread so one that never handshakes cannot hold up the rest.
+    pub fn serve(self: &Arc<Self>, listener: TcpListener) -> Result<()> {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let mux = self.clone();
+            std::thread::spawn(move || {
+                let peer = stream.peer_addr();
+                match mux.accept(stream) {
+                    Ok(id) => println!("Client {} attached from {:?}", id, peer),
+                    Err(e) => error!("Client from {:?} failed to attach: {:?}", peer, e),
+                }
+            });
+        }
+        return Ok(());
+    }
+    fn send_to_vm(&self, client: Option<usize>, id: u32, set: u8, cmd: u8, data: Vec<u8>) {
+        let kind = if (set, cmd) == (15, 1) {
+            data.first().and_then(|k| jdwp::EventKind::deserialize(*k))
+        } else {
+            None
+        };
+        let vm_id = {
+            let mut inner = self.inner.lock().unwrap();
+            let vm_id = inner.next_id;
+            inner.next_id = inner.next_id.wrapping_add(1);
+            inner.in_flight.insert(vm_id, InFlight { client: client, id: id, set: set, cmd: cmd, kind: kind });
+            vm_id
+        };
+        let packet = jdwp::Packet::Command { id: vm_id, set: set, cmd: cmd, data: data };
+        if let Err(e) = write_packet(&mut self.vm.lock().unwrap(), &packet) {
+            error!("Failed to write to VM: {:?}", e);
+            self.inner.lock().unwrap().in_flight.remove(&vm_id);
+        }
+    }
+    fn send_own(&self, cmd: &jdwp::Command) {
+        let idsizes = self.inner.lock().unwrap().state.idsizes;
+        let (set, cmd, data) = cmd.serialize(idsizes);
+        self.send_to_vm(None, 0, set, cmd, data);
+    }
+    fn reply_to(&self, client: usize, id: u32, reply: jdwp::Result<jdwp::Reply>) {
+        let (writer, packet) = {
+            let inner = self.inner.lock().unwrap();
+            (inner.clients.get(&client).cloned(), inner.state.reply_packet(id, &reply))
+        };
+        if let Some(writer) = writer {
+            if let Err(e) = write_packet(&mut writer.lock().unwrap(), &packet) {
+                warn!("Failed to answer client {}: {:?}", client, e);
+            }
+        }
+    }
+    /// Returns false once the client should be disconnected.
+    fn client_packet(&self, client: usize, packet: jdwp::Packet) -> bool {
+        let (id, set, cmd, data) = match packet {
+            jdwp::Packet::Command { id, set, cmd, data } => (id, set, cmd, data),
+            // Answers to commands the VM sent. Those IDs are not remapped.
+            reply => {
+                if let Err(e) = write_packet(&mut self.vm.lock().unwrap(), &reply) {
+                    error!("Failed to write to VM: {:?}", e);
+                }
+                return true;
+            },
+        };
+        let idsizes = self.inner.lock().unwrap().state.idsizes;
+        let decoded = jdwp::Command::deserialize(set, cmd, data.as_slice(), idsizes);
+        let cached = {
+            let inner = self.inner.lock().unwrap();
+            let state = &inner.state;
+            match decoded {
+                Ok(jdwp::Command::Version) => Some(jdwp::Reply::Version {
+                    description: state.description.clone(),
+                    major: state.major,
+                    minor: state.minor,
+                    version: state.version.clone(),
+                    name: state.name.clone(),
+                }),
+                Ok(jdwp::Command::IDSizes) => Some(jdwp::Reply::IDSizes {
+                    field: state.idsizes.field,
+                    method: state.idsizes.method,
+                    object: state.idsizes.object,
+                    reference_type: state.idsizes.reference_type,
+                    frame: state.idsizes.frame,
+                }),
+                Ok(jdwp::Command::Capabilities) => Some(jdwp::Reply::Capabilities(state.capabilities)),
+                Ok(jdwp::Command::CapabilitiesNew) => Some(jdwp::Reply::CapabilitiesNew(state.capabilities)),
+                _ => None,
+            }
+        };
+        if let Some(reply) = cached {
+            self.reply_to(client, id, Ok(reply));
+            return true;
+        }
+        match decoded {
+            Ok(jdwp::Command::Dispose) => {
+                // Only this client goes away; the VM stays attached for the others.
+                self.reply_to(client, id, Ok(jdwp::Reply::Empty));
+                return false;
+            },
+            Ok(jdwp::Command::EventRequestClear { request_id, .. }) => {
+                let mut inner = self.inner.lock().unwrap();
+                match inner.requests.get(&request_id) {
+                    Some((owner, _)) if *owner == client => { inner.requests.remove(&request_id); },
+                    // Never let one client clear another's requests.
+                    Some(_) => {
+                        drop(inner);
+                        self.reply_to(client, id, Ok(jdwp::Reply::Empty));
+                        return true;
+                    },
+                    None => {},
+                }
+            },
+            Ok(jdwp::Command::EventRequestClearAllBreakpoints) => {
+                let mine: Vec<i32> = {
+                    let mut inner = self.inner.lock().unwrap();
+                    let mine = inner.requests.iter()
+                        .filter(|(_, (owner, kind))| *owner == client && *kind == jdwp::EventKind::Breakpoint)
+                        .map(|(rid, _)| *rid)
+                        .collect::<Vec<_>>();
+                    for rid in mine.iter() {
+                        inner.requests.remove(rid);
+                    }
+                    mine
+                };
+                for rid in mine {
+                    self.send_own(&jdwp::Command::EventRequestClear { event_kind: jdwp::EventKind::Breakpoint, request_id: rid });
+                }
+                self.reply_to(client, id, Ok(jdwp::Reply::Empty));
+                return true;
+            },
+            _ => {},
+        }
+        self.send_to_vm(Some(client), id, set, cmd, data);
+        return true;
+    }
+    fn vm_packet(&self, packet: jdwp::Packet) {
+        match packet {
+            jdwp::Packet::Reply { id, error, data } => {
+                let mut inner = self.inner.lock().unwrap();
+                let flight = match inner.in_flight.remove(&id) {
+                    Some(f) => f,
+                    None => { warn!("VM replied to unknown command {}", id); return; },
+                };
+                let client = match flight.client {
+                    Some(c) => c,
+                    None => {
+                        if error != 0 {
+                            warn!("{} failed with {}", jdwp::command_name(flight.set, flight.cmd), error);
+                        }
+                        return;
+                    },
+                };
+                if let (0, Some(kind)) = (error, flight.kind) {
+                    match jdwp::Reply::deserialize(flight.set, flight.cmd, &data, inner.state.idsizes) {
+                        Ok(jdwp::Reply::EventRequestSet(rid)) => { inner.requests.insert(rid, (client, kind)); },
+                        _ => { warn!("Could not decode EventRequest.Set reply"); },
+                    }
+                }
+                let writer = inner.clients.get(&client).cloned();
+                drop(inner);
+                let reply = jdwp::Packet::Reply { id: flight.id, error: error, data: data };
+                if let Some(writer) = writer {
+                    if let Err(e) = write_packet(&mut writer.lock().unwrap(), &reply) {
+                        warn!("Failed to forward reply to client {}: {:?}", client, e);
+                    }
+                }
+            },
+            jdwp::Packet::Command { id, set, cmd, ref data } => {
+                let idsizes = self.inner.lock().unwrap().state.idsizes;
+                match jdwp::Command::deserialize(set, cmd, data, idsizes) {
+                    Ok(jdwp::Command::Composite { suspend_policy, events }) => {
+                        self.fan_out(id, suspend_policy, events, &packet);
+                    },
+                    // Several clients could each answer, so the mux does it.
+                    _ => match client::answer(set, cmd) {
+                        Some(r) => {
+                            let reply = self.inner.lock().unwrap().state.reply_packet(id, &r);
+                            if let Err(e) = write_packet(&mut self.vm.lock().unwrap(), &reply) {
+                                error!("Failed to write to VM: {:?}", e);
+                            }
+                        },
+                        None => {
+                            let writers = self.inner.lock().unwrap().clients.values().cloned().collect::<Vec<_>>();
+                            for writer in writers {
+                                let _ = write_packet(&mut writer.lock().unwrap(), &packet);
+                            }
+                        },
+                    },
+                }
+            },
+        }
+    }
+    fn fan_out(&self, id: u32, suspend_policy: u8, events: Vec<jdwp::Event>, packet: &jdwp::Packet) {
+        let mut orphans = Vec::new();
+        let mut outgoing = Vec::new();
+        {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.clients.is_empty() && events.iter().all(|e| e.request_id() == 0) {
+                inner.held.push(packet.clone());
+                return;
+            }
+            let mut per_client: HashMap<usize, Vec<jdwp::Event>> = HashMap::new();
+            for event in events {
+                if event.request_id() == 0 {
+                    for c in inner.clients.keys() {
+                        per_client.entry(*c).or_default().push(event.clone());
+                    }
+                    continue;
+                }
+                match inner.requests.get(&event.request_id()) {
+                    Some((owner, _)) if inner.clients.contains_key(owner) => {
+                        per_client.entry(*owner).or_default().push(event);
+                    },
+                    _ => orphans.push(event),
+                }
+            }
+            let idsizes = inner.state.idsizes;
+            let delivered = !per_client.is_empty();
+            for (c, events) in per_client {
+                let (set, cmd, data) = jdwp::Command::Composite { suspend_policy: suspend_policy, events: events }.serialize(idsizes);
+                let packet = jdwp::Packet::Command { id: id, set: set, cmd: cmd, data: data };
+                if let Some(writer) = inner.clients.get(&c) {
+                    outgoing.push((writer.clone(), packet));
+                }
+            }
+            if delivered {
+                orphans.clear();
+            }
+        }
+        for (writer, packet) in outgoing {
+            let _ = write_packet(&mut writer.lock().unwrap(), &packet);
+        }
+        // Nobody will resume threads stopped for a client that has left.
+        match suspend_policy {
+            jdwp::SUSPEND_ALL if !orphans.is_empty() => self.send_own(&jdwp::Command::Resume),
+            jdwp::SUSPEND_EVENT_THREAD => {
+                for thread in orphans.iter().filter_map(|e| e.thread()) {
+                    self.send_own(&jdwp::Command::ThreadReferenceResume { thread: thread });
+                }
+            },
+            _ => {},
+        }
+    }
+    fn drop_client(&self, client: usize) {
+        let mine: Vec<(i32, jdwp::EventKind)> = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.clients.remove(&client);
+            for flight in inner.in_flight.values_mut() {
+                if flight.client == Some(client) {
+                    flight.client = None;
+                }
+            }
+            let mine = inner.requests.iter()
+                .filter(|(_, (owner, _))| *owner == client)
+                .map(|(rid, (_, kind))| (*rid, *kind))
+                .collect::<Vec<_>>();
+            for (rid, _) in mine.iter() {
+                inner.requests.remove(rid);
+            }
+            mine
+        };
+        for (rid, kind) in mine {
+            self.send_own(&jdwp::Command::EventRequestClear { event_kind: kind, request_id: rid });
+        }
+        info!("Client {} detached", client);
+    }
+}
+
+/// Attaches to the VM at `target` and accepts debuggers on `listen`.
+pub fn listen(listen: &str, target: &str, max_packet: Option<u32>) -> Result<()> {
+    let mux = Mux::connect(TcpStream::connect(target)?, max_packet)?;
+    let listener = TcpListener::bind(listen)?;
+    println!("Sharing {} on {}", target, listen);
+    return mux.serve(listener);
+}
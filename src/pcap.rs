@@ -0,0 +1,259 @@
+//! Extracts JDWP packets from libpcap captures of a single TCP connection.
+use crate::capture::{Decoder,Direction,Record};
+use crate::client;
+use crate::jdwp;
+use std::collections::BTreeMap;
+use std::io::Read;
+use log::*;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+/// Anything bigger than this is not a sane capture record.
+const MAX_RECORD: u32 = 1 << 20;
+
+pub fn is_pcap(magic: &[u8]) -> bool {
+    return magic.len() >= 4 && matches!(
+        [magic[0], magic[1], magic[2], magic[3]],
+        [0xd4, 0xc3, 0xb2, 0xa1] | [0xa1, 0xb2, 0xc3, 0xd4] | [0x4d, 0x3c, 0xb2, 0xa1] | [0xa1, 0xb2, 0x3c, 0x4d]
+    );
+}
+
+fn invalid(msg: &str) -> std::io::Error {
+    return std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string());
+}
+
+/// One direction of the TCP connection, reassembled in sequence order.
+struct Stream {
+    isn: Option<u32>,
+    /// Stream offset of the next byte we have not seen yet.
+    next: u64,
+    out_of_order: BTreeMap<u64, Vec<u8>>,
+    buf: Vec<u8>,
+    handshake_done: bool,
+    /// The largest JDWP packet accepted, header included.
+    max_packet: u32,
+}
+
+impl Stream {
+    fn new(max_packet: u32) -> Stream {
+        return Stream {
+            isn: None,
+            next: 0,
+            out_of_order: BTreeMap::new(),
+            buf: Vec::new(),
+            handshake_done: false,
+            max_packet: max_packet,
+        };
+    }
+    fn segment(&mut self, seq: u32, syn: bool, payload: &[u8]) {
+        if syn {
+            self.isn = Some(seq.wrapping_add(1));
+            return;
+        }
+        if payload.is_empty() {
+            return;
+        }
+        let isn = *self.isn.get_or_insert(seq);
+        let offset = seq.wrapping_sub(isn) as u64;
+        self.out_of_order.insert(offset, payload.to_vec());
+        while let Some((&offset, _)) = self.out_of_order.iter().next() {
+            if offset > self.next {
+                break;
+            }
+            let data = self.out_of_order.remove(&offset).unwrap();
+            let skip = (self.next - offset) as usize;
+            if skip < data.len() {
+                self.buf.extend_from_slice(&data[skip..]);
+                self.next += (data.len() - skip) as u64;
+            }
+        }
+    }
+    fn packets(&mut self) -> std::io::Result<Vec<jdwp::Packet>> {
+        let mut packets = Vec::new();
+        if !self.handshake_done {
+            let n = std::cmp::min(self.buf.len(), client::HANDSHAKE.len());
+            if self.buf[..n] != client::HANDSHAKE[..n] {
+                return Err(invalid("TCP stream does not start with the JDWP handshake"));
+            }
+            if n < client::HANDSHAKE.len() {
+                return Ok(packets);
+            }
+            self.buf.drain(..n);
+            self.handshake_done = true;
+        }
+        let mut consumed = 0;
+        while self.buf.len() - consumed >= 11 {
+            let rest = &self.buf[consumed..];
+            // Checked before waiting for the rest, so a bogus length cannot
+            // make us buffer the whole capture.
+            let len = jdwp::Packet::data_len(rest[..11].try_into().unwrap(), self.max_packet)? + 11;
+            if rest.len() < len {
+                break;
+            }
+            let mut packet_bytes = &rest[..len];
+            packets.push(jdwp::Packet::read_max(&mut packet_bytes, self.max_packet)?);
+            consumed += len;
+        }
+        self.buf.drain(..consumed);
+        return Ok(packets);
+    }
+}
+
+struct Segment<'a> {
+    src: Vec<u8>,
+    dst: Vec<u8>,
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    syn: bool,
+    payload: &'a [u8],
+}
+
+/// Strips the link layer and returns the IP packet.
+fn link_payload(linktype: u32, frame: &[u8], little_endian: bool) -> Option<&[u8]> {
+    return match linktype {
+        LINKTYPE_NULL => {
+            let family = if little_endian {
+                u32::from_le_bytes(frame.get(0..4)?.try_into().ok()?)
+            } else {
+                u32::from_be_bytes(frame.get(0..4)?.try_into().ok()?)
+            };
+            // AF_INET is 2 everywhere; AF_INET6 differs between BSDs and Linux.
+            if family == 2 || family == 10 || family == 24 || family == 28 || family == 30 {
+                frame.get(4..)
+            } else {
+                None
+            }
+        },
+        LINKTYPE_ETHERNET => {
+            let mut off = 12;
+            let mut ethertype = u16::from_be_bytes(frame.get(off..off + 2)?.try_into().ok()?);
+            while ethertype == 0x8100 || ethertype == 0x88a8 {
+                off += 4;
+                ethertype = u16::from_be_bytes(frame.get(off..off + 2)?.try_into().ok()?);
+            }
+            frame.get(off + 2..)
+        },
+        LINKTYPE_RAW => Some(frame),
+        LINKTYPE_LINUX_SLL => frame.get(16..),
+        LINKTYPE_LINUX_SLL2 => frame.get(20..),
+        _ => None,
+    };
+}
+
+fn tcp_segment(ip: &[u8]) -> Option<Segment<'_>> {
+    let version = ip.first()? >> 4;
+    let (src, dst, tcp) = match version {
+        4 => {
+            let ihl = ((ip[0] & 0xf) as usize) * 4;
+            let total = u16::from_be_bytes(ip.get(2..4)?.try_into().ok()?) as usize;
+            if *ip.get(9)? != 6 || ihl < 20 {
+                return None;
+            }
+            // Captures with TSO often record a total length of 0.
+            let end = if total == 0 { ip.len() } else { std::cmp::min(total, ip.len()) };
+            (ip.get(12..16)?.to_vec(), ip.get(16..20)?.to_vec(), ip.get(ihl..end)?)
+        },
+        6 => {
+            let payload = u16::from_be_bytes(ip.get(4..6)?.try_into().ok()?) as usize;
+            if *ip.get(6)? != 6 {
+                return None;
+            }
+            let end = std::cmp::min(40 + payload, ip.len());
+            (ip.get(8..24)?.to_vec(), ip.get(24..40)?.to_vec(), ip.get(40..end)?)
+        },
+        _ => { return None; },
+    };
+    let data_offset = ((*tcp.get(12)? >> 4) as usize) * 4;
+    return Some(Segment {
+        src: src,
+        dst: dst,
+        src_port: u16::from_be_bytes(tcp.get(0..2)?.try_into().ok()?),
+        dst_port: u16::from_be_bytes(tcp.get(2..4)?.try_into().ok()?),
+        seq: u32::from_be_bytes(tcp.get(4..8)?.try_into().ok()?),
+        syn: tcp.get(13)? & 0x02 != 0,
+        payload: tcp.get(data_offset..)?,
+    });
+}
+
+/// Reads a pcap file and returns the JDWP packets of the first connection
+/// to or from `port`, named and ordered by the time they completed. Packets
+/// over `max_packet` bytes, or `jdwp::MAX_PACKET_SIZE`, are an error.
+pub fn import<R: Read>(reader: R, port: u16, max_packet: Option<u32>) -> std::io::Result<Vec<Record>> {
+    let max_packet = max_packet.unwrap_or(jdwp::MAX_PACKET_SIZE);
+    let mut reader = reader;
+    let mut header = [0u8; 24];
+    reader.read_exact(&mut header)?;
+    if !is_pcap(&header) {
+        return Err(invalid("not a pcap file"));
+    }
+    let little_endian = header[0] == 0xd4 || header[0] == 0x4d;
+    let nanos = header[0] == 0x4d || header[3] == 0x4d;
+    let u32_at = |b: &[u8], off: usize| -> u32 {
+        let bytes: [u8; 4] = b[off..off + 4].try_into().unwrap();
+        if little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) }
+    };
+    let linktype = u32_at(&header, 20) & 0x0fff_ffff;
+
+    let mut to_vm = Stream::new(max_packet);
+    let mut from_vm = Stream::new(max_packet);
+    let mut debugger: Option<(Vec<u8>, u16)> = None;
+    let mut records = Vec::new();
+    loop {
+        let mut rec = [0u8; 16];
+        match reader.read_exact(&mut rec) {
+            Ok(_) => {},
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => { return Err(e); },
+        }
+        let incl_len = u32_at(&rec, 8);
+        if incl_len > MAX_RECORD {
+            return Err(invalid("pcap record too large"));
+        }
+        let mut frame = vec![0u8; incl_len as usize];
+        reader.read_exact(&mut frame)?;
+        let micros = u32_at(&rec, 0) as u64 * 1_000_000
+            + if nanos { u32_at(&rec, 4) as u64 / 1000 } else { u32_at(&rec, 4) as u64 };
+        let segment = match link_payload(linktype, &frame, little_endian).and_then(tcp_segment) {
+            Some(s) => s,
+            None => { continue; },
+        };
+        let (direction, peer) = if segment.dst_port == port {
+            (Direction::ToVm, (segment.src.clone(), segment.src_port))
+        } else if segment.src_port == port {
+            (Direction::FromVm, (segment.dst.clone(), segment.dst_port))
+        } else {
+            continue;
+        };
+        match &debugger {
+            None => { debugger = Some(peer); },
+            Some(d) if *d != peer => {
+                debug!("Skipping segment from a second connection on port {}", port);
+                continue;
+            },
+            _ => {},
+        }
+        let stream = match direction {
+            Direction::ToVm => &mut to_vm,
+            Direction::FromVm => &mut from_vm,
+        };
+        stream.segment(segment.seq, segment.syn, segment.payload);
+        for packet in stream.packets()? {
+            records.push(Record { micros: micros, direction: direction, name: String::new(), packet: packet });
+        }
+    }
+    if !to_vm.buf.is_empty() || !from_vm.buf.is_empty() {
+        warn!("pcap ends with {} + {} bytes of incomplete JDWP data", to_vm.buf.len(), from_vm.buf.len());
+    }
+    records.sort_by_key(|r| r.micros);
+    let mut decoder = Decoder::new();
+    for record in records.iter_mut() {
+        record.name = decoder.name(record.direction, &record.packet);
+        let _ = decoder.decode(record.direction, &record.packet);
+    }
+    return Ok(records);
+}
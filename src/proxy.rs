@@ -0,0 +1,137 @@
+//! Sits between an IDE and a VM, forwarding everything and logging what it sees.
+use crate::{Result,Error};
+use crate::capture::{self,Decoder,Direction,Recorder};
+use crate::client;
+use crate::jdwp;
+use crate::transport::Transport;
+use std::io::{BufReader,BufWriter,Read,Write};
+use std::net::{TcpListener,TcpStream,Shutdown};
+use std::sync::{Arc,Mutex};
+use log::*;
+
+pub struct Proxy {
+    decoder: Mutex<Decoder>,
+    log: Mutex<Box<dyn Write + Send>>,
+    recorder: Option<Arc<Recorder>>,
+    start: u64,
+    max_packet: u32,
+}
+
+impl Proxy {
+    /// Packets over `max_packet` bytes, or `jdwp::MAX_PACKET_SIZE`, end the session.
+    pub fn new<W: Write + Send + 'static>(log: W, recorder: Option<Arc<Recorder>>, max_packet: Option<u32>) -> Proxy {
+        return Proxy {
+            decoder: Mutex::new(Decoder::new()),
+            log: Mutex::new(Box::new(log)),
+            recorder: recorder,
+            start: capture::now_micros(),
+            max_packet: max_packet.unwrap_or(jdwp::MAX_PACKET_SIZE),
+        };
+    }
+    /// Decodes and logs `packet`. Must happen before forwarding so the
+    /// command is in the pending table by the time its reply shows up.
+    fn observe(&self, direction: Direction, packet: &jdwp::Packet) {
+        let now = capture::now_micros();
+        let mut decoder = self.decoder.lock().unwrap();
+        if let Some(recorder) = self.recorder.as_ref() {
+            let name = decoder.name(direction, packet);
+            recorder.record(direction, &name, packet);
+        }
+        let line = capture::format_packet(&mut decoder, now.saturating_sub(self.start), direction, packet);
+        let mut log = self.log.lock().unwrap();
+        let _ = writeln!(log, "{}", line);
+        let _ = log.flush();
+    }
+    fn pump<R: Read, W: Write>(&self, direction: Direction, reader: R, writer: W) -> std::io::Result<()> {
+        let mut reader = reader;
+        let mut writer = writer;
+        loop {
+            let packet = jdwp::Packet::read_max(&mut reader, self.max_packet)?;
+            self.observe(direction, &packet);
+            packet.write(&mut writer)?;
+            writer.flush()?;
+        }
+    }
+    /// Relays the handshake and then packets in both directions until either
+    /// side hangs up. `close` must make both connections return EOF, e.g. by
+    /// shutting down the sockets.
+    pub fn session<A, B, F>(self: &Arc<Self>, ide: A, vm: B, close: F) -> Result<()>
+    where A: Transport, B: Transport, F: Fn() + Send + Sync + 'static
+    {
+        let (ide_read, ide_write) = ide.split()?;
+        let (vm_read, vm_write) = vm.split()?;
+        let mut ide_read = BufReader::new(ide_read);
+        let mut vm_read = BufReader::new(vm_read);
+        let mut ide_write = BufWriter::new(ide_write);
+        let mut vm_write = BufWriter::new(vm_write);
+
+        let mut handshake = [0u8; 14];
+        ide_read.read_exact(&mut handshake)?;
+        if &handshake != client::HANDSHAKE {
+            close();
+            return Err(Error::HandshakeFailed(handshake.to_vec()));
+        }
+        vm_write.write_all(&handshake)?;
+        vm_write.flush()?;
+        vm_read.read_exact(&mut handshake)?;
+        ide_write.write_all(&handshake)?;
+        ide_write.flush()?;
+        if &handshake != client::HANDSHAKE {
+            close();
+            return Err(Error::HandshakeFailed(handshake.to_vec()));
+        }
+
+        let close = Arc::new(close);
+        let to_vm = {
+            let proxy = self.clone();
+            let close = close.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = proxy.pump(Direction::ToVm, ide_read, vm_write) {
+                    info!("IDE side closed: {:?}", e);
+                }
+                close();
+            })
+        };
+        if let Err(e) = self.pump(Direction::FromVm, vm_read, ide_write) {
+            info!("VM side closed: {:?}", e);
+        }
+        close();
+        let _ = to_vm.join();
+        return Ok(());
+    }
+}
+
+/// Accepts IDE connections on `listen` and relays each to a fresh
+/// connection to `target`, logging to stdout.
+pub fn listen(listen: &str, target: &str, recorder: Option<Arc<Recorder>>, max_packet: Option<u32>) -> Result<()> {
+    let listener = TcpListener::bind(listen)?;
+    println!("Proxying {} -> {}", listen, target);
+    for ide in listener.incoming() {
+        let ide = ide?;
+        let vm = match TcpStream::connect(target) {
+            Ok(vm) => vm,
+            Err(e) => {
+                error!("Could not reach {}: {:?}", target, e);
+                continue;
+            },
+        };
+        println!("Session from {:?}", ide.peer_addr());
+        let recorder = recorder.clone();
+        std::thread::spawn(move || {
+            let (ide_close, vm_close) = match (ide.try_clone(), vm.try_clone()) {
+                (Ok(a), Ok(b)) => (a, b),
+                _ => { return; },
+            };
+            let proxy = Arc::new(Proxy::new(std::io::stdout(), recorder, max_packet));
+            let close = move || {
+                let _ = ide_close.shutdown(Shutdown::Both);
+                let _ = vm_close.shutdown(Shutdown::Both);
+            };
+            if let Err(e) = proxy.session(ide, vm, close) {
+                error!("Proxy session failed: {:?}", e);
+            }
+            println!("Session ended");
+        });
+    }
+    return Ok(());
+}
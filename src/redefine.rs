@@ -0,0 +1,338 @@
+//! Hot code replacement: redefining loaded classes from class files, jars,
+//! directories of classes, or dex files on Android.
+use crate::{Result,Error};
+use crate::client::{Client,State};
+use crate::eval;
+use crate::jdwp::{self,Capabilities,Command};
+use crate::request;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path,PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool,Ordering};
+use std::time::{Duration,SystemTime};
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Format {
+    Class { major: u16, minor: u16 },
+    /// The number in the `dex\n035\0` magic.
+    Dex { version: u32 },
+}
+
+/// New bytes for one class.
+#[derive(Debug,Clone)]
+pub struct Definition {
+    pub signature: String,
+    pub format: Format,
+    /// A whole dex file may define several classes; each gets all of it.
+    pub bytes: Vec<u8>,
+    /// Where it came from, e.g. `app.jar!com/example/Main.class`.
+    pub source: String,
+}
+
+fn format_error(source: &str, what: &str) -> Error {
+    return Error::Command(format!("{} isn't a valid class or dex file: {}", source, what));
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let end = self.at.checked_add(n)?;
+        let bytes = self.data.get(self.at..end)?;
+        self.at = end;
+        return Some(bytes);
+    }
+    fn u1(&mut self) -> Option<u8> {
+        return Some(self.take(1)?[0]);
+    }
+    fn u2(&mut self) -> Option<u16> {
+        let b = self.take(2)?;
+        return Some(u16::from_be_bytes([b[0], b[1]]));
+    }
+}
+
+/// The signature and version of a class file.
+pub fn parse_class(bytes: &[u8]) -> Option<(String, u16, u16)> {
+    let mut r = Reader { data: bytes, at: 0 };
+    if r.take(4)? != [0xca, 0xfe, 0xba, 0xbe] {
+        return None;
+    }
+    let minor = r.u2()?;
+    let major = r.u2()?;
+    let count = r.u2()? as usize;
+    // Only class names and UTF-8 entries matter; everything else is skipped.
+    let mut utf8: HashMap<usize, &[u8]> = HashMap::new();
+    let mut classes: HashMap<usize, usize> = HashMap::new();
+    let mut i = 1;
+    while i < count {
+        match r.u1()? {
+            1 => {
+                let len = r.u2()? as usize;
+                utf8.insert(i, r.take(len)?);
+            },
+            7 => { classes.insert(i, r.u2()? as usize); },
+            8 | 16 | 19 | 20 => { r.take(2)?; },
+            15 => { r.take(3)?; },
+            3 | 4 | 9 | 10 | 11 | 12 | 17 | 18 => { r.take(4)?; },
+            5 | 6 => {
+                r.take(8)?;
+                // Longs and doubles take two slots.
+                i += 1;
+            },
+            _ => { return None; },
+        }
+        i += 1;
+    }
+    r.u2()?;
+    let this = r.u2()? as usize;
+    let name = utf8.get(classes.get(&this)?)?;
+    return Some((format!("L{};", String::from_utf8_lossy(name)), major, minor));
+}
+
+/// The version of a dex file and the signatures of the classes it defines.
+pub fn parse_dex(bytes: &[u8]) -> Option<(u32, Vec<String>)> {
+    if bytes.len() < 0x70 || &bytes[0..4] != b"dex\n" || bytes[7] != 0 {
+        return None;
+    }
+    let version: u32 = std::str::from_utf8(&bytes[4..7]).ok()?.parse().ok()?;
+    let u4 = |at: usize| -> Option<usize> {
+        let b = bytes.get(at..at.checked_add(4)?)?;
+        return Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize);
+    };
+    let (strings, types) = (u4(0x3c)?, u4(0x44)?);
+    let (class_count, class_defs) = (u4(0x60)?, u4(0x64)?);
+    let mut signatures = Vec::new();
+    for i in 0..class_count {
+        let type_idx = u4(class_defs.checked_add(i.checked_mul(32)?)?)?;
+        let string_idx = u4(types.checked_add(type_idx.checked_mul(4)?)?)?;
+        let mut at = u4(strings.checked_add(string_idx.checked_mul(4)?)?)?;
+        // Skip the ULEB128 length in UTF-16 units; the data ends at a NUL.
+        while *bytes.get(at)? & 0x80 != 0 {
+            at += 1;
+        }
+        at += 1;
+        let len = bytes.get(at..)?.iter().position(|b| *b == 0)?;
+        signatures.push(String::from_utf8_lossy(&bytes[at..at + len]).into_owned());
+    }
+    return Some((version, signatures));
+}
+
+/// Definitions from one file's bytes, by its magic.
+fn definitions(bytes: Vec<u8>, source: &str) -> Result<Vec<Definition>> {
+    if bytes.starts_with(b"dex\n") {
+        let (version, signatures) = parse_dex(&bytes).ok_or_else(|| format_error(source, "bad dex header"))?;
+        return Ok(signatures.into_iter().map(|signature| Definition {
+            signature: signature,
+            format: Format::Dex { version: version },
+            bytes: bytes.clone(),
+            source: source.to_string(),
+        }).collect());
+    }
+    let (signature, major, minor) = parse_class(&bytes).ok_or_else(|| format_error(source, "bad class file"))?;
+    return Ok(vec![Definition {
+        signature: signature,
+        format: Format::Class { major: major, minor: minor },
+        bytes: bytes,
+        source: source.to_string(),
+    }]);
+}
+
+fn is_code(name: &str) -> bool {
+    return (name.ends_with(".class") && !name.ends_with("module-info.class") && !name.starts_with("META-INF/"))
+        || name.ends_with(".dex");
+}
+
+fn load_zip(path: &Path) -> Result<Vec<Definition>> {
+    let zip_error = |e: zip::result::ZipError| Error::Command(format!("{}: {}", path.display(), e));
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(path)?).map_err(zip_error)?;
+    let mut result = Vec::new();
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(zip_error)?;
+        if !entry.is_file() || !is_code(entry.name()) {
+            continue;
+        }
+        let source = format!("{}!{}", path.display(), entry.name());
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes)?;
+        result.extend(definitions(bytes, &source)?);
+    }
+    return Ok(result);
+}
+
+/// Every .class and .dex file under `dir`.
+fn code_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            code_files(&path, files)?;
+        } else if is_code(&path.to_string_lossy()) {
+            files.push(path);
+        }
+    }
+    return Ok(());
+}
+
+/// Reads a class file, dex file, jar, apk or directory of class files.
+pub fn load(path: &Path) -> Result<Vec<Definition>> {
+    if path.is_dir() {
+        let mut files = Vec::new();
+        code_files(path, &mut files)?;
+        let mut result = Vec::new();
+        for file in files {
+            result.extend(definitions(std::fs::read(&file)?, &file.display().to_string())?);
+        }
+        return Ok(result);
+    }
+    let bytes = std::fs::read(path)?;
+    if bytes.starts_with(b"PK\x03\x04") {
+        return load_zip(path);
+    }
+    return definitions(bytes, &path.display().to_string());
+}
+
+/// The newest class file major version the VM's Java release can load, e.g.
+/// 52 for JDWP 1.8 and 61 for JDWP 17.0.
+pub fn max_class_version(state: &State) -> u16 {
+    let release = if state.major == 1 { state.minor } else { state.major };
+    return (44 + release.max(0)) as u16;
+}
+
+/// What a JDWP error from RedefineClasses means.
+fn explain(e: &jdwp::Error) -> Option<&'static str> {
+    return Some(match e {
+        jdwp::Error::SchemaChangeNotImplemented => "fields were added, removed or changed, which this VM can't do",
+        jdwp::Error::AddMethodNotImplemented => "methods were added, which this VM can't do",
+        jdwp::Error::DeleteMethodNotImplemented => "methods were removed, which this VM can't do",
+        jdwp::Error::HierarchyChangeNotImplemented => "the superclass or interfaces changed, which this VM can't do",
+        jdwp::Error::ClassModifiersChangeNotImplemented => "the class modifiers changed, which this VM can't do",
+        jdwp::Error::MethodModifiersChangeNotImplemented => "method modifiers changed, which this VM can't do",
+        jdwp::Error::ClassAttributeChangeNotImplemented => "class attributes changed, which this VM can't do",
+        jdwp::Error::UnsupportedVersion => "the VM doesn't support this class file version",
+        jdwp::Error::InvalidClassFormat => "the VM rejected the class file format",
+        jdwp::Error::FailsVerification => "the new class fails verification",
+        jdwp::Error::NamesDontMatch => "a class file defines a different class than the one it replaces",
+        jdwp::Error::CircularClassDefinition => "the new classes would be their own superclasses",
+        jdwp::Error::Unimplemented => "this VM doesn't implement RedefineClasses",
+        _ => { return None; },
+    });
+}
+
+/// The result of one redefinition.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Outcome {
+    /// Java names of the classes redefined.
+    pub redefined: Vec<String>,
+    /// Classes in the input that the VM hasn't loaded, which needn't be
+    /// redefined since they'll load from the new code anyway.
+    pub not_loaded: Vec<String>,
+}
+
+/// Redefines every loaded class in `definitions` in one atomic command.
+pub fn redefine(client: &Client, definitions: &[Definition]) -> Result<Outcome> {
+    let (capabilities, max_version) = {
+        let state = client.state();
+        (state.capabilities, max_class_version(&state))
+    };
+    if !capabilities.contains(Capabilities::REDEFINE_CLASSES) {
+        return Err(Error::Command("this VM can't redefine classes (no REDEFINE_CLASSES capability)".to_string()));
+    }
+    for d in definitions {
+        if let Format::Class { major, minor } = d.format {
+            if major > max_version {
+                return Err(Error::Command(format!("{} is class file version {}.{}, but this VM only loads up to {}",
+                    d.source, major, minor, max_version)));
+            }
+        }
+    }
+    let mut classes = Vec::new();
+    let mut outcome = Outcome { redefined: Vec::new(), not_loaded: Vec::new() };
+    for d in definitions {
+        let loaded = client.request(request::classes_by_signature(d.signature.clone()))?;
+        if loaded.is_empty() {
+            outcome.not_loaded.push(eval::type_name(&d.signature));
+        } else {
+            outcome.redefined.push(eval::type_name(&d.signature));
+        }
+        // The same class may be loaded by several class loaders.
+        for class in loaded {
+            classes.push((class.type_id, d.bytes.clone()));
+        }
+    }
+    if classes.is_empty() {
+        return Ok(outcome);
+    }
+    return match client.send_and_wait(&Command::RedefineClasses { classes: classes }) {
+        Ok(_) => Ok(outcome),
+        Err(Error::Jdwp(e)) => match explain(&e) {
+            Some(why) => {
+                let mut why = why.to_string();
+                if e == jdwp::Error::AddMethodNotImplemented && !capabilities.contains(Capabilities::ADD_METHOD) {
+                    why.push_str(" (no ADD_METHOD capability)");
+                } else if e == jdwp::Error::SchemaChangeNotImplemented
+                    && !capabilities.contains(Capabilities::UNRESTRICTEDLY_REDEFINE_CLASSES) {
+                    why.push_str(" (no UNRESTRICTEDLY_REDEFINE_CLASSES capability)");
+                }
+                Err(Error::Command(format!("can't redefine {}: {}", outcome.redefined.join(", "), why)))
+            },
+            None => Err(Error::Jdwp(e)),
+        },
+        Err(e) => Err(e),
+    };
+}
+
+/// Redefines from `path` again whenever a file under it changes, until dropped.
+pub struct Watcher {
+    pub path: PathBuf,
+    stop: Arc<AtomicBool>,
+}
+
+/// Modification times of the files that make up `path`.
+fn snapshot(path: &Path) -> HashMap<PathBuf, SystemTime> {
+    let mut files = Vec::new();
+    if path.is_dir() {
+        let _ = code_files(path, &mut files);
+    } else {
+        files.push(path.to_path_buf());
+    }
+    return files.into_iter()
+        .filter_map(|f| std::fs::metadata(&f).and_then(|m| m.modified()).ok().map(|t| (f, t)))
+        .collect();
+}
+
+impl Watcher {
+    /// Polls `path` every `interval` and passes each redefinition's result
+    /// to `report`. Only the changed files of a directory are redefined.
+    pub fn start<F>(client: Arc<Client>, path: &Path, interval: Duration, mut report: F) -> Watcher
+            where F: FnMut(Result<Outcome>) + Send + 'static {
+        let stop = Arc::new(AtomicBool::new(false));
+        let (thread_stop, root) = (stop.clone(), path.to_path_buf());
+        std::thread::spawn(move || {
+            let mut seen = snapshot(&root);
+            while !thread_stop.load(Ordering::Relaxed) {
+                std::thread::sleep(interval);
+                let now = snapshot(&root);
+                let changed: Vec<&PathBuf> = now.iter().filter(|(f, t)| seen.get(*f) != Some(t)).map(|(f, _)| f).collect();
+                if !changed.is_empty() && !thread_stop.load(Ordering::Relaxed) {
+                    let mut definitions = Vec::new();
+                    let loaded = changed.iter().try_for_each(|f| {
+                        definitions.extend(load(f)?);
+                        return Ok(());
+                    });
+                    report(loaded.and_then(|_| redefine(&client, &definitions)));
+                }
+                seen = now;
+            }
+        });
+        return Watcher { path: path.to_path_buf(), stop: stop };
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,236 @@
+//! Commands paired with the type their reply unpacks to, so callers of
+//! `Client::request` get that type instead of matching on `Reply` themselves.
+//! There is one function per command with a reply worth unpacking, named
+//! after its `Command` variant. Commands whose reply is empty, and the ones
+//! `Client::initialize` sends, go through `Client::send` as before.
+use crate::ddm::Chunk;
+use crate::jdwp::{self,Command,Reply,Tag};
+
+/// A command and how to get a `T` out of its reply.
+pub struct Request<T> {
+    pub command: Command,
+    /// Hands back the reply untouched if it is not the kind expected.
+    pub unpack: fn(Reply) -> std::result::Result<T, Reply>,
+}
+
+impl<T> Request<T> {
+    pub fn new(command: Command, unpack: fn(Reply) -> std::result::Result<T, Reply>) -> Request<T> {
+        return Request { command: command, unpack: unpack };
+    }
+}
+
+/// Method.LineTable.
+#[derive(Debug,Clone,PartialEq)]
+pub struct LineTable {
+    pub start: i64,
+    pub end: i64,
+    pub lines: Vec<jdwp::LineEntry>,
+}
+
+/// Method.VariableTable.
+#[derive(Debug,Clone,PartialEq)]
+pub struct VariableTable {
+    pub arg_count: i32,
+    pub variables: Vec<jdwp::VariableInfo>,
+}
+
+/// ThreadReference.Status.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct ThreadStatus {
+    pub thread_status: i32,
+    pub suspend_status: i32,
+}
+
+/// ClassType or ObjectReference InvokeMethod. One of the two is a null object.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Invoked {
+    pub return_value: Tag,
+    pub exception: Tag,
+}
+
+/// ObjectReference.MonitorInfo.
+#[derive(Debug,Clone,PartialEq)]
+pub struct MonitorInfo {
+    pub owner: u64,
+    pub entry_count: i32,
+    pub waiters: Vec<u64>,
+}
+
+macro_rules! requests {
+    ($($(#[$doc:meta])* fn $name:ident($($arg:ident: $ty:ty),*) -> $out:ty { $command:expr, $reply:pat => $value:expr })*) => {
+        $(
+            $(#[$doc])*
+            pub fn $name($($arg: $ty),*) -> Request<$out> {
+                return Request {
+                    command: $command,
+                    unpack: |reply| match reply {
+                        $reply => Ok($value),
+                        r => Err(r),
+                    },
+                };
+            }
+        )*
+    };
+}
+
+requests! {
+    fn classes_by_signature(signature: String) -> Vec<jdwp::ClassRef> {
+        Command::ClassesBySignature { signature: signature },
+        Reply::ClassesBySignature(classes) => classes
+    }
+    fn all_classes() -> Vec<jdwp::ClassInfo> {
+        Command::AllClasses,
+        Reply::AllClasses(classes) => classes
+    }
+    fn all_classes_with_generic() -> Vec<jdwp::GenericClassInfo> {
+        Command::AllClassesWithGeneric,
+        Reply::AllClassesWithGeneric(classes) => classes
+    }
+    fn all_threads() -> Vec<u64> {
+        Command::AllThreads,
+        Reply::AllThreads(threads) => threads
+    }
+    fn create_string(utf: String) -> u64 {
+        Command::CreateString { utf: utf },
+        Reply::CreateString(string) => string
+    }
+    /// One count per class, in the order asked for.
+    fn instance_counts(ref_types: Vec<u64>) -> Vec<i64> {
+        Command::InstanceCounts { ref_types: ref_types },
+        Reply::InstanceCounts(counts) => counts
+    }
+    fn reference_type_signature(ref_type: u64) -> String {
+        Command::ReferenceTypeSignature { ref_type: ref_type },
+        Reply::ReferenceTypeSignature(signature) => signature
+    }
+    fn reference_type_modifiers(ref_type: u64) -> i32 {
+        Command::ReferenceTypeModifiers { ref_type: ref_type },
+        Reply::ReferenceTypeModifiers(bits) => bits
+    }
+    fn reference_type_fields(ref_type: u64) -> Vec<jdwp::FieldInfo> {
+        Command::ReferenceTypeFields { ref_type: ref_type },
+        Reply::ReferenceTypeFields(fields) => fields
+    }
+    fn reference_type_methods(ref_type: u64) -> Vec<jdwp::MethodInfo> {
+        Command::ReferenceTypeMethods { ref_type: ref_type },
+        Reply::ReferenceTypeMethods(methods) => methods
+    }
+    fn reference_type_get_values(ref_type: u64, fields: Vec<u64>) -> Vec<Tag> {
+        Command::ReferenceTypeGetValues { ref_type: ref_type, fields: fields },
+        Reply::Values(values) => values
+    }
+    fn reference_type_source_file(ref_type: u64) -> String {
+        Command::ReferenceTypeSourceFile { ref_type: ref_type },
+        Reply::ReferenceTypeSourceFile(file) => file
+    }
+    fn reference_type_interfaces(ref_type: u64) -> Vec<u64> {
+        Command::ReferenceTypeInterfaces { ref_type: ref_type },
+        Reply::ReferenceTypeInterfaces(interfaces) => interfaces
+    }
+    fn reference_type_instances(ref_type: u64, max_instances: i32) -> Vec<Tag> {
+        Command::ReferenceTypeInstances { ref_type: ref_type, max_instances: max_instances },
+        Reply::Objects(objects) => objects
+    }
+    /// 0 for java.lang.Object and interfaces.
+    fn class_type_superclass(class: u64) -> u64 {
+        Command::ClassTypeSuperclass { class: class },
+        Reply::ClassTypeSuperclass(superclass) => superclass
+    }
+    fn class_type_invoke_method(class: u64, thread: u64, method: u64, args: Vec<Tag>, options: i32) -> Invoked {
+        Command::ClassTypeInvokeMethod { class: class, thread: thread, method: method, args: args, options: options },
+        Reply::InvokeMethod { return_value, exception } => Invoked { return_value: return_value, exception: exception }
+    }
+    fn method_line_table(ref_type: u64, method: u64) -> LineTable {
+        Command::MethodLineTable { ref_type: ref_type, method: method },
+        Reply::MethodLineTable { start, end, lines } => LineTable { start: start, end: end, lines: lines }
+    }
+    fn method_variable_table(ref_type: u64, method: u64) -> VariableTable {
+        Command::MethodVariableTable { ref_type: ref_type, method: method },
+        Reply::MethodVariableTable { arg_count, variables } => VariableTable { arg_count: arg_count, variables: variables }
+    }
+    /// The type tag and ID of the object's runtime type.
+    fn object_reference_reference_type(object: u64) -> (u8, u64) {
+        Command::ObjectReferenceReferenceType { object: object },
+        Reply::ObjectReferenceReferenceType { ref_type_tag, type_id } => (ref_type_tag, type_id)
+    }
+    fn object_reference_get_values(object: u64, fields: Vec<u64>) -> Vec<Tag> {
+        Command::ObjectReferenceGetValues { object: object, fields: fields },
+        Reply::Values(values) => values
+    }
+    fn object_reference_monitor_info(object: u64) -> MonitorInfo {
+        Command::ObjectReferenceMonitorInfo { object: object },
+        Reply::ObjectReferenceMonitorInfo { owner, entry_count, waiters } => MonitorInfo { owner: owner, entry_count: entry_count, waiters: waiters }
+    }
+    fn object_reference_is_collected(object: u64) -> bool {
+        Command::ObjectReferenceIsCollected { object: object },
+        Reply::IsCollected(collected) => collected
+    }
+    fn object_reference_referring_objects(object: u64, max_referrers: i32) -> Vec<Tag> {
+        Command::ObjectReferenceReferringObjects { object: object, max_referrers: max_referrers },
+        Reply::Objects(objects) => objects
+    }
+    fn object_reference_invoke_method(object: u64, thread: u64, class: u64, method: u64, args: Vec<Tag>, options: i32) -> Invoked {
+        Command::ObjectReferenceInvokeMethod { object: object, thread: thread, class: class, method: method, args: args, options: options },
+        Reply::InvokeMethod { return_value, exception } => Invoked { return_value: return_value, exception: exception }
+    }
+    fn string_reference_value(string: u64) -> jdwp::JavaString {
+        Command::StringReferenceValue { string: string },
+        Reply::StringReferenceValue(value) => value
+    }
+    fn thread_reference_name(thread: u64) -> String {
+        Command::ThreadReferenceName { thread: thread },
+        Reply::ThreadReferenceName(name) => name
+    }
+    fn thread_reference_status(thread: u64) -> ThreadStatus {
+        Command::ThreadReferenceStatus { thread: thread },
+        Reply::ThreadReferenceStatus { thread_status, suspend_status } => ThreadStatus { thread_status: thread_status, suspend_status: suspend_status }
+    }
+    /// `length` -1 means all frames from `start` on.
+    fn thread_reference_frames(thread: u64, start: i32, length: i32) -> Vec<jdwp::FrameInfo> {
+        Command::ThreadReferenceFrames { thread: thread, start: start, length: length },
+        Reply::ThreadReferenceFrames(frames) => frames
+    }
+    fn thread_reference_frame_count(thread: u64) -> i32 {
+        Command::ThreadReferenceFrameCount { thread: thread },
+        Reply::ThreadReferenceFrameCount(count) => count
+    }
+    fn thread_reference_owned_monitors(thread: u64) -> Vec<Tag> {
+        Command::ThreadReferenceOwnedMonitors { thread: thread },
+        Reply::ThreadReferenceOwnedMonitors(monitors) => monitors
+    }
+    /// A null object if the thread isn't waiting for a monitor.
+    fn thread_reference_current_contended_monitor(thread: u64) -> Tag {
+        Command::ThreadReferenceCurrentContendedMonitor { thread: thread },
+        Reply::ThreadReferenceCurrentContendedMonitor(monitor) => monitor
+    }
+    /// Each monitor with the stack depth of the frame that locked it.
+    fn thread_reference_owned_monitors_stack_depth_info(thread: u64) -> Vec<(Tag, i32)> {
+        Command::ThreadReferenceOwnedMonitorsStackDepthInfo { thread: thread },
+        Reply::ThreadReferenceOwnedMonitorsStackDepthInfo(monitors) => monitors
+    }
+    fn array_reference_length(array: u64) -> i32 {
+        Command::ArrayReferenceLength { array: array },
+        Reply::ArrayReferenceLength(length) => length
+    }
+    fn array_reference_get_values(array: u64, first: i32, length: i32) -> Vec<Tag> {
+        Command::ArrayReferenceGetValues { array: array, first: first, length: length },
+        Reply::ArrayReferenceGetValues { values, .. } => values
+    }
+    fn stack_frame_get_values(thread: u64, frame: u64, slots: Vec<(i32, u8)>) -> Vec<Tag> {
+        Command::StackFrameGetValues { thread: thread, frame: frame, slots: slots },
+        Reply::Values(values) => values
+    }
+    fn stack_frame_this_object(thread: u64, frame: u64) -> Tag {
+        Command::StackFrameThisObject { thread: thread, frame: frame },
+        Reply::StackFrameThisObject(this) => this
+    }
+    /// The new request's ID.
+    fn event_request_set(event_kind: jdwp::EventKind, suspend_policy: u8, modifiers: Vec<jdwp::Modifier>) -> i32 {
+        Command::EventRequestSet { event_kind: event_kind, suspend_policy: suspend_policy, modifiers: modifiers },
+        Reply::EventRequestSet(id) => id
+    }
+    fn ddm_chunk(chunk: Chunk) -> Vec<Chunk> {
+        Command::DdmChunk(chunk),
+        Reply::DdmChunks(chunks) => chunks
+    }
+}
@@ -0,0 +1,365 @@
+//! JNI type signatures, with or without generics: `I`, `[Ljava/lang/String;`,
+//! `(ILjava/util/List;)V`, `<T:Ljava/lang/Object;>(TT;)TT;`. Parsed into
+//! `Type`s, which render as Java source text or back as erased signatures.
+use crate::jdwp::Tag;
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Type {
+    Boolean,
+    Byte,
+    Char,
+    Short,
+    Int,
+    Long,
+    Float,
+    Double,
+    /// Only as a method's return type.
+    Void,
+    /// One part per nesting level where a generic signature spells them out,
+    /// as in `Outer<T>.Inner`. The first part has the package, with slashes.
+    Class(Vec<ClassPart>),
+    Array(Box<Type>),
+    /// A type variable like `T`.
+    Variable(String),
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct ClassPart {
+    pub name: String,
+    pub args: Vec<TypeArg>,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum TypeArg {
+    Exactly(Type),
+    /// `? extends T`
+    Extends(Type),
+    /// `? super T`
+    Super(Type),
+    /// `?`
+    Any,
+}
+
+/// A type parameter declaration such as `T extends Number & Comparable<T>`.
+#[derive(Debug,Clone,PartialEq)]
+pub struct TypeParam {
+    pub name: String,
+    pub bounds: Vec<Type>,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct MethodSignature {
+    pub type_params: Vec<TypeParam>,
+    pub params: Vec<Type>,
+    pub returns: Type,
+    pub throws: Vec<Type>,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct ClassSignature {
+    pub type_params: Vec<TypeParam>,
+    pub superclass: Type,
+    pub interfaces: Vec<Type>,
+}
+
+fn join<T, F: Fn(&T) -> String>(items: &[T], separator: &str, f: F) -> String {
+    return items.iter().map(f).collect::<Vec<String>>().join(separator);
+}
+
+impl Type {
+    /// The class named by an erased signature like `java/lang/String`.
+    pub fn class(name: &str) -> Type {
+        return Type::Class(vec![ClassPart { name: name.to_string(), args: Vec::new() }]);
+    }
+    /// As Java source would write it, with packages.
+    pub fn java(&self) -> String {
+        return match self {
+            Type::Boolean => "boolean".to_string(),
+            Type::Byte => "byte".to_string(),
+            Type::Char => "char".to_string(),
+            Type::Short => "short".to_string(),
+            Type::Int => "int".to_string(),
+            Type::Long => "long".to_string(),
+            Type::Float => "float".to_string(),
+            Type::Double => "double".to_string(),
+            Type::Void => "void".to_string(),
+            Type::Class(parts) => join(parts, ".", |part| {
+                let name = part.name.replace('/', ".");
+                if part.args.is_empty() {
+                    name
+                } else {
+                    format!("{}<{}>", name, join(&part.args, ", ", |a| a.java()))
+                }
+            }),
+            Type::Array(element) => format!("{}[]", element.java()),
+            Type::Variable(name) => name.clone(),
+        };
+    }
+    /// The erased signature, e.g. `Ljava/util/List;` for `List<String>`.
+    /// Type variables erase to Object, not to their bounds.
+    pub fn jni(&self) -> String {
+        return match self {
+            Type::Class(parts) => format!("L{};", join(parts, "$", |p| p.name.clone())),
+            Type::Array(element) => format!("[{}", element.jni()),
+            Type::Variable(_) => "Ljava/lang/Object;".to_string(),
+            primitive => (primitive.tag() as char).to_string(),
+        };
+    }
+    /// The JDWP tag for values of this type. Strings, threads and the like
+    /// have their own tags rather than `L`.
+    pub fn tag(&self) -> u8 {
+        return match self {
+            Type::Boolean => b'Z',
+            Type::Byte => b'B',
+            Type::Char => b'C',
+            Type::Short => b'S',
+            Type::Int => b'I',
+            Type::Long => b'J',
+            Type::Float => b'F',
+            Type::Double => b'D',
+            Type::Void => b'V',
+            Type::Array(_) => b'[',
+            Type::Variable(_) => b'L',
+            Type::Class(_) => match self.jni().as_str() {
+                "Ljava/lang/String;" => b's',
+                "Ljava/lang/Thread;" => b't',
+                "Ljava/lang/ThreadGroup;" => b'g',
+                "Ljava/lang/ClassLoader;" => b'l',
+                "Ljava/lang/Class;" => b'c',
+                _ => b'L',
+            },
+        };
+    }
+}
+
+impl TypeArg {
+    pub fn java(&self) -> String {
+        return match self {
+            TypeArg::Exactly(t) => t.java(),
+            TypeArg::Extends(t) => format!("? extends {}", t.java()),
+            TypeArg::Super(t) => format!("? super {}", t.java()),
+            TypeArg::Any => "?".to_string(),
+        };
+    }
+}
+
+/// `<K, V extends Comparable<V>>`, or nothing if there are no parameters.
+/// An Object bound is left out, as javac does.
+pub fn type_params_java(params: &[TypeParam]) -> String {
+    if params.is_empty() {
+        return String::new();
+    }
+    return format!("<{}>", join(params, ", ", |p| {
+        match p.bounds.as_slice() {
+            [] => p.name.clone(),
+            [only] if only.jni() == "Ljava/lang/Object;" => p.name.clone(),
+            bounds => format!("{} extends {}", p.name, join(bounds, " & ", |b| b.java())),
+        }
+    }));
+}
+
+impl MethodSignature {
+    /// A declaration of a method called `name` with its parameter types.
+    pub fn java(&self, name: &str) -> String {
+        let mut text = type_params_java(&self.type_params);
+        if !text.is_empty() {
+            text.push(' ');
+        }
+        text += &format!("{} {}({})", self.returns.java(), name, join(&self.params, ", ", |p| p.java()));
+        if !self.throws.is_empty() {
+            text += &format!(" throws {}", join(&self.throws, ", ", |t| t.java()));
+        }
+        return text;
+    }
+}
+
+/// The value of reference type `tag` for the object `id`, so an argument
+/// goes over the wire tagged as what the method declares.
+pub fn reference(tag: u8, id: u64) -> Option<Tag> {
+    return Some(match tag {
+        b'L' => Tag::Object(id),
+        b'[' => Tag::Array(id),
+        b's' => Tag::String(id),
+        b't' => Tag::Thread(id),
+        b'g' => Tag::ThreadGroup(id),
+        b'l' => Tag::ClassLoader(id),
+        b'c' => Tag::ClassObject(id),
+        _ => { return None; },
+    });
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    at: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        return self.text.as_bytes().get(self.at).copied();
+    }
+    fn next(&mut self) -> Option<u8> {
+        let c = self.peek()?;
+        self.at += 1;
+        return Some(c);
+    }
+    fn expect(&mut self, c: u8) -> Option<()> {
+        return if self.next()? == c { Some(()) } else { None };
+    }
+    fn done(&self) -> bool {
+        return self.at == self.text.len();
+    }
+    /// A non-empty name running up to one of `ends`, which all are ASCII so
+    /// the slice can't split a character.
+    fn identifier(&mut self, ends: &[u8]) -> Option<String> {
+        let start = self.at;
+        while !ends.contains(&self.peek()?) {
+            self.at += 1;
+        }
+        if self.at == start {
+            return None;
+        }
+        return Some(self.text[start..self.at].to_string());
+    }
+    fn java_type(&mut self) -> Option<Type> {
+        let primitive = match self.peek()? {
+            b'Z' => Type::Boolean,
+            b'B' => Type::Byte,
+            b'C' => Type::Char,
+            b'S' => Type::Short,
+            b'I' => Type::Int,
+            b'J' => Type::Long,
+            b'F' => Type::Float,
+            b'D' => Type::Double,
+            _ => { return self.reference(); },
+        };
+        self.at += 1;
+        return Some(primitive);
+    }
+    fn return_type(&mut self) -> Option<Type> {
+        if self.peek()? == b'V' {
+            self.at += 1;
+            return Some(Type::Void);
+        }
+        return self.java_type();
+    }
+    fn reference(&mut self) -> Option<Type> {
+        return match self.next()? {
+            b'L' => {
+                let mut parts = Vec::new();
+                loop {
+                    let name = self.identifier(b";<.")?;
+                    let args = if self.peek()? == b'<' { self.type_args()? } else { Vec::new() };
+                    parts.push(ClassPart { name: name, args: args });
+                    match self.next()? {
+                        b';' => break,
+                        b'.' => {},
+                        _ => { return None; },
+                    }
+                }
+                Some(Type::Class(parts))
+            },
+            b'[' => Some(Type::Array(Box::new(self.java_type()?))),
+            b'T' => {
+                let name = self.identifier(b";")?;
+                self.expect(b';')?;
+                Some(Type::Variable(name))
+            },
+            _ => None,
+        };
+    }
+    fn type_args(&mut self) -> Option<Vec<TypeArg>> {
+        self.expect(b'<')?;
+        let mut args = Vec::new();
+        while self.peek()? != b'>' {
+            args.push(match self.peek()? {
+                b'*' => {
+                    self.at += 1;
+                    TypeArg::Any
+                },
+                b'+' => {
+                    self.at += 1;
+                    TypeArg::Extends(self.reference()?)
+                },
+                b'-' => {
+                    self.at += 1;
+                    TypeArg::Super(self.reference()?)
+                },
+                _ => TypeArg::Exactly(self.reference()?),
+            });
+        }
+        self.at += 1;
+        return if args.is_empty() { None } else { Some(args) };
+    }
+    /// `<T:Ljava/lang/Object;U::Ljava/lang/Runnable;>`, where an empty class
+    /// bound means there are only interface bounds.
+    fn type_params(&mut self) -> Option<Vec<TypeParam>> {
+        if self.peek() != Some(b'<') {
+            return Some(Vec::new());
+        }
+        self.at += 1;
+        let mut params = Vec::new();
+        while self.peek()? != b'>' {
+            let name = self.identifier(b":")?;
+            let mut bounds = Vec::new();
+            self.expect(b':')?;
+            if !matches!(self.peek()?, b':' | b'>') && !self.at_param_name() {
+                bounds.push(self.reference()?);
+            }
+            while self.peek()? == b':' {
+                self.at += 1;
+                bounds.push(self.reference()?);
+            }
+            params.push(TypeParam { name: name, bounds: bounds });
+        }
+        self.at += 1;
+        return if params.is_empty() { None } else { Some(params) };
+    }
+    /// Whether the next thing is another parameter's `Name:` rather than a
+    /// bound. Bounds start with L, [ or T, which a name could too.
+    fn at_param_name(&self) -> bool {
+        let rest = &self.text.as_bytes()[self.at..];
+        let end = rest.iter().position(|c| b":;<>".contains(c));
+        return matches!(end.map(|i| rest[i]), Some(b':'));
+    }
+}
+
+/// A field or variable signature, generic or not.
+pub fn parse_type(signature: &str) -> Option<Type> {
+    let mut parser = Parser { text: signature, at: 0 };
+    let parsed = parser.java_type()?;
+    return if parser.done() { Some(parsed) } else { None };
+}
+
+/// A method signature, generic or not.
+pub fn parse_method(signature: &str) -> Option<MethodSignature> {
+    let mut parser = Parser { text: signature, at: 0 };
+    let type_params = parser.type_params()?;
+    parser.expect(b'(')?;
+    let mut params = Vec::new();
+    while parser.peek()? != b')' {
+        params.push(parser.java_type()?);
+    }
+    parser.at += 1;
+    let returns = parser.return_type()?;
+    let mut throws = Vec::new();
+    while parser.peek() == Some(b'^') {
+        parser.at += 1;
+        throws.push(parser.reference()?);
+    }
+    if !parser.done() {
+        return None;
+    }
+    return Some(MethodSignature { type_params: type_params, params: params, returns: returns, throws: throws });
+}
+
+/// A class's generic signature, from AllClassesWithGeneric or a class file.
+pub fn parse_class(signature: &str) -> Option<ClassSignature> {
+    let mut parser = Parser { text: signature, at: 0 };
+    let type_params = parser.type_params()?;
+    let superclass = parser.reference()?;
+    let mut interfaces = Vec::new();
+    while !parser.done() {
+        interfaces.push(parser.reference()?);
+    }
+    return Some(ClassSignature { type_params: type_params, superclass: superclass, interfaces: interfaces });
+}
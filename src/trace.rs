@@ -0,0 +1,262 @@
+//! Method entry and exit tracing that logs calls without leaving the program
+//! stopped.
+use crate::{Result,Error};
+use crate::client::Client;
+use crate::eval;
+use crate::jdwp::{self,Command,EventKind,Tag};
+use crate::lookup;
+use crate::request;
+use std::collections::HashMap;
+use std::io::Write;
+
+/// Matches `text` against a pattern where `*` stands for any run of characters.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let mut rest = match text.strip_prefix(first) {
+        Some(rest) => rest,
+        None => { return false; },
+    };
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        if i == parts.len() - 1 {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => { return false; },
+        }
+    }
+    return rest.is_empty();
+}
+
+/// A ClassMatch pattern the VM accepts: `*` only at the start or the end.
+/// Anything finer is filtered here.
+fn class_match(pattern: &str) -> String {
+    let pattern = if pattern.contains('.') {
+        pattern.to_string()
+    } else {
+        format!("*{}", pattern.trim_start_matches('*'))
+    };
+    let inner = pattern.trim_start_matches('*').trim_end_matches('*');
+    return match inner.find('*') {
+        Some(at) if pattern.starts_with('*') => format!("*{}", &inner[at + 1..]),
+        Some(at) => format!("{}*", &inner[..at]),
+        None => pattern,
+    };
+}
+
+/// Simple patterns match the class name without its package.
+pub fn class_matches(pattern: &str, class: &str) -> bool {
+    if pattern.contains('.') {
+        return glob_match(pattern, class);
+    }
+    return glob_match(pattern, class.rsplit('.').next().unwrap_or(class));
+}
+
+pub struct Trace {
+    /// As the user wrote it.
+    pub spec: String,
+    class_pattern: String,
+    method_pattern: String,
+    /// The entry and exit requests.
+    pub requests: Vec<(EventKind, i32)>,
+    /// Where lines go; stdout if None.
+    out: Option<Box<dyn Write + Send>>,
+    pub file: Option<String>,
+}
+
+impl Trace {
+    /// The ID the user refers to the trace by.
+    pub fn id(&self) -> i32 {
+        return self.requests[0].1;
+    }
+}
+
+#[derive(Clone)]
+struct Method {
+    class: String,
+    name: String,
+    /// Arguments by slot, if the method has a variable table.
+    args: Option<Vec<jdwp::VariableInfo>>,
+}
+
+/// All traces, each known by the ID of its entry request.
+#[derive(Default)]
+pub struct Tracer {
+    pub traces: Vec<Trace>,
+    /// Calls in progress per thread, for indentation.
+    depth: HashMap<u64, usize>,
+    methods: HashMap<(u64, u64), Method>,
+    threads: HashMap<u64, String>,
+}
+
+impl Tracer {
+    /// Starts tracing `Class.method` where either part may contain `*`.
+    pub fn start(&mut self, client: &Client, spec: &str, file: Option<&str>) -> Result<i32> {
+        let (class_pattern, method_pattern) = match spec.rsplit_once('.') {
+            Some(split) => split,
+            None => { return Err(Error::Command("trace CLASS.METHOD [FILE]".to_string())); },
+        };
+        let out: Option<Box<dyn Write + Send>> = match file {
+            Some(path) => Some(Box::new(std::fs::File::create(path)?)),
+            None => None,
+        };
+        // Return values need MethodExitWithReturnValue, new in JDWP 1.6.
+        let exit = if client.state().supports_version(1, 6) {
+            EventKind::MethodExitWithReturnValue
+        } else {
+            EventKind::MethodExit
+        };
+        let mut requests = Vec::new();
+        for kind in [EventKind::MethodEntry, exit] {
+            let modifiers = vec![jdwp::Modifier::ClassMatch(class_match(class_pattern))];
+            match client.request(request::event_request_set(kind, jdwp::SUSPEND_EVENT_THREAD, modifiers)) {
+                Ok(id) => requests.push((kind, id)),
+                Err(e) => {
+                    self.clear(client, &requests);
+                    return Err(e);
+                },
+            }
+        }
+        let id = requests[0].1;
+        self.traces.push(Trace {
+            spec: spec.to_string(),
+            class_pattern: class_pattern.to_string(),
+            method_pattern: method_pattern.to_string(),
+            requests: requests,
+            out: out,
+            file: file.map(|f| f.to_string()),
+        });
+        return Ok(id);
+    }
+    fn clear(&self, client: &Client, requests: &[(EventKind, i32)]) {
+        for (kind, id) in requests {
+            let _ = client.send_and_wait(&Command::EventRequestClear { event_kind: *kind, request_id: *id });
+        }
+    }
+    /// Stops the trace with this ID, or all of them.
+    pub fn stop(&mut self, client: &Client, id: Option<i32>) -> Result<()> {
+        let (stopping, keeping): (Vec<Trace>, Vec<Trace>) = std::mem::take(&mut self.traces).into_iter()
+            .partition(|t| id.is_none() || id == Some(t.id()));
+        self.traces = keeping;
+        if stopping.is_empty() && id.is_some() {
+            return Err(Error::Command("no such trace".to_string()));
+        }
+        for trace in stopping {
+            self.clear(client, &trace.requests);
+        }
+        if self.traces.is_empty() {
+            self.depth.clear();
+        }
+        return Ok(());
+    }
+    fn method(&mut self, client: &Client, location: &jdwp::Location) -> Result<Method> {
+        let key = (location.class_id, location.method_id);
+        if let Some(m) = self.methods.get(&key) {
+            return Ok(m.clone());
+        }
+        let class = lookup::class_name(client, location.class_id)?;
+        let name = lookup::method(client, location)?
+            .map(|m| m.name).unwrap_or_else(|| format!("{:#x}", location.method_id));
+        let args = match client.request(request::method_variable_table(location.class_id, location.method_id)) {
+            Ok(table) => {
+                let arg_count = table.arg_count;
+                let mut args: Vec<jdwp::VariableInfo> = table.variables.into_iter()
+                    .filter(|v| v.slot < arg_count && v.code_index == 0 && v.name != "this")
+                    .collect();
+                args.sort_by_key(|v| v.slot);
+                Some(args)
+            },
+            _ => None,
+        };
+        let method = Method { class: class, name: name, args: args };
+        self.methods.insert(key, method.clone());
+        return Ok(method);
+    }
+    fn arguments(&self, client: &Client, thread: u64, method: &Method) -> String {
+        let args = match &method.args {
+            Some(args) => args,
+            None => { return "...".to_string(); },
+        };
+        if args.is_empty() {
+            return String::new();
+        }
+        let frame = match client.request(request::thread_reference_frames(thread, 0, 1)) {
+            Ok(frames) if !frames.is_empty() => frames[0].frame_id,
+            _ => { return "...".to_string(); },
+        };
+        let slots = args.iter().map(|v| (v.slot, v.signature.as_bytes()[0])).collect();
+        let values = match client.request(request::stack_frame_get_values(thread, frame, slots)) {
+            Ok(values) => values,
+            _ => { return "...".to_string(); },
+        };
+        return args.iter().zip(values.iter())
+            .map(|(arg, value)| format!("{}={}", arg.name, format(client, value)))
+            .collect::<Vec<_>>()
+            .join(", ");
+    }
+    fn thread_name(&mut self, client: &Client, thread: u64) -> String {
+        if let Some(name) = self.threads.get(&thread) {
+            return name.clone();
+        }
+        let name = lookup::thread_name(client, thread);
+        self.threads.insert(thread, name.clone());
+        return name;
+    }
+    /// Logs a method entry or exit. Returns false for events that aren't
+    /// from a trace, which the caller should handle itself.
+    pub fn on_event(&mut self, client: &Client, event: &jdwp::Event) -> bool {
+        let (request_id, thread, location, value, entry) = match event {
+            jdwp::Event::MethodEntry { request_id, thread, location } => (*request_id, *thread, location, None, true),
+            jdwp::Event::MethodExit { request_id, thread, location } => (*request_id, *thread, location, None, false),
+            jdwp::Event::MethodExitWithReturnValue { request_id, thread, location, value } => {
+                (*request_id, *thread, location, Some(*value), false)
+            },
+            _ => { return false; },
+        };
+        let index = match self.traces.iter().position(|t| t.requests.iter().any(|(_, id)| *id == request_id)) {
+            Some(i) => i,
+            None => { return false; },
+        };
+        let method = match self.method(client, location) {
+            Ok(m) => m,
+            Err(_) => { return true; },
+        };
+        let trace = &self.traces[index];
+        if !class_matches(&trace.class_pattern, &method.class) || !glob_match(&trace.method_pattern, &method.name) {
+            return true;
+        }
+        let line = if entry {
+            let depth = self.depth.get(&thread).cloned().unwrap_or(0);
+            self.depth.insert(thread, depth + 1);
+            let args = self.arguments(client, thread, &method);
+            format!("{}-> {}.{}({})", "  ".repeat(depth), method.class, method.name, args)
+        } else {
+            let depth = self.depth.get(&thread).cloned().unwrap_or(0).saturating_sub(1);
+            self.depth.insert(thread, depth);
+            match value {
+                Some(Tag::Void) | None => format!("{}<- {}.{}", "  ".repeat(depth), method.class, method.name),
+                Some(v) => format!("{}<- {}.{} = {}", "  ".repeat(depth), method.class, method.name, format(client, &v)),
+            }
+        };
+        let name = self.thread_name(client, thread);
+        let trace = &mut self.traces[index];
+        match trace.out.as_mut() {
+            Some(out) => {
+                if let Err(e) = writeln!(out, "[{}] {}", name, line).and_then(|_| out.flush()) {
+                    println!("Trace {} can't write to {}: {}", trace.id(), trace.file.as_deref().unwrap_or(""), e);
+                }
+            },
+            None => println!("[{}] {}", name, line),
+        }
+        return true;
+    }
+}
+
+fn format(client: &Client, value: &Tag) -> String {
+    return match eval::format_value(client, value) {
+        Ok(text) => text,
+        Err(_) => format!("{:?}", value),
+    };
+}
@@ -0,0 +1,100 @@
+use std::io::{Read,Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::{self,Sender,Receiver};
+
+/// A byte stream that JDWP packets can travel over. Splitting yields
+/// independently owned halves so the reader can live on its own thread.
+pub trait Transport: Send + 'static {
+    type Reader: Read + Send + 'static;
+    type Writer: Write + Send + 'static;
+    fn split(self) -> std::io::Result<(Self::Reader, Self::Writer)>;
+}
+
+impl Transport for TcpStream {
+    type Reader = TcpStream;
+    type Writer = TcpStream;
+    fn split(self) -> std::io::Result<(TcpStream, TcpStream)> {
+        let send_end = self.try_clone()?;
+        return Ok((self, send_end));
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {
+    type Reader = UnixStream;
+    type Writer = UnixStream;
+    fn split(self) -> std::io::Result<(UnixStream, UnixStream)> {
+        let send_end = self.try_clone()?;
+        return Ok((self, send_end));
+    }
+}
+
+/// Read half of an in-memory pipe. Returns EOF once the writer is dropped.
+pub struct PipeReader {
+    rx: Receiver<Vec<u8>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+/// Write half of an in-memory pipe.
+pub struct PipeWriter {
+    tx: Sender<Vec<u8>>,
+}
+
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let (tx, rx) = mpsc::channel();
+    return (PipeReader { rx: rx, buf: Vec::new(), pos: 0 }, PipeWriter { tx: tx });
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos >= self.buf.len() {
+            match self.rx.recv() {
+                Ok(chunk) => { self.buf = chunk; self.pos = 0; },
+                Err(_) => { return Ok(0); },
+            }
+        }
+        let n = std::cmp::min(out.len(), self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        return Ok(n);
+    }
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        return match self.tx.send(data.to_vec()) {
+            Ok(_) => Ok(data.len()),
+            Err(_) => Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "pipe reader dropped")),
+        };
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        return Ok(());
+    }
+}
+
+/// One end of an in-memory full duplex connection, mostly for tests.
+pub struct Duplex {
+    pub reader: PipeReader,
+    pub writer: PipeWriter,
+}
+
+/// Creates two connected ends. Whatever one end writes, the other reads.
+pub fn duplex() -> (Duplex, Duplex) {
+    let (r1, w1) = pipe();
+    let (r2, w2) = pipe();
+    return (Duplex { reader: r1, writer: w2 }, Duplex { reader: r2, writer: w1 });
+}
+
+impl Transport for Duplex {
+    type Reader = PipeReader;
+    type Writer = PipeWriter;
+    fn split(self) -> std::io::Result<(PipeReader, PipeWriter)> {
+        return Ok((self.reader, self.writer));
+    }
+}
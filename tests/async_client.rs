@@ -0,0 +1,131 @@
+#![cfg(feature = "async")]
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+use dcd::async_client::AsyncClient;
+use dcd::client::Event;
+use dcd::jdwp::{self,Command,Reply};
+use dcd::mock::{MockVm,Model};
+use dcd::{transport,Error};
+use std::io::{Read,Write};
+use std::time::{Duration,Instant};
+use tokio::io::{AsyncReadExt,AsyncWriteExt,DuplexStream};
+use tokio::runtime::{Handle,Runtime};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+fn runtime() -> Runtime {
+    return tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+}
+
+/// Serves `model` on the far end of a `tokio::io::duplex`. The mock VM is
+/// blocking, so two threads copy bytes between it and the async stream.
+fn serve(handle: Handle, model: Model) -> (MockVm, DuplexStream) {
+    let (client_end, vm_async) = tokio::io::duplex(64 * 1024);
+    let (vm_end, bridge) = transport::duplex();
+    let mut vm = MockVm::new(model);
+    vm.serve(vm_end).unwrap();
+    let (mut from_vm, mut to_vm) = (bridge.reader, bridge.writer);
+    let (mut async_read, mut async_write) = tokio::io::split(vm_async);
+    let write_handle = handle.clone();
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = match from_vm.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            if write_handle.block_on(async_write.write_all(&buf[..n])).is_err() {
+                break;
+            }
+        }
+        let _ = write_handle.block_on(async_write.shutdown());
+    });
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = match handle.block_on(async_read.read(&mut buf)) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            if to_vm.write_all(&buf[..n]).is_err() {
+                break;
+            }
+        }
+    });
+    return (vm, client_end);
+}
+
+async fn connect(model: Model) -> (MockVm, AsyncClient, UnboundedReceiver<Event>) {
+    let (vm, stream) = serve(Handle::current(), model);
+    let (client, events) = AsyncClient::connect(stream).await.unwrap();
+    client.send_and_wait(&Command::IDSizes).await.unwrap();
+    return (vm, client, events);
+}
+
+fn model() -> (Model, u64, u64) {
+    let mut model = Model::default();
+    let class = model.add_class("Lcom/example/Main;", "Main.java");
+    let main = model.add_thread("main");
+    return (model, class, main);
+}
+
+#[test]
+fn concurrent_commands_get_their_own_replies() {
+    let (model, class, main) = model();
+    runtime().block_on(async {
+        let (_vm, client, _events) = connect(model).await;
+        let version = client.send(&Command::Version).await.unwrap();
+        let name = client.send(&Command::ThreadReferenceName { thread: main }).await.unwrap();
+        let signature = client.send(&Command::ReferenceTypeSignature { ref_type: class }).await.unwrap();
+        let bad = client.send(&Command::ThreadReferenceName { thread: 0xdead }).await.unwrap();
+        assert!(matches!(bad.wait().await, Err(Error::Jdwp(jdwp::Error::InvalidThread))));
+        assert_eq!(signature.wait().await.unwrap(), Reply::ReferenceTypeSignature("Lcom/example/Main;".to_string()));
+        assert_eq!(name.wait().await.unwrap(), Reply::ThreadReferenceName("main".to_string()));
+        assert!(matches!(version.wait().await.unwrap(), Reply::Version { .. }));
+        assert_eq!(client.state().name, "MockVM");
+    });
+}
+
+#[test]
+fn events_arrive_on_the_receiver() {
+    let (model, _class, main) = model();
+    runtime().block_on(async {
+        let (vm, _client, mut events) = connect(model).await;
+        vm.emit(jdwp::SUSPEND_NONE, vec![jdwp::Event::ThreadStart { request_id: 3, thread: main }]).unwrap();
+        let (_, cmd) = events.recv().await.unwrap();
+        match cmd {
+            Command::Composite { events, .. } => assert_eq!(events, vec![jdwp::Event::ThreadStart { request_id: 3, thread: main }]),
+            c => panic!("unexpected {:?}", c),
+        }
+        // Unknown commands are answered, not handed over. The second one's
+        // reply shows the event in between got none.
+        let first = vm.send_raw(0x42, 1, vec![]).unwrap();
+        vm.emit(jdwp::SUSPEND_NONE, vec![jdwp::Event::ThreadDeath { request_id: 3, thread: main }]).unwrap();
+        let last = vm.send_raw(0x42, 2, vec![]).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while vm.replies().len() < 2 {
+            assert!(Instant::now() < deadline, "timed out");
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(vm.replies(), vec![
+            jdwp::Packet::Reply { id: first, error: 99, data: vec![] },
+            jdwp::Packet::Reply { id: last, error: 99, data: vec![] },
+        ]);
+        assert!(matches!(events.recv().await.unwrap().1, Command::Composite { .. }));
+        assert!(events.try_recv().is_err());
+    });
+}
+
+#[test]
+fn disconnect_wakes_waiters() {
+    let (model, _class, _main) = model();
+    runtime().block_on(async {
+        let (vm, client, mut events) = connect(model).await;
+        vm.on(|cmd, _model| match cmd {
+            Command::AllThreads => panic!("simulated VM crash"),
+            _ => None,
+        });
+        let res = client.send_and_wait(&Command::AllThreads).await;
+        assert!(matches!(res, Err(Error::Disconnected)));
+        assert!(events.recv().await.is_none());
+        assert!(matches!(client.send(&Command::Version).await, Err(Error::Disconnected)));
+    });
+}
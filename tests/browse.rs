@@ -0,0 +1,99 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+mod common;
+use common::wait_until;
+use dcd::browse::{self,Browser,Member};
+use dcd::jdwp::{self,Command,EventKind};
+use dcd::mock::Model;
+
+struct Classes {
+    base: u64,
+    shape: u64,
+    main: u64,
+    sub: u64,
+}
+
+/// Base <- Main implements Shape <- Sub.
+fn model() -> (Model, Classes) {
+    let mut model = Model::default();
+    let base = model.add_class("Lcom/example/Base;", "Base.java");
+    let shape = model.add_class("Lcom/example/Shape;", "Shape.java");
+    model.class_mut(shape).unwrap().ref_type_tag = jdwp::TYPE_TAG_INTERFACE;
+    model.class_mut(shape).unwrap().mod_bits = 0x601;
+    let main = model.add_class("Lcom/example/Main;", "Main.java");
+    model.class_mut(main).unwrap().superclass = base;
+    model.class_mut(main).unwrap().interfaces = vec![shape];
+    let sub = model.add_class("Lcom/example/Sub;", "Sub.java");
+    model.class_mut(sub).unwrap().superclass = main;
+    model.add_method(main, "<init>", "(I)V", &[]);
+    model.add_method(main, "main", "([Ljava/lang/String;)V", &[]);
+    model.class_mut(main).unwrap().methods[1].mod_bits = 0x89;
+    model.add_field(main, "count", "J");
+    model.class_mut(main).unwrap().fields[0].mod_bits = 0x42;
+    return (model, Classes { base: base, shape: shape, main: main, sub: sub });
+}
+
+#[test]
+fn declarations_read_like_java() {
+    assert_eq!(browse::modifiers(0x19, Member::Field), vec!["public", "static", "final"]);
+    assert_eq!(browse::modifiers(0x601, Member::Class), vec!["public"]);
+    assert_eq!(browse::modifiers(0x421, Member::Class), vec!["public", "abstract"]);
+    // Bridge and varargs share bits with volatile and transient.
+    assert_eq!(browse::modifiers(0xe1, Member::Method), vec!["public", "synchronized"]);
+    assert_eq!(browse::class_kind(0x4011, jdwp::TYPE_TAG_CLASS), "enum");
+    assert_eq!(browse::class_kind(0x2601, jdwp::TYPE_TAG_INTERFACE), "@interface");
+    assert_eq!(browse::class_kind(0, jdwp::TYPE_TAG_INTERFACE), "interface");
+
+    let method = |name: &str, signature: &str, mod_bits| jdwp::MethodInfo {
+        method_id: 1,
+        name: name.to_string(),
+        signature: signature.to_string(),
+        mod_bits: mod_bits,
+    };
+    assert_eq!(browse::method_text("a.Main", &method("main", "([Ljava/lang/String;)V", 0x89)), "public static void main(java.lang.String...)");
+    assert_eq!(browse::method_text("a.Main", &method("<init>", "(I[[J)V", 0x2)), "private Main(int, long[][])");
+    assert_eq!(browse::method_text("a.Main", &method("<clinit>", "()V", 0x8)), "static {}");
+    assert_eq!(browse::method_text("a.Main", &method("access$000", "()I", 0x1008)), "static int access$000() /* synthetic */");
+    let field = jdwp::FieldInfo { field_id: 1, name: "names".to_string(), signature: "Ljava/util/List;".to_string(), mod_bits: 0xc2 };
+    assert_eq!(browse::field_text(&field), "private transient volatile java.util.List names");
+}
+
+#[test]
+fn finds_supertypes_and_subtypes() {
+    let (model, c) = model();
+    let (_vm, client, _events) = common::connect(model);
+    let mut browser = Browser::default();
+    assert_eq!(browser.classes(&client).unwrap().len(), 4);
+    assert_eq!(browser.superclass(&client, c.main).unwrap(), c.base);
+    assert_eq!(browser.interfaces(&client, c.main).unwrap(), vec![c.shape]);
+    assert_eq!(browser.subclasses(&client, c.base, false).unwrap(), vec![c.main]);
+    assert_eq!(browser.subclasses(&client, c.main, false).unwrap(), vec![c.sub]);
+    assert_eq!(browser.subclasses(&client, c.shape, true).unwrap(), vec![c.main]);
+    assert_eq!(browser.name(&client, c.sub).unwrap(), "com.example.Sub");
+    assert_eq!(browser.modifiers(&client, c.shape).unwrap(), 0x601);
+}
+
+#[test]
+fn commands_cache_until_a_class_is_unloaded() {
+    let (model, _) = model();
+    let (vm, mut session) = common::session(model);
+    session.execute("classes com.example.*").unwrap();
+    session.execute("methods Main").unwrap();
+    session.execute("fields Main").unwrap();
+    session.execute("hierarchy Main").unwrap();
+    session.execute("hierarchy Shape").unwrap();
+    session.execute("methods com.example.Main").unwrap();
+    assert!(session.execute("methods Nope").is_err());
+    assert!(session.execute("fields").is_err());
+    let methods = || vm.received().iter().filter(|c| matches!(c, Command::ReferenceTypeMethods { .. })).count();
+    assert_eq!(methods(), 1);
+
+    let unload = vm.model().requests_for(EventKind::ClassUnload);
+    assert_eq!(unload.len(), 1);
+    assert_eq!(unload[0].suspend_policy, jdwp::SUSPEND_NONE);
+    vm.emit(jdwp::SUSPEND_NONE, vec![jdwp::Event::ClassUnload { request_id: unload[0].id, signature: "Lcom/example/Gone;".to_string() }]).unwrap();
+    wait_until(|| {
+        session.execute("methods Main").unwrap();
+        methods() == 2
+    });
+    assert!(!vm.received().contains(&Command::Resume));
+}
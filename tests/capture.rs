@@ -0,0 +1,181 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+use dcd::capture::{self,CaptureReader,Decoder,Direction,Recorder};
+use dcd::ddm;
+use dcd::jdwp::{self,Command};
+use dcd::mock::{self,Model};
+use std::io::Write;
+use std::sync::{Arc,Mutex};
+
+#[derive(Clone,Default)]
+struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(data);
+        return Ok(data.len());
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        return Ok(());
+    }
+}
+
+fn recorded_session() -> Vec<capture::Record> {
+    let mut model = Model::default();
+    model.idsizes = jdwp::IDSizes { field: 4, method: 4, object: 8, reference_type: 8, frame: 4 };
+    let class = model.add_class("LMain;", "Main.java");
+    let thread = model.add_thread("main");
+    let (vm, client, events) = mock::connect(model).unwrap();
+    let buf = SharedBuf::default();
+    client.record(Some(Arc::new(Recorder::new(buf.clone()).unwrap())));
+    client.initialize().unwrap();
+    client.send_and_wait(&Command::ReferenceTypeSignature { ref_type: class }).unwrap();
+    assert!(client.send_and_wait(&Command::ThreadReferenceName { thread: 1 }).is_err());
+    vm.emit(jdwp::SUSPEND_NONE, vec![jdwp::Event::ThreadStart { request_id: 0, thread: thread }]).unwrap();
+    events.recv().unwrap();
+    let bytes = buf.0.lock().unwrap().clone();
+    return CaptureReader::new(bytes.as_slice(), None).unwrap().collect::<std::io::Result<Vec<_>>>().unwrap();
+}
+
+#[test]
+fn records_both_directions_with_names() {
+    let records = recorded_session();
+    let names: Vec<(Direction, &str)> = records.iter().map(|r| (r.direction, r.name.as_str())).collect();
+    assert_eq!(names[0], (Direction::ToVm, "VirtualMachine.Version"));
+    assert_eq!(names[1], (Direction::FromVm, "VirtualMachine.Version reply"));
+    assert!(names.contains(&(Direction::FromVm, "ReferenceType.Signature reply")));
+    assert_eq!(*names.last().unwrap(), (Direction::FromVm, "Event.Composite"));
+    assert!(records.windows(2).all(|w| w[0].micros <= w[1].micros));
+}
+
+#[test]
+fn decoder_expects_no_reply_to_vm_notifications() {
+    let mut decoder = Decoder::new();
+    let sizes = decoder.idsizes();
+    let chunk = |id: u32| {
+        let (set, cmd, data) = Command::DdmChunk(ddm::Chunk::new(ddm::THCR, vec![0, 0, 0, 1])).serialize(sizes);
+        return jdwp::Packet::Command { id: id, set: set, cmd: cmd, data: data };
+    };
+    // The debugger's chunks are answered, the VM's are not.
+    decoder.decode(Direction::ToVm, &chunk(1)).unwrap();
+    assert_eq!(decoder.replies_left(), 1);
+    decoder.decode(Direction::FromVm, &jdwp::Packet::Reply { id: 1, error: 0, data: vec![] }).unwrap();
+    decoder.decode(Direction::FromVm, &chunk(2)).unwrap();
+    assert_eq!(decoder.replies_left(), 0);
+    let unknown = jdwp::Packet::Command { id: 3, set: 0x42, cmd: 1, data: vec![] };
+    assert!(decoder.decode(Direction::FromVm, &unknown).is_err());
+    assert_eq!(decoder.replies_left(), 1);
+    assert!(!decoder.name(Direction::ToVm, &jdwp::Packet::Reply { id: 3, error: 99, data: vec![] }).starts_with("Unmatched"));
+}
+
+#[test]
+fn capture_reader_enforces_the_packet_limit() {
+    let buf = SharedBuf::default();
+    let recorder = Recorder::new(buf.clone()).unwrap();
+    recorder.record(Direction::ToVm, "VirtualMachine.CreateString", &jdwp::Packet::Command { id: 1, set: 1, cmd: 11, data: vec![0; 100] });
+    drop(recorder);
+    let bytes = buf.0.lock().unwrap().clone();
+    assert_eq!(CaptureReader::new(bytes.as_slice(), None).unwrap().count(), 1);
+    let mut limited = CaptureReader::new(bytes.as_slice(), Some(64)).unwrap();
+    assert_eq!(limited.next().unwrap().unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn dump_decodes_every_packet() {
+    let records = recorded_session();
+    let mut out = Vec::new();
+    capture::dump(&records, &mut out).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    assert_eq!(text.lines().count(), records.len());
+    assert!(text.contains("ReferenceTypeSignature(\"LMain;\")"));
+    assert!(text.contains("InvalidThread"));
+    assert!(text.contains("ThreadStart"));
+    assert!(!text.contains("undecodable"));
+}
+
+#[test]
+fn replay_round_trips() {
+    let records = recorded_session();
+    let stats = capture::replay(&records, &mut std::io::sink()).unwrap();
+    assert_eq!(stats.packets, records.len());
+    assert_eq!(stats.round_tripped, records.len());
+}
+
+fn tcp_frame(to_vm: bool, seq: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+    let (src_port, dst_port) = if to_vm { (50000u16, 8700u16) } else { (8700, 50000) };
+    let mut frame = vec![0u8; 12];
+    frame.extend_from_slice(&0x0800u16.to_be_bytes());
+    let total = (20 + 20 + payload.len()) as u16;
+    frame.extend_from_slice(&[0x45, 0]);
+    frame.extend_from_slice(&total.to_be_bytes());
+    frame.extend_from_slice(&[0, 0, 0, 0, 64, 6, 0, 0, 127, 0, 0, 1, 127, 0, 0, 1]);
+    frame.extend_from_slice(&src_port.to_be_bytes());
+    frame.extend_from_slice(&dst_port.to_be_bytes());
+    frame.extend_from_slice(&seq.to_be_bytes());
+    frame.extend_from_slice(&[0, 0, 0, 0, 0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+    frame.extend_from_slice(payload);
+    return frame;
+}
+
+fn pcap(frames: &[(u32, Vec<u8>)]) -> Vec<u8> {
+    let mut out = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+    out.extend_from_slice(&[0u8; 8]);
+    out.extend_from_slice(&65535u32.to_le_bytes());
+    out.extend_from_slice(&1u32.to_le_bytes());
+    for (usec, frame) in frames {
+        out.extend_from_slice(&1u32.to_le_bytes());
+        out.extend_from_slice(&usec.to_le_bytes());
+        out.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        out.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        out.extend_from_slice(frame);
+    }
+    return out;
+}
+
+#[test]
+fn pcap_import_reassembles_streams() {
+    let version = jdwp::Packet::Command { id: 7, set: 1, cmd: 1, data: vec![] }.to_bytes();
+    let reply = jdwp::Reply::Version {
+        description: "d".to_string(),
+        major: 1,
+        minor: 6,
+        version: "v".to_string(),
+        name: "n".to_string(),
+    }.serialize(Default::default());
+    let reply = jdwp::Packet::Reply { id: 7, error: 0, data: reply }.to_bytes();
+    let mut to_vm = b"JDWP-Handshake".to_vec();
+    to_vm.extend_from_slice(&version);
+    let frames = vec![
+        (0, tcp_frame(true, 99, 0x02, &[])),
+        (1, tcp_frame(false, 499, 0x12, &[])),
+        // The second half of the debugger's data arrives before the first.
+        (2, tcp_frame(true, 100 + 10, 0x18, &to_vm[10..])),
+        (3, tcp_frame(true, 100, 0x18, &to_vm[..10])),
+        (4, tcp_frame(false, 500, 0x18, b"JDWP-Handshake")),
+        (5, tcp_frame(false, 514, 0x18, &reply[..5])),
+        // A retransmission overlapping what we already have.
+        (6, tcp_frame(false, 514, 0x18, &reply)),
+    ];
+    let records = dcd::pcap::import(pcap(&frames).as_slice(), 8700, None).unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].direction, Direction::ToVm);
+    assert_eq!(records[0].name, "VirtualMachine.Version");
+    assert_eq!(records[1].name, "VirtualMachine.Version reply");
+    assert_eq!(records[1].micros, 1_000_006);
+    let stats = capture::replay(&records, &mut std::io::sink()).unwrap();
+    assert_eq!(stats.round_tripped, 2);
+}
+
+#[test]
+fn pcap_import_enforces_the_packet_limit() {
+    let mut to_vm = b"JDWP-Handshake".to_vec();
+    to_vm.extend_from_slice(&jdwp::Packet::Command { id: 1, set: 1, cmd: 11, data: vec![0; 100] }.to_bytes());
+    let frames = vec![(0, tcp_frame(true, 100, 0x18, &to_vm))];
+    let bytes = pcap(&frames);
+    assert_eq!(dcd::pcap::import(bytes.as_slice(), 8700, None).unwrap().len(), 1);
+    let err = dcd::pcap::import(bytes.as_slice(), 8700, Some(64)).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    // Only the header of an oversized packet is needed to reject it.
+    let header = &to_vm[..14 + 11];
+    let err = dcd::pcap::import(pcap(&[(0, tcp_frame(true, 100, 0x18, header))]).as_slice(), 8700, Some(64)).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,51 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+mod common;
+use common::wait_until;
+use dcd::jdwp::{self,Command,EventKind,Modifier,Tag};
+use dcd::mock::Model;
+
+#[test]
+fn catchpoints_report_message_and_backtrace() {
+    let mut model = Model::default();
+    let throwable = model.add_class("Ljava/lang/Throwable;", "Throwable.java");
+    let detail = model.add_field(throwable, "detailMessage", "Ljava/lang/String;");
+    let ise = model.add_class("Ljava/lang/IllegalStateException;", "IllegalStateException.java");
+    model.class_mut(ise).unwrap().superclass = throwable;
+    let class = model.add_class("Lcom/example/Main;", "Main.java");
+    let run = model.add_method(class, "run", "()V", &[(0, 20), (6, 21)]);
+    let main = model.add_method(class, "main", "([Ljava/lang/String;)V", &[(0, 5), (2, 6)]);
+    let message = model.add_string("bad state");
+    let exception = model.add_object(ise, &[(detail, Tag::String(message))]);
+    let thread = model.add_thread("main");
+    model.push_frame(thread, class, main, 2);
+    model.push_frame(thread, class, run, 6);
+    let (vm, mut session) = common::session(model);
+
+    session.execute("catch throw IllegalStateException exclude java.* sun.*").unwrap();
+    session.execute("catch uncaught").unwrap();
+    assert!(session.execute("catch throw Nope").is_err());
+    assert!(session.execute("catch sometimes").is_err());
+    let requests = vm.model().requests_for(EventKind::Exception);
+    assert_eq!(requests[0].modifiers, vec![
+        Modifier::ExceptionOnly { ref_type: ise, caught: true, uncaught: true },
+        Modifier::ClassExclude("java.*".to_string()),
+        Modifier::ClassExclude("sun.*".to_string()),
+    ]);
+    assert_eq!(requests[1].modifiers, vec![Modifier::ExceptionOnly { ref_type: 0, caught: false, uncaught: true }]);
+
+    let at = |method, index| jdwp::Location { type_tag: jdwp::TYPE_TAG_CLASS, class_id: class, method_id: method, index: index };
+    vm.emit(jdwp::SUSPEND_ALL, vec![jdwp::Event::Exception {
+        request_id: requests[0].id,
+        thread: thread,
+        location: at(run, 6),
+        exception: Tag::Object(exception),
+        catch_location: at(main, 2),
+    }]).unwrap();
+    wait_until(|| vm.received().iter().any(|c| matches!(c, Command::ObjectReferenceGetValues { object, .. } if *object == exception)));
+    wait_until(|| vm.received().iter().any(|c| matches!(c, Command::ThreadReferenceFrames { length: -1, .. })));
+    wait_until(|| session.execute("where").is_ok());
+    assert!(!vm.received().contains(&Command::Resume));
+
+    session.execute(&format!("delete {}", requests[1].id)).unwrap();
+    assert_eq!(vm.model().requests_for(EventKind::Exception).len(), 1);
+}
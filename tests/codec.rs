@@ -0,0 +1,402 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+use dcd::ddm::Chunk;
+use dcd::jdwp::*;
+use dcd::mutf8;
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::sample::select;
+
+/// Every combination of 4 and 8 byte IDs, so all-4, all-8 and mixed.
+fn sizes() -> impl Strategy<Value = IDSizes> {
+    let size = || select(vec![4, 8]);
+    return (size(), size(), size(), size(), size()).prop_map(|(field, method, object, reference_type, frame)| IDSizes {
+        field: field,
+        method: method,
+        object: object,
+        reference_type: reference_type,
+        frame: frame,
+    });
+}
+
+/// An ID that fits in `size` bytes.
+fn id(size: i32) -> impl Strategy<Value = u64> {
+    return any::<u64>().prop_map(move |id| if size >= 8 { id } else { id & ((1 << (8 * size)) - 1) });
+}
+
+fn location(s: IDSizes) -> impl Strategy<Value = Location> {
+    return (any::<u8>(), id(s.reference_type), id(s.method), any::<u64>()).prop_map(|(type_tag, class_id, method_id, index)| Location {
+        type_tag: type_tag,
+        class_id: class_id,
+        method_id: method_id,
+        index: index,
+    });
+}
+
+/// Floats without NaN, which isn't equal to itself.
+fn float() -> impl Strategy<Value = f32> {
+    use proptest::num::f32::*;
+    return NORMAL | SUBNORMAL | ZERO | INFINITE | POSITIVE | NEGATIVE;
+}
+
+fn double() -> impl Strategy<Value = f64> {
+    use proptest::num::f64::*;
+    return NORMAL | SUBNORMAL | ZERO | INFINITE | POSITIVE | NEGATIVE;
+}
+
+const PRIMITIVE_TAGS: &[u8] = b"BCFDIJSZ";
+
+/// An untagged value of primitive type `tag`.
+fn primitive(tag: u8) -> BoxedStrategy<Tag> {
+    return match tag {
+        b'B' => any::<u8>().prop_map(Tag::Byte).boxed(),
+        b'C' => any::<u16>().prop_map(Tag::Char).boxed(),
+        b'F' => float().prop_map(Tag::Float).boxed(),
+        b'D' => double().prop_map(Tag::Double).boxed(),
+        b'I' => any::<i32>().prop_map(Tag::Int).boxed(),
+        b'J' => any::<i64>().prop_map(Tag::Long).boxed(),
+        b'S' => any::<i16>().prop_map(Tag::Short).boxed(),
+        _ => any::<bool>().prop_map(Tag::Boolean).boxed(),
+    };
+}
+
+fn object(s: IDSizes) -> impl Strategy<Value = Tag> {
+    let ctor = select(vec![Tag::Array as fn(u64) -> Tag, Tag::Object, Tag::String, Tag::Thread, Tag::ThreadGroup, Tag::ClassLoader, Tag::ClassObject]);
+    return (ctor, id(s.object)).prop_map(|(ctor, id)| ctor(id));
+}
+
+fn value(s: IDSizes) -> impl Strategy<Value = Tag> {
+    return prop_oneof![
+        object(s),
+        select(PRIMITIVE_TAGS.to_vec()).prop_flat_map(primitive),
+        Just(Tag::Void),
+    ];
+}
+
+fn string() -> impl Strategy<Value = String> {
+    return any::<String>();
+}
+
+/// What StringReference.Value would decode `bytes` to, valid or not.
+fn java_string() -> impl Strategy<Value = JavaString> {
+    let decoded = vec(any::<u8>(), 0..16).prop_map(|bytes| match mutf8::decode(&bytes) {
+        (text, true) => JavaString { text: text, raw: None },
+        (text, false) => JavaString { text: text, raw: Some(bytes) },
+    });
+    return prop_oneof![string().prop_map(|s| JavaString::from(s.as_str())), decoded];
+}
+
+fn event_kind() -> impl Strategy<Value = EventKind> {
+    return (0..=255u8).prop_filter_map("not an event kind", EventKind::deserialize);
+}
+
+fn chunk() -> impl Strategy<Value = Chunk> {
+    return (any::<u32>(), vec(any::<u8>(), 0..16)).prop_map(|(kind, data)| Chunk::new(kind, data));
+}
+
+fn modifier(s: IDSizes) -> impl Strategy<Value = Modifier> {
+    return prop_oneof![
+        any::<i32>().prop_map(Modifier::Count),
+        any::<i32>().prop_map(Modifier::Conditional),
+        id(s.object).prop_map(Modifier::ThreadOnly),
+        id(s.reference_type).prop_map(Modifier::ClassOnly),
+        string().prop_map(Modifier::ClassMatch),
+        string().prop_map(Modifier::ClassExclude),
+        location(s).prop_map(Modifier::LocationOnly),
+        (id(s.reference_type), any::<bool>(), any::<bool>()).prop_map(|(ref_type, caught, uncaught)| Modifier::ExceptionOnly {
+            ref_type: ref_type,
+            caught: caught,
+            uncaught: uncaught,
+        }),
+        (id(s.reference_type), id(s.field)).prop_map(|(declaring, field_id)| Modifier::FieldOnly { declaring: declaring, field_id: field_id }),
+        (id(s.object), any::<i32>(), any::<i32>()).prop_map(|(thread, size, depth)| Modifier::Step { thread: thread, size: size, depth: depth }),
+        id(s.object).prop_map(Modifier::InstanceOnly),
+        string().prop_map(Modifier::SourceNameMatch),
+    ];
+}
+
+fn event(s: IDSizes) -> impl Strategy<Value = Event> {
+    let r = any::<i32>;
+    let t = move || id(s.object);
+    let l = move || location(s);
+    let v = move || value(s);
+    return prop_oneof![
+        (r(), t()).prop_map(|(request_id, thread)| Event::VMStart { request_id: request_id, thread: thread }),
+        r().prop_map(|request_id| Event::VMDeath { request_id: request_id }),
+        (r(), t(), l()).prop_map(|(request_id, thread, location)| Event::SingleStep { request_id: request_id, thread: thread, location: location }),
+        (r(), t(), l()).prop_map(|(request_id, thread, location)| Event::Breakpoint { request_id: request_id, thread: thread, location: location }),
+        (r(), t(), l()).prop_map(|(request_id, thread, location)| Event::MethodEntry { request_id: request_id, thread: thread, location: location }),
+        (r(), t(), l()).prop_map(|(request_id, thread, location)| Event::MethodExit { request_id: request_id, thread: thread, location: location }),
+        (r(), t(), l(), v()).prop_map(|(request_id, thread, location, value)| Event::MethodExitWithReturnValue {
+            request_id: request_id, thread: thread, location: location, value: value,
+        }),
+        (r(), t(), v(), l()).prop_map(|(request_id, thread, object, location)| Event::MonitorContendedEnter {
+            request_id: request_id, thread: thread, object: object, location: location,
+        }),
+        (r(), t(), v(), l()).prop_map(|(request_id, thread, object, location)| Event::MonitorContendedEntered {
+            request_id: request_id, thread: thread, object: object, location: location,
+        }),
+        (r(), t(), v(), l(), any::<i64>()).prop_map(|(request_id, thread, object, location, timeout)| Event::MonitorWait {
+            request_id: request_id, thread: thread, object: object, location: location, timeout: timeout,
+        }),
+        (r(), t(), v(), l(), any::<bool>()).prop_map(|(request_id, thread, object, location, timed_out)| Event::MonitorWaited {
+            request_id: request_id, thread: thread, object: object, location: location, timed_out: timed_out,
+        }),
+        (r(), t(), l(), v(), l()).prop_map(|(request_id, thread, location, exception, catch_location)| Event::Exception {
+            request_id: request_id, thread: thread, location: location, exception: exception, catch_location: catch_location,
+        }),
+        (r(), t()).prop_map(|(request_id, thread)| Event::ThreadStart { request_id: request_id, thread: thread }),
+        (r(), t()).prop_map(|(request_id, thread)| Event::ThreadDeath { request_id: request_id, thread: thread }),
+        (r(), t(), any::<u8>(), id(s.reference_type), string(), any::<i32>()).prop_map(|(request_id, thread, ref_type_tag, type_id, signature, status)| Event::ClassPrepare {
+            request_id: request_id, thread: thread, ref_type_tag: ref_type_tag, type_id: type_id, signature: signature, status: status,
+        }),
+        (r(), string()).prop_map(|(request_id, signature)| Event::ClassUnload { request_id: request_id, signature: signature }),
+        (r(), t(), l(), any::<u8>(), id(s.reference_type), id(s.field), v()).prop_map(|(request_id, thread, location, ref_type_tag, type_id, field_id, object)| Event::FieldAccess {
+            request_id: request_id, thread: thread, location: location, ref_type_tag: ref_type_tag, type_id: type_id, field_id: field_id, object: object,
+        }),
+        (r(), t(), l(), any::<u8>(), id(s.reference_type), id(s.field), v(), v()).prop_map(|(request_id, thread, location, ref_type_tag, type_id, field_id, object, value)| Event::FieldModification {
+            request_id: request_id, thread: thread, location: location, ref_type_tag: ref_type_tag, type_id: type_id, field_id: field_id, object: object, value: value,
+        }),
+    ];
+}
+
+/// Every command that can be decoded. The SetValues commands carry
+/// untagged values, so they only go one way.
+fn command(s: IDSizes) -> impl Strategy<Value = Command> {
+    let o = move || id(s.object);
+    let r = move || id(s.reference_type);
+    let m = move || id(s.method);
+    let f = move || id(s.field);
+    let i = any::<i32>;
+    let v = move || value(s);
+    let simple = select(vec![
+        Command::Version, Command::AllClasses, Command::AllClassesWithGeneric, Command::AllThreads, Command::Dispose,
+        Command::IDSizes, Command::Suspend, Command::Resume, Command::Capabilities, Command::CapabilitiesNew,
+        Command::EventRequestClearAllBreakpoints,
+    ]);
+    let vm = prop_oneof![
+        simple,
+        string().prop_map(|signature| Command::ClassesBySignature { signature: signature }),
+        i().prop_map(|exit_code| Command::Exit { exit_code: exit_code }),
+        string().prop_map(|utf| Command::CreateString { utf: utf }),
+        vec((o(), i()), 0..4).prop_map(|requests| Command::DisposeObjects { requests: requests }),
+        vec((r(), vec(any::<u8>(), 0..8)), 0..3).prop_map(|classes| Command::RedefineClasses { classes: classes }),
+        vec(r(), 0..4).prop_map(|ref_types| Command::InstanceCounts { ref_types: ref_types }),
+    ];
+    let types = prop_oneof![
+        r().prop_map(|ref_type| Command::ReferenceTypeSignature { ref_type: ref_type }),
+        r().prop_map(|ref_type| Command::ReferenceTypeModifiers { ref_type: ref_type }),
+        r().prop_map(|ref_type| Command::ReferenceTypeFields { ref_type: ref_type }),
+        r().prop_map(|ref_type| Command::ReferenceTypeMethods { ref_type: ref_type }),
+        (r(), vec(f(), 0..4)).prop_map(|(ref_type, fields)| Command::ReferenceTypeGetValues { ref_type: ref_type, fields: fields }),
+        r().prop_map(|ref_type| Command::ReferenceTypeSourceFile { ref_type: ref_type }),
+        r().prop_map(|ref_type| Command::ReferenceTypeInterfaces { ref_type: ref_type }),
+        (r(), i()).prop_map(|(ref_type, max_instances)| Command::ReferenceTypeInstances { ref_type: ref_type, max_instances: max_instances }),
+        r().prop_map(|class| Command::ClassTypeSuperclass { class: class }),
+        (r(), o(), m(), vec(v(), 0..4), i()).prop_map(|(class, thread, method, args, options)| Command::ClassTypeInvokeMethod {
+            class: class, thread: thread, method: method, args: args, options: options,
+        }),
+        (r(), m()).prop_map(|(ref_type, method)| Command::MethodLineTable { ref_type: ref_type, method: method }),
+        (r(), m()).prop_map(|(ref_type, method)| Command::MethodVariableTable { ref_type: ref_type, method: method }),
+    ];
+    let objects = prop_oneof![
+        o().prop_map(|object| Command::ObjectReferenceReferenceType { object: object }),
+        (o(), vec(f(), 0..4)).prop_map(|(object, fields)| Command::ObjectReferenceGetValues { object: object, fields: fields }),
+        o().prop_map(|object| Command::ObjectReferenceMonitorInfo { object: object }),
+        o().prop_map(|object| Command::ObjectReferenceDisableCollection { object: object }),
+        o().prop_map(|object| Command::ObjectReferenceEnableCollection { object: object }),
+        o().prop_map(|object| Command::ObjectReferenceIsCollected { object: object }),
+        (o(), i()).prop_map(|(object, max_referrers)| Command::ObjectReferenceReferringObjects { object: object, max_referrers: max_referrers }),
+        (o(), o(), r(), m(), vec(v(), 0..4), i()).prop_map(|(object, thread, class, method, args, options)| Command::ObjectReferenceInvokeMethod {
+            object: object, thread: thread, class: class, method: method, args: args, options: options,
+        }),
+        o().prop_map(|string| Command::StringReferenceValue { string: string }),
+        o().prop_map(|array| Command::ArrayReferenceLength { array: array }),
+        (o(), i(), i()).prop_map(|(array, first, length)| Command::ArrayReferenceGetValues { array: array, first: first, length: length }),
+    ];
+    let threads = prop_oneof![
+        o().prop_map(|thread| Command::ThreadReferenceName { thread: thread }),
+        o().prop_map(|thread| Command::ThreadReferenceSuspend { thread: thread }),
+        o().prop_map(|thread| Command::ThreadReferenceResume { thread: thread }),
+        o().prop_map(|thread| Command::ThreadReferenceStatus { thread: thread }),
+        (o(), i(), i()).prop_map(|(thread, start, length)| Command::ThreadReferenceFrames { thread: thread, start: start, length: length }),
+        o().prop_map(|thread| Command::ThreadReferenceFrameCount { thread: thread }),
+        o().prop_map(|thread| Command::ThreadReferenceOwnedMonitors { thread: thread }),
+        o().prop_map(|thread| Command::ThreadReferenceCurrentContendedMonitor { thread: thread }),
+        o().prop_map(|thread| Command::ThreadReferenceOwnedMonitorsStackDepthInfo { thread: thread }),
+        (o(), v()).prop_map(|(thread, value)| Command::ThreadReferenceForceEarlyReturn { thread: thread, value: value }),
+        (o(), id(s.frame), vec((i(), any::<u8>()), 0..4)).prop_map(|(thread, frame, slots)| Command::StackFrameGetValues {
+            thread: thread, frame: frame, slots: slots,
+        }),
+        (o(), id(s.frame), vec((i(), v()), 0..4)).prop_map(|(thread, frame, values)| Command::StackFrameSetValues {
+            thread: thread, frame: frame, values: values,
+        }),
+        (o(), id(s.frame)).prop_map(|(thread, frame)| Command::StackFrameThisObject { thread: thread, frame: frame }),
+        (o(), id(s.frame)).prop_map(|(thread, frame)| Command::StackFramePopFrames { thread: thread, frame: frame }),
+    ];
+    let events = prop_oneof![
+        (event_kind(), any::<u8>(), vec(modifier(s), 0..4)).prop_map(|(event_kind, suspend_policy, modifiers)| Command::EventRequestSet {
+            event_kind: event_kind, suspend_policy: suspend_policy, modifiers: modifiers,
+        }),
+        (event_kind(), i()).prop_map(|(event_kind, request_id)| Command::EventRequestClear { event_kind: event_kind, request_id: request_id }),
+        (any::<u8>(), vec(event(s), 0..4)).prop_map(|(suspend_policy, events)| Command::Composite { suspend_policy: suspend_policy, events: events }),
+        chunk().prop_map(Command::DdmChunk),
+    ];
+    return prop_oneof![vm, types, objects, threads, events];
+}
+
+/// A reply and the command it answers, which says how to decode it.
+fn reply(s: IDSizes) -> impl Strategy<Value = (u8, u8, Reply)> {
+    let o = move || id(s.object);
+    let r = move || id(s.reference_type);
+    let i = any::<i32>;
+    let v = move || value(s);
+    let at = |set: u8, cmd: u8| move |reply: Reply| (set, cmd, reply);
+    let empty = select(vec![
+        (1, 6), (1, 8), (1, 9), (1, 10), (1, 14), (1, 18), (3, 2), (9, 3), (9, 7), (9, 8),
+        (11, 2), (11, 3), (11, 14), (13, 3), (15, 2), (15, 3), (16, 2), (16, 4),
+    ]).prop_map(|(set, cmd)| (set, cmd, Reply::Empty));
+    let vm = prop_oneof![
+        empty,
+        (string(), i(), i(), string(), string()).prop_map(|(description, major, minor, version, name)| Reply::Version {
+            description: description, major: major, minor: minor, version: version, name: name,
+        }).prop_map(at(1, 1)),
+        vec((any::<u8>(), r(), i()), 0..4).prop_map(|classes| Reply::ClassesBySignature(classes.into_iter().map(|(ref_type_tag, type_id, status)| ClassRef {
+            ref_type_tag: ref_type_tag, type_id: type_id, status: status,
+        }).collect())).prop_map(at(1, 2)),
+        vec((any::<u8>(), r(), string(), i()), 0..4).prop_map(|classes| Reply::AllClasses(classes.into_iter().map(|(ref_type_tag, type_id, signature, status)| ClassInfo {
+            ref_type_tag: ref_type_tag, type_id: type_id, signature: signature, status: status,
+        }).collect())).prop_map(at(1, 3)),
+        vec(o(), 0..4).prop_map(Reply::AllThreads).prop_map(at(1, 4)),
+        (i(), i(), i(), i(), i()).prop_map(|(field, method, object, reference_type, frame)| Reply::IDSizes {
+            field: field, method: method, object: object, reference_type: reference_type, frame: frame,
+        }).prop_map(at(1, 7)),
+        o().prop_map(Reply::CreateString).prop_map(at(1, 11)),
+        (0..0x80u32).prop_map(|bits| Reply::Capabilities(Capabilities::from_bits(bits).unwrap())).prop_map(at(1, 12)),
+        (0..0x20_0000u32).prop_map(|bits| Reply::CapabilitiesNew(Capabilities::from_bits(bits).unwrap())).prop_map(at(1, 17)),
+        vec((any::<u8>(), r(), string(), string(), i()), 0..4).prop_map(|classes| Reply::AllClassesWithGeneric(classes.into_iter().map(|(ref_type_tag, type_id, signature, generic_signature, status)| GenericClassInfo {
+            ref_type_tag: ref_type_tag, type_id: type_id, signature: signature, generic_signature: generic_signature, status: status,
+        }).collect())).prop_map(at(1, 20)),
+        vec(any::<i64>(), 0..4).prop_map(Reply::InstanceCounts).prop_map(at(1, 21)),
+    ];
+    let types = prop_oneof![
+        string().prop_map(Reply::ReferenceTypeSignature).prop_map(at(2, 1)),
+        i().prop_map(Reply::ReferenceTypeModifiers).prop_map(at(2, 3)),
+        vec((id(s.field), string(), string(), i()), 0..4).prop_map(|fields| Reply::ReferenceTypeFields(fields.into_iter().map(|(field_id, name, signature, mod_bits)| FieldInfo {
+            field_id: field_id, name: name, signature: signature, mod_bits: mod_bits,
+        }).collect())).prop_map(at(2, 4)),
+        vec((id(s.method), string(), string(), i()), 0..4).prop_map(|methods| Reply::ReferenceTypeMethods(methods.into_iter().map(|(method_id, name, signature, mod_bits)| MethodInfo {
+            method_id: method_id, name: name, signature: signature, mod_bits: mod_bits,
+        }).collect())).prop_map(at(2, 5)),
+        (select(vec![(2, 6), (9, 2), (16, 1)]), vec(v(), 0..4)).prop_map(|((set, cmd), values)| (set, cmd, Reply::Values(values))),
+        string().prop_map(Reply::ReferenceTypeSourceFile).prop_map(at(2, 7)),
+        vec(r(), 0..4).prop_map(Reply::ReferenceTypeInterfaces).prop_map(at(2, 10)),
+        (select(vec![(2, 16), (9, 10)]), vec(v(), 0..4)).prop_map(|((set, cmd), objects)| (set, cmd, Reply::Objects(objects))),
+        r().prop_map(Reply::ClassTypeSuperclass).prop_map(at(3, 1)),
+        (select(vec![(3, 3), (9, 6)]), v(), v()).prop_map(|((set, cmd), return_value, exception)| (set, cmd, Reply::InvokeMethod {
+            return_value: return_value, exception: exception,
+        })),
+        (any::<i64>(), any::<i64>(), vec((any::<i64>(), i()), 0..4)).prop_map(|(start, end, lines)| Reply::MethodLineTable {
+            start: start, end: end, lines: lines.into_iter().map(|(code_index, line)| LineEntry { code_index: code_index, line: line }).collect(),
+        }).prop_map(at(6, 1)),
+        (i(), vec((any::<i64>(), string(), string(), i(), i()), 0..4)).prop_map(|(arg_count, variables)| Reply::MethodVariableTable {
+            arg_count: arg_count,
+            variables: variables.into_iter().map(|(code_index, name, signature, length, slot)| VariableInfo {
+                code_index: code_index, name: name, signature: signature, length: length, slot: slot,
+            }).collect(),
+        }).prop_map(at(6, 2)),
+    ];
+    let objects = prop_oneof![
+        (any::<u8>(), r()).prop_map(|(ref_type_tag, type_id)| Reply::ObjectReferenceReferenceType { ref_type_tag: ref_type_tag, type_id: type_id }).prop_map(at(9, 1)),
+        (o(), i(), vec(o(), 0..4)).prop_map(|(owner, entry_count, waiters)| Reply::ObjectReferenceMonitorInfo {
+            owner: owner, entry_count: entry_count, waiters: waiters,
+        }).prop_map(at(9, 5)),
+        any::<bool>().prop_map(Reply::IsCollected).prop_map(at(9, 9)),
+        java_string().prop_map(Reply::StringReferenceValue).prop_map(at(10, 1)),
+        i().prop_map(Reply::ArrayReferenceLength).prop_map(at(13, 1)),
+        select(PRIMITIVE_TAGS.to_vec()).prop_flat_map(|tag| vec(primitive(tag), 0..4).prop_map(move |values| Reply::ArrayReferenceGetValues {
+            tag: tag, values: values,
+        })).prop_map(at(13, 2)),
+        (select(b"L[stglc".to_vec()), vec(v(), 0..4)).prop_map(|(tag, values)| Reply::ArrayReferenceGetValues { tag: tag, values: values }).prop_map(at(13, 2)),
+        v().prop_map(Reply::StackFrameThisObject).prop_map(at(16, 3)),
+    ];
+    let threads = prop_oneof![
+        string().prop_map(Reply::ThreadReferenceName).prop_map(at(11, 1)),
+        (i(), i()).prop_map(|(thread_status, suspend_status)| Reply::ThreadReferenceStatus {
+            thread_status: thread_status, suspend_status: suspend_status,
+        }).prop_map(at(11, 4)),
+        vec((id(s.frame), location(s)), 0..4).prop_map(|frames| Reply::ThreadReferenceFrames(frames.into_iter().map(|(frame_id, location)| FrameInfo {
+            frame_id: frame_id, location: location,
+        }).collect())).prop_map(at(11, 6)),
+        i().prop_map(Reply::ThreadReferenceFrameCount).prop_map(at(11, 7)),
+        vec(v(), 0..4).prop_map(Reply::ThreadReferenceOwnedMonitors).prop_map(at(11, 8)),
+        v().prop_map(Reply::ThreadReferenceCurrentContendedMonitor).prop_map(at(11, 9)),
+        vec((v(), i()), 0..4).prop_map(Reply::ThreadReferenceOwnedMonitorsStackDepthInfo).prop_map(at(11, 13)),
+        i().prop_map(Reply::EventRequestSet).prop_map(at(15, 1)),
+        vec(chunk(), 0..3).prop_map(Reply::DdmChunks).prop_map(at(199, 1)),
+    ];
+    return prop_oneof![vm, types, objects, threads];
+}
+
+fn packet() -> impl Strategy<Value = Packet> {
+    return prop_oneof![
+        (any::<u32>(), any::<u8>(), any::<u8>(), vec(any::<u8>(), 0..32)).prop_map(|(id, set, cmd, data)| Packet::Command { id: id, set: set, cmd: cmd, data: data }),
+        (any::<u32>(), any::<u16>(), vec(any::<u8>(), 0..32)).prop_map(|(id, error, data)| Packet::Reply { id: id, error: error, data: data }),
+    ];
+}
+
+/// Any ID sizes a VM could claim, including nonsense.
+fn any_sizes() -> impl Strategy<Value = IDSizes> {
+    let size = || -1..=9i32;
+    return prop_oneof![
+        sizes(),
+        (size(), size(), size(), size(), size()).prop_map(|(field, method, object, reference_type, frame)| IDSizes {
+            field: field, method: method, object: object, reference_type: reference_type, frame: frame,
+        }),
+    ];
+}
+
+/// Mostly command sets that exist, so the decoders get past the first match.
+fn set_and_cmd() -> impl Strategy<Value = (u8, u8)> {
+    let set = prop_oneof![select(vec![1u8, 2, 3, 6, 9, 10, 11, 13, 15, 16, 64, 199]), any::<u8>()];
+    return (set, prop_oneof![0..25u8, Just(100), any::<u8>()]);
+}
+
+proptest! {
+    #[test]
+    fn commands_round_trip((sizes, cmd) in sizes().prop_flat_map(|s| (Just(s), command(s)))) {
+        let (set, id, data) = cmd.serialize(sizes);
+        prop_assert_eq!(Command::deserialize(set, id, &data, sizes), Ok(cmd));
+    }
+
+    #[test]
+    fn replies_round_trip((sizes, (set, cmd, reply)) in sizes().prop_flat_map(|s| (Just(s), reply(s)))) {
+        let data = reply.serialize(sizes);
+        prop_assert_eq!(Reply::deserialize(set, cmd, &data, sizes), Ok(reply));
+    }
+
+    #[test]
+    fn packets_round_trip(packet in packet()) {
+        let bytes = packet.to_bytes();
+        prop_assert_eq!(Packet::read(&mut &bytes[..]).unwrap(), packet);
+    }
+
+    #[test]
+    fn strings_round_trip(text in string()) {
+        prop_assert_eq!(mutf8::decode(&mutf8::encode(&text)), (text, true));
+    }
+
+    /// Whatever decodes encodes to something that decodes the same way.
+    #[test]
+    fn arbitrary_bytes_decode_without_panicking((set, cmd) in set_and_cmd(), data in vec(any::<u8>(), 0..64), sizes in any_sizes()) {
+        if let Ok(reply) = Reply::deserialize(set, cmd, &data, sizes) {
+            let bytes = reply.serialize(sizes);
+            prop_assert_eq!(Reply::deserialize(set, cmd, &bytes, sizes).map(|r| r.serialize(sizes)), Ok(bytes));
+        }
+        if let Ok(command) = Command::deserialize(set, cmd, &data, sizes) {
+            let (_, _, bytes) = command.serialize(sizes);
+            prop_assert_eq!(Command::deserialize(set, cmd, &bytes, sizes).map(|c| c.serialize(sizes).2), Ok(bytes));
+        }
+        let _ = Packet::read(&mut &data[..]);
+        let _ = mutf8::decode(&data);
+    }
+}
@@ -0,0 +1,62 @@
+//! Helpers shared by the integration tests.
+#![allow(dead_code)]
+use dcd::client::{Client,Event};
+use dcd::cui::Session;
+use dcd::jdwp;
+use dcd::mock::{self,MockVm,Model};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration,Instant};
+
+/// Polls `f` until it holds, failing the test after five seconds.
+pub fn wait_until<F: FnMut() -> bool>(mut f: F) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !f() {
+        assert!(Instant::now() < deadline, "timed out");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// A mock VM serving `model`, and a client that has already initialized.
+pub fn connect(model: Model) -> (MockVm, Client, Receiver<Event>) {
+    let (vm, client, events) = mock::connect(model).unwrap();
+    client.initialize().unwrap();
+    return (vm, client, events);
+}
+
+/// A mock VM serving `model`, with a CUI session attached to it.
+pub fn session(model: Model) -> (MockVm, Session) {
+    let (vm, client, events) = mock::connect(model).unwrap();
+    let session = Session::new(client, events).unwrap();
+    return (vm, session);
+}
+
+/// What most tests debug: `com.example.Main` with a `run()V` method, and a
+/// thread called main with `run` on its stack. Tests add their own objects.
+pub struct Program {
+    pub model: Model,
+    pub class: u64,
+    pub run: u64,
+    pub thread: u64,
+    /// The `run` frame.
+    pub frame: u64,
+    pub location: jdwp::Location,
+}
+
+/// `lines` is run's line table as (code index, line), and main is at `index`.
+pub fn program(lines: &[(i64, i32)], index: u64) -> Program {
+    let mut model = Model::default();
+    let class = model.add_class("Lcom/example/Main;", "Main.java");
+    let run = model.add_method(class, "run", "()V", lines);
+    let thread = model.add_thread("main");
+    let frame = model.push_frame(thread, class, run, index);
+    let location = jdwp::Location { type_tag: jdwp::TYPE_TAG_CLASS, class_id: class, method_id: run, index: index };
+    return Program { model: model, class: class, run: run, thread: thread, frame: frame, location: location };
+}
+
+impl Program {
+    /// Main suspended, as at a breakpoint.
+    pub fn suspended(mut self) -> Program {
+        self.model.thread_mut(self.thread).unwrap().suspend_count = 1;
+        return self;
+    }
+}
@@ -0,0 +1,97 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+use dcd::ddm::{self,Chunk,Message};
+use dcd::jdwp::{self,Command,Reply};
+use dcd::mock::{self,Model};
+
+fn utf16(s: &str) -> Vec<u8> {
+    let mut out = (s.encode_utf16().count() as u32).to_be_bytes().to_vec();
+    for u in s.encode_utf16() {
+        out.extend_from_slice(&u.to_be_bytes());
+    }
+    return out;
+}
+
+fn hello_reply() -> Chunk {
+    let mut data = Vec::new();
+    data.extend_from_slice(&1u32.to_be_bytes());
+    data.extend_from_slice(&4242u32.to_be_bytes());
+    data.extend_from_slice(&2u32.to_be_bytes());
+    data.extend_from_slice(&3u32.to_be_bytes());
+    data.extend_from_slice(&[0, b'V', 0, b'M', 0, b'a', 0, b'p', 0, b'p']);
+    data.extend_from_slice(&10u32.to_be_bytes());
+    return Chunk::new(ddm::HELO, data);
+}
+
+#[test]
+fn chunks_round_trip_through_jdwp() {
+    let sizes = jdwp::IDSizes { field: 8, method: 8, object: 8, reference_type: 8, frame: 8 };
+    let cmd = Command::DdmChunk(ddm::start_profiling("/sdcard/t.trace", 1024, 0));
+    let (set, id, data) = cmd.serialize(sizes);
+    assert_eq!((set, id), (199, 1));
+    assert_eq!(Command::deserialize(set, id, &data, sizes).unwrap(), cmd);
+    let reply = Reply::DdmChunks(vec![hello_reply(), Chunk::new(ddm::THDE, 7u32.to_be_bytes().to_vec())]);
+    assert_eq!(Reply::deserialize(199, 1, &reply.serialize(sizes), sizes).unwrap(), reply);
+    assert_eq!(Reply::deserialize(199, 1, &[], sizes).unwrap(), Reply::DdmChunks(vec![]));
+    // A chunk claiming more data than the packet holds.
+    assert!(Reply::deserialize(199, 1, &[b'T', b'H', b'D', b'E', 0, 0, 0, 9, 0], sizes).is_err());
+}
+
+#[test]
+fn decodes_payloads() {
+    match ddm::decode(&hello_reply()).unwrap() {
+        Message::Hello { pid, vm, app, user, abi, .. } => {
+            assert_eq!((pid, vm.as_str(), app.as_str()), (4242, "VM", "app"));
+            assert_eq!((user, abi), (Some(10), None));
+        },
+        m => panic!("unexpected {:?}", m),
+    }
+    let mut thcr = 3u32.to_be_bytes().to_vec();
+    thcr.extend_from_slice(&utf16("main"));
+    assert_eq!(ddm::decode(&Chunk::new(ddm::THCR, thcr)).unwrap(), Message::ThreadCreated { thread: 3, name: "main".to_string() });
+
+    // One allocation of a String from Main.run, line 12.
+    let mut real = vec![15, 9, 8];
+    real.extend_from_slice(&1u16.to_be_bytes());
+    real.extend_from_slice(&(15u32 + 9 + 8).to_be_bytes());
+    real.extend_from_slice(&[0, 2, 0, 1, 0, 1]);
+    real.extend_from_slice(&[0, 0, 0, 24, 0, 5, 0, 0, 1]);
+    real.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 12]);
+    real.extend_from_slice(&utf16("java.lang.String"));
+    real.extend_from_slice(&utf16("Main"));
+    real.extend_from_slice(&utf16("run"));
+    real.extend_from_slice(&utf16("Main.java"));
+    match ddm::decode(&Chunk::new(ddm::REAL, real.clone())).unwrap() {
+        Message::Allocations(allocations) => {
+            assert_eq!(allocations.len(), 1);
+            assert_eq!((allocations[0].size, allocations[0].thread), (24, 5));
+            assert_eq!(allocations[0].class, "java.lang.String");
+            assert_eq!(allocations[0].frames[0], ddm::AllocFrame {
+                class: "Main".to_string(),
+                method: "run".to_string(),
+                file: "Main.java".to_string(),
+                line: 12,
+            });
+        },
+        m => panic!("unexpected {:?}", m),
+    }
+    real.truncate(real.len() - 4);
+    assert!(ddm::decode(&Chunk::new(ddm::REAL, real)).is_err());
+}
+
+#[test]
+fn unsolicited_chunks_reach_the_event_stream() {
+    let (vm, client, events) = mock::connect(Model::default()).unwrap();
+    vm.on(|cmd, _model| match cmd {
+        Command::DdmChunk(c) if c.kind == ddm::HELO => Some(Ok(Reply::DdmChunks(vec![hello_reply()]))),
+        _ => None,
+    });
+    client.initialize().unwrap();
+    match client.send_and_wait(&Command::DdmChunk(ddm::hello())).unwrap() {
+        Reply::DdmChunks(chunks) => assert_eq!(chunks, vec![hello_reply()]),
+        r => panic!("unexpected {:?}", r),
+    }
+    let wait = Command::DdmChunk(Chunk::new(ddm::WAIT, vec![0]));
+    vm.send_command(&wait, client.state().idsizes).unwrap();
+    let (_, cmd) = events.recv_timeout(std::time::Duration::from_secs(5)).unwrap();
+    assert_eq!(cmd, wait);
+}
@@ -0,0 +1,146 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+mod common;
+use common::{wait_until,Program};
+use dcd::eval::{self,BinaryOp,Evaluator,Expr,UnaryOp};
+use dcd::jdwp::{self,Command,Reply,Tag};
+use dcd::mock::{self,MockVm};
+
+/// Main extends Base, stopped in Main.run with locals i and arr. Also returns
+/// the `twice` method.
+fn fixture() -> (Program, u64) {
+    let mut p = common::program(&[(0, 10), (4, 11), (9, 12)], 9).suspended();
+    let model = &mut p.model;
+    let base = model.add_class("Lcom/example/Base;", "Base.java");
+    let id = model.add_field(base, "id", "I");
+    model.class_mut(p.class).unwrap().superclass = base;
+    let count = model.add_field(p.class, "count", "I");
+    let name = model.add_field(p.class, "name", "Ljava/lang/String;");
+    let limit = model.add_field(p.class, "LIMIT", "I");
+    model.class_mut(p.class).unwrap().fields[2].mod_bits |= 0x8;
+    model.class_mut(p.class).unwrap().statics.insert(limit, Tag::Int(100));
+    let twice = model.add_method(p.class, "twice", "(I)I", &[]);
+    model.add_variable(p.class, p.run, "i", "I", 1);
+    model.add_variable(p.class, p.run, "arr", "[I", 2);
+    let bob = model.add_string("bob");
+    let this = model.add_object(p.class, &[(id, Tag::Int(7)), (count, Tag::Int(3)), (name, Tag::String(bob))]);
+    let arr = model.add_array("[I", &[Tag::Int(10), Tag::Int(20), Tag::Int(30)]);
+    model.this_objects.insert(p.frame, this);
+    model.set_local(p.frame, 1, Tag::Int(5));
+    model.set_local(p.frame, 2, Tag::Array(arr));
+    return (p, twice);
+}
+
+fn name(n: &str) -> Box<Expr> {
+    return Box::new(Expr::Name(n.to_string()));
+}
+
+#[test]
+fn parses_java_precedence() {
+    let expr = eval::parse("a + b * 2 == -c && !d").unwrap();
+    let sum = Expr::Binary(BinaryOp::Add, name("a"), Box::new(Expr::Binary(BinaryOp::Mul, name("b"), Box::new(Expr::Literal(Tag::Int(2))))));
+    let eq = Expr::Binary(BinaryOp::Eq, Box::new(sum), Box::new(Expr::Unary(UnaryOp::Neg, name("c"))));
+    assert_eq!(expr, Expr::Binary(BinaryOp::And, Box::new(eq), Box::new(Expr::Unary(UnaryOp::Not, name("d")))));
+
+    let expr = eval::parse("x.y[1].f(2L, \"a\\n\") instanceof java.util.List").unwrap();
+    let index = Expr::Index(Box::new(Expr::Field(name("x"), "y".to_string())), Box::new(Expr::Literal(Tag::Int(1))));
+    let call = Expr::Call(Some(Box::new(index)), "f".to_string(), vec![Expr::Literal(Tag::Long(2)), Expr::Str("a\n".to_string())]);
+    assert_eq!(expr, Expr::InstanceOf(Box::new(call), "java.util.List".to_string()));
+
+    assert_eq!(eval::parse("'x' + 0x10 + 1.5f").unwrap(), Expr::Binary(BinaryOp::Add,
+        Box::new(Expr::Binary(BinaryOp::Add, Box::new(Expr::Literal(Tag::Char(b'x' as u16))), Box::new(Expr::Literal(Tag::Int(16))))),
+        Box::new(Expr::Literal(Tag::Float(1.5)))));
+    for bad in ["1 +", "a b", "\"open", "f(1,", "a = 1"] {
+        assert!(eval::parse(bad).is_err(), "{} parsed", bad);
+    }
+    assert!(eval::parse_assignment("arr[i] = i + 1").is_ok());
+    assert!(eval::parse_assignment("a + 1 = 2").is_err());
+}
+
+#[test]
+fn literals_reach_the_minimum_values() {
+    assert_eq!(eval::parse("-2147483648").unwrap(), Expr::Literal(Tag::Int(i32::MIN)));
+    assert_eq!(eval::parse("-9223372036854775808L").unwrap(), Expr::Literal(Tag::Long(i64::MIN)));
+    assert_eq!(eval::parse("-0x80000000").unwrap(), Expr::Literal(Tag::Int(i32::MIN)));
+    assert_eq!(eval::parse("- 1.5").unwrap(), Expr::Literal(Tag::Double(-1.5)));
+    assert_eq!(eval::parse("1 - 2").unwrap(), Expr::Binary(BinaryOp::Sub, Box::new(Expr::Literal(Tag::Int(1))), Box::new(Expr::Literal(Tag::Int(2)))));
+    for bad in ["2147483648", "-2147483649", "9223372036854775808L", "-9223372036854775809L"] {
+        assert!(matches!(eval::parse(bad), Err(dcd::Error::Eval(_))), "{} parsed", bad);
+    }
+}
+
+#[test]
+fn deep_nesting_is_refused() {
+    let parens = format!("{}1{}", "(".repeat(10_000), ")".repeat(10_000));
+    assert!(matches!(eval::parse(&parens), Err(dcd::Error::Command(_))));
+    assert!(matches!(eval::parse(&format!("{}true", "!".repeat(10_000))), Err(dcd::Error::Command(_))));
+    let nested = format!("{}1{}", "(".repeat(32), ")".repeat(32));
+    assert_eq!(eval::parse(&nested).unwrap(), Expr::Literal(Tag::Int(1)));
+}
+
+#[test]
+fn evaluates_in_a_frame() {
+    let (f, twice) = fixture();
+    let (vm, client, _events) = mock::connect(f.model).unwrap();
+    vm.on(move |cmd, _model| match cmd {
+        Command::ObjectReferenceInvokeMethod { method, args, .. } if *method == twice => match args[0] {
+            Tag::Int(n) => Some(Ok(Reply::InvokeMethod { return_value: Tag::Int(n * 2), exception: Tag::Object(0) })),
+            _ => Some(Err(jdwp::Error::TypeMismatch)),
+        },
+        _ => None,
+    });
+    client.initialize().unwrap();
+    let frame = match client.send_and_wait(&Command::ThreadReferenceFrames { thread: f.thread, start: 0, length: 1 }).unwrap() {
+        Reply::ThreadReferenceFrames(frames) => frames[0],
+        r => panic!("unexpected {:?}", r),
+    };
+    assert_eq!((frame.frame_id, frame.location.class_id), (f.frame, f.class));
+    let e = Evaluator::in_frame(&client, f.thread, frame);
+    let eval = |text: &str| e.evaluate(&eval::parse(text).unwrap());
+    assert_eq!(eval("i + 1").unwrap(), Tag::Int(6));
+    assert_eq!(eval("count * 2L").unwrap(), Tag::Long(6));
+    assert_eq!(eval("this.id").unwrap(), Tag::Int(7));
+    assert_eq!(eval("LIMIT - i > 90 && com.example.Main.LIMIT == 100").unwrap(), Tag::Boolean(true));
+    assert_eq!(eval("arr[2] + arr.length").unwrap(), Tag::Int(33));
+    assert_eq!(eval("7 / 2").unwrap(), Tag::Int(3));
+    assert_eq!(eval("7 / 2.0").unwrap(), Tag::Double(3.5));
+    assert_eq!(eval("twice(i)").unwrap(), Tag::Int(10));
+    assert_eq!(eval("this instanceof Base && !(name instanceof Main)").unwrap(), Tag::Boolean(true));
+    let greeting = eval("name + \"!\" + i").unwrap();
+    assert_eq!(e.format(&greeting).unwrap(), "\"bob!5\"");
+    assert_eq!(e.format(&eval("arr").unwrap()).unwrap(), "int[3] {10, 20, 30}");
+    assert!(e.format(&eval("this").unwrap()).unwrap().starts_with("com.example.Main@0x"));
+    assert!(matches!(eval("arr[3]"), Err(dcd::Error::Eval(_))));
+    assert!(matches!(eval("1 / 0"), Err(dcd::Error::Eval(_))));
+    assert!(matches!(eval("nosuch + 1"), Err(dcd::Error::Eval(_))));
+}
+
+#[test]
+fn set_var_and_conditional_breakpoints() {
+    let (f, _) = fixture();
+    let (vm, mut session) = common::session(f.model);
+    session.execute(&format!("thread {:#x}", f.thread)).unwrap();
+    session.execute("set var i = i * 2").unwrap();
+    assert_eq!(vm.model().locals[&f.frame][&1], Tag::Int(10));
+    session.execute("print arr[0] + i").unwrap();
+
+    session.execute("break Main:12 if i > 100").unwrap();
+    let request = vm.model().requests_for(jdwp::EventKind::Breakpoint)[0].clone();
+    let location = match request.modifiers[0] {
+        jdwp::Modifier::LocationOnly(location) => location,
+        ref m => panic!("unexpected {:?}", m),
+    };
+    assert_eq!(location.index, 9);
+    let hit = jdwp::Event::Breakpoint { request_id: request.id, thread: f.thread, location: location };
+    let resumes = |vm: &MockVm| vm.received().iter().filter(|c| **c == Command::Resume).count();
+
+    // i is 10, so the VM is let go again.
+    vm.emit(jdwp::SUSPEND_ALL, vec![hit.clone()]).unwrap();
+    wait_until(|| resumes(&vm) == 1);
+
+    session.execute("set var i = 101").unwrap();
+    vm.emit(jdwp::SUSPEND_ALL, vec![hit]).unwrap();
+    wait_until(|| vm.received().iter().any(|c| matches!(c, Command::ThreadReferenceName { .. })));
+    assert_eq!(resumes(&vm), 1);
+    assert!(session.execute("break Main:99").is_err());
+    assert!(session.execute("break Nope.run").is_err());
+}
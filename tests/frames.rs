@@ -0,0 +1,96 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+mod common;
+use dcd::cui::Session;
+use dcd::jdwp::{Capabilities,Tag};
+use dcd::mock::MockVm;
+
+struct Fixture {
+    vm: MockVm,
+    session: Session,
+    thread: u64,
+}
+
+/// Main.run calling compute(int), which returns a long, stopped in compute.
+/// Each method pushed after it is a new innermost frame.
+fn start(capabilities: Capabilities, callees: &[(&str, &str)]) -> Fixture {
+    let mut p = common::program(&[(0, 10), (4, 11)], 4).suspended();
+    let (model, class, thread) = (&mut p.model, p.class, p.thread);
+    model.capabilities = capabilities;
+    let compute = model.add_method(class, "compute", "(I)J", &[(0, 20), (2, 21)]);
+    model.add_variable(class, compute, "n", "I", 0);
+    let frame = model.push_frame(thread, class, compute, 2);
+    model.set_local(frame, 0, Tag::Int(7));
+    for (name, signature) in callees {
+        let method = model.add_method(class, name, signature, &[(0, 30)]);
+        model.push_frame(thread, class, method, 0);
+    }
+    let (vm, mut session) = common::session(p.model);
+    session.execute(&format!("thread {:#x}", thread)).unwrap();
+    return Fixture { vm: vm, session: session, thread: thread };
+}
+
+fn both() -> Capabilities {
+    return Capabilities::POP_FRAMES | Capabilities::FORCE_EARLY_RETURN;
+}
+
+#[test]
+fn return_checks_the_value_against_the_method() {
+    let mut f = start(both(), &[]);
+    assert!(f.session.execute("return").is_err());
+    assert!(f.session.execute("return \"seven\"").is_err());
+    assert!(f.session.execute("return n > 1").is_err());
+    f.session.execute("return n * 2").unwrap();
+    assert_eq!(f.vm.model().early_returns, vec![(f.thread, Tag::Long(14))]);
+
+    f.session.execute("frame 1").unwrap();
+    assert!(f.session.execute("return 1").is_err());
+    assert_eq!(f.vm.model().early_returns.len(), 1);
+
+    let mut f = start(both(), &[("log", "()V"), ("name", "()Ljava/lang/String;"), ("flag", "()B")]);
+    f.session.execute("return 100").unwrap();
+    assert!(f.session.execute("return 300").is_err());
+    assert_eq!(f.vm.model().early_returns[0], (f.thread, Tag::Byte(100)));
+    f.session.execute("pop").unwrap();
+    f.session.execute("return null").unwrap();
+    assert_eq!(f.vm.model().early_returns[1], (f.thread, Tag::Object(0)));
+    f.session.execute("pop").unwrap();
+    assert!(f.session.execute("return 1").is_err());
+    f.session.execute("return").unwrap();
+    assert_eq!(f.vm.model().early_returns[2], (f.thread, Tag::Void));
+
+    let mut f = start(Capabilities::POP_FRAMES, &[]);
+    assert!(f.session.execute("return 1").is_err());
+}
+
+#[test]
+fn return_accepts_null_of_any_reference_tag() {
+    let mut p = common::program(&[(0, 10)], 0).suspended();
+    let (model, class, thread) = (&mut p.model, p.class, p.thread);
+    model.capabilities = both();
+    let name = model.add_method(class, "name", "()Ljava/lang/String;", &[(0, 20)]);
+    model.add_variable(class, name, "s", "Ljava/lang/String;", 0);
+    let frame = model.push_frame(thread, class, name, 0);
+    model.set_local(frame, 0, Tag::String(0));
+    let (vm, mut session) = common::session(p.model);
+    session.execute(&format!("thread {:#x}", thread)).unwrap();
+    session.execute("return s").unwrap();
+    assert_eq!(vm.model().early_returns, vec![(thread, Tag::String(0))]);
+}
+
+#[test]
+fn pop_drops_the_selected_frame_and_those_above() {
+    let mut f = start(both(), &[("log", "()V")]);
+    f.session.execute("frame 1").unwrap();
+    f.session.execute("pop").unwrap();
+    // StackFrame.PopFrames is 16/4; the mock decodes with the same table.
+    assert!(f.vm.packets().contains(&(16, 4)));
+    let frames = f.vm.model().thread(f.thread).unwrap().frames.clone();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].location.index, 4);
+    assert!(f.session.execute("frame 1").is_err());
+    assert!(f.session.execute("pop").is_err());
+
+    let mut f = start(Capabilities::FORCE_EARLY_RETURN, &[]);
+    assert!(f.session.execute("pop").is_err());
+    assert_eq!(f.vm.model().thread(f.thread).unwrap().frames.len(), 2);
+}
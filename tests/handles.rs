@@ -0,0 +1,68 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+mod common;
+use dcd::cui::Session;
+use dcd::handles::DISPOSE_BATCH;
+use dcd::jdwp::{self,Tag};
+use dcd::mock::{MockVm,Model};
+
+/// Main.cache holds a Node; `extra` more Nodes are only reachable by ID.
+fn start(extra: usize) -> (MockVm, Session, u64, Vec<u64>) {
+    let mut model = Model::default();
+    let node = model.add_class("Lcom/example/Node;", "Node.java");
+    let count = model.add_field(node, "count", "I");
+    let main = model.add_class("Lcom/example/Main;", "Main.java");
+    let cache = model.add_field(main, "cache", "Lcom/example/Node;");
+    model.class_mut(main).unwrap().fields[0].mod_bits |= 0x8;
+    let cached = model.add_object(node, &[(count, Tag::Int(3))]);
+    model.class_mut(main).unwrap().statics.insert(cache, Tag::Object(cached));
+    let others = (0..extra).map(|_| model.add_object(node, &[(count, Tag::Int(0))])).collect();
+    let (vm, session) = common::session(model);
+    return (vm, session, cached, others);
+}
+
+#[test]
+fn pinned_objects_survive_until_released() {
+    let (vm, mut session, cached, _) = start(0);
+    session.execute("pin com.example.Main.cache").unwrap();
+    session.execute("pin com.example.Main.cache").unwrap();
+    session.execute("pin 6 * 7").unwrap();
+    session.execute("print $1.count + $3").unwrap();
+    session.execute("pins").unwrap();
+    assert_eq!(vm.model().uncollectable.get(&cached), Some(&1));
+    assert!(!vm.model().collect(cached));
+
+    session.execute("unpin $1").unwrap();
+    assert!(vm.model().uncollectable.contains_key(&cached));
+    assert!(session.execute("unpin $1").is_err());
+    assert!(session.execute("print $1").is_err());
+    session.execute("print $2.count").unwrap();
+    session.execute("unpin $2").unwrap();
+    assert!(vm.model().uncollectable.is_empty());
+    assert!(vm.model().disposed.is_empty());
+
+    // Disposed once, with both references it was handed out with.
+    session.execute("unpin all").unwrap();
+    assert_eq!(vm.model().disposed, vec![vec![(cached, 2)]]);
+    assert!(vm.model().collect(cached));
+    match session.execute("print com.example.Main.cache.count") {
+        Err(dcd::Error::Jdwp(jdwp::Error::InvalidObject)) => {},
+        r => panic!("unexpected {:?}", r),
+    }
+}
+
+#[test]
+fn released_objects_are_disposed_in_batches() {
+    let (vm, mut session, _, others) = start(DISPOSE_BATCH);
+    let (main, field) = {
+        let model = vm.model();
+        (model.classes[1].id, model.classes[1].fields[0].id)
+    };
+    for (i, object) in others.iter().enumerate() {
+        vm.model().class_mut(main).unwrap().statics.insert(field, Tag::Object(*object));
+        session.execute("pin com.example.Main.cache").unwrap();
+        session.execute(&format!("unpin ${}", i + 1)).unwrap();
+        assert_eq!(vm.model().disposed.len(), if i + 1 < DISPOSE_BATCH { 0 } else { 1 });
+    }
+    assert_eq!(vm.model().disposed[0].len(), DISPOSE_BATCH);
+    assert!(vm.model().uncollectable.is_empty());
+}
@@ -0,0 +1,86 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+mod common;
+use common::wait_until;
+use dcd::cui::Session;
+use dcd::jdwp::{self,Command,Tag};
+use dcd::mock::MockVm;
+
+struct Fixture {
+    vm: MockVm,
+    session: Session,
+    thread: u64,
+    frame: u64,
+    exception: u64,
+    location: jdwp::Location,
+}
+
+/// Main.run with locals i and o, stopped, and an exception to throw.
+fn start() -> Fixture {
+    let mut p = common::program(&[(0, 10), (4, 11)], 4).suspended();
+    let model = &mut p.model;
+    let error = model.add_class("Ljava/lang/Error;", "Error.java");
+    model.add_variable(p.class, p.run, "i", "I", 1);
+    model.add_variable(p.class, p.run, "o", "Ljava/lang/Object;", 2);
+    let exception = model.add_object(error, &[]);
+    model.set_local(p.frame, 1, Tag::Int(5));
+    model.set_local(p.frame, 2, Tag::Object(0));
+    let (vm, session) = common::session(p.model);
+    return Fixture { vm: vm, session: session, thread: p.thread, frame: p.frame, exception: exception, location: p.location };
+}
+
+impl Fixture {
+    fn local(&self, slot: i32) -> Tag {
+        return self.vm.model().locals[&self.frame][&slot];
+    }
+}
+
+#[test]
+fn printed_values_are_numbered() {
+    let mut f = start();
+    assert!(f.session.execute("print $_").is_err());
+    f.session.execute(&format!("thread {:#x}", f.thread)).unwrap();
+    f.session.execute("print i * 2").unwrap();
+    f.session.execute("print $1 + 1").unwrap();
+    f.session.execute("set var i = $2").unwrap();
+    assert_eq!(f.local(1), Tag::Int(11));
+    f.session.execute("set var i = $_ * $1").unwrap();
+    assert_eq!(f.local(1), Tag::Int(110));
+    assert!(f.session.execute("print $3").is_err());
+
+    f.session.execute("unpin $2").unwrap();
+    f.session.execute("set var i = $_").unwrap();
+    assert_eq!(f.local(1), Tag::Int(10));
+    f.session.execute("pins").unwrap();
+}
+
+#[test]
+fn events_set_automatic_variables() {
+    let mut f = start();
+    assert!(f.session.execute("print $thread").is_err());
+    f.vm.emit(jdwp::SUSPEND_ALL, vec![jdwp::Event::Exception {
+        request_id: 7,
+        thread: f.thread,
+        location: f.location,
+        exception: Tag::Object(f.exception),
+        catch_location: f.location,
+    }]).unwrap();
+    wait_until(|| f.session.execute("print $exception").is_ok());
+    f.session.execute("set var o = $exception").unwrap();
+    assert_eq!(f.local(2), Tag::Object(f.exception));
+    f.session.execute("set var i = $frame").unwrap();
+    assert_eq!(f.local(1), Tag::Int(0));
+    f.session.execute("print $thread != null").unwrap();
+    assert!(f.session.execute("print $retval").is_err());
+
+    f.vm.emit(jdwp::SUSPEND_ALL, vec![jdwp::Event::MethodExitWithReturnValue {
+        request_id: 8,
+        thread: f.thread,
+        location: f.location,
+        value: Tag::Int(42),
+    }]).unwrap();
+    wait_until(|| f.session.execute("print $retval").is_ok());
+    assert!(f.session.execute("print $exception").is_err());
+    f.session.execute("set var i = $retval").unwrap();
+    assert_eq!(f.local(1), Tag::Int(42));
+    assert!(!f.vm.received().contains(&Command::Resume));
+}
@@ -0,0 +1,151 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+mod common;
+use dcd::ddm::{self,Chunk};
+use dcd::hprof;
+use dcd::jdwp::{Command,Reply,Tag};
+use dcd::mock::Model;
+
+/// Writes HPROF with 4 byte identifiers.
+struct Dump {
+    out: Vec<u8>,
+    heap: Vec<u8>,
+}
+
+impl Dump {
+    fn new() -> Dump {
+        let mut out = b"JAVA PROFILE 1.0.3\0".to_vec();
+        out.extend_from_slice(&4u32.to_be_bytes());
+        out.extend_from_slice(&0u64.to_be_bytes());
+        return Dump { out: out, heap: Vec::new() };
+    }
+    fn record(&mut self, tag: u8, body: &[u8]) {
+        self.out.push(tag);
+        self.out.extend_from_slice(&0u32.to_be_bytes());
+        self.out.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        self.out.extend_from_slice(body);
+    }
+    fn string(&mut self, id: u32, s: &str) {
+        let mut body = id.to_be_bytes().to_vec();
+        body.extend_from_slice(s.as_bytes());
+        self.record(0x01, &body);
+    }
+    fn class(&mut self, id: u32, name_id: u32, name: &str, super_id: u32, size: u32, fields: &[(u32, u8)]) {
+        self.string(name_id, name);
+        let mut body = 1u32.to_be_bytes().to_vec();
+        body.extend_from_slice(&id.to_be_bytes());
+        body.extend_from_slice(&0u32.to_be_bytes());
+        body.extend_from_slice(&name_id.to_be_bytes());
+        self.record(0x02, &body);
+        let h = &mut self.heap;
+        h.push(0x20);
+        for v in [id, 0, super_id, 0, 0, 0, 0, 0, size] {
+            h.extend_from_slice(&v.to_be_bytes());
+        }
+        h.extend_from_slice(&[0, 0, 0, 0]);
+        h.extend_from_slice(&(fields.len() as u16).to_be_bytes());
+        for (name, ty) in fields {
+            h.extend_from_slice(&name.to_be_bytes());
+            h.push(*ty);
+        }
+        h.push(0x05);
+        h.extend_from_slice(&id.to_be_bytes());
+    }
+    fn instance(&mut self, id: u32, class: u32, values: &[u32]) {
+        let h = &mut self.heap;
+        h.push(0x21);
+        h.extend_from_slice(&id.to_be_bytes());
+        h.extend_from_slice(&0u32.to_be_bytes());
+        h.extend_from_slice(&class.to_be_bytes());
+        h.extend_from_slice(&((values.len() * 4) as u32).to_be_bytes());
+        for v in values {
+            h.extend_from_slice(&v.to_be_bytes());
+        }
+    }
+    fn finish(mut self) -> Vec<u8> {
+        let heap = std::mem::take(&mut self.heap);
+        self.record(0x1c, &heap);
+        self.record(0x2c, &[]);
+        return self.out;
+    }
+}
+
+/// Node { Node next; Node other; int value } with A -> B -> C, A -> D -> C,
+/// an unreachable E, and a byte[] on the app heap held by C.
+fn sample() -> Vec<u8> {
+    let mut d = Dump::new();
+    d.string(1, "next");
+    d.string(2, "other");
+    d.string(3, "value");
+    d.string(4, "app");
+    d.class(0x100, 10, "java.lang.Object", 0, 0, &[]);
+    d.class(0x200, 11, "Node", 0x100, 12, &[(1, 2), (2, 2), (3, 10)]);
+    d.heap.push(0xff);
+    d.heap.extend_from_slice(&0xa_u32.to_be_bytes());
+    d.instance(0xa, 0x200, &[0xb, 0xd, 1]);
+    d.instance(0xb, 0x200, &[0xc, 0, 2]);
+    d.instance(0xd, 0x200, &[0xc, 0, 4]);
+    d.instance(0xe, 0x200, &[0xc, 0, 5]);
+    d.heap.push(0xfe);
+    d.heap.extend_from_slice(&0x41u32.to_be_bytes());
+    d.heap.extend_from_slice(&4u32.to_be_bytes());
+    d.instance(0xc, 0x200, &[0, 0xf, 3]);
+    d.heap.push(0x23);
+    d.heap.extend_from_slice(&0xfu32.to_be_bytes());
+    d.heap.extend_from_slice(&0u32.to_be_bytes());
+    d.heap.extend_from_slice(&5u32.to_be_bytes());
+    d.heap.push(8);
+    d.heap.extend_from_slice(b"hello");
+    return d.finish();
+}
+
+#[test]
+fn parses_classes_instances_and_fields() {
+    let heap = hprof::parse_bytes(&sample()).unwrap();
+    assert_eq!(heap.class_name(0x200), "Node");
+    assert_eq!(heap.instances_of("Node").iter().map(|o| o.id).collect::<Vec<_>>(), vec![0xa, 0xb, 0xc, 0xd, 0xe]);
+    assert_eq!(heap.fields(0xa), vec![
+        ("next".to_string(), Tag::Object(0xb)),
+        ("other".to_string(), Tag::Object(0xd)),
+        ("value".to_string(), Tag::Int(1)),
+    ]);
+    assert_eq!(heap.heap_names[&0x41], "app");
+    assert_eq!(heap.objects[&0xc].heap, 0x41);
+    assert_eq!(heap.objects[&0xa].heap, 0);
+    let histogram = heap.histogram();
+    assert_eq!((histogram[0].name.as_str(), histogram[0].count, histogram[0].shallow), ("Node", 5, 60));
+    assert_eq!((histogram[1].name.as_str(), histogram[1].shallow), ("byte[]", 5));
+    let truncated = sample();
+    assert!(hprof::parse_bytes(&truncated[..truncated.len() - 10]).is_err());
+}
+
+#[test]
+fn dominator_tree_and_retained_sizes() {
+    let heap = hprof::parse_bytes(&sample()).unwrap();
+    let dominators = heap.dominators();
+    // C is reachable through both B and D, so only A dominates it.
+    assert_eq!(dominators.idom(0xc), Some(0xa));
+    assert_eq!(dominators.idom(0xb), Some(0xa));
+    assert_eq!(dominators.idom(0xf), Some(0xc));
+    assert_eq!(dominators.retained(0xc), Some(12 + 5));
+    assert_eq!(dominators.retained(0xb), Some(12));
+    assert_eq!(dominators.retained(0xa), Some(4 * 12 + 5));
+    assert_eq!(dominators.retained(0xe), None);
+    assert_eq!(dominators.largest(1), vec![(0xa, 53)]);
+}
+
+#[test]
+fn streams_a_dump_from_the_vm() {
+    let dump = sample();
+    let (vm, mut session) = common::session(Model::default());
+    let reply = dump.clone();
+    vm.on(move |cmd, _model| match cmd {
+        Command::DdmChunk(c) if c.kind == ddm::HPDS => Some(Ok(Reply::DdmChunks(vec![Chunk::new(ddm::HPDS, reply.clone())]))),
+        _ => None,
+    });
+    let path = std::env::temp_dir().join(format!("dcd-test-{}.hprof", std::process::id()));
+    session.execute(&format!("heap dump {}", path.display())).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), dump);
+    std::fs::remove_file(&path).unwrap();
+    session.execute("heap histogram").unwrap();
+    session.execute("heap show 0xa").unwrap();
+}
@@ -0,0 +1,101 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+mod common;
+use dcd::client::Client;
+use dcd::instances::{self,End,Referrers};
+use dcd::jdwp::{Capabilities,Tag};
+use dcd::mock::{self,MockVm,Model};
+
+struct Fixture {
+    vm: MockVm,
+    client: Client,
+    node: u64,
+    nodes: Vec<u64>,
+    holder: u64,
+    array: u64,
+}
+
+/// holder -> nodes[0] -> nodes[1] -> nodes[2] <- array, and two Holders.
+fn start(capabilities: Capabilities) -> Fixture {
+    let mut model = Model::default();
+    model.capabilities = capabilities;
+    let node = model.add_class("Lcom/example/Node;", "Node.java");
+    let next = model.add_field(node, "next", "Lcom/example/Node;");
+    let holder_class = model.add_class("Lcom/example/Holder;", "Holder.java");
+    let head = model.add_field(holder_class, "head", "Lcom/example/Node;");
+    model.add_class("Lcom/example/Unused;", "Unused.java");
+    let last = model.add_object(node, &[(next, Tag::Object(0))]);
+    let middle = model.add_object(node, &[(next, Tag::Object(last))]);
+    let first = model.add_object(node, &[(next, Tag::Object(middle))]);
+    let holder = model.add_object(holder_class, &[(head, Tag::Object(first))]);
+    model.add_object(holder_class, &[(head, Tag::Object(0))]);
+    let array = model.add_array("[Lcom/example/Node;", &[Tag::Object(last)]);
+    let (vm, client, _events) = common::connect(model);
+    return Fixture { vm: vm, client: client, node: node, nodes: vec![first, middle, last], holder: holder, array: array };
+}
+
+fn leaf(object: Tag, end: End) -> Referrers {
+    return Referrers { object: object, end: end, referrers: Vec::new(), truncated: false };
+}
+
+#[test]
+fn lists_and_counts_instances() {
+    let f = start(Capabilities::GET_INSTANCE_INFO);
+    let nodes: Vec<Tag> = f.nodes.iter().map(|n| Tag::Object(*n)).collect();
+    let mut found = instances::instances(&f.client, f.node, 0).unwrap();
+    found.sort_by_key(|t| t.object_id());
+    let mut expected = nodes.clone();
+    expected.sort_by_key(|t| t.object_id());
+    assert_eq!(found, expected);
+    assert_eq!(instances::instances(&f.client, f.node, 2).unwrap().len(), 2);
+    assert_eq!(instances::counts(&f.client, &[f.node]).unwrap(), vec![3]);
+    assert_eq!(instances::referrers(&f.client, f.nodes[2], 0).unwrap(), vec![Tag::Object(f.nodes[1]), Tag::Array(f.array)]);
+    drop(f.vm);
+
+    let f = start(Capabilities::empty());
+    assert!(instances::instances(&f.client, f.node, 0).is_err());
+}
+
+#[test]
+fn walks_referrers_back_to_roots() {
+    let f = start(Capabilities::GET_INSTANCE_INFO);
+    let tree = instances::referrer_tree(&f.client, Tag::Object(f.nodes[2]), 5, 10).unwrap();
+    let holder = leaf(Tag::Object(f.holder), End::Root);
+    let first = Referrers { object: Tag::Object(f.nodes[0]), end: End::Expanded, referrers: vec![holder], truncated: false };
+    let middle = Referrers { object: Tag::Object(f.nodes[1]), end: End::Expanded, referrers: vec![first], truncated: false };
+    assert_eq!(tree, Referrers {
+        object: Tag::Object(f.nodes[2]),
+        end: End::Expanded,
+        referrers: vec![middle, leaf(Tag::Array(f.array), End::Root)],
+        truncated: false,
+    });
+
+    let tree = instances::referrer_tree(&f.client, Tag::Object(f.nodes[2]), 1, 1).unwrap();
+    assert!(tree.truncated);
+    assert_eq!(tree.referrers, vec![leaf(Tag::Object(f.nodes[1]), End::DepthLimit)]);
+
+    // Point the last node back at the middle one to make a loop.
+    {
+        let mut model = f.vm.model();
+        let next = model.class(f.node).unwrap().fields[0].id;
+        if let mock::MockData::Fields(fields) = &mut model.objects.get_mut(&f.nodes[2]).unwrap().data {
+            fields.insert(next, Tag::Object(f.nodes[1]));
+        }
+    }
+    let tree = instances::referrer_tree(&f.client, Tag::Object(f.nodes[1]), 5, 10).unwrap();
+    let last = tree.referrers.iter().find(|r| r.object == Tag::Object(f.nodes[2])).unwrap();
+    assert!(last.referrers.contains(&leaf(Tag::Object(f.nodes[1]), End::Cycle)), "{:?}", last);
+}
+
+#[test]
+fn commands() {
+    let mut model = Model::default();
+    model.capabilities = Capabilities::GET_INSTANCE_INFO;
+    let node = model.add_class("Lcom/example/Node;", "Node.java");
+    let object = model.add_object(node, &[]);
+    let (_vm, mut session) = common::session(model);
+    session.execute("instances Node").unwrap();
+    session.execute("instance-counts com.example.*").unwrap();
+    session.execute(&format!("referrers {:#x} 3", object)).unwrap();
+    assert!(session.execute("instances Nope").is_err());
+    assert!(session.execute("referrers").is_err());
+}
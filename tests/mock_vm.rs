@@ -0,0 +1,243 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+mod common;
+use common::Program;
+use dcd::jdwp::{self,Command,Reply};
+use dcd::mock::{self,Model};
+use dcd::client::{self,State};
+use dcd::request::{self,Request};
+use dcd::{cui,ddm,Error};
+use std::time::{Duration,Instant};
+
+/// Main.run two frames deep on main, a count field, and an idle worker
+/// thread, which is also returned.
+fn fixture() -> (Program, u64) {
+    let mut p = common::program(&[(0, 10), (4, 11), (9, 12)], 4);
+    p.model.add_field(p.class, "count", "I");
+    let worker = p.model.add_thread("worker");
+    p.model.push_frame(p.thread, p.class, p.run, 9);
+    return (p, worker);
+}
+
+#[test]
+fn initialize_caches_vm_info() {
+    let mut model = Model::default();
+    model.idsizes = jdwp::IDSizes { field: 4, method: 8, object: 4, reference_type: 8, frame: 2 };
+    model.capabilities = jdwp::Capabilities::POP_FRAMES | jdwp::Capabilities::GET_INSTANCE_INFO;
+    let (_vm, client, _events) = mock::connect(model).unwrap();
+    client.initialize().unwrap();
+    let state = client.state();
+    assert_eq!(state.name, "MockVM");
+    assert_eq!((state.major, state.minor), (1, 8));
+    assert_eq!(state.idsizes.frame, 2);
+    assert_eq!(state.idsizes.object, 4);
+    assert_eq!(state.capabilities, jdwp::Capabilities::POP_FRAMES | jdwp::Capabilities::GET_INSTANCE_INFO);
+}
+
+#[test]
+fn concurrent_commands_get_their_own_replies() {
+    let (f, worker) = fixture();
+    let (_vm, client, _events) = common::connect(f.model);
+    let threads = client.send(&Command::AllThreads).unwrap();
+    let classes = client.send(&Command::AllClasses).unwrap();
+    let name = client.send(&Command::ThreadReferenceName { thread: worker }).unwrap();
+    let methods = client.send(&Command::ReferenceTypeMethods { ref_type: f.class }).unwrap();
+    assert_eq!(name.wait().unwrap(), Reply::ThreadReferenceName("worker".to_string()));
+    match methods.wait().unwrap() {
+        Reply::ReferenceTypeMethods(m) => assert_eq!(m[0].method_id, f.run),
+        r => panic!("unexpected {:?}", r),
+    }
+    match classes.wait().unwrap() {
+        Reply::AllClasses(c) => assert_eq!(c[0].signature, "Lcom/example/Main;"),
+        r => panic!("unexpected {:?}", r),
+    }
+    assert_eq!(threads.wait().unwrap(), Reply::AllThreads(vec![f.thread, worker]));
+}
+
+#[test]
+fn typed_requests_unpack_their_reply() {
+    let (f, worker) = fixture();
+    let (_vm, client, _events) = common::connect(f.model);
+    client.send_and_wait(&Command::Suspend).unwrap();
+    let frames = client.send_typed(request::thread_reference_frames(f.thread, 0, -1)).unwrap();
+    assert_eq!(client.request(request::thread_reference_name(f.thread)).unwrap(), "main");
+    assert_eq!(frames.wait().unwrap().len(), 2);
+    let table = client.request(request::method_line_table(f.class, f.run)).unwrap();
+    assert_eq!((table.start, table.end, table.lines.len()), (0, 9, 3));
+    // A reply of the wrong kind comes back whole in the error.
+    let wrong = Request::new(Command::AllThreads, |reply| match reply {
+        Reply::ThreadReferenceName(name) => Ok(name),
+        r => Err(r),
+    });
+    match client.request(wrong) {
+        Err(Error::UnexpectedReply(r)) => assert_eq!(*r, Reply::AllThreads(vec![f.thread, worker])),
+        r => panic!("unexpected {:?}", r),
+    }
+}
+
+#[test]
+fn jdwp_errors_resolve_the_failing_command() {
+    let (f, _) = fixture();
+    let (_vm, client, _events) = common::connect(f.model);
+    let bad = client.send(&Command::ThreadReferenceName { thread: 0xdead }).unwrap();
+    let good = client.send(&Command::ThreadReferenceName { thread: f.thread }).unwrap();
+    let running = client.send(&Command::ThreadReferenceFrames { thread: f.thread, start: 0, length: -1 }).unwrap();
+    assert!(matches!(bad.wait(), Err(Error::Jdwp(jdwp::Error::InvalidThread))));
+    assert!(good.wait().is_ok());
+    assert!(matches!(running.wait(), Err(Error::Jdwp(jdwp::Error::ThreadNotSuspended))));
+}
+
+#[test]
+fn frames_and_line_tables() {
+    let (f, _) = fixture();
+    let (_vm, client, _events) = common::connect(f.model);
+    client.send_and_wait(&Command::Suspend).unwrap();
+    let frames = match client.send_and_wait(&Command::ThreadReferenceFrames { thread: f.thread, start: 0, length: -1 }).unwrap() {
+        Reply::ThreadReferenceFrames(frames) => frames,
+        r => panic!("unexpected {:?}", r),
+    };
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].location.index, 9);
+    let table = client.send_and_wait(&Command::MethodLineTable { ref_type: f.class, method: f.run }).unwrap();
+    match table {
+        Reply::MethodLineTable { start, end, lines } => {
+            assert_eq!((start, end), (0, 9));
+            assert_eq!(lines[2].line, 12);
+        },
+        r => panic!("unexpected {:?}", r),
+    }
+}
+
+#[test]
+fn emitted_events_arrive_separately_from_replies() {
+    let (f, worker) = fixture();
+    let (vm, client, events) = common::connect(f.model);
+    let location = jdwp::Location { type_tag: jdwp::TYPE_TAG_CLASS, class_id: f.class, method_id: f.run, index: 4 };
+    let reply = client.send_and_wait(&Command::EventRequestSet {
+        event_kind: jdwp::EventKind::Breakpoint,
+        suspend_policy: jdwp::SUSPEND_EVENT_THREAD,
+        modifiers: vec![jdwp::Modifier::LocationOnly(location)],
+    }).unwrap();
+    let request_id = match reply {
+        Reply::EventRequestSet(id) => id,
+        r => panic!("unexpected {:?}", r),
+    };
+    assert_eq!(vm.model().requests_for(jdwp::EventKind::Breakpoint)[0].id, request_id);
+
+    let pending = client.send(&Command::AllThreads).unwrap();
+    vm.emit(jdwp::SUSPEND_EVENT_THREAD, vec![jdwp::Event::Breakpoint {
+        request_id: request_id,
+        thread: f.thread,
+        location: location,
+    }]).unwrap();
+    assert!(pending.wait().is_ok());
+    let (_, cmd) = events.recv_timeout(Duration::from_secs(5)).unwrap();
+    match cmd {
+        Command::Composite { suspend_policy, events } => {
+            assert_eq!(suspend_policy, jdwp::SUSPEND_EVENT_THREAD);
+            assert_eq!(events[0].request_id(), request_id);
+            assert_eq!(events[0].location(), Some(location));
+        },
+        c => panic!("unexpected {:?}", c),
+    }
+    assert_eq!(vm.model().thread(f.thread).unwrap().suspend_count, 1);
+    assert_eq!(vm.model().thread(worker).unwrap().suspend_count, 0);
+}
+
+#[test]
+fn unknown_vm_commands_are_refused_under_their_own_id() {
+    assert_eq!(client::answer(64, 100), None);
+    assert_eq!(client::answer(ddm::DDM_SET, ddm::DDM_CHUNK), None);
+    let answer = client::answer(0x42, 1).unwrap();
+    assert_eq!(answer, Err(jdwp::Error::Unimplemented));
+    let reply = State::default().reply_packet(0x1234_5678, &answer);
+    assert_eq!(reply, jdwp::Packet::Reply { id: 0x1234_5678, error: 99, data: vec![] });
+    assert_eq!(reply.to_bytes(), vec![0, 0, 0, 11, 0x12, 0x34, 0x56, 0x78, 0x80, 0, 99]);
+}
+
+#[test]
+fn vm_commands_get_answered_unless_they_are_notifications() {
+    let (f, _) = fixture();
+    let (vm, client, events) = common::connect(f.model);
+    let sizes = client.state().idsizes;
+    let unknown = vm.send_raw(0x42, 1, vec![1, 2, 3]).unwrap();
+    let version = vm.send_raw(1, 1, vec![]).unwrap();
+    vm.emit(jdwp::SUSPEND_NONE, vec![jdwp::Event::VMDeath { request_id: 0 }]).unwrap();
+    vm.send_command(&Command::DdmChunk(ddm::Chunk::new(ddm::THDE, 7u32.to_be_bytes().to_vec())), sizes).unwrap();
+    // Replies go out in order, so once this one is in, any for the event
+    // or the chunk would be too.
+    let last = vm.send_raw(0x42, 2, vec![]).unwrap();
+
+    // Commands that decode are still handed over, answered or not.
+    let (id, cmd) = events.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!((id, cmd), (version, Command::Version));
+    assert!(matches!(events.recv_timeout(Duration::from_secs(5)).unwrap().1, Command::Composite { .. }));
+    assert!(matches!(events.recv_timeout(Duration::from_secs(5)).unwrap().1, Command::DdmChunk(_)));
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !vm.replies().iter().any(|r| r.id() == last) {
+        assert!(Instant::now() < deadline, "timed out");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(vm.replies(), vec![
+        jdwp::Packet::Reply { id: unknown, error: 99, data: vec![] },
+        jdwp::Packet::Reply { id: version, error: 99, data: vec![] },
+        jdwp::Packet::Reply { id: last, error: 99, data: vec![] },
+    ]);
+}
+
+#[test]
+fn scripted_handlers_override_the_model() {
+    let (f, _) = fixture();
+    let (vm, client, _events) = mock::connect(f.model).unwrap();
+    vm.on(|cmd, _model| match cmd {
+        Command::ThreadReferenceName { .. } => Some(Err(jdwp::Error::VmDead)),
+        _ => None,
+    });
+    client.initialize().unwrap();
+    let res = client.send_and_wait(&Command::ThreadReferenceName { thread: f.thread });
+    assert!(matches!(res, Err(Error::Jdwp(jdwp::Error::VmDead))));
+    assert!(vm.received().contains(&Command::ThreadReferenceName { thread: f.thread }));
+}
+
+#[test]
+fn disconnect_wakes_waiters() {
+    let (f, _) = fixture();
+    let (vm, client, _events) = mock::connect(f.model).unwrap();
+    vm.on(|cmd, _model| match cmd {
+        Command::AllThreads => panic!("simulated VM crash"),
+        _ => None,
+    });
+    client.initialize().unwrap();
+    let res = client.send_and_wait(&Command::AllThreads);
+    assert!(matches!(res, Err(Error::Disconnected)));
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_sockets_carry_a_session() {
+    let (f, worker) = fixture();
+    let path = std::env::temp_dir().join(format!("dcd-mock-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+    let mut vm = mock::MockVm::new(f.model);
+    let stream = std::os::unix::net::UnixStream::connect(&path).unwrap();
+    vm.serve(listener.accept().unwrap().0).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let (client, events) = dcd::client::Client::connect(stream).unwrap();
+    client.initialize().unwrap();
+    assert_eq!(client.state().name, "MockVM");
+    assert_eq!(client.send_and_wait(&Command::ThreadReferenceName { thread: worker }).unwrap(), Reply::ThreadReferenceName("worker".to_string()));
+    vm.emit(jdwp::SUSPEND_NONE, vec![jdwp::Event::ThreadStart { request_id: 1, thread: worker }]).unwrap();
+    assert!(matches!(events.recv_timeout(Duration::from_secs(5)).unwrap().1, Command::Composite { .. }));
+}
+
+#[test]
+fn cui_starts_against_mock() {
+    let (f, _) = fixture();
+    let (vm, client, events) = mock::connect(f.model).unwrap();
+    let mut session = cui::Session::new(client, events).unwrap();
+    assert_eq!(vm.received()[0], Command::Version);
+    assert!(session.execute("help").unwrap());
+    assert!(matches!(session.execute("ddm hello"), Err(Error::Jdwp(jdwp::Error::Unimplemented))));
+    assert!(!session.execute("quit").unwrap());
+}
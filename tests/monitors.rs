@@ -0,0 +1,135 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+mod common;
+use dcd::jdwp::{self,Capabilities,Command,EventKind,Tag};
+use dcd::mock::Model;
+use dcd::monitors::{self,Contention,Wait};
+use std::time::{Duration,Instant};
+
+struct Fixture {
+    model: Model,
+    first: u64,
+    second: u64,
+    bystander: u64,
+    a: u64,
+    b: u64,
+}
+
+/// `first` holds a and wants b, `second` holds b and wants a, and
+/// `bystander` queues up behind them for a.
+fn fixture(capabilities: Capabilities) -> Fixture {
+    let mut p = common::program(&[(0, 10)], 0);
+    let (model, class, run) = (&mut p.model, p.class, p.run);
+    model.capabilities = capabilities;
+    let lock = model.add_class("Lcom/example/Lock;", "Lock.java");
+    let transfer = model.add_method(class, "transfer", "()V", &[(0, 20), (5, 21)]);
+    let a = model.add_object(lock, &[]);
+    let b = model.add_object(lock, &[]);
+    // The main thread is `first`.
+    let mut threads = vec![p.thread];
+    for name in ["second", "bystander"] {
+        let thread = model.add_thread(name);
+        model.push_frame(thread, class, run, 0);
+        threads.push(thread);
+    }
+    for thread in threads.iter() {
+        model.push_frame(*thread, class, transfer, 5);
+    }
+    model.lock(a, threads[0], 1);
+    model.lock(b, threads[1], 0);
+    model.contend(threads[0], b);
+    model.contend(threads[1], a);
+    model.contend(threads[2], a);
+    return Fixture { model: p.model, first: threads[0], second: threads[1], bystander: threads[2], a: a, b: b };
+}
+
+fn all() -> Capabilities {
+    return Capabilities::GET_OWNED_MONITOR_INFO | Capabilities::GET_CURRENT_CONTENDED_MONITOR
+        | Capabilities::GET_MONITOR_INFO | Capabilities::GET_MONITOR_FRAME_INFO;
+}
+
+#[test]
+fn finds_the_cycle_and_where_each_lock_was_taken() {
+    let f = fixture(all());
+    let (_vm, client, _events) = common::connect(f.model);
+    client.send_and_wait(&Command::Suspend).unwrap();
+    let threads = monitors::collect(&client).unwrap();
+    let first = threads.iter().find(|t| t.thread == f.first).unwrap();
+    assert_eq!(first.contended, Some(f.b));
+    assert_eq!(first.owned.len(), 1);
+    assert_eq!(first.owned[0].object, f.a);
+    assert_eq!(first.owned[0].frame.unwrap().location.index, 0);
+    assert!(threads.iter().find(|t| t.thread == f.bystander).unwrap().owned.is_empty());
+
+    let owners = monitors::owners(&client, &threads).unwrap();
+    let cycles = monitors::deadlocks(&threads, &owners);
+    assert_eq!(cycles, vec![vec![
+        Wait { thread: f.first, monitor: f.b, owner: f.second },
+        Wait { thread: f.second, monitor: f.a, owner: f.first },
+    ]]);
+}
+
+#[test]
+fn command_resumes_the_vm_and_needs_the_capabilities() {
+    let f = fixture(all());
+    let (vm, mut session) = common::session(f.model);
+    session.execute("deadlocks").unwrap();
+    assert!(vm.model().threads.iter().all(|t| t.suspend_count == 0));
+
+    let f = fixture(Capabilities::GET_MONITOR_INFO);
+    let (vm, mut session) = common::session(f.model);
+    assert!(session.execute("deadlocks").is_err());
+    assert!(vm.model().threads.iter().all(|t| t.suspend_count == 0));
+}
+
+#[test]
+fn contention_is_timed_per_lock_and_call_site() {
+    let mut contention = Contention::default();
+    contention.requests = vec![(EventKind::MonitorContendedEnter, 1), (EventKind::MonitorContendedEntered, 2),
+        (EventKind::MonitorWait, 3), (EventKind::MonitorWaited, 4)];
+    let at = |index: u64| jdwp::Location { type_tag: 1, class_id: 0x10, method_id: 0x11, index: index };
+    let t0 = Instant::now();
+    let ms = |n: u64| t0 + Duration::from_millis(n);
+    let events = [
+        (ms(0), jdwp::Event::MonitorContendedEnter { request_id: 1, thread: 1, object: Tag::Object(0xa), location: at(5) }),
+        (ms(0), jdwp::Event::MonitorContendedEnter { request_id: 1, thread: 2, object: Tag::Object(0xb), location: at(9) }),
+        (ms(30), jdwp::Event::MonitorContendedEntered { request_id: 2, thread: 1, object: Tag::Object(0xa), location: at(5) }),
+        (ms(40), jdwp::Event::MonitorContendedEnter { request_id: 1, thread: 1, object: Tag::Object(0xa), location: at(5) }),
+        (ms(55), jdwp::Event::MonitorContendedEntered { request_id: 2, thread: 2, object: Tag::Object(0xb), location: at(9) }),
+        (ms(60), jdwp::Event::MonitorContendedEntered { request_id: 2, thread: 1, object: Tag::Object(0xa), location: at(5) }),
+        (ms(60), jdwp::Event::MonitorWait { request_id: 3, thread: 2, object: Tag::Object(0xb), location: at(12), timeout: 0 }),
+        (ms(160), jdwp::Event::MonitorWaited { request_id: 4, thread: 2, object: Tag::Object(0xb), location: at(12), timed_out: false }),
+    ];
+    for (time, event) in events.iter() {
+        assert!(contention.on_event(event, *time));
+    }
+    let other = jdwp::Event::MonitorContendedEnter { request_id: 9, thread: 1, object: Tag::Object(0xa), location: at(5) };
+    assert!(!contention.on_event(&other, ms(200)));
+
+    let locks = monitors::sorted(&contention.locks);
+    assert_eq!(locks.iter().map(|(o, _)| *o).collect::<Vec<_>>(), vec![0xb, 0xa]);
+    assert_eq!((locks[0].1.contended, locks[0].1.blocked, locks[0].1.waits, locks[0].1.waited),
+        (1, Duration::from_millis(55), 1, Duration::from_millis(100)));
+    assert_eq!((locks[1].1.contended, locks[1].1.blocked, locks[1].1.longest), (2, Duration::from_millis(50), Duration::from_millis(30)));
+    let sites = monitors::sorted(&contention.sites);
+    assert_eq!(sites.iter().map(|(l, _)| l.index).collect::<Vec<_>>(), vec![9, 5, 12]);
+}
+
+#[test]
+fn monitor_trace_requests_events_without_suspending() {
+    let mut model = Model::default();
+    model.capabilities = Capabilities::REQUEST_MONITOR_EVENTS;
+    let (vm, mut session) = common::session(model);
+    assert!(session.execute("monitor-trace").is_err());
+    session.execute("monitor-trace on").unwrap();
+    for kind in [EventKind::MonitorContendedEnter, EventKind::MonitorContendedEntered, EventKind::MonitorWait, EventKind::MonitorWaited] {
+        let requests = vm.model().requests_for(kind);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].suspend_policy, jdwp::SUSPEND_NONE);
+    }
+    session.execute("monitor-trace 5").unwrap();
+    session.execute("monitor-trace off").unwrap();
+    assert!(vm.model().requests.is_empty());
+
+    let (_vm, mut session) = common::session(Model::default());
+    assert!(session.execute("monitor-trace on").is_err());
+}
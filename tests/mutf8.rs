@@ -0,0 +1,46 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+use dcd::jdwp::{Command,Deserializer,IDSizes,JavaString,Reply,Serializer};
+use dcd::mutf8;
+
+const SIZES: IDSizes = IDSizes { field: 8, method: 8, object: 8, reference_type: 8, frame: 8 };
+
+#[test]
+fn encodes_nul_and_supplementary_characters() {
+    assert_eq!(mutf8::encode("a\0b"), b"a\xc0\x80b");
+    assert_eq!(mutf8::encode("é"), "é".as_bytes());
+    // U+1F600 is the pair D83D DE00, three bytes each.
+    assert_eq!(mutf8::encode("\u{1f600}"), b"\xed\xa0\xbd\xed\xb8\x80");
+    for text in ["", "plain", "a\0b", "\u{1f600} and \u{20ac}", "\u{ffff}\u{10000}"] {
+        assert_eq!(mutf8::decode(&mutf8::encode(text)), (text.to_string(), true), "{:?}", text);
+    }
+}
+
+#[test]
+fn lossy_decoding_is_flagged() {
+    // A lone high surrogate, a raw NUL, plain UTF-8 and a truncated sequence.
+    assert_eq!(mutf8::decode(b"a\xed\xa0\xbdb"), ("a\u{fffd}b".to_string(), false));
+    assert_eq!(mutf8::decode(b"a\0b"), ("a\0b".to_string(), false));
+    assert_eq!(mutf8::decode("\u{1f600}".as_bytes()), ("\u{1f600}".to_string(), false));
+    assert_eq!(mutf8::decode(b"\xe2\x82"), ("\u{fffd}\u{fffd}".to_string(), false));
+}
+
+#[test]
+fn string_values_keep_their_bytes() {
+    let raw = b"x\xed\xb8\x80".to_vec();
+    let mut data = Vec::new();
+    data.extend_from_slice(&(raw.len() as i32).to_be_bytes());
+    data.extend_from_slice(&raw);
+    let reply = Reply::deserialize(10, 1, &data, SIZES).unwrap();
+    assert_eq!(reply, Reply::StringReferenceValue(JavaString { text: "x\u{fffd}".to_string(), raw: Some(raw) }));
+    assert_eq!(reply.serialize(SIZES), data);
+
+    // Names and other strings decode the same way, without failing.
+    let mut d = Deserializer(&data[..], SIZES);
+    assert_eq!(d.deserialize_string().unwrap(), "x\u{fffd}");
+    let mut s = Serializer(Vec::new(), SIZES);
+    s.serialize_string("\0\u{1f600}");
+    assert_eq!(s.0, b"\0\0\0\x08\xc0\x80\xed\xa0\xbd\xed\xb8\x80");
+
+    let (_, _, data) = Command::CreateString { utf: "\u{1f600}".to_string() }.serialize(SIZES);
+    assert_eq!(Command::deserialize(1, 11, &data, SIZES).unwrap(), Command::CreateString { utf: "\u{1f600}".to_string() });
+}
@@ -0,0 +1,230 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+mod common;
+use common::wait_until;
+use dcd::client::Client;
+use dcd::jdwp::{self,Command,Reply};
+use dcd::mock::{Model,MockVm};
+use dcd::mux::Mux;
+use dcd::transport::{self,Transport};
+use std::io::Write;
+use std::net::{TcpListener,TcpStream};
+use std::sync::{Arc,Condvar,Mutex};
+use std::time::Duration;
+
+fn shared_vm() -> (MockVm, Arc<Mux>, u64, u64) {
+    return limited_vm(None);
+}
+
+fn limited_vm(max_packet: Option<u32>) -> (MockVm, Arc<Mux>, u64, u64) {
+    let mut model = Model::default();
+    let class = model.add_class("LMain;", "Main.java");
+    let main = model.add_thread("main");
+    let (vm_end, mux_end) = transport::duplex();
+    let mut vm = MockVm::new(model);
+    vm.serve(vm_end).unwrap();
+    let mux = Mux::connect(mux_end, max_packet).unwrap();
+    return (vm, mux, class, main);
+}
+
+fn attach(mux: &Arc<Mux>) -> (Client, std::sync::mpsc::Receiver<dcd::client::Event>) {
+    let (mux_end, client_end) = transport::duplex();
+    let handle = {
+        let mux = mux.clone();
+        std::thread::spawn(move || mux.add_client(mux_end).unwrap())
+    };
+    let (client, events) = Client::connect(client_end).unwrap();
+    handle.join().unwrap();
+    client.initialize().unwrap();
+    return (client, events);
+}
+
+/// The mux's end of a debugger connection whose writes can be held up, as if
+/// the debugger stopped reading.
+struct Stalling {
+    inner: transport::Duplex,
+    stalled: Arc<(Mutex<bool>, Condvar)>,
+}
+
+struct StallingWriter {
+    inner: transport::PipeWriter,
+    stalled: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Write for StallingWriter {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let (lock, cond) = &*self.stalled;
+        let _held = cond.wait_while(lock.lock().unwrap(), |stalled| *stalled).unwrap();
+        return self.inner.write(data);
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        return self.inner.flush();
+    }
+}
+
+impl Transport for Stalling {
+    type Reader = transport::PipeReader;
+    type Writer = StallingWriter;
+    fn split(self) -> std::io::Result<(transport::PipeReader, StallingWriter)> {
+        return Ok((self.inner.reader, StallingWriter { inner: self.inner.writer, stalled: self.stalled }));
+    }
+}
+
+fn set_stalled(stalled: &Arc<(Mutex<bool>, Condvar)>, value: bool) {
+    let (lock, cond) = &**stalled;
+    *lock.lock().unwrap() = value;
+    cond.notify_all();
+}
+
+fn breakpoint(client: &Client) -> i32 {
+    let reply = client.send_and_wait(&Command::EventRequestSet {
+        event_kind: jdwp::EventKind::Breakpoint,
+        suspend_policy: jdwp::SUSPEND_NONE,
+        modifiers: vec![],
+    }).unwrap();
+    return match reply {
+        Reply::EventRequestSet(id) => id,
+        r => panic!("unexpected {:?}", r),
+    };
+}
+
+#[test]
+fn clients_share_one_connection() {
+    let (vm, mux, class, main) = shared_vm();
+    let (a, _a_events) = attach(&mux);
+    let (b, _b_events) = attach(&mux);
+    assert_eq!(b.state().name, "MockVM");
+    // Both clients use the same packet IDs; each must get its own answer.
+    let from_a = a.send(&Command::ReferenceTypeSignature { ref_type: class }).unwrap();
+    let from_b = b.send(&Command::ThreadReferenceName { thread: main }).unwrap();
+    assert_eq!(from_a.id(), from_b.id());
+    assert_eq!(from_b.wait().unwrap(), Reply::ThreadReferenceName("main".to_string()));
+    assert_eq!(from_a.wait().unwrap(), Reply::ReferenceTypeSignature("LMain;".to_string()));
+    // Version and IDSizes were only ever asked once, by the mux itself.
+    let received = vm.received();
+    assert_eq!(received.iter().filter(|c| **c == Command::Version).count(), 1);
+    assert_eq!(received.iter().filter(|c| **c == Command::IDSizes).count(), 1);
+}
+
+#[test]
+fn events_go_to_the_client_that_asked() {
+    let (vm, mux, class, main) = shared_vm();
+    let (a, a_events) = attach(&mux);
+    let (b, b_events) = attach(&mux);
+    let for_a = breakpoint(&a);
+    let for_b = breakpoint(&b);
+    let location = jdwp::Location { type_tag: jdwp::TYPE_TAG_CLASS, class_id: class, method_id: 1, index: 0 };
+    vm.emit(jdwp::SUSPEND_NONE, vec![
+        jdwp::Event::Breakpoint { request_id: for_a, thread: main, location: location },
+        jdwp::Event::Breakpoint { request_id: for_b, thread: main, location: location },
+        jdwp::Event::ThreadStart { request_id: 0, thread: main },
+    ]).unwrap();
+    for (events, mine) in [(&a_events, for_a), (&b_events, for_b)] {
+        match events.recv_timeout(Duration::from_secs(5)).unwrap().1 {
+            Command::Composite { events, .. } => {
+                let ids: Vec<i32> = events.iter().map(|e| e.request_id()).collect();
+                assert_eq!(ids, vec![mine, 0]);
+            },
+            c => panic!("unexpected {:?}", c),
+        }
+    }
+}
+
+#[test]
+fn clients_cannot_clear_each_others_requests() {
+    let (vm, mux, _class, _main) = shared_vm();
+    let (a, _a_events) = attach(&mux);
+    let (b, _b_events) = attach(&mux);
+    let for_a = breakpoint(&a);
+    breakpoint(&b);
+    b.send_and_wait(&Command::EventRequestClear { event_kind: jdwp::EventKind::Breakpoint, request_id: for_a }).unwrap();
+    b.send_and_wait(&Command::EventRequestClearAllBreakpoints).unwrap();
+    wait_until(|| vm.model().requests_for(jdwp::EventKind::Breakpoint).len() == 1);
+    assert_eq!(vm.model().requests_for(jdwp::EventKind::Breakpoint)[0].id, for_a);
+    assert!(!vm.received().contains(&Command::EventRequestClearAllBreakpoints));
+}
+
+#[test]
+fn detaching_clears_requests_and_keeps_the_vm() {
+    let (vm, mux, _class, main) = shared_vm();
+    let (a, _a_events) = attach(&mux);
+    let (b, _b_events) = attach(&mux);
+    breakpoint(&a);
+    breakpoint(&b);
+    a.send_and_wait(&Command::Dispose).unwrap();
+    wait_until(|| mux.client_count() == 1);
+    wait_until(|| vm.model().requests_for(jdwp::EventKind::Breakpoint).len() == 1);
+    assert!(!vm.received().contains(&Command::Dispose));
+    assert_eq!(b.send_and_wait(&Command::ThreadReferenceName { thread: main }).unwrap(),
+        Reply::ThreadReferenceName("main".to_string()));
+}
+
+#[test]
+fn mux_answers_vm_commands_once() {
+    let (vm, mux, _class, _main) = shared_vm();
+    let (_a, a_events) = attach(&mux);
+    let (_b, b_events) = attach(&mux);
+    let id = vm.send_raw(0x42, 1, vec![]).unwrap();
+    let last = vm.send_raw(0x42, 2, vec![]).unwrap();
+    wait_until(|| vm.replies().iter().any(|r| r.id() == last));
+    assert_eq!(vm.replies(), vec![
+        jdwp::Packet::Reply { id: id, error: 99, data: vec![] },
+        jdwp::Packet::Reply { id: last, error: 99, data: vec![] },
+    ]);
+    // Neither client saw the commands: the first thing each gets is this.
+    vm.emit(jdwp::SUSPEND_NONE, vec![jdwp::Event::VMDeath { request_id: 0 }]).unwrap();
+    for events in [a_events, b_events] {
+        let (_, cmd) = events.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(cmd, Command::Composite { .. }), "{:?}", cmd);
+    }
+}
+
+#[test]
+fn oversized_client_packets_drop_only_that_client() {
+    let (_vm, mux, _class, main) = limited_vm(Some(4096));
+    let (a, _a_events) = attach(&mux);
+    let (b, _b_events) = attach(&mux);
+    // Depending on timing the write itself fails or the reply never comes.
+    assert!(a.send_and_wait(&Command::CreateString { utf: "x".repeat(8192) }).is_err());
+    wait_until(|| mux.client_count() == 1);
+    assert_eq!(b.send_and_wait(&Command::ThreadReferenceName { thread: main }).unwrap(),
+        Reply::ThreadReferenceName("main".to_string()));
+}
+
+#[test]
+fn a_silent_client_does_not_block_the_next() {
+    let (_vm, mux, _class, main) = shared_vm();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    {
+        let mux = mux.clone();
+        std::thread::spawn(move || mux.serve(listener));
+    }
+    // Connects and never handshakes.
+    let _silent = TcpStream::connect(addr).unwrap();
+    let (client, _events) = Client::connect(TcpStream::connect(addr).unwrap()).unwrap();
+    client.initialize().unwrap();
+    assert_eq!(client.send_and_wait(&Command::ThreadReferenceName { thread: main }).unwrap(),
+        Reply::ThreadReferenceName("main".to_string()));
+    assert_eq!(mux.client_count(), 1);
+}
+
+#[test]
+fn a_stalled_client_does_not_block_the_others() {
+    let (vm, mux, _class, main) = shared_vm();
+    let stalled = Arc::new((Mutex::new(false), Condvar::new()));
+    let (mux_end, client_end) = transport::duplex();
+    let handle = {
+        let (mux, stalled) = (mux.clone(), stalled.clone());
+        std::thread::spawn(move || mux.add_client(Stalling { inner: mux_end, stalled: stalled }).unwrap())
+    };
+    let (a, a_events) = Client::connect(client_end).unwrap();
+    handle.join().unwrap();
+    a.initialize().unwrap();
+    let (b, _b_events) = attach(&mux);
+    set_stalled(&stalled, true);
+    // The VM reader now sits in a write to `a`; `b` must still be served.
+    vm.emit(jdwp::SUSPEND_NONE, vec![jdwp::Event::ThreadStart { request_id: 0, thread: main }]).unwrap();
+    assert!(matches!(b.send_and_wait(&Command::Version).unwrap(), Reply::Version { .. }));
+    set_stalled(&stalled, false);
+    assert!(matches!(a_events.recv_timeout(Duration::from_secs(5)).unwrap().1, Command::Composite { .. }));
+}
@@ -0,0 +1,63 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+use dcd::jdwp::{self,Command,Error,IDSizes,Packet,PacketError,Reply,MAX_PACKET_SIZE};
+use std::io::ErrorKind;
+
+fn header(length: u32, flags: u8) -> Vec<u8> {
+    let mut bytes = length.to_be_bytes().to_vec();
+    bytes.extend_from_slice(&[0, 0, 0, 7, flags, 1, 1]);
+    return bytes;
+}
+
+fn rejected(bytes: &[u8], max: u32) -> PacketError {
+    let e = Packet::read_max(&mut &bytes[..], max).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    return *e.get_ref().unwrap().downcast_ref::<PacketError>().unwrap();
+}
+
+#[test]
+fn reads_what_it_writes() {
+    let packet = Packet::Reply { id: 3, error: 0, data: vec![1, 2, 3] };
+    let bytes = packet.to_bytes();
+    assert_eq!(Packet::read(&mut &bytes[..]).unwrap(), packet);
+    assert_eq!(Packet::read_max(&mut &bytes[..], 14).unwrap(), packet);
+}
+
+#[test]
+fn bad_headers_name_the_field() {
+    let e = rejected(&header(4, 0), MAX_PACKET_SIZE);
+    assert_eq!(e, PacketError::LengthTooShort(4));
+    assert_eq!(e.field(), "length");
+    let e = rejected(&header(15, 0), 14);
+    assert_eq!(e, PacketError::LengthTooLong { length: 15, max: 14 });
+    assert_eq!(e.to_string(), "JDWP packet length 15 is over the limit of 14");
+    assert_eq!(rejected(&header(u32::MAX, 0), MAX_PACKET_SIZE).field(), "length");
+    let e = rejected(&header(11, 0x81), MAX_PACKET_SIZE);
+    assert_eq!(e, PacketError::UnknownFlags(0x81));
+    assert_eq!(e.field(), "flags");
+}
+
+#[test]
+fn lying_lengths_fail_without_allocating() {
+    // Claims nearly 4 GB but sends three bytes.
+    let mut bytes = header(u32::MAX - 1, 0);
+    bytes.extend_from_slice(&[1, 2, 3]);
+    let e = Packet::read_max(&mut &bytes[..], u32::MAX).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+    assert_eq!(Packet::read(&mut &header(20, 0)[..7]).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn bad_bodies_name_the_field() {
+    let sizes = IDSizes { field: 8, method: 8, object: 8, reference_type: 8, frame: 8 };
+    // ThreadReference.Name claiming five bytes of name but carrying one.
+    let e = Reply::deserialize(11, 1, &[0, 0, 0, 5, b'm'], sizes).unwrap_err();
+    assert_eq!(e, Error::Field { name: "thread_name", source: Box::new(Error::InvalidLength) });
+    assert_eq!(e.serialize(), Error::InvalidLength.serialize());
+    // A breakpoint event cut off inside its location.
+    let location = jdwp::Location { type_tag: jdwp::TYPE_TAG_CLASS, class_id: 2, method_id: 3, index: 4 };
+    let event = jdwp::Event::Breakpoint { request_id: 1, thread: 5, location: location };
+    let (set, cmd, data) = Command::Composite { suspend_policy: jdwp::SUSPEND_ALL, events: vec![event] }.serialize(sizes);
+    let e = Command::deserialize(set, cmd, &data[..data.len() - 20], sizes).unwrap_err();
+    assert_eq!(e.field_path().unwrap(), "events.location.class_id");
+    assert_eq!(*e.root(), Error::AbsentInformation);
+}
@@ -0,0 +1,52 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+use dcd::client::Client;
+use dcd::jdwp::{self,Command,Reply};
+use dcd::mock::{MockVm,Model};
+use dcd::proxy::Proxy;
+use dcd::transport;
+use std::io::Write;
+use std::sync::{Arc,Mutex};
+
+#[derive(Clone,Default)]
+struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(data);
+        return Ok(data.len());
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        return Ok(());
+    }
+}
+
+#[test]
+fn proxy_forwards_and_decodes_both_sides() {
+    let mut model = Model::default();
+    model.idsizes = jdwp::IDSizes { field: 4, method: 4, object: 4, reference_type: 4, frame: 4 };
+    let class = model.add_class("LFoo;", "Foo.java");
+    let thread = model.add_thread("main");
+    let (proxy_vm, vm_end) = transport::duplex();
+    let (proxy_ide, ide_end) = transport::duplex();
+    let mut vm = MockVm::new(model);
+    vm.serve(vm_end).unwrap();
+    let log = SharedBuf::default();
+    let proxy = Arc::new(Proxy::new(log.clone(), None, None));
+    let session = std::thread::spawn(move || proxy.session(proxy_ide, proxy_vm, || {}));
+
+    let (client, events) = Client::connect(ide_end).unwrap();
+    client.initialize().unwrap();
+    let reply = client.send_and_wait(&Command::ReferenceTypeSignature { ref_type: class }).unwrap();
+    assert_eq!(reply, Reply::ReferenceTypeSignature("LFoo;".to_string()));
+    vm.emit(jdwp::SUSPEND_NONE, vec![jdwp::Event::ThreadStart { request_id: 3, thread: thread }]).unwrap();
+    let (_, event) = events.recv().unwrap();
+    assert!(matches!(event, Command::Composite { .. }));
+    drop(client);
+    session.join().unwrap().unwrap();
+
+    let text = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+    assert!(text.contains("-> #0      VirtualMachine.Version: Version"));
+    assert!(text.contains("<- #3      ReferenceType.Signature reply: ReferenceTypeSignature(\"LFoo;\")"));
+    assert!(text.contains("<- #1073741825 Event.Composite"));
+    assert!(!text.contains("undecodable"));
+}
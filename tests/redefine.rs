@@ -0,0 +1,172 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+mod common;
+use common::wait_until;
+use dcd::cui::Session;
+use dcd::jdwp::{self,Capabilities,Command};
+use dcd::mock::{self,MockVm,Model};
+use dcd::redefine::{self,Format};
+use proptest::collection::vec;
+use proptest::prelude::*;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration,SystemTime};
+
+/// A class file with nothing in it but its name, and a long constant to
+/// check two-slot entries are skipped right.
+fn class_file(name: &str, major: u16) -> Vec<u8> {
+    let mut bytes = vec![0xca, 0xfe, 0xba, 0xbe, 0, 0];
+    bytes.extend_from_slice(&major.to_be_bytes());
+    bytes.extend_from_slice(&5u16.to_be_bytes());
+    bytes.extend_from_slice(&[5, 0, 0, 0, 0, 0, 0, 0, 42]);
+    bytes.push(1);
+    bytes.extend_from_slice(&(name.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(name.as_bytes());
+    bytes.extend_from_slice(&[7, 0, 3]);
+    bytes.extend_from_slice(&[0, 0x21, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    return bytes;
+}
+
+/// A dex header whose tables define one class.
+fn dex_file(signature: &str) -> Vec<u8> {
+    let mut bytes = b"dex\n039\0".to_vec();
+    bytes.resize(0x70, 0);
+    let mut set = |at: usize, value: u32| bytes[at..at + 4].copy_from_slice(&value.to_le_bytes());
+    set(0x38, 1);
+    set(0x3c, 0x70);
+    set(0x40, 1);
+    set(0x44, 0x74);
+    set(0x60, 1);
+    set(0x64, 0x78);
+    bytes.extend_from_slice(&0x98u32.to_le_bytes());
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    bytes.extend_from_slice(&[0; 32]);
+    bytes.push(signature.len() as u8);
+    bytes.extend_from_slice(signature.as_bytes());
+    bytes.push(0);
+    return bytes;
+}
+
+fn temp(name: &str) -> PathBuf {
+    return std::env::temp_dir().join(format!("dcd-redefine-{}-{}", std::process::id(), name));
+}
+
+fn start(capabilities: Capabilities) -> (MockVm, Session, u64) {
+    let mut model = Model::default();
+    model.capabilities = capabilities;
+    let main = model.add_class("Lcom/example/Main;", "Main.java");
+    let (vm, session) = common::session(model);
+    return (vm, session, main);
+}
+
+#[test]
+fn parses_class_and_dex_files() {
+    assert_eq!(redefine::parse_class(&class_file("com/example/Main", 52)), Some(("Lcom/example/Main;".to_string(), 52, 0)));
+    assert_eq!(redefine::parse_class(&class_file("com/example/Main", 52)[..20]), None);
+    assert_eq!(redefine::parse_dex(&dex_file("Lcom/example/Dex;")), Some((39, vec!["Lcom/example/Dex;".to_string()])));
+    assert_eq!(redefine::parse_dex(&dex_file("Lcom/example/Dex;")[..0x80]), None);
+
+    let path = temp("single.dex");
+    std::fs::write(&path, dex_file("Lcom/example/Dex;")).unwrap();
+    let definitions = redefine::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(definitions.len(), 1);
+    assert_eq!(definitions[0].format, Format::Dex { version: 39 });
+}
+
+proptest! {
+    /// Valid files with some bytes overwritten and the end cut off, so the
+    /// parsers get past their magic checks.
+    #[test]
+    fn damaged_files_dont_panic(edits in vec((any::<usize>(), any::<u8>()), 0..8), keep in any::<usize>()) {
+        for mut bytes in [dex_file("Lcom/example/Dex;"), class_file("com/example/Main", 52)] {
+            for (at, value) in edits.iter() {
+                let len = bytes.len();
+                bytes[at % len] = *value;
+            }
+            bytes.truncate(keep % (bytes.len() + 1));
+            let _ = redefine::parse_dex(&bytes);
+            let _ = redefine::parse_class(&bytes);
+        }
+    }
+}
+
+#[test]
+fn redefines_loaded_classes_from_a_jar() {
+    let (vm, mut session, main) = start(Capabilities::REDEFINE_CLASSES);
+    let path = temp("app.jar");
+    {
+        let mut jar = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+        let options = zip::write::FileOptions::default();
+        jar.start_file("META-INF/MANIFEST.MF", options).unwrap();
+        jar.write_all(b"Manifest-Version: 1.0\n").unwrap();
+        jar.start_file("com/example/Main.class", options).unwrap();
+        jar.write_all(&class_file("com/example/Main", 52)).unwrap();
+        jar.start_file("com/example/Later.class", options).unwrap();
+        jar.write_all(&class_file("com/example/Later", 52)).unwrap();
+        jar.finish().unwrap();
+    }
+    session.execute(&format!("redefine {}", path.display())).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(vm.model().redefined, vec![(main, class_file("com/example/Main", 52))]);
+
+    // Java 21 classes don't load on a 1.8 VM, so they aren't sent.
+    let path = temp("New.class");
+    std::fs::write(&path, class_file("com/example/Main", 65)).unwrap();
+    assert!(session.execute(&format!("redefine {}", path.display())).is_err());
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(vm.model().redefined.len(), 1);
+}
+
+#[test]
+fn explains_what_the_vm_refused() {
+    let mut model = Model::default();
+    model.capabilities = Capabilities::REDEFINE_CLASSES;
+    model.add_class("Lcom/example/Main;", "Main.java");
+    let (vm, client, _events) = mock::connect(model).unwrap();
+    vm.on(|cmd, _model| match cmd {
+        Command::RedefineClasses { .. } => Some(Err(jdwp::Error::SchemaChangeNotImplemented)),
+        _ => None,
+    });
+    client.initialize().unwrap();
+    let definitions = vec![redefine::Definition {
+        signature: "Lcom/example/Main;".to_string(),
+        format: Format::Class { major: 52, minor: 0 },
+        bytes: class_file("com/example/Main", 52),
+        source: "Main.class".to_string(),
+    }];
+    match redefine::redefine(&client, &definitions) {
+        Err(dcd::Error::Command(text)) => {
+            assert!(text.contains("com.example.Main"), "{}", text);
+            assert!(text.contains("fields were added"), "{}", text);
+        },
+        r => panic!("unexpected {:?}", r),
+    }
+
+    let (_vm, mut session, _main) = start(Capabilities::empty());
+    let path = temp("Main.class");
+    std::fs::write(&path, class_file("com/example/Main", 52)).unwrap();
+    assert!(session.execute(&format!("redefine {}", path.display())).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn watch_redefines_changed_files() {
+    let (vm, mut session, main) = start(Capabilities::REDEFINE_CLASSES);
+    let dir = temp("classes");
+    std::fs::create_dir_all(dir.join("com/example")).unwrap();
+    let file = dir.join("com/example/Main.class");
+    std::fs::write(&file, class_file("com/example/Main", 52)).unwrap();
+    session.execute(&format!("redefine watch {}", dir.display())).unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(vm.model().redefined.is_empty());
+
+    let rebuilt = class_file("com/example/Main", 51);
+    std::fs::write(&file, &rebuilt).unwrap();
+    let f = std::fs::File::options().write(true).open(&file).unwrap();
+    f.set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+    wait_until(|| vm.model().redefined.len() == 1);
+    assert_eq!(vm.model().redefined[0], (main, rebuilt));
+    session.execute("redefine watch off").unwrap();
+    assert!(session.execute("redefine watch off").is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
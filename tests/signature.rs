@@ -0,0 +1,63 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+use dcd::jdwp::Tag;
+use dcd::signature::{self,Type,TypeArg};
+
+#[test]
+fn field_signatures_render_as_java() {
+    let java = |s: &str| signature::parse_type(s).unwrap().java();
+    assert_eq!(java("I"), "int");
+    assert_eq!(java("[[J"), "long[][]");
+    assert_eq!(java("[Ljava/lang/String;"), "java.lang.String[]");
+    assert_eq!(java("Ljava/util/Map<Ljava/lang/String;+Ljava/lang/Number;>;"), "java.util.Map<java.lang.String, ? extends java.lang.Number>");
+    assert_eq!(java("Ljava/util/List<-TT;>;"), "java.util.List<? super T>");
+    assert_eq!(java("Ljava/lang/Class<*>;"), "java.lang.Class<?>");
+    assert_eq!(java("La/Outer<TK;>.Inner<[I>;"), "a.Outer<K>.Inner<int[]>");
+    assert_eq!(signature::parse_type("La/Outer<TK;>.Inner;").unwrap().jni(), "La/Outer$Inner;");
+    assert_eq!(signature::parse_type("Ljava/util/List<Ljava/lang/String;>;").unwrap().jni(), "Ljava/util/List;");
+
+    for bad in ["", "V", "Q", "L;", "Ljava/lang/String", "II", "[", "TT", "Ljava/util/List<>;", "Ljava/util/List<TT;"] {
+        assert_eq!(signature::parse_type(bad), None, "{}", bad);
+    }
+}
+
+#[test]
+fn method_and_class_signatures_parse() {
+    let method = signature::parse_method("<T:Ljava/lang/Object;>(TT;)V").unwrap();
+    assert_eq!(method.params, vec![Type::Variable("T".to_string())]);
+    assert_eq!(method.returns, Type::Void);
+    assert_eq!(method.java("accept"), "<T> void accept(T)");
+
+    let method = signature::parse_method("<T::Ljava/lang/Comparable<-TT;>;>([TT;I)TT;^Ljava/io/IOException;").unwrap();
+    assert_eq!(method.java("max"), "<T extends java.lang.Comparable<? super T>> T max(T[], int) throws java.io.IOException");
+    assert_eq!(method.params[0].jni(), "[Ljava/lang/Object;");
+    assert_eq!(signature::parse_method("()V").unwrap().params, vec![]);
+    assert_eq!(signature::parse_method("(I"), None);
+    assert_eq!(signature::parse_method("(I)"), None);
+    assert_eq!(signature::parse_method("(V)V"), None);
+
+    let class = signature::parse_class("<K:Ljava/lang/Object;V:Ljava/lang/Object;>Ljava/util/AbstractMap<TK;TV;>;Ljava/lang/Cloneable;").unwrap();
+    assert_eq!(signature::type_params_java(&class.type_params), "<K, V>");
+    assert_eq!(class.superclass.java(), "java.util.AbstractMap<K, V>");
+    assert_eq!(class.interfaces, vec![Type::class("java/lang/Cloneable")]);
+    let class = signature::parse_class("<E:Ljava/lang/Enum<TE;>;>Ljava/lang/Object;").unwrap();
+    assert_eq!(signature::type_params_java(&class.type_params), "<E extends java.lang.Enum<E>>");
+    match &class.type_params[0].bounds[0] {
+        Type::Class(parts) => assert_eq!(parts[0].args, vec![TypeArg::Exactly(Type::Variable("E".to_string()))]),
+        t => panic!("unexpected {:?}", t),
+    }
+}
+
+#[test]
+fn tags_follow_the_declared_type() {
+    let tag = |s: &str| signature::parse_type(s).unwrap().tag();
+    assert_eq!(tag("Ljava/lang/String;"), b's');
+    assert_eq!(tag("Ljava/lang/Thread;"), b't');
+    assert_eq!(tag("Ljava/lang/Class<*>;"), b'c');
+    assert_eq!(tag("Ljava/lang/Object;"), b'L');
+    assert_eq!(tag("[Ljava/lang/String;"), b'[');
+    assert_eq!(tag("TT;"), b'L');
+    assert_eq!(tag("J"), b'J');
+    assert_eq!(signature::reference(b's', 5), Some(Tag::String(5)));
+    assert_eq!(signature::reference(b'g', 5), Some(Tag::ThreadGroup(5)));
+    assert_eq!(signature::reference(b'I', 5), None);
+}
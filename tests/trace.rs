@@ -0,0 +1,60 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+mod common;
+use common::wait_until;
+use dcd::jdwp::{self,Command,EventKind,Modifier,Tag};
+use dcd::mock::{MockVm,Model};
+use dcd::trace::glob_match;
+
+#[test]
+fn globs() {
+    assert!(glob_match("ad*", "add"));
+    assert!(glob_match("*", ""));
+    assert!(glob_match("a*d*y", "addy"));
+    assert!(glob_match("run", "run"));
+    assert!(!glob_match("run", "runner"));
+    assert!(!glob_match("*Impl", "MainImpl2"));
+}
+
+#[test]
+fn traces_calls_and_returns_to_a_file() {
+    let mut model = Model::default();
+    let class = model.add_class("Lcom/example/Main;", "Main.java");
+    let add = model.add_method(class, "add", "(II)I", &[(0, 7), (4, 8)]);
+    let run = model.add_method(class, "run", "()V", &[(0, 3)]);
+    model.class_mut(class).unwrap().methods.iter_mut().find(|m| m.id == add).unwrap().arg_count = 2;
+    model.add_variable(class, add, "a", "I", 0);
+    model.add_variable(class, add, "b", "I", 1);
+    let thread = model.add_thread("worker");
+    let frame = model.push_frame(thread, class, add, 0);
+    model.set_local(frame, 0, Tag::Int(2));
+    model.set_local(frame, 1, Tag::Int(3));
+    let (vm, mut session) = common::session(model);
+
+    let path = std::env::temp_dir().join(format!("dcd-trace-{}.log", std::process::id()));
+    session.execute(&format!("trace Main.ad* {}", path.display())).unwrap();
+    let entry = vm.model().requests_for(EventKind::MethodEntry)[0].clone();
+    let exit = vm.model().requests_for(EventKind::MethodExitWithReturnValue)[0].clone();
+    for request in [&entry, &exit] {
+        assert_eq!(request.suspend_policy, jdwp::SUSPEND_EVENT_THREAD);
+        assert_eq!(request.modifiers, vec![Modifier::ClassMatch("*Main".to_string())]);
+    }
+
+    let location = |method: u64, index: u64| jdwp::Location { type_tag: 1, class_id: class, method_id: method, index: index };
+    let resumes = |vm: &MockVm| vm.received().iter().filter(|c| matches!(c, Command::ThreadReferenceResume { .. })).count();
+    vm.emit(jdwp::SUSPEND_EVENT_THREAD, vec![jdwp::Event::MethodEntry { request_id: entry.id, thread: thread, location: location(run, 0) }]).unwrap();
+    vm.emit(jdwp::SUSPEND_EVENT_THREAD, vec![jdwp::Event::MethodEntry { request_id: entry.id, thread: thread, location: location(add, 0) }]).unwrap();
+    vm.emit(jdwp::SUSPEND_EVENT_THREAD, vec![jdwp::Event::MethodExitWithReturnValue {
+        request_id: exit.id, thread: thread, location: location(add, 4), value: Tag::Int(5),
+    }]).unwrap();
+    wait_until(|| resumes(&vm) == 3);
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(log, "[worker] -> com.example.Main.add(a=2, b=3)\n[worker] <- com.example.Main.add = 5\n");
+
+    session.execute(&format!("untrace {}", entry.id)).unwrap();
+    assert!(vm.model().requests_for(EventKind::MethodEntry).is_empty());
+    assert!(vm.model().requests_for(EventKind::MethodExitWithReturnValue).is_empty());
+    assert!(session.execute("untrace 99").is_err());
+    assert!(session.execute("trace nodot").is_err());
+}
@@ -0,0 +1,69 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+mod common;
+use common::wait_until;
+use dcd::cui::Session;
+use dcd::jdwp::{self,Command,EventKind,Modifier,Tag};
+use dcd::mock::MockVm;
+use dcd::Error;
+
+struct Fixture {
+    vm: MockVm,
+    session: Session,
+    class: u64,
+    count: u64,
+    object: u64,
+    thread: u64,
+    location: jdwp::Location,
+}
+
+/// Main with a count field and one instance, running in Main.run.
+fn start(capabilities: jdwp::Capabilities) -> Fixture {
+    let mut p = common::program(&[(0, 5), (3, 6)], 3);
+    p.model.capabilities = capabilities;
+    let count = p.model.add_field(p.class, "count", "I");
+    let object = p.model.add_object(p.class, &[(count, Tag::Int(41))]);
+    let (vm, session) = common::session(p.model);
+    return Fixture { vm: vm, session: session, class: p.class, count: count, object: object, thread: p.thread, location: p.location };
+}
+
+#[test]
+fn refuses_without_the_capability() {
+    let mut f = start(jdwp::Capabilities::WATCH_FIELD_MODIFICATION);
+    match f.session.execute("rwatch Main.count") {
+        Err(Error::Command(msg)) => assert!(msg.contains("WATCH_FIELD_ACCESS"), "{}", msg),
+        r => panic!("unexpected {:?}", r),
+    }
+    assert!(f.session.execute("awatch Main.count").is_err());
+    assert!(f.vm.model().requests.is_empty());
+    assert!(f.session.execute("watch Main.nosuch").is_err());
+}
+
+#[test]
+fn watches_one_object_and_reports_old_and_new_values() {
+    let mut f = start(jdwp::Capabilities::WATCH_FIELD_MODIFICATION | jdwp::Capabilities::WATCH_FIELD_ACCESS);
+    f.session.execute(&format!("awatch com.example.Main.count {:#x}", f.object)).unwrap();
+    let requests = f.vm.model().requests.clone();
+    assert_eq!(requests.iter().map(|r| r.event_kind).collect::<Vec<_>>(), vec![EventKind::FieldAccess, EventKind::FieldModification]);
+    for r in requests.iter() {
+        assert_eq!(r.modifiers, vec![Modifier::FieldOnly { declaring: f.class, field_id: f.count }, Modifier::InstanceOnly(f.object)]);
+    }
+
+    f.vm.emit(jdwp::SUSPEND_ALL, vec![jdwp::Event::FieldModification {
+        request_id: requests[1].id,
+        thread: f.thread,
+        location: f.location,
+        ref_type_tag: jdwp::TYPE_TAG_CLASS,
+        type_id: f.class,
+        field_id: f.count,
+        object: Tag::Object(f.object),
+        value: Tag::Int(42),
+    }]).unwrap();
+    // The old value is read back from the object before anything resumes.
+    wait_until(|| f.vm.received().iter().any(|c| matches!(c, Command::ObjectReferenceGetValues { object, .. } if *object == f.object)));
+    wait_until(|| f.session.execute("where").is_ok());
+    assert!(!f.vm.received().contains(&Command::Resume));
+
+    f.session.execute(&format!("delete {}", requests[0].id)).unwrap();
+    assert_eq!(f.vm.model().requests.len(), 1);
+    assert!(f.session.execute(&format!("delete {}", requests[0].id)).is_err());
+}